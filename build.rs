@@ -0,0 +1,50 @@
+//! Records what went into the build, for `--version` and the `monitoring.info` series
+
+use std::{
+    env,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    // Built from a source tarball, there's no commit to tell
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=10", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MONITORING_GIT_COMMIT={}", commit);
+
+    // Reproducible builds pin the date through SOURCE_DATE_EPOCH
+    let built = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time behind Unix epoch time")
+                .as_secs()
+        });
+    let date = chrono::DateTime::from_timestamp(built as i64, 0)
+        .expect("Build time out of range")
+        .format("%Y-%m-%d");
+    println!("cargo:rustc-env=MONITORING_BUILD_DATE={}", date);
+
+    let mut features = env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .filter(|feature| feature != "default")
+        .collect::<Vec<_>>();
+    features.sort();
+    println!("cargo:rustc-env=MONITORING_FEATURES={}", features.join(","));
+}
@@ -0,0 +1,257 @@
+//! Collecting the readings of other monitoring instances and shipping them from one place
+//!
+//! Each source is another instance serving its HTTP API. The aggregator follows its `/stream`,
+//! prefixes every datapoint with the source's name (e.g. `garage.workshop.temperature`) and
+//! writes them all to a single sink, so only the aggregating Pi needs internet access and the
+//! metrics API key.
+//!
+//! The stream is asked for in the compact format of [`crate::wire`], which takes a fraction of
+//! the bytes over a weak WiFi link. Sources that don't know it yet send server-sent events
+//! instead, which are followed just as well.
+
+#[cfg(feature = "http")]
+use crate::{
+    pipeline::{self, DropPolicy},
+    sinks::Sink,
+    spool::Spool,
+    state::State,
+    wire, Datapoint,
+};
+use std::str::FromStr;
+#[cfg(feature = "http")]
+use std::{future::Future, time::Duration};
+#[cfg(feature = "http")]
+use tokio::{sync::mpsc, task::JoinSet};
+
+/// How long to wait before reconnecting to a source, doubling up to the maximum on every
+/// failed attempt in a row
+#[cfg(feature = "http")]
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+#[cfg(feature = "http")]
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(300);
+
+/// Sources send a keepalive every 30 seconds, so a stream quiet for longer than this is dead
+#[cfg(feature = "http")]
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Another instance to collect readings from
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// Prefixed to the names of the source's datapoints, e.g. `home.kitchen-pi`
+    pub name: String,
+    /// Where the source serves its HTTP API, e.g. `http://kitchen-pi.local:8080`
+    pub url: String,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    /// Parses `name=url`, or just `url` to name the source after its host
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let (name, url) = match source.split_once('=') {
+            Some((name, url)) => (name.to_string(), url.to_string()),
+            None => {
+                let url = source
+                    .parse::<hyper::Uri>()
+                    .map_err(|err| err.to_string())?;
+                let host = url
+                    .host()
+                    .ok_or_else(|| format!("{} has no host to name it after", source))?;
+                (host.to_string(), source.to_string())
+            }
+        };
+        if name.is_empty() {
+            return Err(format!("{} needs a name", source));
+        }
+
+        Ok(Source {
+            name,
+            url: url.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+/// Follows every source and writes their readings to `sink`, queueing up to `queue_capacity`
+/// (at least 1) batches and keeping those the sink fails to take in `spool`, if any. With
+/// `merge_unchanged`, repeated values are left out of the batches, see
+/// [`pipeline::compact`]. Sources that go away are retried until they're back. Runs until
+/// `stopped` resolves, then stops following the sources and returns once the readings already
+/// taken are written (or spooled).
+#[cfg(feature = "http")]
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    sources: Vec<Source>,
+    token: Option<String>,
+    sink: &dyn Sink,
+    queue_capacity: usize,
+    drop_policy: DropPolicy,
+    spool: Option<&Spool>,
+    merge_unchanged: bool,
+    stopped: impl Future<Output = ()>,
+) {
+    let (sender, mut receiver) = mpsc::channel(queue_capacity);
+    let client = reqwest::Client::new();
+    let mut followers = JoinSet::new();
+    for source in sources {
+        followers.spawn(follow(
+            client.clone(),
+            source,
+            token.clone(),
+            sender.clone(),
+        ));
+    }
+    drop(sender);
+
+    let (queue, batches) = pipeline::sink_queue(queue_capacity, drop_policy);
+    let state = State::default();
+    tokio::join!(
+        async move {
+            tokio::pin!(stopped);
+            loop {
+                tokio::select! {
+                    readings = receiver.recv() => match readings {
+                        Some(readings) => queue.push(readings),
+                        None => break,
+                    },
+                    _ = &mut stopped => {
+                        // Stopped first, letting go of the queue once what they took is in it
+                        followers.shutdown().await;
+                        while let Some(readings) = receiver.recv().await {
+                            queue.push(readings);
+                        }
+                        break;
+                    }
+                }
+            }
+        },
+        pipeline::write_batches(batches, sink, &state, spool, merge_unchanged),
+    );
+}
+
+/// Streams a source's readings into `sender`, reconnecting whenever the stream breaks
+#[cfg(feature = "http")]
+#[tracing::instrument(skip_all, fields(source = %source.name))]
+async fn follow(
+    client: reqwest::Client,
+    source: Source,
+    token: Option<String>,
+    sender: mpsc::Sender<Vec<Datapoint>>,
+) {
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        match stream(&client, &source, token.as_deref(), &sender, &mut delay).await {
+            Ok(()) => tracing::warn!("Stream ended, reconnecting in {:?}", delay),
+            Err(err) => tracing::warn!("Stream failed: {}, reconnecting in {:?}", err, delay),
+        }
+        if sender.is_closed() {
+            return;
+        }
+
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+/// Reads the source's server-sent events until the connection closes. Resets `delay` once
+/// connected.
+#[cfg(feature = "http")]
+async fn stream(
+    client: &reqwest::Client,
+    source: &Source,
+    token: Option<&str>,
+    sender: &mpsc::Sender<Vec<Datapoint>>,
+    delay: &mut Duration,
+) -> Result<(), reqwest::Error> {
+    let mut request = client.get(format!("{}/stream", source.url)).header(
+        reqwest::header::ACCEPT,
+        format!("{}, text/event-stream", wire::CONTENT_TYPE),
+    );
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let mut response = request.send().await?.error_for_status()?;
+    let compact = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .is_some_and(|kind| kind == wire::CONTENT_TYPE);
+    tracing::info!(compact, "Following {}", source.url);
+    *delay = RECONNECT_DELAY;
+
+    let mut decoder = wire::Decoder::new();
+    let mut buffer = Vec::new();
+    loop {
+        let chunk = match tokio::time::timeout(IDLE_TIMEOUT, response.chunk()).await {
+            Ok(chunk) => chunk?,
+            Err(_) => {
+                tracing::warn!("No events for {:?}, dropping the connection", IDLE_TIMEOUT);
+                return Ok(());
+            }
+        };
+        let Some(chunk) = chunk else {
+            return Ok(());
+        };
+        if compact {
+            decoder.extend(&chunk);
+            loop {
+                let mut readings = match decoder.next_batch() {
+                    Ok(Some(readings)) => readings,
+                    Ok(None) => break,
+                    Err(err) => {
+                        // The frames after a bad one can't be told apart anymore
+                        tracing::warn!("Unreadable stream: {}, dropping the connection", err);
+                        return Ok(());
+                    }
+                };
+                for datapoint in &mut readings {
+                    datapoint.name = format!("{}.{}", source.name, datapoint.name);
+                }
+                if sender.send(readings).await.is_err() {
+                    return Ok(());
+                }
+            }
+            continue;
+        }
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.windows(2).position(|window| window == b"\n\n") {
+            let event = String::from_utf8_lossy(&buffer[..end]).into_owned();
+            buffer.drain(..end + 2);
+
+            if let Some(readings) = parse_event(&event, &source.name) {
+                if sender.send(readings).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Turns a `readings` event into datapoints named under the source
+#[cfg(feature = "http")]
+fn parse_event(event: &str, prefix: &str) -> Option<Vec<Datapoint>> {
+    let mut kind = "message";
+    let mut data = String::new();
+    for line in event.lines() {
+        if let Some(value) = line.strip_prefix("event:") {
+            kind = value.trim();
+        } else if let Some(value) = line.strip_prefix("data:") {
+            data.push_str(value.trim_start());
+        }
+    }
+    if kind != "readings" {
+        return None;
+    }
+
+    match serde_json::from_str::<Vec<Datapoint>>(&data) {
+        Ok(mut readings) => {
+            for datapoint in &mut readings {
+                datapoint.name = format!("{}.{}", prefix, datapoint.name);
+            }
+            Some(readings)
+        }
+        Err(err) => {
+            tracing::warn!("Ignoring malformed readings: {}", err);
+            None
+        }
+    }
+}
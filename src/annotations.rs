@@ -0,0 +1,123 @@
+//! Marking the service's [`Event`]s on the Grafana graphs, so gaps and spikes come with context
+//!
+//! Every event is posted to Grafana's annotations API, tagged `monitoring`, its kind and its
+//! sensor, e.g. `["monitoring", "alert", "kitchen"]`. The dashboard generated by
+//! [`crate::grafana::dashboard`] overlays them on its panels.
+
+use crate::{error::SinkError, events::Event, grafana::ANNOTATION_TAG, ratelimit::RateLimiter};
+use chrono::Utc;
+use std::{io::Write, sync::Arc};
+use tokio::sync::{
+    broadcast::{self, error::RecvError, error::TryRecvError},
+    watch,
+};
+
+/// Posts annotations to a Grafana instance, e.g. `https://example.grafana.net`
+pub struct GrafanaAnnotations {
+    url: String,
+    token: String,
+    client: reqwest::Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    dry_run: bool,
+}
+
+impl GrafanaAnnotations {
+    /// Authenticates with a service account token allowed to write annotations
+    pub fn new(url: impl Into<String>, token: impl Into<String>) -> Self {
+        GrafanaAnnotations {
+            url: url.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            client: reqwest::Client::new(),
+            rate_limiter: None,
+            dry_run: false,
+        }
+    }
+
+    /// Wait for the rate limiter before every post, e.g. the one shared with the sink
+    pub fn rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Print every annotation that would be posted to stdout rather than post it
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    pub async fn post(&self, event: &Event) -> Result<(), SinkError> {
+        let mut tags = vec![ANNOTATION_TAG, event.kind()];
+        tags.extend(event.sensor());
+        let url = format!("{}/api/annotations", self.url);
+        let annotation = serde_json::json!({
+            "time": Utc::now().timestamp_millis(),
+            "tags": tags,
+            "text": event.text(),
+        });
+        if self.dry_run {
+            let mut stdout = std::io::stdout().lock();
+            let _ = writeln!(
+                stdout,
+                "POST {}\n{}",
+                url,
+                serde_json::to_string_pretty(&annotation)?
+            );
+            return Ok(());
+        }
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .json(&annotation)
+            .send()
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status @ (reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                Err(SinkError::Unauthorized(status))
+            }
+            status => Err(SinkError::Status(status)),
+        }
+    }
+}
+
+/// Posts the events as they happen until shutdown, and then those that happened meanwhile
+pub(crate) async fn run(
+    annotations: &GrafanaAnnotations,
+    mut events: broadcast::Receiver<Event>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let event = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "Annotations fell behind, leaving out some events");
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        post(annotations, &event).await;
+    }
+
+    loop {
+        match events.try_recv() {
+            Ok(event) => post(annotations, &event).await,
+            Err(TryRecvError::Lagged(_)) => continue,
+            Err(_) => return,
+        }
+    }
+}
+
+async fn post(annotations: &GrafanaAnnotations, event: &Event) {
+    if let Err(err) = annotations.post(event).await {
+        tracing::warn!("Unable to annotate \"{}\": {}", event.text(), err);
+    }
+}
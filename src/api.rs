@@ -0,0 +1,843 @@
+//! The embedded HTTP API, serving the latest readings to other devices on the LAN
+//!
+//! - `GET /` - an HTML dashboard of the current readings, their trend and sensor health
+//! - `GET /readings` - the latest values, timestamp and status of every sensor. Answers
+//!   conditional requests (`If-None-Match`, `If-Modified-Since`) with `304 Not Modified` until
+//!   the readings change, and is only serialized again once they do.
+//! - `GET /sensors` - the configured sensors
+//! - `GET /metadata` - where every sensor is, its type and tags, and the unit, description and
+//!   calibration of each of its metrics, for dashboards to label the series by
+//! - `POST /sensors` - add a sensor, `POST /sensors/{name}` - rename, disable or re-enable it,
+//!   `DELETE /sensors/{name}` - remove it. These need the API token and are persisted to
+//!   `sensors.yaml`.
+//! - `GET /healthz` - liveness: the sampling loop is still ticking
+//! - `GET /readyz` - readiness: at least one sensor was read and one batch was written
+//! - `GET /history?sensor=&metric=&from=&to=&step=` - a metric's recent readings, optionally
+//!   averaged into `step` second buckets
+//! - `GET /stream` - every new batch of readings as it's taken, as server-sent events, or in
+//!   the compact binary format of [`crate::wire`] to clients accepting it
+//! - `POST /ingest` - readings pushed by other devices, e.g. ESP8266/ESP32 sensors, shipped
+//!   along with the service's own. Needs the ingest token.
+//!
+//! Everything but the health checks can be put behind a bearer token or basic auth, and the
+//! API served over TLS, for Pis reachable from outside a trusted LAN.
+
+use crate::{
+    config::{self, Calibration, Sensor},
+    dashboard,
+    error::ConfigError,
+    history::History,
+    locale::Locale,
+    manager::{ManageError, SensorManager, SensorUpdate},
+    service::ApiAuth,
+    state::{State, Version},
+    wire, Datapoint,
+};
+use base64::Engine;
+use chrono::DateTime;
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use hyper::{
+    body::Bytes,
+    header,
+    server::{accept, conn::AddrIncoming},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::Infallible,
+    fs::File,
+    future::{self, Future},
+    io::BufReader,
+    net::SocketAddr,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time,
+};
+use tokio_rustls::{rustls, TlsAcceptor};
+
+/// How many TLS handshakes may be in progress at once, and how long each may take, so that
+/// stalled clients can't stop others from connecting
+const TLS_HANDSHAKES: usize = 16;
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest request body accepted by the endpoints taking one
+const MAX_BODY_BYTES: u64 = 64 * 1024;
+
+/// How often an idle event stream sends a comment, so proxies don't close it between readings
+const STREAM_KEEPALIVE: Duration = Duration::from_secs(30);
+
+/// Everything the API handlers read from
+pub(crate) struct Api {
+    pub manager: Arc<SensorManager>,
+    /// Bearer token required to change the sensors; changes are refused without one
+    pub api_token: Option<String>,
+    /// Credentials required for everything else but the health checks
+    pub auth: Option<ApiAuth>,
+    pub state: Arc<State>,
+    pub history: Arc<History>,
+    /// How the dashboard page shows the readings
+    pub locale: Locale,
+    /// How long the sampling loop may go without ticking before the service counts as wedged
+    pub liveness_window: Duration,
+    pub readings: broadcast::Sender<Vec<Datapoint>>,
+    /// Where pushed readings join the sensors' own
+    pub ingest: mpsc::Sender<Vec<Datapoint>>,
+    /// Shared token devices push readings with; pushes are refused without one
+    pub ingest_token: Option<String>,
+    /// How long clients may reuse `/readings` before asking again; they revalidate every time
+    /// without one
+    pub max_age: Option<Duration>,
+    /// The latest serialized `/readings`, and the version of the states it was taken from
+    pub readings_cache: Mutex<Option<(Version, Bytes)>>,
+    /// Resolution of pushed readings that don't say how often they're sent
+    pub refresh: i32,
+    /// Ends the open event streams, which would otherwise hold up the graceful shutdown
+    pub shutdown: watch::Receiver<bool>,
+}
+
+/// A reading pushed to `/ingest`
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pushed {
+    /// Sensor name, e.g. `porch`
+    name: String,
+    metric: String,
+    value: f64,
+    /// Unix timestamp of the reading (default: when it's received)
+    time: Option<i64>,
+    /// How often the sensor sends its readings in seconds (default: the service refresh time)
+    interval: Option<i32>,
+}
+
+/// `/ingest` takes a single reading or a list of them
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Push {
+    One(Pushed),
+    Many(Vec<Pushed>),
+}
+
+#[derive(Serialize)]
+struct Series {
+    sensor: String,
+    metric: String,
+    /// `[time, value]` pairs, oldest first
+    points: Vec<(i64, f64)>,
+}
+
+/// What `/metadata` tells about a sensor
+#[derive(Serialize)]
+struct SensorMetadata {
+    sensor: String,
+    /// Where its series are written, e.g. `cottage.bedroom.sensor1`
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room: Option<String>,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    tags: BTreeMap<String, String>,
+    disabled: bool,
+    metrics: Vec<MetricMetadata>,
+}
+
+#[derive(Serialize)]
+struct MetricMetadata {
+    metric: String,
+    series: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    calibration: Vec<Calibration>,
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+/// Loads a PEM certificate chain and private key for serving the API over TLS
+pub(crate) fn load_tls(cert: &Path, key: &Path) -> Result<Arc<rustls::ServerConfig>, ConfigError> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|err| ConfigError::from_io(path.to_path_buf(), err))
+    };
+    let invalid = |path: &Path, reason: &dyn std::fmt::Display| {
+        ConfigError::Invalid(format!("{}: {}", path.display(), reason))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .map_err(|err| invalid(cert, &err))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(invalid(cert, &"no certificates found"));
+    }
+
+    let key = rustls_pemfile::read_all(&mut open(key)?)
+        .map_err(|err| invalid(key, &err))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid(key, &"no private key found"))?;
+
+    let config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| invalid(cert, &err))?;
+
+    Ok(Arc::new(config))
+}
+
+/// Binds the listening socket straight away, so that a taken port fails the service on startup,
+/// and returns the bound address and the future serving requests until `shutdown` resolves
+pub(crate) fn serve(
+    addr: SocketAddr,
+    api: Arc<Api>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(SocketAddr, BoxFuture<'static, Result<(), hyper::Error>>), hyper::Error> {
+    macro_rules! make_service {
+        () => {
+            make_service_fn(move |_| {
+                let api = api.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request| {
+                        let api = api.clone();
+                        async move { Ok::<_, Infallible>(handle(&api, request).await) }
+                    }))
+                }
+            })
+        };
+    }
+
+    let mut incoming = AddrIncoming::bind(&addr)?;
+    let addr = incoming.local_addr();
+
+    let Some(tls) = tls else {
+        tracing::info!("Serving the HTTP API on http://{}", addr);
+        let server = Server::builder(incoming).serve(make_service!());
+        return Ok((addr, server.with_graceful_shutdown(shutdown).boxed()));
+    };
+
+    let acceptor = TlsAcceptor::from(tls);
+    let connections = futures::stream::poll_fn(move |cx| {
+        accept::Accept::poll_accept(Pin::new(&mut incoming), cx)
+    })
+    .filter_map(|connection| {
+        future::ready(
+            connection
+                .map_err(|err| tracing::warn!("Failed to accept an API connection: {}", err))
+                .ok(),
+        )
+    })
+    .map(move |connection| time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(connection)))
+    .buffer_unordered(TLS_HANDSHAKES)
+    .filter_map(|handshake| {
+        future::ready(match handshake {
+            Ok(Ok(stream)) => Some(Ok::<_, Infallible>(stream)),
+            Ok(Err(err)) => {
+                tracing::debug!("TLS handshake failed: {}", err);
+                None
+            }
+            Err(_) => {
+                tracing::debug!("TLS handshake timed out");
+                None
+            }
+        })
+    });
+
+    tracing::info!("Serving the HTTP API on https://{}", addr);
+    let server = Server::builder(accept::from_stream(connections)).serve(make_service!());
+    Ok((addr, server.with_graceful_shutdown(shutdown).boxed()))
+}
+
+async fn handle(api: &Api, request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path();
+    // Only the orchestrators' probes are answered while saving power
+    if api.state.low_power().is_some() && !matches!(path, "/healthz" | "/readyz") {
+        return text(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "in low-power mode, running on battery".to_string(),
+        );
+    }
+    // Checked against the API token instead
+    if (path == "/sensors" && request.method() == Method::POST) || path.starts_with("/sensors/") {
+        return manage(api, request).await;
+    }
+
+    if path == "/ingest" {
+        return ingest(api, request).await;
+    }
+
+    // Orchestrators probe these without credentials, and they give nothing away
+    let public = matches!(path, "/healthz" | "/readyz");
+    match &api.auth {
+        Some(auth) if !public && !authenticated(&request, auth) => return unauthenticated(auth),
+        _ => {}
+    }
+
+    match (request.method(), path) {
+        (&Method::GET, "/") => html(dashboard::render(
+            &api.state.snapshot(),
+            &api.history,
+            &api.locale,
+        )),
+        (&Method::GET, "/readings") => readings(api, &request),
+        (&Method::GET, "/sensors") => json(&api.manager.sensors().await),
+        (&Method::GET, "/metadata") => json(&metadata(api).await),
+        (&Method::GET, "/healthz") => health(liveness(api)),
+        (&Method::GET, "/readyz") => health(readiness(api)),
+        (&Method::GET, "/history") => match history(api, request.uri().query().unwrap_or_default())
+        {
+            Ok(series) => json(&series),
+            Err(reason) => text(StatusCode::BAD_REQUEST, reason),
+        },
+        (&Method::GET, "/stream") => {
+            let compact = request
+                .headers()
+                .get_all(header::ACCEPT)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|accept| accept.contains(wire::CONTENT_TYPE));
+            stream(api, compact)
+        }
+        _ => status(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Answers with the latest readings, from the cache unless they changed since they were last
+/// serialized, or with `304 Not Modified` if the client has them already
+fn readings(api: &Api, request: &Request<Body>) -> Response<Body> {
+    let header = |name| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let version = api.state.version();
+    // If-Modified-Since is only looked at without an If-None-Match, see RFC 9110 13.1.3
+    let unchanged = match header(header::IF_NONE_MATCH) {
+        Some(tags) => tags
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag(version)),
+        None => header(header::IF_MODIFIED_SINCE)
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| version.changed_at <= since.timestamp()),
+    };
+    if unchanged {
+        return validators(api, version)
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .expect("Valid response");
+    }
+
+    let mut cache = api.readings_cache.lock().expect("Cache lock poisoned");
+    let (version, body) = match cache.as_ref() {
+        Some((cached, body)) if *cached == version => (version, body.clone()),
+        _ => {
+            // Tagged with the snapshot's own version, in case the readings changed meanwhile
+            let (version, snapshot) = api.state.versioned_snapshot();
+            match serde_json::to_vec(&snapshot) {
+                Ok(body) => {
+                    let body = Bytes::from(body);
+                    *cache = Some((version, body.clone()));
+                    (version, body)
+                }
+                Err(err) => {
+                    tracing::error!("Unable to serialize API response: {}", err);
+                    return status(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+            }
+        }
+    };
+    drop(cache);
+
+    validators(api, version)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .expect("Valid response")
+}
+
+fn etag(version: Version) -> String {
+    format!("\"{}-{}\"", version.epoch, version.changes)
+}
+
+/// A response with the headers telling clients when to ask for the readings again
+fn validators(api: &Api, version: Version) -> hyper::http::response::Builder {
+    let last_modified = DateTime::from_timestamp(version.changed_at, 0)
+        .unwrap_or_default()
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string();
+    let cache_control = match api.max_age {
+        Some(max_age) => format!("max-age={}", max_age.as_secs()),
+        None => "no-cache".to_string(),
+    };
+    Response::builder()
+        .header(header::ETAG, etag(version))
+        .header(header::LAST_MODIFIED, last_modified)
+        .header(header::CACHE_CONTROL, cache_control)
+}
+
+/// The metadata of every configured sensor, with the metrics it's known to write, those it
+/// wrote and those it has metadata for
+async fn metadata(api: &Api) -> Vec<SensorMetadata> {
+    let states = api.state.snapshot();
+    api.manager
+        .sensors()
+        .await
+        .into_iter()
+        .map(|sensor| {
+            let mut metrics = config::known_metrics(&sensor)
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+            metrics.extend(sensor.units.keys().cloned());
+            metrics.extend(sensor.descriptions.keys().cloned());
+            metrics.extend(sensor.calibration.iter().map(|c| c.metric.clone()));
+            if let Some(state) = states.iter().find(|state| state.sensor == sensor.name) {
+                metrics.extend(state.values.keys().cloned());
+            }
+
+            SensorMetadata {
+                metrics: metrics
+                    .into_iter()
+                    .map(|metric| MetricMetadata {
+                        series: sensor.series(&metric),
+                        unit: sensor.unit(&metric).map(str::to_string),
+                        description: sensor.descriptions.get(&metric).cloned(),
+                        calibration: sensor
+                            .calibration
+                            .iter()
+                            .filter(|calibration| calibration.metric == metric)
+                            .cloned()
+                            .collect(),
+                        metric,
+                    })
+                    .collect(),
+                path: sensor.path(),
+                kind: sensor.kind.name(),
+                disabled: sensor.disabled,
+                sensor: sensor.name,
+                site: sensor.site,
+                room: sensor.room,
+                tags: sensor.tags,
+            }
+        })
+        .collect()
+}
+
+/// Handles the requests changing the sensors
+async fn manage(api: &Api, request: Request<Body>) -> Response<Body> {
+    let method = request.method().clone();
+    let name = request.uri().path()["/sensors".len()..]
+        .trim_start_matches('/')
+        .to_string();
+    if !matches!(
+        (&method, name.is_empty()),
+        (&Method::POST, _) | (&Method::DELETE, false)
+    ) {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    match &api.api_token {
+        None => {
+            return text(
+                StatusCode::FORBIDDEN,
+                "changing the sensors needs an API token to be configured".to_string(),
+            )
+        }
+        Some(token) if !has_bearer_token(&request, token) => {
+            return status(StatusCode::UNAUTHORIZED)
+        }
+        Some(_) => {}
+    }
+
+    let body = match read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let (result, created) = match (method, name.is_empty()) {
+        (Method::POST, true) => match serde_json::from_slice::<Sensor>(&body) {
+            Ok(sensor) => (api.manager.add(sensor).await, true),
+            Err(err) => return text(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        (Method::POST, false) => match serde_json::from_slice::<SensorUpdate>(&body) {
+            Ok(update) => (api.manager.update(&name, update).await, false),
+            Err(err) => return text(StatusCode::BAD_REQUEST, err.to_string()),
+        },
+        _ => (api.manager.remove(&name).await, false),
+    };
+
+    match result {
+        Ok(()) => {
+            let mut response = json(&api.manager.sensors().await);
+            if created {
+                *response.status_mut() = StatusCode::CREATED;
+            }
+            response
+        }
+        Err(err) => {
+            let code = match err {
+                ManageError::NotFound(_) => StatusCode::NOT_FOUND,
+                ManageError::Exists(_) => StatusCode::CONFLICT,
+                ManageError::Config(ConfigError::Invalid(_)) => StatusCode::BAD_REQUEST,
+                ManageError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                ManageError::Service(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            tracing::warn!("Failed to change the sensors: {}", err);
+            text(code, err.to_string())
+        }
+    }
+}
+
+/// Handles readings pushed by other devices
+async fn ingest(api: &Api, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::POST {
+        return status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    match &api.ingest_token {
+        None => {
+            return text(
+                StatusCode::FORBIDDEN,
+                "pushing readings needs an ingest token to be configured".to_string(),
+            )
+        }
+        Some(token) if !has_bearer_token(&request, token) => {
+            return status(StatusCode::UNAUTHORIZED)
+        }
+        Some(_) => {}
+    }
+
+    let body = match read_body(request).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    let pushed = match serde_json::from_slice::<Push>(&body) {
+        Ok(Push::One(reading)) => vec![reading],
+        Ok(Push::Many(readings)) => readings,
+        Err(err) => return text(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time behind Unix epoch time")
+        .as_secs() as i64;
+    // All of them first, so a rejected push leaves nothing of it behind
+    for reading in &pushed {
+        if let Some(invalid) = [&reading.name, &reading.metric]
+            .into_iter()
+            .find(|label| !valid_label(label))
+        {
+            return text(
+                StatusCode::BAD_REQUEST,
+                format!("invalid name {:?}, use letters, digits, _ and -", invalid),
+            );
+        }
+        if !reading.value.is_finite() {
+            return text(StatusCode::BAD_REQUEST, "values must be finite".to_string());
+        }
+    }
+
+    let mut datapoints = Vec::with_capacity(pushed.len());
+    for reading in pushed {
+        let time = reading.time.unwrap_or(now);
+        api.state.record_pushed(
+            &reading.name,
+            time,
+            &[(reading.metric.clone(), reading.value)],
+        );
+        datapoints.push(Datapoint {
+            name: format!("{}.{}", reading.name, reading.metric),
+            interval: reading.interval.unwrap_or(api.refresh),
+            value: reading.value,
+            time,
+        });
+    }
+
+    match api.ingest.try_send(datapoints) {
+        Ok(()) => status(StatusCode::ACCEPTED),
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            tracing::warn!("Readings queue is full, dropping pushed readings");
+            status(StatusCode::SERVICE_UNAVAILABLE)
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => status(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+fn valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Reads a request's body, refusing ones larger than [`MAX_BODY_BYTES`]
+async fn read_body(request: Request<Body>) -> Result<Vec<u8>, Response<Body>> {
+    // Checks the declared length up front, and the actual one for chunked bodies
+    if hyper::body::HttpBody::size_hint(request.body()).lower() > MAX_BODY_BYTES {
+        return Err(status(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    let mut body = request.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = hyper::body::HttpBody::data(&mut body).await {
+        let chunk = chunk.map_err(|err| text(StatusCode::BAD_REQUEST, err.to_string()))?;
+        if (bytes.len() + chunk.len()) as u64 > MAX_BODY_BYTES {
+            return Err(status(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+fn authenticated(request: &Request<Body>, auth: &ApiAuth) -> bool {
+    match auth {
+        ApiAuth::Bearer(token) => has_bearer_token(request, token),
+        ApiAuth::Basic { username, password } => {
+            let given = authorization(request, "Basic ")
+                .and_then(|credentials| {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(credentials)
+                        .ok()
+                })
+                .unwrap_or_default();
+            constant_time_eq(&given, format!("{}:{}", username, password).as_bytes())
+        }
+    }
+}
+
+fn unauthenticated(auth: &ApiAuth) -> Response<Body> {
+    let mut response = status(StatusCode::UNAUTHORIZED);
+    if let ApiAuth::Basic { .. } = auth {
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            header::HeaderValue::from_static("Basic realm=\"monitoring\""),
+        );
+    }
+    response
+}
+
+fn has_bearer_token(request: &Request<Body>, token: &str) -> bool {
+    let given = authorization(request, "Bearer ").unwrap_or_default();
+    constant_time_eq(given.as_bytes(), token.as_bytes())
+}
+
+/// The credentials of the request's `Authorization` header, if it uses `scheme`
+fn authorization<'a>(request: &'a Request<Body>, scheme: &str) -> Option<&'a str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(scheme))
+}
+
+/// Compares secrets taking the same time however much of them matches
+fn constant_time_eq(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn history(api: &Api, query: &str) -> Result<Series, String> {
+    let mut sensor = None;
+    let mut metric = None;
+    let mut from = i64::MIN;
+    let mut to = i64::MAX;
+    let mut step = None;
+
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let number = || {
+            value
+                .parse::<i64>()
+                .map_err(|_| format!("invalid {} `{}`", key, value))
+        };
+        match key {
+            "sensor" => sensor = Some(value.to_string()),
+            "metric" => metric = Some(value.to_string()),
+            "from" => from = number()?,
+            "to" => to = number()?,
+            "step" => step = Some(number()?).filter(|step| *step > 0),
+            _ => return Err(format!("unknown parameter `{}`", key)),
+        }
+    }
+
+    let sensor = sensor.ok_or("the sensor parameter is required")?;
+    let metric = metric.ok_or("the metric parameter is required")?;
+    let path = api.state.path(&sensor).unwrap_or_else(|| sensor.clone());
+    let points = api
+        .history
+        .range(&format!("{}.{}", path, metric), from, to, step);
+
+    Ok(Series {
+        sensor,
+        metric,
+        points,
+    })
+}
+
+fn liveness(api: &Api) -> Result<(), String> {
+    match api.state.since_last_tick() {
+        Some(since) if since > api.liveness_window => {
+            Err(format!("no sensor sampled for {}s", since.as_secs()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn readiness(api: &Api) -> Result<(), String> {
+    if !api.state.has_read() {
+        return Err("no sensor read successfully yet".to_string());
+    }
+    if !api.state.has_written() {
+        return Err("no readings written successfully yet".to_string());
+    }
+
+    Ok(())
+}
+
+fn health(check: Result<(), String>) -> Response<Body> {
+    let (code, health) = match check {
+        Ok(()) => (
+            StatusCode::OK,
+            Health {
+                status: "ok",
+                reason: None,
+            },
+        ),
+        Err(reason) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Health {
+                status: "unavailable",
+                reason: Some(reason),
+            },
+        ),
+    };
+
+    let mut response = json(&health);
+    *response.status_mut() = code;
+    response
+}
+
+fn stream(api: &Api, compact: bool) -> Response<Body> {
+    let mut readings = api.readings.subscribe();
+    let mut shutdown = api.shutdown.clone();
+    let (mut sender, body) = Body::channel();
+
+    tokio::spawn(async move {
+        let mut keepalive = time::interval(STREAM_KEEPALIVE);
+        keepalive.tick().await;
+        let mut encoder = wire::Encoder::new();
+
+        loop {
+            let event = tokio::select! {
+                batch = readings.recv() => match batch {
+                    Ok(batch) => {
+                        let event = if compact {
+                            encoder.encode(&batch)
+                        } else {
+                            serde_json::to_string(&batch)
+                                .map(|data| format!("event: readings\ndata: {}\n\n", data).into())
+                                .map_err(|err| err.to_string())
+                        };
+                        match event {
+                            Ok(event) => event,
+                            Err(err) => {
+                                tracing::error!("Unable to serialize readings: {}", err);
+                                continue;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::debug!(missed, "Event stream client fell behind");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                _ = keepalive.tick() => if compact {
+                    wire::keepalive()
+                } else {
+                    b": keepalive\n\n".to_vec()
+                },
+                _ = shutdown.wait_for(|stop| *stop) => break,
+            };
+
+            if sender.send_data(event.into()).await.is_err() {
+                // The client went away
+                break;
+            }
+        }
+    });
+
+    Response::builder()
+        .header(
+            header::CONTENT_TYPE,
+            if compact {
+                wire::CONTENT_TYPE
+            } else {
+                "text/event-stream"
+            },
+        )
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body)
+        .expect("Valid response")
+}
+
+fn json(body: &impl Serialize) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .expect("Valid response"),
+        Err(err) => {
+            tracing::error!("Unable to serialize API response: {}", err);
+            status(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn html(body: String) -> Response<Body> {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))
+        .expect("Valid response")
+}
+
+fn text(status: StatusCode, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .expect("Valid response")
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(status.canonical_reason().unwrap_or_default()))
+        .expect("Valid response")
+}
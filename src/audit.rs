@@ -0,0 +1,292 @@
+//! Telling from the data kept on disk whether the sensors are wired and working properly
+//!
+//! The read attempts of a capture file (see [`crate::capture`]) and the batches waiting in a
+//! spool are scanned over a time window for what a bad connection or a dying sensor looks like:
+//!
+//! - failed reads, and the error they failed with most often
+//! - gaps, where no reading came in for more than twice the sensor's interval
+//! - flatlines, where a metric kept the exact same value for [`FLATLINE_READINGS`] readings in
+//!   a row, like a sensor that stopped updating
+//! - values outside of what the sensor can physically report, see
+//!   [`SensorType::physical_range`](crate::config::SensorType::physical_range)
+//!
+//! Only a capture has the failed reads; the spool only holds readings, and only those the
+//! endpoint didn't take yet.
+
+use crate::{capture::Entry, config::Sensor, interval::Interval, locale::Locale, spool::Spool};
+use chrono::{DateTime, Local};
+use std::{collections::BTreeMap, fmt, io};
+
+/// How many readings in a row with the same value make a flatline
+pub const FLATLINE_READINGS: usize = 10;
+
+/// What was found in a sensor's data
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorReport {
+    pub sensor: String,
+    /// Read attempts in the window, failed ones included
+    pub reads: usize,
+    pub failed: usize,
+    /// The error the reads failed with most often
+    pub common_error: Option<String>,
+    pub gaps: Vec<Stretch>,
+    pub flatlines: Vec<Flatline>,
+    pub out_of_range: Vec<OutOfRange>,
+}
+
+/// A stretch of time, as Unix timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stretch {
+    pub from: i64,
+    pub to: i64,
+}
+
+impl Stretch {
+    pub fn secs(&self) -> i64 {
+        self.to - self.from
+    }
+}
+
+/// A metric that kept the same value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flatline {
+    pub metric: String,
+    pub value: f64,
+    pub readings: usize,
+    pub during: Stretch,
+}
+
+/// The readings of a metric outside of its physical range
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutOfRange {
+    pub metric: String,
+    pub readings: usize,
+    /// The furthest one out
+    pub worst: f64,
+}
+
+impl SensorReport {
+    /// Whether nothing looked wrong
+    pub fn is_ok(&self) -> bool {
+        self.reads > 0
+            && self.failed == 0
+            && self.gaps.is_empty()
+            && self.flatlines.is_empty()
+            && self.out_of_range.is_empty()
+    }
+}
+
+/// Scans the read attempts from `from` to `to` of each enabled sensor, sampled every `refresh`
+/// seconds unless it has its own interval
+pub fn audit(
+    sensors: &[Sensor],
+    entries: &[Entry],
+    refresh: i32,
+    from: i64,
+    to: i64,
+) -> Vec<SensorReport> {
+    sensors
+        .iter()
+        .filter(|sensor| !sensor.disabled)
+        .map(|sensor| {
+            let mut attempts = entries
+                .iter()
+                .filter(|entry| entry.sensor == sensor.name && (from..=to).contains(&entry.time))
+                .collect::<Vec<_>>();
+            attempts.sort_by_key(|entry| entry.time);
+            audit_sensor(
+                sensor,
+                &attempts,
+                sensor.interval.map_or(refresh, Interval::secs),
+            )
+        })
+        .collect()
+}
+
+fn audit_sensor(sensor: &Sensor, attempts: &[&Entry], interval: i32) -> SensorReport {
+    let mut errors = BTreeMap::<&str, usize>::new();
+    for error in attempts.iter().filter_map(|entry| entry.error.as_deref()) {
+        *errors.entry(error).or_default() += 1;
+    }
+    let readings = attempts
+        .iter()
+        .filter_map(|entry| Some((entry.time, entry.metrics.as_ref()?)))
+        .collect::<Vec<_>>();
+
+    let gaps = readings
+        .windows(2)
+        .map(|pair| Stretch {
+            from: pair[0].0,
+            to: pair[1].0,
+        })
+        .filter(|gap| gap.secs() > 2 * i64::from(interval))
+        .collect();
+
+    let mut series = BTreeMap::<&str, Vec<(i64, f64)>>::new();
+    for (time, metrics) in &readings {
+        for (metric, value) in metrics.iter() {
+            series.entry(metric).or_default().push((*time, *value));
+        }
+    }
+    let mut flatlines = Vec::new();
+    let mut out_of_range = Vec::new();
+    for (metric, values) in series {
+        for run in values.chunk_by(|a, b| a.1 == b.1) {
+            if run.len() >= FLATLINE_READINGS {
+                flatlines.push(Flatline {
+                    metric: metric.to_string(),
+                    value: run[0].1,
+                    readings: run.len(),
+                    during: Stretch {
+                        from: run[0].0,
+                        to: run[run.len() - 1].0,
+                    },
+                });
+            }
+        }
+
+        let Some((min, max)) = sensor.kind.physical_range(metric) else {
+            continue;
+        };
+        let outside = values
+            .iter()
+            .map(|(_, value)| *value)
+            .filter(|value| !(min..=max).contains(value))
+            .collect::<Vec<_>>();
+        if let Some(worst) = outside
+            .iter()
+            .copied()
+            .max_by(|a, b| distance(*a, min, max).total_cmp(&distance(*b, min, max)))
+        {
+            out_of_range.push(OutOfRange {
+                metric: metric.to_string(),
+                readings: outside.len(),
+                worst,
+            });
+        }
+    }
+
+    SensorReport {
+        sensor: sensor.name.clone(),
+        reads: attempts.len(),
+        failed: errors.values().sum(),
+        common_error: errors
+            .into_iter()
+            .max_by_key(|(_, count)| *count)
+            .map(|(error, _)| error.to_string()),
+        gaps,
+        flatlines,
+        out_of_range,
+    }
+}
+
+/// How far a value is outside of a range
+fn distance(value: f64, min: f64, max: f64) -> f64 {
+    (min - value).max(value - max)
+}
+
+/// The readings in the spool's batches, as the read attempts that took them. The metrics are
+/// calibrated already, and series other than the sensors' own (like the daily extremes) are
+/// left out.
+pub fn spooled_entries(spool: &Spool, sensors: &[Sensor]) -> io::Result<Vec<Entry>> {
+    let prefixes = sensors
+        .iter()
+        .map(|sensor| (format!("{}.", sensor.path()), &sensor.name))
+        .collect::<Vec<_>>();
+
+    let mut readings = BTreeMap::<(&String, i64), BTreeMap<String, f64>>::new();
+    for batch in spool.pending()? {
+        for datapoint in spool.load(&batch)? {
+            let Some((metric, sensor)) = prefixes.iter().find_map(|(prefix, sensor)| {
+                let metric = datapoint.name.strip_prefix(prefix.as_str())?;
+                (!metric.contains('.')).then_some((metric, *sensor))
+            }) else {
+                continue;
+            };
+            readings
+                .entry((sensor, datapoint.time))
+                .or_default()
+                .insert(metric.to_string(), datapoint.value);
+        }
+    }
+
+    Ok(readings
+        .into_iter()
+        .map(|((sensor, time), metrics)| Entry {
+            time,
+            sensor: sensor.clone(),
+            metrics: Some(metrics),
+            error: None,
+        })
+        .collect())
+}
+
+/// A length of time, e.g. `2h 05m`
+fn duration(secs: i64) -> String {
+    match secs {
+        secs if secs < 60 => format!("{}s", secs),
+        secs if secs < 3600 => format!("{}m", secs / 60),
+        secs => format!("{}h {:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn time(time: i64) -> String {
+    DateTime::from_timestamp(time, 0)
+        .map(|time| Locale::default().date_time(&time.with_timezone(&Local)))
+        .unwrap_or_else(|| time.to_string())
+}
+
+impl fmt::Display for SensorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verdict = match (self.reads, self.is_ok()) {
+            (0, _) => "no reads",
+            (_, true) => "ok",
+            (_, false) => "check the wiring",
+        };
+        writeln!(f, "{}: {}", self.sensor, verdict)?;
+        if self.reads == 0 {
+            return Ok(());
+        }
+
+        write!(
+            f,
+            "  reads: {}, {} failed ({:.1}%)",
+            self.reads,
+            self.failed,
+            self.failed as f64 * 100.0 / self.reads as f64
+        )?;
+        match &self.common_error {
+            Some(error) => writeln!(f, ", mostly: {}", error)?,
+            None => writeln!(f)?,
+        }
+        if let Some(longest) = self.gaps.iter().max_by_key(|gap| gap.secs()) {
+            writeln!(
+                f,
+                "  gaps: {}, the longest {} from {}",
+                self.gaps.len(),
+                duration(longest.secs()),
+                time(longest.from)
+            )?;
+        }
+        for flatline in &self.flatlines {
+            writeln!(
+                f,
+                "  flatline: {} stuck at {} for {} readings ({}) from {}",
+                flatline.metric,
+                flatline.value,
+                flatline.readings,
+                duration(flatline.during.secs()),
+                time(flatline.during.from)
+            )?;
+        }
+        for out_of_range in &self.out_of_range {
+            writeln!(
+                f,
+                "  out of range: {} {} times, as far out as {}",
+                out_of_range.metric, out_of_range.readings, out_of_range.worst
+            )?;
+        }
+
+        Ok(())
+    }
+}
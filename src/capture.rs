@@ -0,0 +1,154 @@
+//! Recording every raw sensor read to a file, and replaying such a capture through the pipeline
+//!
+//! A capture is a JSON line per read attempt, failed ones included:
+//!
+//! ```text
+//! {"time":1700000000,"sensor":"kitchen","metrics":{"temperature":21.4,"humidity":40.1}}
+//! {"time":1700000002,"sensor":"kitchen","error":"checksum value of the reading is incorrect"}
+//! ```
+//!
+//! Replaying one reproduces what the pipeline made of those reads (the aggregation, the history,
+//! the HTTP API and the writes to the sink) without the hardware that produced them.
+
+use crate::{
+    config::Sensor,
+    error::{ConfigError, SensorError},
+    interval::Interval,
+    state::State,
+    Datapoint,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+use tokio::sync::mpsc;
+
+/// One read attempt of a sensor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: i64,
+    pub sensor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<BTreeMap<String, f64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Appends every read attempt to a capture file
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Opens the capture file for appending, creating it if needed
+    pub fn create(path: &Path) -> Result<Self, ConfigError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| ConfigError::from_io(path.to_path_buf(), err))?;
+
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn record(
+        &self,
+        sensor: &str,
+        time: i64,
+        result: &Result<Vec<(String, f64)>, SensorError>,
+    ) {
+        let (metrics, error) = match result {
+            Ok(metrics) => (Some(metrics.iter().cloned().collect()), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        let entry = Entry {
+            time,
+            sensor: sensor.to_string(),
+            metrics,
+            error,
+        };
+
+        let mut line = serde_json::to_vec(&entry).expect("Capture entries always serialize");
+        line.push(b'\n');
+        // A single write per line, so entries of sensors read at the same time don't interleave
+        if let Err(err) = self
+            .file
+            .lock()
+            .expect("Capture file lock poisoned")
+            .write_all(&line)
+        {
+            tracing::warn!("Unable to record the reading: {}", err);
+        }
+    }
+}
+
+/// Reads a capture file
+pub fn load(path: &Path) -> Result<Vec<Entry>, ConfigError> {
+    let file = File::open(path).map_err(|err| ConfigError::from_io(path.to_path_buf(), err))?;
+
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|(index, line)| {
+            let line = line.map_err(|err| ConfigError::from_io(path.to_path_buf(), err))?;
+            serde_json::from_str(&line).map_err(|err| {
+                ConfigError::Invalid(format!("{} line {}: {}", path.display(), index + 1, err))
+            })
+        })
+        .collect()
+}
+
+/// Feeds the captured reads of the configured sensors into the pipeline as if they had just
+/// been taken, at their recorded times; entries of other sensors are skipped
+pub(crate) async fn replay(
+    entries: Vec<Entry>,
+    sensors: &[Sensor],
+    refresh: i32,
+    state: &State,
+    sender: mpsc::Sender<Vec<Datapoint>>,
+) {
+    let mut replayed = 0;
+    for entry in entries {
+        let Some(sensor) = sensors.iter().find(|sensor| sensor.name == entry.sensor) else {
+            continue;
+        };
+        replayed += 1;
+
+        let metrics = match (entry.metrics, entry.error) {
+            // Captured raw, as the sensor read them
+            (Some(metrics), _) => {
+                let mut metrics = metrics.into_iter().collect::<Vec<_>>();
+                sensor.calibrate(&mut metrics);
+                metrics
+            }
+            (None, error) => {
+                let error = SensorError::Replayed(error.unwrap_or_default());
+                tracing::warn!(sensor = %sensor.name, "Error reading the sensor: {}", error);
+                state.record_error(&sensor.name, &error);
+                continue;
+            }
+        };
+        state.record_reading(&sensor.name, entry.time, &metrics);
+
+        let resolution = sensor.interval.map_or(refresh, Interval::secs);
+        let datapoints = metrics
+            .iter()
+            .map(|(metric, value)| {
+                Datapoint::new(*value, metric, sensor, entry.time as u64, resolution)
+            })
+            .collect();
+        // Unlike live readings, none of the capture is dropped when the queue is full
+        if sender.send(datapoints).await.is_err() {
+            break;
+        }
+    }
+
+    tracing::info!(entries = replayed, "Replayed the capture");
+}
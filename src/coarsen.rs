@@ -0,0 +1,152 @@
+//! Writing coarser readings to the sinks than the service keeps, for sharing them publicly
+//!
+//! A sensor's `coarsen` rules round its metrics to some decimals or to multiples of a step, or
+//! hold them back until they're old enough not to tell whether anyone's home:
+//!
+//! ```yaml
+//! - name: living_room
+//!   pin: 4
+//!   coarsen:
+//!     - metric: temperature
+//!       step: 0.5
+//!       delay_secs: 3600
+//!       sinks: [endpoint]
+//! ```
+//!
+//! Only what's written to the sinks a rule names is coarsened: the HTTP API, the display, the
+//! hooks and the other sinks still get the exact readings. Rules match metrics by their exact
+//! label, so a `write_raw` sensor's `temperature.raw` needs a rule of its own. Datapoints held
+//! back are written along with the first batch after they're old enough. They're kept in
+//! memory until then, so those still held back when the service stops are lost.
+
+use crate::{
+    config::{Sensor, SinkKind},
+    error::SinkError,
+    interval::Interval,
+    sinks::Sink,
+    Datapoint,
+};
+use futures::future::BoxFuture;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    decimals: Option<u32>,
+    step: Option<f64>,
+    delay: Option<Interval>,
+}
+
+impl Rule {
+    fn apply(&self, value: f64) -> f64 {
+        let mut value = value;
+        if let Some(step) = self.step {
+            // Rounded again, so steps like 0.1 don't leave 21.400000000000002 behind
+            value = ((value / step).round() * step * 1e9).round() / 1e9;
+        }
+        if let Some(decimals) = self.decimals {
+            let scale = 10f64.powi(decimals.min(15) as i32);
+            value = (value * scale).round() / scale;
+        }
+        value
+    }
+}
+
+/// A sink writing coarsened readings to another one, see the [module docs](self)
+pub struct Coarsened {
+    inner: Arc<dyn Sink>,
+    /// The rules by the name of the series they apply to
+    rules: HashMap<String, Rule>,
+    /// The datapoints held back, until they're old enough
+    held: Mutex<Vec<Datapoint>>,
+}
+
+impl Coarsened {
+    /// Wraps `inner`, a sink of the given kind, if any of the sensors' rules apply to it
+    pub fn wrap(inner: Arc<dyn Sink>, sensors: &[Sensor], kind: SinkKind) -> Arc<dyn Sink> {
+        let rules = sensors
+            .iter()
+            .flat_map(|sensor| {
+                sensor
+                    .coarsen
+                    .iter()
+                    .filter(|coarsen| coarsen.sinks.is_empty() || coarsen.sinks.contains(&kind))
+                    .map(|coarsen| {
+                        let rule = Rule {
+                            decimals: coarsen.decimals,
+                            step: coarsen.step,
+                            delay: coarsen.delay_secs,
+                        };
+                        (sensor.series(&coarsen.metric), rule)
+                    })
+            })
+            .collect::<HashMap<_, _>>();
+        if rules.is_empty() {
+            return inner;
+        }
+
+        Arc::new(Coarsened {
+            inner,
+            rules,
+            held: Mutex::default(),
+        })
+    }
+
+    /// Whether a datapoint is old enough to be written
+    fn is_due(&self, datapoint: &Datapoint, now: i64) -> bool {
+        match self.rules.get(&datapoint.name).and_then(|rule| rule.delay) {
+            Some(delay) => datapoint.time.saturating_add(i64::from(delay.secs())) <= now,
+            None => true,
+        }
+    }
+}
+
+impl Sink for Coarsened {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time behind Unix epoch time")
+                .as_secs() as i64;
+            let coarsened = readings.iter().cloned().map(|mut datapoint| {
+                if let Some(rule) = self.rules.get(&datapoint.name) {
+                    datapoint.value = rule.apply(datapoint.value);
+                }
+                datapoint
+            });
+
+            let (mut due, released) = {
+                let mut held = self.held.lock().expect("Coarsening lock poisoned");
+                let (released, still_held): (Vec<_>, Vec<_>) = held
+                    .drain(..)
+                    .partition(|datapoint| self.is_due(datapoint, now));
+                *held = still_held;
+                let (due, new_held): (Vec<_>, Vec<_>) =
+                    coarsened.partition(|datapoint| self.is_due(datapoint, now));
+                held.extend(new_held);
+                (due, released)
+            };
+            let released_count = released.len();
+            due.splice(0..0, released);
+            if due.is_empty() {
+                return Ok(());
+            }
+
+            let written = self.inner.write(&due).await;
+            if written.is_err() {
+                // The batch's own datapoints are spooled by the pipeline, but the ones released
+                // from before are only kept here
+                let mut held = self.held.lock().expect("Coarsening lock poisoned");
+                held.extend(due.drain(..released_count));
+            }
+            written
+        })
+    }
+
+    fn sensors_changed(&self, sensors: &[Sensor]) {
+        self.inner.sensors_changed(sensors);
+    }
+}
@@ -0,0 +1,1008 @@
+//! The `sensors.yaml` configuration format
+
+use crate::{error::ConfigError, interval::Interval, pulse, schedule::Window};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// How often sensors are sampled unless configured otherwise: every 15 minutes
+pub const DEFAULT_REFRESH: Interval = Interval::from_secs(900);
+
+/// How often a DHT22 may be sampled at most: it needs 2 seconds between reads
+pub const DHT22_MIN_INTERVAL: Interval = Interval::from_secs(2);
+
+/// The lowest humidity a good read of a DHT22 returns, in %
+pub const DHT22_MIN_PLAUSIBLE_HUMIDITY: f64 = 3.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sensor {
+    pub name: String,
+
+    /// Where the sensor is, e.g. `cottage`, prefixed to its series' paths
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+
+    /// The room the sensor is in, e.g. `bedroom`, prefixed to its series' paths after the site
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+
+    /// Graphite tags added to the names of the sensor's series when they're posted, e.g.
+    /// `{floor: upstairs}` for `cottage.bedroom.sensor1.temperature;floor=upstairs`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+
+    /// The units of the sensor's metrics, e.g. `{co2: ppm}`, served by `/metadata` and added as
+    /// `unit` tags with `--metadata-tags` (default: `°C` for `temperature` and `%` for
+    /// `humidity`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub units: BTreeMap<String, String>,
+
+    /// What the sensor's metrics measure, e.g. `{co2: CO2 concentration behind the sofa}`, served
+    /// by `/metadata`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub descriptions: BTreeMap<String, String>,
+
+    /// What kind of sensor this is (default: `dht22`)
+    #[serde(rename = "type", default)]
+    pub kind: SensorType,
+
+    /// GPIO pin a `dht22` or `pulse` sensor is connected to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<u8>,
+
+    /// Program and arguments run every cycle for a `command` sensor, see [`crate::plugins`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+
+    /// Serial device a `serial` sensor is read from, e.g. `/dev/ttyACM0`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+
+    /// Baud rate of a `serial` sensor's device (default: 115200)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub baud: Option<u32>,
+
+    /// How a `serial` sensor's lines or `radio` sensor's packets are formatted (default: `json`)
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub format: LineFormat,
+
+    /// Metric names of the values on a `csv` formatted line, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<String>,
+
+    /// Node ID a `radio` sensor's packets are sent from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<u8>,
+
+    /// Frequency of the RFM69 radio in MHz, the same for every `radio` sensor (default: 868)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f32>,
+
+    /// SPI chip select (CE) line of the RFM69 radio, the same for every `radio` sensor (default: 0)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chip_select: Option<u8>,
+
+    /// Metric name of a `pulse` sensor's readings, e.g. `rain` (default: `pulses`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metric: Option<String>,
+
+    /// How many pulses of a `pulse` sensor make one unit of its metric, e.g. 3.58 for a rain
+    /// gauge tipping every 0.2794 mm (default: 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pulses_per_unit: Option<f64>,
+
+    /// The time a `pulse` sensor's rate is given per, e.g. `1h` for mm/h of rain (default: `1s`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_per: Option<Interval>,
+
+    /// How long a `pulse` sensor's switch may bounce for after a pulse, e.g. `10ms` or `10`;
+    /// edges in the meantime aren't counted (default: 10ms)
+    #[serde(
+        default,
+        with = "crate::interval::millis",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub debounce_ms: Option<Interval>,
+
+    /// Name of a bus the sensor shares with others, e.g. `i2c-1`, so that they're read one at a time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+
+    /// How long a read may take before it counts as failed, e.g. `5s` or `5` (default: 2s for a
+    /// `dht22`, 10s otherwise)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<Interval>,
+
+    /// How long after the Pi boots the sensor takes to give valid readings, e.g. `2m` or `120`;
+    /// it isn't sampled before then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warmup_secs: Option<Interval>,
+
+    /// GPIO pin powering the sensor, switched off and on again after `power_cycle_after`
+    /// consecutive failed reads to reset a latched-up sensor
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_pin: Option<u8>,
+
+    /// Enable the Pi's internal pull-up resistor on the data pin, for a bare DHT22 wired without
+    /// the 10 kΩ one (breakout boards have it already). It's weaker at around 50 kΩ, so on
+    /// cables longer than a meter or so an external one is still needed. A `pulse` sensor's
+    /// switch closing to ground needs it too, unless it has a pull-up of its own.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub pull_up: bool,
+
+    /// How many reads in a row have to fail before the sensor is power cycled (default: 5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_cycle_after: Option<u32>,
+
+    /// How long to wait between attempts at reading the sensor, e.g. `4s` or `4000` for one on
+    /// a long cable that needs a while to recover (default: 2100ms)
+    #[serde(
+        default,
+        with = "crate::interval::millis",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub retry_interval_ms: Option<Interval>,
+
+    /// How many attempts a cycle makes at reading the sensor before giving up on it until the
+    /// next cycle (default: as many as the cycle has time for)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+
+    /// How often to sample this sensor, e.g. `30s` or `500ms`, overriding the service refresh
+    /// time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<Interval>,
+
+    /// Sample the sensor at other intervals at some times of day, e.g.
+    /// `["06:00-22:00/300", "22:00-06:00/1800"]`, see [`crate::schedule`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedule: Vec<Window>,
+
+    /// The shortest interval the sensor may be sampled at, e.g. for one that self-heats when
+    /// polled rapidly (default: 2s for a `dht22`, 1s otherwise)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_interval: Option<Interval>,
+
+    /// Name shared by sensors placed together, whose readings are checked against each other
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+
+    /// How far apart the group's readings of a metric may be, e.g. `{temperature: 1.5}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub max_divergence: BTreeMap<String, f64>,
+
+    /// Corrections for the sensor's inaccuracies, applied to its readings before anything else
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calibration: Vec<Calibration>,
+
+    /// Also write the raw readings of the calibrated metrics, as `<metric>.raw`, to check the
+    /// calibration against
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub write_raw: bool,
+
+    /// Coarser values of some of the sensor's metrics to write to the sinks than the exact ones
+    /// the HTTP API, display and hooks get, e.g. for a public dashboard, see [`crate::coarsen`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coarsen: Vec<Coarsen>,
+
+    /// Also write whether some of the sensor's metrics are rising, falling or steady, as
+    /// `<metric>.trend`, see [`crate::trend`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trends: Vec<Trend>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<Alert>,
+
+    /// Program and arguments run on every reading, with the reading as JSON on stdin, see
+    /// [`crate::hooks`]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_reading: Option<Vec<String>>,
+
+    /// Program and arguments run when one of the sensor's alerts fires or resolves
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_alert: Option<Vec<String>>,
+
+    /// Program and arguments run when the sensor starts failing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on_sensor_failure: Option<Vec<String>>,
+
+    /// Keep one of the sensor's metrics around a target by switching a GPIO pin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control: Option<Control>,
+
+    /// Drive a PWM fan's speed from one of the sensor's metrics
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fan: Option<Fan>,
+
+    /// Keep the sensor in the configuration without sampling it
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub disabled: bool,
+}
+
+impl Sensor {
+    /// The path the sensor's series are written under, e.g. `cottage.bedroom.sensor1`
+    pub fn path(&self) -> String {
+        [
+            self.site.as_deref(),
+            self.room.as_deref(),
+            Some(self.name.as_str()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(".")
+    }
+
+    /// The name of one of the sensor's series, e.g. `cottage.bedroom.sensor1.temperature`
+    pub fn series(&self, metric: &str) -> String {
+        format!("{}.{}", self.path(), metric)
+    }
+
+    /// The sensor's tags in Graphite's format, e.g. `;floor=upstairs;zone=north`, or nothing
+    pub fn graphite_tags(&self) -> String {
+        self.tags
+            .iter()
+            .map(|(name, value)| format!(";{}={}", name, value))
+            .collect()
+    }
+
+    /// The unit of one of the sensor's metrics, if it's configured or the metric is a
+    /// temperature or humidity
+    pub fn unit(&self, metric: &str) -> Option<&str> {
+        self.units
+            .get(metric)
+            .map(String::as_str)
+            .or_else(|| default_unit(metric))
+    }
+
+    /// The shortest interval the sensor may be sampled at
+    pub fn min_interval(&self) -> Interval {
+        self.min_interval.unwrap_or(match self.kind {
+            SensorType::Dht22 => DHT22_MIN_INTERVAL,
+            _ => Interval::from_secs(1),
+        })
+    }
+
+    /// Applies the sensor's calibration to a raw reading. Corrections depending on another
+    /// metric go by its raw value. With `write_raw`, the calibrated metrics' raw values are
+    /// added as `<metric>.raw`.
+    pub fn calibrate(&self, metrics: &mut Vec<(String, f64)>) {
+        let raw = metrics.clone();
+        let value = |metric: &str| {
+            raw.iter()
+                .find(|(name, _)| name == metric)
+                .map(|(_, value)| *value)
+        };
+        for calibration in &self.calibration {
+            let by = calibration.by.as_deref().unwrap_or(&calibration.metric);
+            let Some(by) = value(by) else {
+                continue;
+            };
+            for (_, value) in metrics
+                .iter_mut()
+                .filter(|(name, _)| *name == calibration.metric)
+            {
+                *value += calibration.correction(by);
+            }
+        }
+
+        if self.write_raw {
+            metrics.extend(
+                raw.into_iter()
+                    .filter(|(name, _)| {
+                        self.calibration
+                            .iter()
+                            .any(|calibration| calibration.metric == *name)
+                    })
+                    .map(|(name, value)| (format!("{}.raw", name), value)),
+            );
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorType {
+    /// A DHT22 temperature and humidity sensor on a GPIO pin
+    #[default]
+    Dht22,
+    /// An external plugin program reporting its own metrics
+    Command,
+    /// A microcontroller node writing lines of readings to a USB/UART serial device
+    Serial,
+    /// A remote node sending packets of readings to an RFM69 radio on the Pi's SPI bus
+    Radio,
+    /// The Pi's own SoC temperature
+    Cpu,
+    /// A reed switch pulsing a GPIO pin, e.g. a rain gauge or an anemometer
+    Pulse,
+}
+
+impl SensorType {
+    /// The name the type is configured by, e.g. `dht22`
+    pub fn name(self) -> &'static str {
+        match self {
+            SensorType::Dht22 => "dht22",
+            SensorType::Command => "command",
+            SensorType::Serial => "serial",
+            SensorType::Radio => "radio",
+            SensorType::Cpu => "cpu",
+            SensorType::Pulse => "pulse",
+        }
+    }
+
+    /// The lowest and highest value of a metric the sensor can physically report, as given by
+    /// its datasheet. Readings outside of it mean a wrong pin or sensor type rather than the
+    /// weather.
+    pub fn physical_range(self, metric: &str) -> Option<(f64, f64)> {
+        match (self, metric) {
+            (SensorType::Dht22, "temperature") => Some((-40.0, 80.0)),
+            (SensorType::Dht22, "humidity") => Some((0.0, 100.0)),
+            (SensorType::Cpu, "temperature") => Some((-40.0, 125.0)),
+            _ => None,
+        }
+    }
+
+    /// The lowest and highest value of a metric a good read of the sensor returns. A DHT22
+    /// sometimes returns a humidity of 1 or 2 % on a bad read that still passes the checksum,
+    /// so its humidity starts at [`DHT22_MIN_PLAUSIBLE_HUMIDITY`] rather than 0.
+    pub fn plausible_range(self, metric: &str) -> Option<(f64, f64)> {
+        match (self, metric) {
+            (SensorType::Dht22, "humidity") => Some((DHT22_MIN_PLAUSIBLE_HUMIDITY, 100.0)),
+            _ => self.physical_range(metric),
+        }
+    }
+}
+
+/// The format of the lines a `serial` sensor writes, or the packets a `radio` sensor sends
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineFormat {
+    /// A JSON object of metric names and values, e.g. `{"temperature": 21.4}`
+    #[default]
+    Json,
+    /// Comma separated values, named by the sensor's `fields`, e.g. `21.4,40.2`
+    Csv,
+}
+
+/// A correction added to one of the sensor's metrics, e.g. for a DHT22 whose humidity reads
+/// high when it's cold and low when it's warm
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Calibration {
+    /// Metric label corrected, e.g. `humidity`
+    pub metric: String,
+    /// Metric label whose raw value the correction depends on (default: the corrected metric)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub by: Option<String>,
+    /// `[value, correction]` points in increasing order, interpolated in between and held flat
+    /// beyond the ends, e.g. `[[5, -4.0], [20, 0], [35, 2.5]]`
+    pub points: Vec<[f32; 2]>,
+}
+
+/// How one of the sensor's metrics is coarsened before it's written to the sinks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Coarsen {
+    /// Metric label coarsened, e.g. `temperature`
+    pub metric: String,
+    /// Round the values to this many decimals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u32>,
+    /// Round the values to the nearest multiple of this, e.g. `0.5`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<f64>,
+    /// Hold the datapoints back until they're this old, e.g. `1h` or `3600`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay_secs: Option<Interval>,
+    /// Which sinks get the coarsened values (default: all of them)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkKind>,
+}
+
+/// How one of the sensor's metrics is told to be rising, falling or steady
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trend {
+    /// Metric label followed, e.g. `temperature`
+    pub metric: String,
+    /// How far back the readings the trend is taken over go, e.g. `30m` or `1800` (default:
+    /// 30m)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_secs: Option<Interval>,
+    /// How much the metric has to change by per hour to be rising or falling rather than steady
+    /// (default: 0.5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f64>,
+}
+
+/// The kinds of sink the readings can be written to
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    /// The metrics endpoint, e.g. Graphite
+    Endpoint,
+    /// A local agent's unix socket
+    Socket,
+}
+
+/// A threshold rule evaluated against one of the sensor's metrics every cycle
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Alert {
+    /// Metric label the rule applies to, e.g. `temperature` or `humidity`
+    pub metric: String,
+    /// Fire when the value goes above this threshold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option<f32>,
+    /// Fire when the value goes below this threshold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<f32>,
+    /// Fire when the value, e.g. of a surface temperature, gets within this many degrees of the
+    /// dew point, where condensation forms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point_within: Option<f32>,
+    /// The sensor whose temperature and humidity the dew point is taken from (default: this one)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dew_point_of: Option<String>,
+    /// Only fire while these conditions on the latest readings of other sensors hold as well,
+    /// e.g. the outdoor temperature being below 0
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub and: Vec<Condition>,
+    /// Drive a GPIO pin (relay, LED, buzzer) while the alert is firing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpio: Option<GpioAction>,
+}
+
+/// A threshold on the latest reading of a sensor's metric, which a composite alert needs to hold
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Condition {
+    pub sensor: String,
+    pub metric: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GpioAction {
+    pub pin: u8,
+    /// Drive the pin low instead of high while active (common for relay boards)
+    #[serde(default)]
+    pub active_low: bool,
+    /// Keep the output on for at least this long once switched on, e.g. `5m` or `300`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_on_secs: Option<Interval>,
+    /// Keep the output off for at least this long once switched off, e.g. `2m` or `120`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_off_secs: Option<Interval>,
+}
+
+/// A thermostat/humidistat loop run on every reading
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Control {
+    /// Metric label to control, e.g. `temperature` or `humidity`
+    pub metric: String,
+    pub target: f32,
+    /// How far the value may drift past the target before the output switches
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Whether the attached device raises (heater) or lowers (dehumidifier) the value
+    pub mode: ControlMode,
+    pub gpio: GpioAction,
+}
+
+/// A fan curve run on every reading, e.g. to quiet the Pi's own fan when its CPU is cool
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fan {
+    /// Metric label driving the fan, e.g. `temperature`
+    pub metric: String,
+    /// GPIO pin the fan's PWM input (or its transistor) is connected to
+    pub pin: u8,
+    /// PWM frequency in Hz (default: 100). 4-pin PC fans expect 25000, best on a hardware PWM pin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    /// `[value, duty %]` points in increasing order, interpolated in between and held flat
+    /// beyond the ends, e.g. `[[45, 0], [55, 40], [70, 100]]`
+    pub curve: Vec<[f32; 2]>,
+    /// How far the value has to fall before the fan slows down again
+    #[serde(default)]
+    pub hysteresis: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlMode {
+    Raise,
+    Lower,
+}
+
+impl Alert {
+    pub fn is_breached(&self, value: f64) -> bool {
+        let above = self.above.is_some_and(|limit| value > f64::from(limit));
+        let below = self.below.is_some_and(|limit| value < f64::from(limit));
+        above || below
+    }
+
+    /// Whether the alert of a sensor fires on a value, breaching its thresholds or getting too
+    /// close to the dew point while its other conditions hold. `latest` gives the latest
+    /// reading of a sensor's metric; missing readings fire nothing.
+    pub fn fires(
+        &self,
+        sensor: &str,
+        value: f64,
+        latest: impl Fn(&str, &str) -> Option<f64>,
+    ) -> bool {
+        let dew_point = self.dew_point_within.is_some_and(|within| {
+            let of = self.dew_point_of.as_deref().unwrap_or(sensor);
+            latest(of, "temperature")
+                .zip(latest(of, "humidity"))
+                .is_some_and(|(temperature, humidity)| {
+                    value - dew_point(temperature, humidity) < f64::from(within)
+                })
+        });
+        let conditions = self.and.iter().all(|condition| {
+            latest(&condition.sensor, &condition.metric).is_some_and(|value| {
+                let above = condition
+                    .above
+                    .is_some_and(|limit| value > f64::from(limit));
+                let below = condition
+                    .below
+                    .is_some_and(|limit| value < f64::from(limit));
+                above || below
+            })
+        });
+
+        (self.is_breached(value) || dew_point) && conditions
+    }
+}
+
+/// The dew point of air at a temperature (°C) and relative humidity (%), by the Magnus formula
+pub fn dew_point(temperature: f64, humidity: f64) -> f64 {
+    const B: f64 = 17.62;
+    const C: f64 = 243.12;
+    let gamma = (humidity.max(0.1) / 100.0).ln() + B * temperature / (C + temperature);
+    C * gamma / (B - gamma)
+}
+
+impl Control {
+    /// Decides whether the output should be on, keeping the current state inside the hysteresis band
+    pub fn wants_on(&self, value: f64, on: bool) -> bool {
+        let low = f64::from(self.target - self.hysteresis);
+        let high = f64::from(self.target + self.hysteresis);
+        match self.mode {
+            ControlMode::Raise if value < low => true,
+            ControlMode::Raise if value > high => false,
+            ControlMode::Lower if value > high => true,
+            ControlMode::Lower if value < low => false,
+            _ => on,
+        }
+    }
+}
+
+impl Fan {
+    /// The duty cycle the curve gives for a value, from 0.0 to 1.0
+    pub fn duty_cycle(&self, value: f64) -> f64 {
+        interpolate(&self.curve, value) / 100.0
+    }
+}
+
+impl Calibration {
+    /// The correction the points give for a value
+    pub fn correction(&self, value: f64) -> f64 {
+        interpolate(&self.points, value)
+    }
+}
+
+/// Linearly interpolates between `[x, y]` points in increasing order, holding flat beyond the
+/// ends
+fn interpolate(points: &[[f32; 2]], value: f64) -> f64 {
+    let points = points.iter().map(|[at, y]| (f64::from(*at), f64::from(*y)));
+
+    let mut previous: Option<(f64, f64)> = None;
+    for (at, y) in points {
+        if value <= at {
+            return match previous {
+                Some((from, from_y)) if at > from => {
+                    from_y + (y - from_y) * (value - from) / (at - from)
+                }
+                _ => y,
+            };
+        }
+        previous = Some((at, y));
+    }
+
+    previous.map_or(0.0, |(_, y)| y)
+}
+
+pub async fn load_sensors_config(sensors_config_path: &Path) -> Result<Vec<Sensor>, ConfigError> {
+    let sensors = fs::read_to_string(sensors_config_path)
+        .map_err(|err| ConfigError::from_io(sensors_config_path.to_path_buf(), err))?;
+
+    let sensors: Vec<Sensor> = serde_yaml::from_str(&sensors)?;
+    validate(&sensors)?;
+
+    Ok(sensors)
+}
+
+/// Writes the sensors back to `sensors.yaml`, replacing the file in one go so that a crash
+/// half way through can't leave it truncated
+pub async fn save_sensors_config(
+    sensors_config_path: &Path,
+    sensors: &[Sensor],
+) -> Result<(), ConfigError> {
+    let yaml = serde_yaml::to_string(sensors)?;
+    let temporary = sensors_config_path.with_extension("yaml.tmp");
+
+    fs::write(&temporary, yaml)
+        .and_then(|_| fs::rename(&temporary, sensors_config_path))
+        .map_err(|err| ConfigError::from_io(sensors_config_path.to_path_buf(), err))
+}
+
+/// Where the sensors changed over the HTTP API are persisted
+#[derive(Debug, Clone)]
+pub enum ConfigFile {
+    /// A `sensors.yaml`
+    Sensors(PathBuf),
+    /// The sensors of the profile called `name` in a profiles file
+    Profile { path: PathBuf, name: String },
+}
+
+/// The unit of a metric whose name gives it away
+pub fn default_unit(metric: &str) -> Option<&'static str> {
+    match metric {
+        "temperature" => Some("°C"),
+        "humidity" => Some("%"),
+        _ => None,
+    }
+}
+
+/// The metrics a sensor is known to write: temperature and humidity for a `dht22`, temperature
+/// for a `cpu`, the amount, rate and total of a `pulse` and the fields of a `csv` formatted
+/// sensor. Plugins and JSON formatted sensors
+/// report whatever metrics they like, so theirs are only known once they do.
+pub fn known_metrics(sensor: &Sensor) -> Vec<String> {
+    match sensor.kind {
+        SensorType::Dht22 => vec!["temperature".to_string(), "humidity".to_string()],
+        SensorType::Cpu => vec!["temperature".to_string()],
+        SensorType::Pulse => {
+            let metric = sensor.metric.as_deref().unwrap_or(pulse::DEFAULT_METRIC);
+            vec![
+                metric.to_string(),
+                format!("{}.rate", metric),
+                format!("{}.total", metric),
+            ]
+        }
+        SensorType::Serial | SensorType::Radio if sensor.format == LineFormat::Csv => {
+            sensor.fields.clone()
+        }
+        SensorType::Command | SensorType::Serial | SensorType::Radio => Vec::new(),
+    }
+}
+
+/// How many series the enabled sensors are known to write, see [`known_metrics`]
+pub fn known_series(sensors: &[Sensor]) -> usize {
+    sensors
+        .iter()
+        .filter(|sensor| !sensor.disabled)
+        .map(|sensor| known_metrics(sensor).len())
+        .sum()
+}
+
+/// Checks that the enabled sensors without an `interval` of their own may be sampled every
+/// `refresh`
+pub fn validate_refresh(sensors: &[Sensor], refresh: Interval) -> Result<(), ConfigError> {
+    sensors
+        .iter()
+        .filter(|sensor| !sensor.disabled && sensor.interval.is_none())
+        .try_for_each(|sensor| check_interval(sensor, refresh))
+}
+
+/// Checks that the enabled sensors without an `interval` or `schedule` of their own may be
+/// sampled on the service's `schedule`
+pub fn validate_schedule(sensors: &[Sensor], schedule: &[Window]) -> Result<(), ConfigError> {
+    sensors
+        .iter()
+        .filter(|sensor| {
+            !sensor.disabled && sensor.interval.is_none() && sensor.schedule.is_empty()
+        })
+        .try_for_each(|sensor| {
+            schedule
+                .iter()
+                .try_for_each(|window| check_interval(sensor, window.interval))
+        })
+}
+
+fn check_interval(sensor: &Sensor, interval: Interval) -> Result<(), ConfigError> {
+    if interval < sensor.min_interval() {
+        return Err(ConfigError::Invalid(format!(
+            "sensor {} can't be sampled more often than every {}, but would be every {}",
+            sensor.name,
+            sensor.min_interval(),
+            interval
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that every sensor has a unique name and the settings its type needs
+pub fn validate(sensors: &[Sensor]) -> Result<(), ConfigError> {
+    let mut names = HashSet::new();
+    for sensor in sensors {
+        if !names.insert(&sensor.name) {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} is configured more than once",
+                sensor.name
+            )));
+        }
+
+        match sensor.kind {
+            SensorType::Dht22 | SensorType::Pulse if sensor.pin.is_none() => {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} needs a pin",
+                    sensor.name
+                )));
+            }
+            SensorType::Command if sensor.command.is_empty() => {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} needs a command to run",
+                    sensor.name
+                )));
+            }
+            SensorType::Serial if sensor.device.is_none() => {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} needs a serial device",
+                    sensor.name
+                )));
+            }
+            SensorType::Radio if sensor.node.is_none() => {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} needs the node ID it sends from",
+                    sensor.name
+                )));
+            }
+            SensorType::Serial | SensorType::Radio
+                if sensor.format == LineFormat::Csv && sensor.fields.is_empty() =>
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} needs the fields of its csv readings",
+                    sensor.name
+                )));
+            }
+            _ => {}
+        }
+
+        for (key, segment) in [("site", &sensor.site), ("room", &sensor.room)] {
+            if matches!(segment, Some(segment) if segment.is_empty() || segment.contains(['.', ' ']))
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {}'s {} has to be a single path segment, without dots or spaces",
+                    sensor.name, key
+                )));
+            }
+        }
+
+        // What Graphite doesn't take in tag names and values, besides the space graphs would trip on
+        for (name, value) in &sensor.tags {
+            if name.is_empty() || name.contains([';', '!', '^', '=', '~', ' ']) {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} has an invalid tag name {:?}",
+                    sensor.name, name
+                )));
+            }
+            if value.is_empty() || value.starts_with('~') || value.contains([';', ' ']) {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {}'s tag {} has an invalid value {:?}",
+                    sensor.name, name, value
+                )));
+            }
+        }
+
+        if let Some(interval) = sensor.interval {
+            check_interval(sensor, interval)?;
+        }
+        for window in &sensor.schedule {
+            check_interval(sensor, window.interval)?;
+        }
+
+        if sensor.power_pin.is_some() && sensor.power_pin == sensor.pin {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} can't be powered from its own data pin",
+                sensor.name
+            )));
+        }
+        // Graphite separates tags with `;` and reserves a leading `~`
+        if let Some((metric, unit)) = sensor
+            .units
+            .iter()
+            .find(|(_, unit)| unit.is_empty() || unit.contains(';') || unit.starts_with('~'))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} has an invalid unit {:?} for {}, units can't be empty, contain ; or start with ~",
+                sensor.name, unit, metric
+            )));
+        }
+        if sensor.pull_up && !matches!(sensor.kind, SensorType::Dht22 | SensorType::Pulse) {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} has no data pin to pull up, only dht22 and pulse sensors do",
+                sensor.name
+            )));
+        }
+        if sensor.power_cycle_after == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} needs at least 1 failed read before being power cycled",
+                sensor.name
+            )));
+        }
+        for hook in [
+            &sensor.on_reading,
+            &sensor.on_alert,
+            &sensor.on_sensor_failure,
+        ] {
+            if hook.as_ref().is_some_and(Vec::is_empty) {
+                return Err(ConfigError::Invalid(format!(
+                    "sensor {} has a hook without a command",
+                    sensor.name
+                )));
+            }
+        }
+        if sensor
+            .pulses_per_unit
+            .is_some_and(|pulses| !pulses.is_finite() || pulses <= 0.0)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} needs more than 0 pulses per unit",
+                sensor.name
+            )));
+        }
+        if sensor
+            .metric
+            .as_ref()
+            .is_some_and(|metric| metric.is_empty() || metric.contains([' ', ';']))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} has an invalid metric name, it can't be empty or contain spaces or ;",
+                sensor.name
+            )));
+        }
+        if sensor.max_attempts == Some(0) {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {} needs at least 1 attempt per cycle",
+                sensor.name
+            )));
+        }
+    }
+
+    for (sensor, fan) in sensors
+        .iter()
+        .filter_map(|sensor| sensor.fan.as_ref().map(|fan| (sensor, fan)))
+    {
+        let ascending = fan
+            .curve
+            .windows(2)
+            .all(|points| points[0][0] < points[1][0]);
+        let duties = fan
+            .curve
+            .iter()
+            .all(|[_, duty]| (0.0..=100.0).contains(duty));
+        if fan.curve.is_empty() || !ascending || !duties {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s fan curve needs [value, duty %] points in increasing order, with duties from 0 to 100",
+                sensor.name
+            )));
+        }
+    }
+
+    for (sensor, calibration) in sensors.iter().flat_map(|sensor| {
+        sensor
+            .calibration
+            .iter()
+            .map(move |calibration| (sensor, calibration))
+    }) {
+        let ascending = calibration
+            .points
+            .windows(2)
+            .all(|points| points[0][0] < points[1][0]);
+        if calibration.points.is_empty() || !ascending {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} calibration needs [value, correction] points in increasing order",
+                sensor.name, calibration.metric
+            )));
+        }
+    }
+
+    for (sensor, coarsen) in sensors
+        .iter()
+        .flat_map(|sensor| sensor.coarsen.iter().map(move |coarsen| (sensor, coarsen)))
+    {
+        if coarsen
+            .step
+            .is_some_and(|step| !step.is_finite() || step <= 0.0)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} coarsening needs a step above 0",
+                sensor.name, coarsen.metric
+            )));
+        }
+    }
+
+    for (sensor, alert) in sensors
+        .iter()
+        .flat_map(|sensor| sensor.alerts.iter().map(move |alert| (sensor, alert)))
+    {
+        if alert.above.is_none() && alert.below.is_none() && alert.dew_point_within.is_none() {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} alert needs an above, below or dew_point_within threshold",
+                sensor.name, alert.metric
+            )));
+        }
+        let unknown = alert
+            .dew_point_of
+            .iter()
+            .chain(alert.and.iter().map(|condition| &condition.sensor))
+            .find(|name| !sensors.iter().any(|other| &other.name == *name));
+        if let Some(name) = unknown {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} alert refers to sensor {}, which isn't configured",
+                sensor.name, alert.metric, name
+            )));
+        }
+        if alert
+            .and
+            .iter()
+            .any(|condition| condition.above.is_none() && condition.below.is_none())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} alert has a condition without an above or below threshold",
+                sensor.name, alert.metric
+            )));
+        }
+    }
+
+    for (sensor, trend) in sensors
+        .iter()
+        .flat_map(|sensor| sensor.trends.iter().map(move |trend| (sensor, trend)))
+    {
+        if trend
+            .window_secs
+            .is_some_and(|window| window < Interval::from_secs(1))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} trend needs a window of at least 1s",
+                sensor.name, trend.metric
+            )));
+        }
+        if trend
+            .threshold
+            .is_some_and(|threshold| !threshold.is_finite() || threshold < 0.0)
+        {
+            return Err(ConfigError::Invalid(format!(
+                "sensor {}'s {} trend needs a threshold of at least 0",
+                sensor.name, trend.metric
+            )));
+        }
+    }
+
+    let mut radios = sensors
+        .iter()
+        .filter(|sensor| sensor.kind == SensorType::Radio)
+        .map(|sensor| (sensor.frequency, sensor.chip_select));
+    if let Some(radio) = radios.next() {
+        if radios.any(|other| other != radio) {
+            return Err(ConfigError::Invalid(
+                "radio sensors share one radio, so their frequency and chip_select must match"
+                    .to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
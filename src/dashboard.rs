@@ -0,0 +1,127 @@
+//! A single self-contained HTML page with the current readings, their recent trend and the
+//! health of every sensor, for checking on things from a phone without Grafana
+
+use crate::{
+    history::History,
+    locale::Locale,
+    state::{SensorState, SensorStatus},
+};
+use chrono::{Local, TimeZone};
+use std::fmt::Write;
+
+const SPARKLINE_WIDTH: f64 = 160.0;
+const SPARKLINE_HEIGHT: f64 = 32.0;
+
+const STYLE: &str = "\
+body{font-family:system-ui,sans-serif;margin:1rem;background:#111;color:#eee}\
+h1{font-size:1.2rem}\
+section{background:#222;border-radius:.5rem;padding:.75rem 1rem;margin-bottom:1rem}\
+h2{font-size:1rem;margin:0 0 .5rem}\
+.ok{color:#6c6}.failing{color:#e66}.pending{color:#aaa}\
+table{border-collapse:collapse}td{padding:.2rem .75rem .2rem 0}\
+.value{font-size:1.4rem;font-variant-numeric:tabular-nums}\
+polyline{fill:none;stroke:#6af;stroke-width:1.5}\
+small{color:#aaa}";
+
+pub(crate) fn render(sensors: &[SensorState], history: &History, locale: &Locale) -> String {
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width,initial-scale=1\">\
+         <meta http-equiv=\"refresh\" content=\"60\">\
+         <title>Sensors</title><style>{}</style></head><body><h1>Sensors</h1>",
+        STYLE
+    );
+
+    for sensor in sensors {
+        render_sensor(&mut page, sensor, history, locale);
+    }
+
+    page.push_str("</body></html>");
+    page
+}
+
+fn render_sensor(page: &mut String, sensor: &SensorState, history: &History, locale: &Locale) {
+    let status = match sensor.status {
+        SensorStatus::Pending => "pending",
+        SensorStatus::WarmingUp => "warming up",
+        SensorStatus::Ok => "ok",
+        SensorStatus::Failing => "failing",
+    };
+    let _ = write!(
+        page,
+        "<section><h2>{} <span class=\"{}\">&#9679; {}</span></h2><table>",
+        escape(&sensor.sensor),
+        status,
+        status
+    );
+
+    for (metric, value) in &sensor.values {
+        let points = history.series(&format!("{}.{}", sensor.path, metric));
+        let _ = write!(
+            page,
+            "<tr><td>{}</td><td class=\"value\">{}</td><td>{}</td></tr>",
+            escape(metric),
+            escape(&locale.value(metric, *value)),
+            sparkline(&points)
+        );
+    }
+    page.push_str("</table>");
+
+    if let Some(time) = sensor
+        .time
+        .and_then(|time| Local.timestamp_opt(time, 0).single())
+    {
+        let _ = write!(
+            page,
+            "<small>Updated {}</small>",
+            escape(&locale.date_time(&time))
+        );
+    }
+    if let (SensorStatus::Failing, Some(error)) = (sensor.status, &sensor.last_error) {
+        let _ = write!(
+            page,
+            "<br><small class=\"failing\">{} ({} failed attempts)</small>",
+            escape(error),
+            sensor.failures
+        );
+    }
+
+    page.push_str("</section>");
+}
+
+/// An inline SVG line of the values, scaled to fill the box
+fn sparkline(points: &[(i64, f64)]) -> String {
+    if points.len() < 2 {
+        return String::new();
+    }
+
+    let (min, max) = points.iter().fold(
+        (f64::INFINITY, f64::NEG_INFINITY),
+        |(min, max), (_, value)| (min.min(*value), max.max(*value)),
+    );
+    let range = if max > min { max - min } else { 1.0 };
+    let step = SPARKLINE_WIDTH / (points.len() - 1) as f64;
+
+    let mut line = String::new();
+    for (i, (_, value)) in points.iter().enumerate() {
+        let x = i as f64 * step;
+        let y = SPARKLINE_HEIGHT - 1.0 - (value - min) / range * (SPARKLINE_HEIGHT - 2.0);
+        let _ = write!(line, "{:.1},{:.1} ", x, y);
+    }
+
+    format!(
+        "<svg width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\"><polyline points=\"{}\"/></svg>",
+        line.trim_end(),
+        w = SPARKLINE_WIDTH,
+        h = SPARKLINE_HEIGHT
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,309 @@
+//! Telling a DHT22's wiring problems from software ones, by timing its signal
+//!
+//! A DHT22 answers the start signal with 40 bits, each a ~50 µs low pulse followed by a high
+//! one of ~27 µs for a 0 and ~70 µs for a 1. The reads done by `diagnose` time every pulse
+//! rather than just decoding them, and what goes wrong points at the cause:
+//!
+//! - a data line that's low before the start signal has no pull-up resistor
+//! - no answer at all is a sensor without power or ground, or on another pin
+//! - 1 bits that come out short are a line that's slow to rise, from a pull-up that's too weak
+//!   for the cable, e.g. the Pi's internal one
+//! - bits cut off or misread while the timings are otherwise right are the process being
+//!   interrupted mid-read, a software issue
+
+use crate::error::SensorError;
+use std::fmt;
+
+/// How many reads a pin is diagnosed with by default
+pub const DEFAULT_READS: usize = 10;
+
+/// High pulses at least this long are 1 bits, halfway between a 0's ~27 µs and a 1's ~70 µs
+const BIT_THRESHOLD_US: u32 = 48;
+
+/// High pulses this close to the threshold could have been either bit
+const AMBIGUOUS_US: std::ops::RangeInclusive<u32> = 38..=58;
+
+/// 1 bits shorter than this on average are a line that's slow to rise
+const SHORT_ONE_US: f64 = 60.0;
+
+/// A bit's pulses, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    pub low_us: u32,
+    pub high_us: u32,
+}
+
+/// What one read of a DHT22 picked up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Capture {
+    /// The data line was low before the start signal
+    LineLow,
+    /// The sensor didn't answer the start signal
+    NoResponse,
+    /// The sensor stopped answering after these bits, or the read missed a pulse
+    Truncated(Vec<Pulse>),
+    /// All 40 bits
+    Complete(Vec<Pulse>),
+}
+
+/// The shortest, longest and average length of a kind of pulse
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub mean_us: f64,
+    pub min_us: u32,
+    pub max_us: u32,
+}
+
+impl Timing {
+    fn of(pulses: impl Iterator<Item = u32>) -> Option<Timing> {
+        let pulses = pulses.collect::<Vec<_>>();
+        Some(Timing {
+            mean_us: pulses.iter().map(|us| f64::from(*us)).sum::<f64>() / pulses.len() as f64,
+            min_us: *pulses.iter().min()?,
+            max_us: *pulses.iter().max()?,
+        })
+    }
+}
+
+/// The most likely cause of a pin's failed reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Ok,
+    NoPullUp,
+    NoResponse,
+    WeakPullUp,
+    Interrupted,
+}
+
+/// What the reads of a pin showed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinReport {
+    pub pin: u8,
+    pub reads: usize,
+    pub valid: usize,
+    pub checksum_failures: usize,
+    pub truncated: usize,
+    pub no_response: usize,
+    pub line_low: usize,
+    pub zero_high: Option<Timing>,
+    pub one_high: Option<Timing>,
+    pub low: Option<Timing>,
+    /// Bits that were picked up, and how many of them could have been either
+    pub bits: usize,
+    pub ambiguous_bits: usize,
+    pub verdict: Verdict,
+}
+
+/// Whether a read's bits add up to their checksum
+fn checksum_matches(bits: &[Pulse]) -> bool {
+    let mut bytes = [0u8; 5];
+    for (i, bit) in bits.iter().enumerate().take(40) {
+        bytes[i / 8] = bytes[i / 8] << 1 | u8::from(bit.high_us >= BIT_THRESHOLD_US);
+    }
+    bytes[4]
+        == bytes[0]
+            .wrapping_add(bytes[1])
+            .wrapping_add(bytes[2])
+            .wrapping_add(bytes[3])
+}
+
+/// Sums up the reads of a pin
+pub fn analyse(pin: u8, captures: &[Capture]) -> PinReport {
+    let count = |matches: fn(&Capture) -> bool| captures.iter().filter(|c| matches(c)).count();
+    let bits = captures
+        .iter()
+        .flat_map(|capture| match capture {
+            Capture::Truncated(bits) | Capture::Complete(bits) => bits.as_slice(),
+            Capture::LineLow | Capture::NoResponse => &[],
+        })
+        .collect::<Vec<_>>();
+    let valid = count(|c| matches!(c, Capture::Complete(bits) if checksum_matches(bits)));
+    let checksum_failures =
+        count(|c| matches!(c, Capture::Complete(bits) if !checksum_matches(bits)));
+    let line_low = count(|c| matches!(c, Capture::LineLow));
+    let no_response = count(|c| matches!(c, Capture::NoResponse));
+
+    let one_high = Timing::of(
+        bits.iter()
+            .map(|bit| bit.high_us)
+            .filter(|us| *us >= BIT_THRESHOLD_US),
+    );
+    let verdict = if line_low * 2 > captures.len() {
+        Verdict::NoPullUp
+    } else if no_response * 2 > captures.len() {
+        Verdict::NoResponse
+    } else if valid == captures.len() {
+        Verdict::Ok
+    } else if one_high.is_some_and(|timing| timing.mean_us < SHORT_ONE_US) {
+        Verdict::WeakPullUp
+    } else {
+        Verdict::Interrupted
+    };
+
+    PinReport {
+        pin,
+        reads: captures.len(),
+        valid,
+        checksum_failures,
+        truncated: count(|c| matches!(c, Capture::Truncated(_))),
+        no_response,
+        line_low,
+        zero_high: Timing::of(
+            bits.iter()
+                .map(|bit| bit.high_us)
+                .filter(|us| *us < BIT_THRESHOLD_US),
+        ),
+        one_high,
+        low: Timing::of(bits.iter().map(|bit| bit.low_us)),
+        bits: bits.len(),
+        ambiguous_bits: bits
+            .iter()
+            .filter(|bit| AMBIGUOUS_US.contains(&bit.high_us))
+            .count(),
+        verdict,
+    }
+}
+
+/// Reads the DHT22 on `pin` once, timing every pulse, with the internal pull-up resistor
+/// enabled for the read if `pull_up` is set
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+pub fn capture(pin: u8, pull_up: bool) -> Result<Capture, SensorError> {
+    use rppal::gpio::{Gpio, IoPin, Level, Mode, PullUpDown};
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// How long a level may last before the pulse counts as missed
+    const LEVEL_TIMEOUT: Duration = Duration::from_micros(500);
+
+    /// How long the line stays at `level`, in microseconds
+    fn pulse(io: &IoPin, level: Level) -> Option<u32> {
+        let start = Instant::now();
+        while io.read() == level {
+            if start.elapsed() > LEVEL_TIMEOUT {
+                return None;
+            }
+        }
+        Some(start.elapsed().as_micros() as u32)
+    }
+
+    let gpio = Gpio::new().map_err(SensorError::Gpio)?;
+    let mut io = gpio
+        .get(pin)
+        .map_err(SensorError::Gpio)?
+        .into_io(Mode::Input);
+    if pull_up {
+        io.set_pullupdown(PullUpDown::PullUp);
+    }
+    thread::sleep(Duration::from_millis(10));
+    if io.read() == Level::Low {
+        return Ok(Capture::LineLow);
+    }
+
+    // Timed at the same real-time priority as the sensors' own reads, if allowed
+    let prioritized = set_scheduler(libc::SCHED_FIFO, 32);
+    io.set_mode(Mode::Output);
+    io.write(Level::Low);
+    thread::sleep(Duration::from_millis(20));
+    io.set_mode(Mode::Input);
+
+    let capture = if pulse(&io, Level::High).is_none()
+        || pulse(&io, Level::Low).is_none()
+        || pulse(&io, Level::High).is_none()
+    {
+        Capture::NoResponse
+    } else {
+        let mut bits = Vec::with_capacity(40);
+        for _ in 0..40 {
+            match (pulse(&io, Level::Low), pulse(&io, Level::High)) {
+                (Some(low_us), Some(high_us)) => bits.push(Pulse { low_us, high_us }),
+                _ => break,
+            }
+        }
+        if bits.len() == 40 {
+            Capture::Complete(bits)
+        } else {
+            Capture::Truncated(bits)
+        }
+    };
+    if prioritized {
+        set_scheduler(libc::SCHED_OTHER, 0);
+    }
+
+    Ok(capture)
+}
+
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+fn set_scheduler(policy: libc::c_int, priority: libc::c_int) -> bool {
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    // SAFETY: sets the calling thread's own scheduling policy, with a valid parameter
+    unsafe { libc::sched_setscheduler(0, policy, &param) == 0 }
+}
+
+#[cfg(not(all(feature = "dht22", target_os = "linux")))]
+pub fn capture(_pin: u8, _pull_up: bool) -> Result<Capture, SensorError> {
+    Err(SensorError::Unsupported("DHT22"))
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Ok => "ok",
+            Verdict::NoPullUp => "the data line idles low, add a 10 kΩ pull-up resistor between data and 3.3 V, or set `pull_up: true` for a short cable",
+            Verdict::NoResponse => "the sensor doesn't answer, check its power and ground and which pin its data line is on",
+            Verdict::WeakPullUp => "the 1 bits come out short as the line is slow to rise, use a stronger pull-up resistor (10 kΩ, or 4.7 kΩ on a long cable)",
+            Verdict::Interrupted => "the timings look right but bits get cut off or misread, so the reads are being interrupted: allow real-time priority (CAP_SYS_NICE) and keep other load off the Pi",
+        })
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} µs on average ({}-{})",
+            self.mean_us, self.min_us, self.max_us
+        )
+    }
+}
+
+impl fmt::Display for PinReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = |count: usize, of: usize| count as f64 * 100.0 / of.max(1) as f64;
+        writeln!(f, "GPIO {}: {}", self.pin, self.verdict)?;
+        writeln!(
+            f,
+            "  reads: {}, {} valid, {} checksum failures ({:.1}%), {} cut short, {} unanswered, {} with the line low",
+            self.reads,
+            self.valid,
+            self.checksum_failures,
+            share(self.checksum_failures, self.reads),
+            self.truncated,
+            self.no_response,
+            self.line_low
+        )?;
+        for (pulses, timing) in [
+            ("0 bits high", self.zero_high),
+            ("1 bits high", self.one_high),
+            ("low between bits", self.low),
+        ] {
+            if let Some(timing) = timing {
+                writeln!(f, "  {}: {}", pulses, timing)?;
+            }
+        }
+        if self.bits > 0 {
+            writeln!(
+                f,
+                "  ambiguous bits: {} of {} ({:.1}%)",
+                self.ambiguous_bits,
+                self.bits,
+                share(self.ambiguous_bits, self.bits)
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,707 @@
+//! Showing the current readings on a small display attached to the Pi (behind the `display`
+//! feature)
+//!
+//! For headless installs - a boiler room, a greenhouse - a 128x64 SSD1306 OLED or an HD44780
+//! character LCD with a PCF8574 I2C backpack shows one sensor at a time: its name on the first
+//! line and its latest values below. Sensors with more metrics than fit get several pages.
+//!
+//! A Waveshare e-paper HAT instead lists every metric with today's minimum and maximum, redrawn
+//! every few minutes. The panel only draws power while it's redrawn and keeps showing the last
+//! readings once the Pi is off, which suits battery powered installs.
+
+use crate::{
+    history::History,
+    locale::Locale,
+    state::{SensorState, SensorStatus, State},
+};
+use chrono::{Local, TimeZone};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+/// How long each page is shown unless configured otherwise, in seconds
+pub const DEFAULT_ROTATE_SECS: u64 = 5;
+
+/// How often an e-paper panel is redrawn unless configured otherwise, in seconds. Every redraw
+/// flashes the panel and wears it a little.
+pub const DEFAULT_EPAPER_REFRESH_SECS: u64 = 600;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayKind {
+    /// 128x64 SSD1306 OLED, 21 columns by 6 lines of text
+    Ssd1306,
+    /// 16x2 HD44780 character LCD behind a PCF8574 backpack
+    Lcd1602,
+    /// 20x4 HD44780 character LCD behind a PCF8574 backpack
+    Lcd2004,
+    /// Waveshare 2.13" V4 e-paper HAT (250x122) on the SPI bus, 35 columns by 9 lines of text
+    Epd2in13,
+}
+
+impl DisplayKind {
+    /// Columns and lines of text
+    fn size(self) -> (usize, usize) {
+        match self {
+            DisplayKind::Ssd1306 => (21, 6),
+            DisplayKind::Lcd1602 => (16, 2),
+            DisplayKind::Lcd2004 => (20, 4),
+            DisplayKind::Epd2in13 => (35, 9),
+        }
+    }
+
+    /// The usual I2C address of the module. The e-paper HAT's on the SPI bus instead.
+    pub fn default_address(self) -> u16 {
+        match self {
+            DisplayKind::Ssd1306 => 0x3C,
+            DisplayKind::Lcd1602 | DisplayKind::Lcd2004 => 0x27,
+            DisplayKind::Epd2in13 => 0,
+        }
+    }
+
+    pub fn is_epaper(self) -> bool {
+        self == DisplayKind::Epd2in13
+    }
+}
+
+impl FromStr for DisplayKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "ssd1306" => Ok(DisplayKind::Ssd1306),
+            "lcd1602" => Ok(DisplayKind::Lcd1602),
+            "lcd2004" => Ok(DisplayKind::Lcd2004),
+            "epd2in13" => Ok(DisplayKind::Epd2in13),
+            _ => Err(format!(
+                "unknown display {}, expected ssd1306, lcd1602, lcd2004 or epd2in13",
+                kind
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DisplayConfig {
+    pub kind: DisplayKind,
+    /// I2C address of the module (default: the kind's usual one)
+    pub address: Option<u16>,
+    /// How long each page is shown, i.e. how often the display is redrawn
+    pub rotate: Duration,
+    /// Which sensors to show, in order (default: all of them)
+    pub sensors: Vec<String>,
+}
+
+impl DisplayConfig {
+    pub fn new(kind: DisplayKind) -> Self {
+        DisplayConfig {
+            kind,
+            address: None,
+            rotate: Duration::from_secs(if kind.is_epaper() {
+                DEFAULT_EPAPER_REFRESH_SECS
+            } else {
+                DEFAULT_ROTATE_SECS
+            }),
+            sensors: Vec::new(),
+        }
+    }
+}
+
+/// Shows the sensors' latest readings until shutdown, then clears the display - except for
+/// e-paper, which keeps showing them. A display that can't be opened is logged and left alone,
+/// the readings are still shipped.
+pub(crate) async fn run(
+    config: DisplayConfig,
+    locale: Locale,
+    state: Arc<State>,
+    history: Arc<History>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let address = config.address.unwrap_or(config.kind.default_address());
+    let mut screen = match screen::open(config.kind, address) {
+        Ok(screen) => screen,
+        Err(err) => {
+            tracing::warn!("Unable to open the {:?} display: {}", config.kind, err);
+            return;
+        }
+    };
+    tracing::info!(
+        address,
+        "Showing the readings on the {:?} display",
+        config.kind
+    );
+
+    let (columns, rows) = config.kind.size();
+    let mut rotate = tokio::time::interval(config.rotate);
+    for page in 0.. {
+        tokio::select! {
+            _ = rotate.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+
+        let pages = if config.kind.is_epaper() {
+            summary_pages(
+                &state.snapshot(),
+                &config.sensors,
+                &history,
+                &locale,
+                columns,
+                rows,
+            )
+        } else {
+            pages(&state.snapshot(), &config.sensors, &locale, columns, rows)
+        };
+        let lines = pages[page % pages.len()].clone();
+        screen = match show(screen, lines).await {
+            Some(screen) => screen,
+            None => return,
+        };
+    }
+
+    if !config.kind.is_epaper() {
+        let _ = show(screen, Vec::new()).await;
+    }
+}
+
+/// Writes a page in the background, the bus being far slower than the runtime would like.
+/// Returns the screen back, unless the write task died.
+async fn show(
+    mut screen: Box<dyn screen::Screen>,
+    lines: Vec<String>,
+) -> Option<Box<dyn screen::Screen>> {
+    let shown = tokio::task::spawn_blocking(move || {
+        if let Err(err) = screen.show(&lines) {
+            tracing::warn!("Failed to update the display: {}", err);
+        }
+        screen
+    });
+
+    shown.await.ok()
+}
+
+/// The selected sensors' states, in order
+fn selected<'a>(snapshot: &'a [SensorState], selected: &[String]) -> Vec<&'a SensorState> {
+    if selected.is_empty() {
+        snapshot.iter().collect()
+    } else {
+        selected
+            .iter()
+            .filter_map(|name| snapshot.iter().find(|state| &state.sensor == name))
+            .collect()
+    }
+}
+
+/// Splits the selected sensors into pages of at most `rows` lines, `columns` wide
+fn pages(
+    snapshot: &[SensorState],
+    selected_sensors: &[String],
+    locale: &Locale,
+    columns: usize,
+    rows: usize,
+) -> Vec<Vec<String>> {
+    let mut pages = Vec::new();
+    for sensor in selected(snapshot, selected_sensors) {
+        let title = match sensor.status {
+            SensorStatus::Failing => format!("{} !", sensor.sensor),
+            _ => sensor.sensor.clone(),
+        };
+        let metrics = sensor
+            .values
+            .iter()
+            .map(|(metric, value)| metric_text(metric, &locale.value(metric, *value), columns))
+            .collect::<Vec<_>>();
+        let metrics = if metrics.is_empty() {
+            vec![match sensor.status {
+                SensorStatus::Failing => "no reading".to_string(),
+                SensorStatus::WarmingUp => "warming up...".to_string(),
+                _ => "waiting...".to_string(),
+            }]
+        } else {
+            metrics
+        };
+
+        for chunk in metrics.chunks(rows.saturating_sub(1).max(1)) {
+            let mut page = vec![truncate(&title, columns)];
+            page.extend(chunk.iter().cloned());
+            page.truncate(rows);
+            pages.push(page);
+        }
+    }
+
+    if pages.is_empty() {
+        pages.push(vec!["no sensors".to_string()]);
+    }
+    pages
+}
+
+/// Every selected metric's latest value with today's minimum and maximum under the time of the
+/// update, split into pages of at most `rows` lines. The extremes are the daily ones tracked
+/// with `--daily-extremes` if they are, or else only go as far back as the history does: its
+/// last [`crate::history::DEFAULT_HISTORY_POINTS`] readings, less than a day for sensors read
+/// more often than once a minute.
+fn summary_pages(
+    snapshot: &[SensorState],
+    selected_sensors: &[String],
+    history: &History,
+    locale: &Locale,
+    columns: usize,
+    rows: usize,
+) -> Vec<Vec<String>> {
+    let now = Local::now();
+    let midnight = now
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .and_then(|midnight| Local.from_local_datetime(&midnight).earliest())
+        .map_or(now.timestamp(), |midnight| midnight.timestamp());
+
+    let mut lines = Vec::new();
+    for sensor in selected(snapshot, selected_sensors) {
+        if sensor.values.is_empty() {
+            lines.push(metric_text(&sensor.sensor, "-", columns));
+        }
+        for (metric, value) in &sensor.values {
+            let name = format!("{}.{}", sensor.path, metric);
+            let tracked = |extreme: &str| {
+                let series = format!("{}.{}", name, extreme);
+                let today = history.range(&series, midnight, now.timestamp(), None);
+                today.last().map(|(_, value)| *value)
+            };
+            let (min, max) = match (tracked("daily_min"), tracked("daily_max")) {
+                (Some(min), Some(max)) => (min.min(*value), max.max(*value)),
+                _ => history
+                    .range(&name, midnight, now.timestamp(), None)
+                    .iter()
+                    .fold((*value, *value), |(min, max), (_, value)| {
+                        (min.min(*value), max.max(*value))
+                    }),
+            };
+            let failing = if sensor.status == SensorStatus::Failing {
+                "!"
+            } else {
+                ""
+            };
+
+            lines.push(metric_text(
+                &name,
+                &format!(
+                    "{}{} {}-{}",
+                    failing,
+                    locale.value(metric, *value),
+                    locale.number(min),
+                    locale.number(max)
+                ),
+                columns,
+            ));
+        }
+    }
+    if lines.is_empty() {
+        lines.push("no sensors".to_string());
+    }
+
+    let title = format!("Updated {}   (today min-max)", locale.time(&now));
+    lines
+        .chunks(rows.saturating_sub(1).max(1))
+        .map(|chunk| {
+            let mut page = vec![truncate(&title, columns)];
+            page.extend(chunk.iter().cloned());
+            page
+        })
+        .collect()
+}
+
+/// The metric's name and its value right-aligned, shortening the name to make room
+fn metric_text(metric: &str, value: &str, columns: usize) -> String {
+    let name_width = columns.saturating_sub(value.chars().count() + 1);
+    format!(
+        "{:<width$} {}",
+        truncate(metric, name_width),
+        value,
+        width = name_width
+    )
+}
+
+fn truncate(text: &str, columns: usize) -> String {
+    text.chars().take(columns).collect()
+}
+
+#[cfg(all(feature = "display", target_os = "linux"))]
+mod screen {
+    use super::DisplayKind;
+    use embedded_graphics::{
+        mono_font::{
+            iso_8859_1::{FONT_6X10, FONT_7X13},
+            MonoFont, MonoTextStyle,
+        },
+        pixelcolor::BinaryColor,
+        prelude::*,
+        text::{Baseline, Text},
+    };
+    use rppal::{
+        gpio::{Gpio, InputPin, OutputPin},
+        i2c::I2c,
+        spi::{Bus, Mode, SlaveSelect, Spi},
+    };
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    pub(super) trait Screen: Send {
+        /// Replaces what's shown with these lines of text
+        fn show(&mut self, lines: &[String]) -> Result<(), String>;
+    }
+
+    pub(super) fn open(kind: DisplayKind, address: u16) -> Result<Box<dyn Screen>, String> {
+        if kind == DisplayKind::Epd2in13 {
+            return Ok(Box::new(Epd2in13::new()?));
+        }
+
+        let mut i2c = I2c::new().map_err(display_error)?;
+        i2c.set_slave_address(address).map_err(display_error)?;
+
+        Ok(match kind {
+            DisplayKind::Ssd1306 => Box::new(Ssd1306::new(i2c)?),
+            DisplayKind::Lcd1602 => Box::new(Lcd::new(i2c, 16, 2)?),
+            DisplayKind::Lcd2004 => Box::new(Lcd::new(i2c, 20, 4)?),
+            DisplayKind::Epd2in13 => unreachable!("Not on the I2C bus"),
+        })
+    }
+
+    fn display_error(err: impl std::error::Error) -> String {
+        err.to_string()
+    }
+
+    /// Draws lines of text `line_height` apart, from the top left corner
+    fn draw_lines<D>(target: &mut D, lines: &[String], font: &MonoFont, line_height: i32)
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let style = MonoTextStyle::new(font, BinaryColor::On);
+        for (row, line) in lines.iter().enumerate() {
+            let top = 2 + line_height * row as i32;
+            let _ =
+                Text::with_baseline(line, Point::new(0, top), style, Baseline::Top).draw(target);
+        }
+    }
+
+    struct Ssd1306 {
+        i2c: I2c,
+    }
+
+    /// 128x64 panel with the internal charge pump, horizontal addressing and the origin in the
+    /// top left corner
+    const SSD1306_INIT: &[u8] = &[
+        0xAE, // Display off
+        0xD5, 0x80, // Clock divide ratio
+        0xA8, 0x3F, // Multiplex ratio, 64 lines
+        0xD3, 0x00, // No display offset
+        0x40, // Start line 0
+        0x8D, 0x14, // Charge pump on
+        0x20, 0x00, // Horizontal addressing
+        0xA1, // Segment remap
+        0xC8, // Scan from COM63 to COM0
+        0xDA, 0x12, // COM pins configuration
+        0x81, 0xCF, // Contrast
+        0xD9, 0xF1, // Pre-charge period
+        0xDB, 0x40, // VCOMH deselect level
+        0xA4, // Show the RAM contents
+        0xA6, // Not inverted
+        0xAF, // Display on
+    ];
+
+    impl Ssd1306 {
+        fn new(mut i2c: I2c) -> Result<Self, String> {
+            let mut command = vec![0x00];
+            command.extend_from_slice(SSD1306_INIT);
+            i2c.write(&command).map_err(display_error)?;
+            Ok(Ssd1306 { i2c })
+        }
+    }
+
+    impl Screen for Ssd1306 {
+        fn show(&mut self, lines: &[String]) -> Result<(), String> {
+            let mut frame = Frame([0; 1024]);
+            draw_lines(&mut frame, lines, &FONT_6X10, 10);
+
+            // Every column and page, then the whole frame
+            self.i2c
+                .write(&[0x00, 0x21, 0, 127, 0x22, 0, 7])
+                .map_err(display_error)?;
+            for chunk in frame.0.chunks(32) {
+                let mut data = vec![0x40];
+                data.extend_from_slice(chunk);
+                self.i2c.write(&data).map_err(display_error)?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// The SSD1306's RAM layout: a byte per column for every 8 line page, the top line in the
+    /// least significant bit
+    struct Frame([u8; 1024]);
+
+    impl OriginDimensions for Frame {
+        fn size(&self) -> Size {
+            Size::new(128, 64)
+        }
+    }
+
+    impl DrawTarget for Frame {
+        type Color = BinaryColor;
+        type Error = std::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                    continue;
+                };
+                if x >= 128 || y >= 64 {
+                    continue;
+                }
+
+                let byte = &mut self.0[x + y / 8 * 128];
+                if color.is_on() {
+                    *byte |= 1 << (y % 8);
+                } else {
+                    *byte &= !(1 << (y % 8));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// An HD44780 in 4-bit mode, its data lines on the PCF8574's upper nibble
+    struct Lcd {
+        i2c: I2c,
+        columns: usize,
+        rows: usize,
+    }
+
+    const LCD_REGISTER_SELECT: u8 = 0x01;
+    const LCD_ENABLE: u8 = 0x04;
+    const LCD_BACKLIGHT: u8 = 0x08;
+    /// Where each line starts in the display RAM
+    const LCD_ROW_OFFSETS: [u8; 4] = [0x00, 0x40, 0x14, 0x54];
+    /// The degree sign in the HD44780's A00 character ROM
+    const LCD_DEGREE: u8 = 0xDF;
+
+    impl Lcd {
+        fn new(i2c: I2c, columns: usize, rows: usize) -> Result<Self, String> {
+            let mut lcd = Lcd { i2c, columns, rows };
+
+            // Reset into 8-bit mode whatever state it's in, then switch to 4-bit
+            thread::sleep(Duration::from_millis(50));
+            for _ in 0..3 {
+                lcd.write_nibble(0x30, 0)?;
+                thread::sleep(Duration::from_millis(5));
+            }
+            lcd.write_nibble(0x20, 0)?;
+
+            lcd.command(0x28)?; // 4-bit, 2 line mode (4 line modules wrap around)
+            lcd.command(0x0C)?; // Display on, no cursor
+            lcd.command(0x06)?; // Move right after every character
+            lcd.command(0x01)?; // Clear
+            thread::sleep(Duration::from_millis(2));
+
+            Ok(lcd)
+        }
+
+        fn command(&mut self, command: u8) -> Result<(), String> {
+            self.send(command, 0)
+        }
+
+        fn send(&mut self, byte: u8, mode: u8) -> Result<(), String> {
+            self.write_nibble(byte & 0xF0, mode)?;
+            self.write_nibble(byte << 4, mode)
+        }
+
+        /// Latches the upper nibble of `bits` on the enable pulse's falling edge
+        fn write_nibble(&mut self, bits: u8, mode: u8) -> Result<(), String> {
+            let bits = (bits & 0xF0) | mode | LCD_BACKLIGHT;
+            self.i2c
+                .write(&[bits | LCD_ENABLE])
+                .map_err(display_error)?;
+            self.i2c.write(&[bits]).map_err(display_error)?;
+            thread::sleep(Duration::from_micros(50));
+            Ok(())
+        }
+    }
+
+    impl Screen for Lcd {
+        fn show(&mut self, lines: &[String]) -> Result<(), String> {
+            for (row, offset) in LCD_ROW_OFFSETS.iter().enumerate().take(self.rows) {
+                self.command(0x80 | offset)?;
+
+                let line = lines.get(row).map(String::as_str).unwrap_or_default();
+                let characters = line
+                    .chars()
+                    .map(|character| match character {
+                        ' '..='}' => character as u8,
+                        '°' => LCD_DEGREE,
+                        _ => b'?',
+                    })
+                    .chain(std::iter::repeat(b' '))
+                    .take(self.columns);
+                for character in characters {
+                    self.send(character, LCD_REGISTER_SELECT)?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    /// The Waveshare 2.13" V4 HAT: an SSD1680 controller on SPI0 CE0, with the data/command,
+    /// reset and busy lines on BCM 25, 17 and 24. It's woken up for every redraw and put into
+    /// deep sleep after.
+    struct Epd2in13 {
+        spi: Spi,
+        data_command: OutputPin,
+        reset: OutputPin,
+        busy: InputPin,
+    }
+
+    const EPD_DATA_COMMAND_PIN: u8 = 25;
+    const EPD_RESET_PIN: u8 = 17;
+    const EPD_BUSY_PIN: u8 = 24;
+    /// The panel's native portrait size; text is drawn in landscape
+    const EPD_WIDTH: usize = 122;
+    const EPD_HEIGHT: usize = 250;
+    const EPD_LINE_BYTES: usize = EPD_WIDTH.div_ceil(8);
+    /// A full refresh takes a couple of seconds, anything much longer means it's not listening
+    const EPD_BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+    impl Epd2in13 {
+        fn new() -> Result<Self, String> {
+            let gpio = Gpio::new().map_err(display_error)?;
+            let pin = |pin| gpio.get(pin).map_err(display_error);
+
+            Ok(Epd2in13 {
+                spi: Spi::new(Bus::Spi0, SlaveSelect::Ss0, 4_000_000, Mode::Mode0)
+                    .map_err(display_error)?,
+                data_command: pin(EPD_DATA_COMMAND_PIN)?.into_output(),
+                reset: pin(EPD_RESET_PIN)?.into_output_high(),
+                busy: pin(EPD_BUSY_PIN)?.into_input(),
+            })
+        }
+
+        /// Resets the controller out of deep sleep and sets it up for a full refresh
+        fn wake(&mut self) -> Result<(), String> {
+            self.reset.set_high();
+            thread::sleep(Duration::from_millis(20));
+            self.reset.set_low();
+            thread::sleep(Duration::from_millis(2));
+            self.reset.set_high();
+            thread::sleep(Duration::from_millis(20));
+            self.wait()?;
+
+            self.command(0x12, &[])?; // Software reset
+            self.wait()?;
+            self.command(0x01, &[0xF9, 0x00, 0x00])?; // 250 gate lines
+            self.command(0x11, &[0x03])?; // Increment x then y
+            self.command(0x44, &[0x00, (EPD_LINE_BYTES - 1) as u8])?; // RAM x range, in bytes
+            self.command(0x45, &[0x00, 0x00, 0xF9, 0x00])?; // RAM y range
+            self.command(0x4E, &[0x00])?; // RAM x counter
+            self.command(0x4F, &[0x00, 0x00])?; // RAM y counter
+            self.command(0x3C, &[0x05])?; // White border
+            self.command(0x21, &[0x00, 0x80])?; // Display update control
+            self.command(0x18, &[0x80])?; // Internal temperature sensor
+            self.wait()
+        }
+
+        fn command(&mut self, command: u8, data: &[u8]) -> Result<(), String> {
+            self.data_command.set_low();
+            self.spi.write(&[command]).map_err(display_error)?;
+            if !data.is_empty() {
+                self.data_command.set_high();
+                for chunk in data.chunks(1024) {
+                    self.spi.write(chunk).map_err(display_error)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn wait(&self) -> Result<(), String> {
+            let deadline = Instant::now() + EPD_BUSY_TIMEOUT;
+            while self.busy.is_high() {
+                if Instant::now() >= deadline {
+                    return Err("the e-paper panel stayed busy".to_string());
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(())
+        }
+    }
+
+    impl Screen for Epd2in13 {
+        fn show(&mut self, lines: &[String]) -> Result<(), String> {
+            let mut panel = Panel(vec![0xFF; EPD_LINE_BYTES * EPD_HEIGHT]);
+            draw_lines(&mut panel, lines, &FONT_7X13, 13);
+
+            self.wake()?;
+            self.command(0x24, &panel.0)?; // Black and white RAM
+            self.command(0x22, &[0xF7])?; // Full update sequence
+            self.command(0x20, &[])?; // Activate it
+            self.wait()?;
+            self.command(0x10, &[0x01]) // Deep sleep
+        }
+    }
+
+    /// The SSD1680's RAM layout: a bit per pixel along each of the 250 portrait lines, the
+    /// leftmost in the most significant bit, cleared for black. Drawn on in landscape.
+    struct Panel(Vec<u8>);
+
+    impl OriginDimensions for Panel {
+        fn size(&self) -> Size {
+            Size::new(EPD_HEIGHT as u32, EPD_WIDTH as u32)
+        }
+    }
+
+    impl DrawTarget for Panel {
+        type Color = BinaryColor;
+        type Error = std::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y)) else {
+                    continue;
+                };
+                if x >= EPD_HEIGHT || y >= EPD_WIDTH {
+                    continue;
+                }
+
+                // Rotated a quarter turn, so the HAT's header ends up on the left
+                let (column, line) = (y, EPD_HEIGHT - 1 - x);
+                let byte = &mut self.0[column / 8 + line * EPD_LINE_BYTES];
+                let bit = 0x80 >> (column % 8);
+                if color.is_on() {
+                    *byte &= !bit;
+                } else {
+                    *byte |= bit;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(all(feature = "display", target_os = "linux")))]
+mod screen {
+    use super::DisplayKind;
+
+    pub(super) trait Screen: Send {
+        fn show(&mut self, lines: &[String]) -> Result<(), String>;
+    }
+
+    pub(super) fn open(kind: DisplayKind, address: u16) -> Result<Box<dyn Screen>, String> {
+        Err(format!(
+            "built without display support, can't drive the {:?} at {:#04x}",
+            kind, address
+        ))
+    }
+}
@@ -0,0 +1,379 @@
+//! Resolving the metrics endpoint through flaky links
+//!
+//! On LTE and similar uplinks lookups often fail while data would flow just fine. The
+//! [`CachingResolver`] keeps the addresses every host resolved to for a while, and when looking
+//! a host up again fails it falls back to the last addresses that worked instead of failing the
+//! request.
+//!
+//! Some providers only route IPv6, or hand out resolvers that time out or return nothing. The
+//! resolver can then keep to the addresses of one [`IpFamily`], and send its lookups straight to
+//! another DNS server over UDP instead of going through the system's.
+
+#[cfg(feature = "http")]
+use hyper::client::connect::dns::Name;
+#[cfg(feature = "http")]
+use reqwest::dns::{Addrs, Resolve, Resolving};
+#[cfg(feature = "http")]
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+#[cfg(feature = "http")]
+use tokio::net::UdpSocket;
+
+/// How long resolved addresses are used before looking the host up again, by default
+pub const DEFAULT_DNS_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// The port DNS servers are given at unless another is
+pub const DNS_PORT: u16 = 53;
+
+/// How long a DNS server is waited for before asking again, and how many times it's asked
+#[cfg(feature = "http")]
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+#[cfg(feature = "http")]
+const QUERY_ATTEMPTS: usize = 3;
+
+/// Which addresses to connect to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpFamily {
+    /// Whichever the host resolves to
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for IpFamily {
+    type Err = String;
+
+    fn from_str(family: &str) -> Result<Self, Self::Err> {
+        match family {
+            "any" => Ok(IpFamily::Any),
+            "ipv4" | "4" => Ok(IpFamily::Ipv4),
+            "ipv6" | "6" => Ok(IpFamily::Ipv6),
+            _ => Err(format!(
+                "unknown IP family {}, expected any, ipv4 or ipv6",
+                family
+            )),
+        }
+    }
+}
+
+impl IpFamily {
+    pub fn allows(&self, addr: &IpAddr) -> bool {
+        match self {
+            IpFamily::Any => true,
+            IpFamily::Ipv4 => addr.is_ipv4(),
+            IpFamily::Ipv6 => addr.is_ipv6(),
+        }
+    }
+}
+
+/// Parses a DNS server's address, e.g. `1.1.1.1`, `2001:4860:4860::8888` or `[::1]:5353`,
+/// at port 53 unless another is given
+pub fn parse_server(server: &str) -> Result<SocketAddr, String> {
+    server
+        .parse::<SocketAddr>()
+        .or_else(|_| {
+            server
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        })
+        .map_err(|_| {
+            format!(
+                "invalid DNS server {}, expected an IP address like 1.1.1.1 or [::1]:5353",
+                server
+            )
+        })
+}
+
+#[cfg(feature = "http")]
+struct Cached {
+    addrs: Vec<SocketAddr>,
+    resolved: Instant,
+}
+
+/// A resolver for [`reqwest`] caching the addresses it resolved, see the [module docs](self)
+#[cfg(feature = "http")]
+pub struct CachingResolver {
+    ttl: Duration,
+    family: IpFamily,
+    /// The DNS server to ask instead of the system's resolver
+    server: Option<SocketAddr>,
+    cache: Arc<Mutex<HashMap<String, Cached>>>,
+    failures: Arc<AtomicU64>,
+}
+
+#[cfg(feature = "http")]
+impl CachingResolver {
+    /// Looks hosts up again once their addresses are older than `ttl`, which may be zero to
+    /// look them up every time and only fall back to the last addresses when that fails
+    pub fn new(ttl: Duration) -> Self {
+        CachingResolver {
+            ttl,
+            family: IpFamily::Any,
+            server: None,
+            cache: Arc::default(),
+            failures: Arc::default(),
+        }
+    }
+
+    /// Only resolve hosts to addresses of this family
+    pub fn family(mut self, family: IpFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    /// Ask this DNS server rather than the system's resolver
+    pub fn server(mut self, server: SocketAddr) -> Self {
+        self.server = Some(server);
+        self
+    }
+
+    /// How many lookups failed since the resolver was set up, fallen back on or not
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "http")]
+impl Resolve for CachingResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (ttl, family, server) = (self.ttl, self.family, self.server);
+        let (cache, failures) = (self.cache.clone(), self.failures.clone());
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            let fresh = cache
+                .lock()
+                .expect("DNS cache lock poisoned")
+                .get(&host)
+                .filter(|cached| cached.resolved.elapsed() < ttl)
+                .map(|cached| cached.addrs.clone());
+            if let Some(addrs) = fresh {
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+
+            let resolved = match server {
+                Some(server) => query(server, &host, family).await,
+                None => {
+                    let lookup = host.clone();
+                    tokio::task::spawn_blocking(move || {
+                        (lookup.as_str(), 0)
+                            .to_socket_addrs()
+                            .map(|addrs| addrs.map(|addr| addr.ip()).collect::<Vec<_>>())
+                    })
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::other(err)))
+                }
+            }
+            .and_then(|ips| {
+                let addrs = ips
+                    .into_iter()
+                    .filter(|ip| family.allows(ip))
+                    .map(|ip| SocketAddr::new(ip, 0))
+                    .collect::<Vec<_>>();
+                if addrs.is_empty() {
+                    Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        match family {
+                            IpFamily::Any => "no addresses found",
+                            IpFamily::Ipv4 => "no IPv4 addresses found",
+                            IpFamily::Ipv6 => "no IPv6 addresses found",
+                        },
+                    ))
+                } else {
+                    Ok(addrs)
+                }
+            });
+
+            let mut cache = cache.lock().expect("DNS cache lock poisoned");
+            match resolved {
+                Ok(addrs) => {
+                    cache.insert(
+                        host,
+                        Cached {
+                            addrs: addrs.clone(),
+                            resolved: Instant::now(),
+                        },
+                    );
+                    Ok(Box::new(addrs.into_iter()) as Addrs)
+                }
+                Err(err) => {
+                    let dns_failures = failures.fetch_add(1, Ordering::Relaxed) + 1;
+                    match cache.get(&host) {
+                        Some(cached) => {
+                            tracing::warn!(
+                                dns_failures,
+                                "Unable to resolve {}, using the last addresses it resolved to: {}",
+                                host,
+                                err
+                            );
+                            Ok(Box::new(cached.addrs.clone().into_iter()) as Addrs)
+                        }
+                        None => {
+                            tracing::warn!(dns_failures, "Unable to resolve {}: {}", host, err);
+                            Err(err.into())
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// Looks a host's addresses of a family up with a DNS server, over UDP
+#[cfg(feature = "http")]
+async fn query(server: SocketAddr, host: &str, family: IpFamily) -> io::Result<Vec<IpAddr>> {
+    let socket = UdpSocket::bind(match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    })
+    .await?;
+    socket.connect(server).await?;
+
+    let types: &[u16] = match family {
+        IpFamily::Any => &[TYPE_A, TYPE_AAAA],
+        IpFamily::Ipv4 => &[TYPE_A],
+        IpFamily::Ipv6 => &[TYPE_AAAA],
+    };
+    let mut ips = Vec::new();
+    for (id, kind) in (rand_id()..).zip(types) {
+        let request = encode_query(id, host, *kind)?;
+        let mut response = [0; 1232];
+        let mut answered = None;
+        for _ in 0..QUERY_ATTEMPTS {
+            socket.send(&request).await?;
+            // Datagrams left over from an earlier attempt are told apart by their ID
+            let received = tokio::time::timeout(QUERY_TIMEOUT, async {
+                loop {
+                    let len = socket.recv(&mut response).await?;
+                    if response[..len].starts_with(&id.to_be_bytes()) {
+                        return io::Result::Ok(len);
+                    }
+                }
+            })
+            .await;
+            if let Ok(len) = received {
+                answered = Some(len?);
+                break;
+            }
+        }
+        let len = answered.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("the DNS server {} didn't answer", server),
+            )
+        })?;
+        ips.extend(decode_answers(&response[..len])?);
+    }
+
+    Ok(ips)
+}
+
+#[cfg(feature = "http")]
+const TYPE_A: u16 = 1;
+#[cfg(feature = "http")]
+const TYPE_AAAA: u16 = 28;
+#[cfg(feature = "http")]
+const CLASS_IN: u16 = 1;
+
+/// A query ID that's hard to guess, from the clock rather than another dependency
+#[cfg(feature = "http")]
+fn rand_id() -> u16 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    (nanos ^ (nanos >> 16)) as u16 & 0x7fff
+}
+
+/// A recursive query for one record type of a host
+#[cfg(feature = "http")]
+fn encode_query(id: u16, host: &str, kind: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(host.len() + 18);
+    query.extend(id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend([0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid host name {}", host),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend(label.as_bytes());
+    }
+    query.push(0);
+    query.extend(kind.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+
+    Ok(query)
+}
+
+/// The A and AAAA records answered, following no CNAMEs since servers answer with their targets'
+/// records as well
+#[cfg(feature = "http")]
+fn decode_answers(response: &[u8]) -> io::Result<Vec<IpAddr>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    let u16_at = |at: usize| {
+        response
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(malformed)
+    };
+    // Past a name, made of labels and possibly ending in a pointer to another name
+    let skip_name = |mut at: usize| -> io::Result<usize> {
+        loop {
+            match *response.get(at).ok_or_else(malformed)? {
+                0 => return Ok(at + 1),
+                len if len & 0xc0 == 0xc0 => return Ok(at + 2),
+                len => at += 1 + usize::from(len),
+            }
+        }
+    };
+
+    let flags = u16_at(2)?;
+    match flags & 0x000f {
+        0 => {}
+        // No such name
+        3 => return Ok(Vec::new()),
+        rcode => {
+            return Err(io::Error::other(format!(
+                "the DNS server failed the lookup (rcode {})",
+                rcode
+            )))
+        }
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(at)? + 4;
+    }
+    let mut ips = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(at)?;
+        let (kind, len) = (u16_at(at)?, usize::from(u16_at(at + 8)?));
+        let data = response.get(at + 10..at + 10 + len).ok_or_else(malformed)?;
+        match (kind, <[u8; 4]>::try_from(data), <[u8; 16]>::try_from(data)) {
+            (TYPE_A, Ok(octets), _) => ips.push(IpAddr::from(octets)),
+            (TYPE_AAAA, _, Ok(octets)) => ips.push(IpAddr::from(octets)),
+            _ => {}
+        }
+        at += 10 + len;
+    }
+
+    Ok(ips)
+}
@@ -0,0 +1,194 @@
+//! Errors surfaced by the monitoring pipeline
+
+use crate::gpio;
+use std::{io, path::PathBuf};
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    Sensor(#[from] SensorError),
+
+    #[error(transparent)]
+    Sink(#[from] SinkError),
+
+    #[error("unable to set up GPIO output: {0}")]
+    Output(#[from] gpio::Error),
+
+    #[error("HTTP API error: {0}")]
+    Api(#[from] hyper::Error),
+
+    #[error("terminal error: {0}")]
+    Terminal(#[source] io::Error),
+
+    #[error("unable to drop privileges: {0}")]
+    Privileges(#[source] io::Error),
+
+    #[error("unable to mount the hwmon filesystem: {0}")]
+    Hwmon(#[source] io::Error),
+
+    #[error("unable to start the SNMP agent: {0}")]
+    Snmp(#[source] io::Error),
+
+    #[error("unable to watch the power supply: {0}")]
+    Power(#[source] io::Error),
+}
+
+impl Error {
+    /// Whether repeating the failed operation later could succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Config(_)
+            | Error::Output(_)
+            | Error::Api(_)
+            | Error::Terminal(_)
+            | Error::Privileges(_)
+            | Error::Hwmon(_)
+            | Error::Snmp(_)
+            | Error::Power(_) => false,
+            Error::Sensor(err) => err.is_retryable(),
+            Error::Sink(err) => err.is_retryable(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("sensors config file not found at {} ({source})", path.display())]
+    NotFound { path: PathBuf, source: io::Error },
+
+    #[error("insufficient permissions to read sensors config at {} ({source})", path.display())]
+    PermissionDenied { path: PathBuf, source: io::Error },
+
+    #[error("unable to read sensors config at {} ({source})", path.display())]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("invalid sensors config: {0}")]
+    Parse(#[from] serde_yaml::Error),
+
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+impl ConfigError {
+    pub(crate) fn from_io(path: PathBuf, source: io::Error) -> Self {
+        match source.kind() {
+            io::ErrorKind::NotFound => ConfigError::NotFound { path, source },
+            io::ErrorKind::PermissionDenied => ConfigError::PermissionDenied { path, source },
+            _ => ConfigError::Io { path, source },
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SensorError {
+    #[error("checksum value of the reading is incorrect")]
+    Checksum,
+
+    #[error("timeout reading the sensor value")]
+    Timeout,
+
+    #[error("the read didn't finish within {0:?}, abandoned it")]
+    Stuck(std::time::Duration),
+
+    #[error("an abandoned read of the sensor is still running")]
+    Wedged,
+
+    #[error("the read of the sensor panicked")]
+    Panicked,
+
+    #[error("no valid reading within the cycle's {0:?} deadline")]
+    Deadline(std::time::Duration),
+
+    #[error("problem reading GPIO value: {0}")]
+    Gpio(gpio::Error),
+
+    #[error("plugin failed: {0}")]
+    Plugin(String),
+
+    #[error("serial device failed: {0}")]
+    Serial(String),
+
+    #[error("radio failed: {0}")]
+    Radio(String),
+
+    #[error("unable to read the CPU temperature: {0}")]
+    Cpu(std::io::Error),
+
+    #[error("built without {0} support")]
+    Unsupported(&'static str),
+
+    #[error("implausible {0} of {1}")]
+    Implausible(String, f64),
+
+    /// A failed read recorded in a capture, with its original message
+    #[error("{0}")]
+    Replayed(String),
+}
+
+impl SensorError {
+    /// Checksum errors and timeouts are routine for a DHT22, GPIO access problems and a build
+    /// without the hardware support are not
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, SensorError::Gpio(_) | SensorError::Unsupported(_))
+    }
+}
+
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+impl From<dht22_pi::ReadingError> for SensorError {
+    fn from(err: dht22_pi::ReadingError) -> Self {
+        match err {
+            dht22_pi::ReadingError::Checksum => SensorError::Checksum,
+            dht22_pi::ReadingError::Timeout => SensorError::Timeout,
+            dht22_pi::ReadingError::Gpio(err) => SensorError::Gpio(err),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error("unable to serialize datapoints: {0}")]
+    Serialize(#[from] serde_json::Error),
+
+    #[error("unable to encode datapoints: {0}")]
+    Encode(String),
+
+    #[cfg(feature = "http")]
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+
+    #[error("unauthorized, check the API key ({0})")]
+    Unauthorized(hyper::StatusCode),
+
+    #[error("bad request: {0}")]
+    BadRequest(String),
+
+    #[error("unexpected response status {0}")]
+    Status(hyper::StatusCode),
+
+    #[error("socket error: {0}")]
+    Socket(#[from] io::Error),
+}
+
+impl SinkError {
+    /// Network failures, rate limiting and server errors are worth retrying, rejected
+    /// payloads and credentials are not
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SinkError::Serialize(_)
+            | SinkError::Encode(_)
+            | SinkError::Unauthorized(_)
+            | SinkError::BadRequest(_) => false,
+            #[cfg(feature = "http")]
+            SinkError::Request(err) => !err.is_builder() && !err.is_decode(),
+            SinkError::Socket(_) => true,
+            SinkError::Status(status) => {
+                status.is_server_error() || *status == hyper::StatusCode::TOO_MANY_REQUESTS
+            }
+        }
+    }
+}
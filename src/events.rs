@@ -0,0 +1,84 @@
+//! Notable things happening to the service, worth marking on the graphs: it starting and
+//! stopping, its sensors being changed, failing and recovering, and alerts firing and resolving
+//!
+//! They're published through [`crate::state::State::record_event`] and can be followed with
+//! [`crate::state::State::subscribe_events`].
+
+/// Something that happened to the service
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Started,
+    Stopping,
+    /// Sensors were added, changed or removed over the HTTP API
+    SensorsChanged(String),
+    /// A sensor that was fine failed to read
+    SensorFailing {
+        sensor: String,
+        error: String,
+    },
+    /// A failing sensor read fine again
+    SensorRecovered {
+        sensor: String,
+    },
+    /// A sensor's first reading was outside of what its type can physically report
+    ImpossibleReading {
+        sensor: String,
+        reading: String,
+    },
+    AlertFiring {
+        sensor: String,
+        series: String,
+        value: f64,
+    },
+    AlertResolved {
+        sensor: String,
+        series: String,
+        value: f64,
+    },
+}
+
+impl Event {
+    /// What happened, e.g. `kitchen is failing: timeout reading the sensor value`
+    pub fn text(&self) -> String {
+        match self {
+            Event::Started => "Monitoring started".to_string(),
+            Event::Stopping => "Monitoring stopping".to_string(),
+            Event::SensorsChanged(change) => format!("Sensors changed: {}", change),
+            Event::SensorFailing { sensor, error } => format!("{} is failing: {}", sensor, error),
+            Event::SensorRecovered { sensor } => format!("{} recovered", sensor),
+            Event::ImpossibleReading { sensor, reading } => {
+                format!("{} read an impossible value: {}", sensor, reading)
+            }
+            Event::AlertFiring { series, value, .. } => {
+                format!("Alert on {} is firing (value: {})", series, value)
+            }
+            Event::AlertResolved { series, value, .. } => {
+                format!("Alert on {} resolved (value: {})", series, value)
+            }
+        }
+    }
+
+    /// The kind of event, e.g. `alert`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Event::Started | Event::Stopping => "service",
+            Event::SensorsChanged(_) => "config",
+            Event::SensorFailing { .. }
+            | Event::SensorRecovered { .. }
+            | Event::ImpossibleReading { .. } => "sensor",
+            Event::AlertFiring { .. } | Event::AlertResolved { .. } => "alert",
+        }
+    }
+
+    /// The sensor it happened to, if any
+    pub fn sensor(&self) -> Option<&str> {
+        match self {
+            Event::SensorFailing { sensor, .. }
+            | Event::SensorRecovered { sensor }
+            | Event::ImpossibleReading { sensor, .. }
+            | Event::AlertFiring { sensor, .. }
+            | Event::AlertResolved { sensor, .. } => Some(sensor),
+            _ => None,
+        }
+    }
+}
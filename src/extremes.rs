@@ -0,0 +1,136 @@
+//! Each day's lowest and highest readings, so what the low was last night doesn't need a query
+//!
+//! Every sensor series also gets a `<series>.daily_min` and a `<series>.daily_max` series,
+//! e.g. `kitchen.temperature.daily_min`, holding the lowest and highest reading since midnight.
+//! They're written along with every reading and start over with the first reading of a new day.
+//! Days are counted in the local timezone unless another is given, as `utc` or a fixed offset
+//! like `+02:00`; zones with daylight saving time are picked through the `TZ` environment
+//! variable instead, e.g. `TZ=Europe/Vilnius`. The extremes are only kept in memory, so after a
+//! restart they start over from the first reading.
+
+use crate::{config::Sensor, Datapoint};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use std::{collections::HashMap, str::FromStr};
+
+/// Where the days of the daily extremes start and end
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timezone {
+    /// The system's timezone, or the one `TZ` names
+    #[default]
+    Local,
+    /// A fixed offset from UTC, e.g. `+02:00`
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    fn from_str(timezone: &str) -> Result<Self, Self::Err> {
+        match timezone {
+            "local" => Ok(Timezone::Local),
+            "utc" | "UTC" => Ok(Timezone::Fixed(
+                FixedOffset::east_opt(0).expect("UTC is valid"),
+            )),
+            offset => offset.parse().map(Timezone::Fixed).map_err(|_| {
+                format!(
+                    "unknown timezone {}, expected local, utc or an offset like +02:00",
+                    timezone
+                )
+            }),
+        }
+    }
+}
+
+impl Timezone {
+    /// The day a Unix timestamp falls on
+    fn day(&self, time: i64) -> Option<NaiveDate> {
+        let utc = DateTime::from_timestamp(time, 0)?;
+        Some(match self {
+            Timezone::Local => utc.with_timezone(&chrono::Local).date_naive(),
+            Timezone::Fixed(offset) => utc.with_timezone(offset).date_naive(),
+        })
+    }
+}
+
+/// A series' extremes so far today
+struct Day {
+    date: NaiveDate,
+    min: f64,
+    max: f64,
+}
+
+pub(crate) struct DailyExtremes {
+    timezone: Timezone,
+    /// The sensors' paths followed by a dot, telling their series apart from derived ones
+    prefixes: Vec<String>,
+    days: HashMap<String, Day>,
+}
+
+impl DailyExtremes {
+    pub(crate) fn new(sensors: &[Sensor], timezone: Timezone) -> Self {
+        let prefixes = sensors
+            .iter()
+            .filter(|sensor| !sensor.disabled)
+            .map(|sensor| format!("{}.", sensor.path()))
+            .collect();
+
+        DailyExtremes {
+            timezone,
+            prefixes,
+            days: HashMap::new(),
+        }
+    }
+
+    /// Follows the sensors' series after they've changed, keeping today's extremes of those
+    /// still configured
+    pub(crate) fn update(&mut self, sensors: &[Sensor]) {
+        self.prefixes = DailyExtremes::new(sensors, self.timezone).prefixes;
+        let prefixes = &self.prefixes;
+        self.days
+            .retain(|name, _| prefixes.iter().any(|prefix| name.starts_with(prefix)));
+    }
+
+    /// Folds the readings into their series' extremes, returning the datapoints to write
+    pub(crate) fn track(&mut self, readings: &[Datapoint]) -> Vec<Datapoint> {
+        let mut extremes = Vec::new();
+        for datapoint in readings {
+            let name = &datapoint.name;
+            if !self.prefixes.iter().any(|prefix| name.starts_with(prefix)) {
+                continue;
+            }
+            let Some(date) = self.timezone.day(datapoint.time) else {
+                continue;
+            };
+
+            let value = datapoint.value;
+            let day = self.days.entry(name.clone()).or_insert(Day {
+                date,
+                min: value,
+                max: value,
+            });
+            if day.date < date {
+                *day = Day {
+                    date,
+                    min: value,
+                    max: value,
+                };
+            } else if day.date > date {
+                // A late reading from a day that's already over, e.g. pushed over the API
+                continue;
+            }
+            day.min = day.min.min(value);
+            day.max = day.max.max(value);
+
+            for (suffix, value) in [("daily_min", day.min), ("daily_max", day.max)] {
+                extremes.push(Datapoint {
+                    name: format!("{}.{}", name, suffix),
+                    interval: datapoint.interval,
+                    value,
+                    time: datapoint.time,
+                });
+            }
+        }
+
+        extremes
+    }
+}
@@ -0,0 +1,279 @@
+//! Access to the Raspberry Pi's GPIO pins
+//!
+//! Wraps `rppal` when built with the `gpio` feature for Linux. Otherwise the pins are stubs that
+//! only log what they would have done, so the crate builds and runs on any machine.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+pub use rppal::gpio::Error;
+
+#[cfg(not(all(feature = "gpio", target_os = "linux")))]
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("built without GPIO support")]
+    Unsupported,
+}
+
+/// A handle to the GPIO peripheral
+#[derive(Clone)]
+pub struct Gpio {
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    inner: rppal::gpio::Gpio,
+}
+
+impl Gpio {
+    pub fn new() -> Result<Self, Error> {
+        Ok(Gpio {
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            inner: rppal::gpio::Gpio::new()?,
+        })
+    }
+
+    /// Claims the pin as an output, failing if it's already in use
+    pub fn output(&self, pin: u8) -> Result<OutputPin, Error> {
+        Ok(OutputPin {
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            inner: self.inner.get(pin)?.into_output(),
+            pin,
+        })
+    }
+
+    /// Claims the pin as an input, with its pull-down resistor so that it reads low unless driven
+    pub fn input(&self, pin: u8) -> Result<InputPin, Error> {
+        Ok(InputPin {
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            inner: self.inner.get(pin)?.into_input_pulldown(),
+            pin,
+        })
+    }
+
+    /// Enables the pin's internal pull-up resistor and leaves it on, for sensors that drive the
+    /// pin themselves and so have to claim it on their own
+    pub fn pull_up(&self, pin: u8) -> Result<(), Error> {
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        {
+            let mut input = self.inner.get(pin)?.into_input_pullup();
+            // Dropping the pin would switch the resistor off again
+            input.set_reset_on_drop(false);
+        }
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        tracing::debug!("GPIO {} pulled up (stub)", pin);
+        Ok(())
+    }
+
+    /// Claims the pin as an input counting its falling edges, e.g. of a reed switch closing to
+    /// ground, on an interrupt. Edges within `debounce` of the last one counted are taken for
+    /// the switch bouncing and ignored. A stub never counts a pulse.
+    pub fn pulse_counter(
+        &self,
+        pin: u8,
+        pull_up: bool,
+        debounce: Duration,
+    ) -> Result<PulseCounter, Error> {
+        let pulses = Arc::new(AtomicU64::new(0));
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        let input = {
+            let input = self.inner.get(pin)?;
+            let mut input = match pull_up {
+                true => input.into_input_pullup(),
+                false => input.into_input(),
+            };
+            let pulses = pulses.clone();
+            let mut counted: Option<std::time::Instant> = None;
+            input.set_async_interrupt(rppal::gpio::Trigger::FallingEdge, move |_| {
+                let now = std::time::Instant::now();
+                if counted.is_some_and(|counted| now.duration_since(counted) < debounce) {
+                    return;
+                }
+                counted = Some(now);
+                pulses.fetch_add(1, Ordering::Relaxed);
+            })?;
+            input
+        };
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        tracing::debug!(
+            pull_up,
+            "GPIO {} counting pulses debounced by {:?} (stub)",
+            pin,
+            debounce
+        );
+
+        Ok(PulseCounter {
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            _input: input,
+            pin,
+            pulses,
+        })
+    }
+
+    /// Claims the pin as a PWM output at `frequency` Hz, starting at a 0% duty cycle
+    ///
+    /// Pins 12, 13, 18 and 19 use the hardware PWM channels when they're enabled with the
+    /// `pwm-2chan` overlay, other pins fall back to software PWM, which is jittery and costs
+    /// some CPU above a few hundred Hz.
+    pub fn pwm(&self, pin: u8, frequency: f64) -> Result<PwmPin, Error> {
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        {
+            use rppal::pwm::{Channel, Polarity, Pwm};
+
+            let channel = match pin {
+                12 | 18 => Some(Channel::Pwm0),
+                13 | 19 => Some(Channel::Pwm1),
+                _ => None,
+            };
+            if let Some(channel) = channel {
+                match Pwm::with_frequency(channel, frequency, 0.0, Polarity::Normal, true) {
+                    Ok(pwm) => {
+                        return Ok(PwmPin {
+                            inner: PwmInner::Hardware(pwm),
+                            pin,
+                            frequency,
+                        })
+                    }
+                    Err(err) => tracing::debug!(
+                        "Hardware PWM unavailable on GPIO {} ({}), using software PWM",
+                        pin,
+                        err
+                    ),
+                }
+            }
+
+            let mut output = self.inner.get(pin)?.into_output_low();
+            output.set_pwm_frequency(frequency, 0.0)?;
+            Ok(PwmPin {
+                inner: PwmInner::Software(output),
+                pin,
+                frequency,
+            })
+        }
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        Ok(PwmPin { pin, frequency })
+    }
+}
+
+/// A GPIO pin configured as an output, returned to its previous state when dropped
+pub struct OutputPin {
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    inner: rppal::gpio::OutputPin,
+    pin: u8,
+}
+
+impl OutputPin {
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    pub fn set_high(&mut self) {
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        self.inner.set_high();
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        tracing::debug!("GPIO {} set high (stub)", self.pin);
+    }
+
+    pub fn set_low(&mut self) {
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        self.inner.set_low();
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        tracing::debug!("GPIO {} set low (stub)", self.pin);
+    }
+}
+
+/// A GPIO pin configured as an input
+pub struct InputPin {
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    inner: rppal::gpio::InputPin,
+    pin: u8,
+}
+
+impl InputPin {
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// Whether the pin reads high, which a stub never does
+    pub fn is_high(&self) -> bool {
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        return self.inner.is_high();
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        false
+    }
+}
+
+/// A GPIO pin counting pulses, which stops counting when dropped
+pub struct PulseCounter {
+    /// Held on to for its interrupt, which is cleared when the pin is dropped
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    _input: rppal::gpio::InputPin,
+    pin: u8,
+    pulses: Arc<AtomicU64>,
+}
+
+impl PulseCounter {
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// How many pulses were counted since the pin was claimed
+    pub fn count(&self) -> u64 {
+        self.pulses.load(Ordering::Relaxed)
+    }
+}
+
+/// A GPIO pin driven with a PWM signal, switched off when dropped
+pub struct PwmPin {
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    inner: PwmInner,
+    pin: u8,
+    frequency: f64,
+}
+
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+enum PwmInner {
+    Hardware(rppal::pwm::Pwm),
+    Software(rppal::gpio::OutputPin),
+}
+
+impl PwmPin {
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// Sets the share of every period the pin is high, from 0.0 to 1.0
+    pub fn set_duty_cycle(&mut self, duty_cycle: f64) {
+        let duty_cycle = duty_cycle.clamp(0.0, 1.0);
+        #[cfg(all(feature = "gpio", target_os = "linux"))]
+        let result = match &mut self.inner {
+            PwmInner::Hardware(pwm) => pwm
+                .set_duty_cycle(duty_cycle)
+                .map_err(|err| err.to_string()),
+            PwmInner::Software(output) => output
+                .set_pwm_frequency(self.frequency, duty_cycle)
+                .map_err(|err| err.to_string()),
+        };
+        #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+        let result: Result<(), String> = {
+            tracing::debug!(
+                "GPIO {} PWM at {} Hz set to {:.0}% (stub)",
+                self.pin,
+                self.frequency,
+                duty_cycle * 100.0
+            );
+            Ok(())
+        };
+
+        if let Err(err) = result {
+            tracing::warn!(
+                "Failed to set the PWM duty cycle of GPIO {}: {}",
+                self.pin,
+                err
+            );
+        }
+    }
+}
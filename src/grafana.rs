@@ -0,0 +1,140 @@
+//! Generating a Grafana dashboard for the configured sensors, ready to import
+//!
+//! Every sensor gets a row with a time series panel per metric it's known to write, reading the
+//! same Graphite series the service posts. Alert thresholds show as lines on their metric's
+//! panel, and annotations tagged `monitoring` are overlaid on every panel.
+
+use crate::config::{self, Sensor};
+use serde_json::{json, Value};
+
+/// Panels per row, each half the dashboard's width
+const PANELS_PER_ROW: usize = 2;
+const PANEL_WIDTH: usize = 12;
+const PANEL_HEIGHT: usize = 8;
+
+/// The tag of the annotations overlaid on the panels
+pub const ANNOTATION_TAG: &str = "monitoring";
+
+/// A dashboard titled `title` for the enabled sensors, querying the Graphite data source with
+/// the given UID (default: picked on import)
+pub fn dashboard(sensors: &[Sensor], title: &str, datasource: Option<&str>) -> Value {
+    let datasource = json!({
+        "type": "graphite",
+        "uid": datasource.unwrap_or("${datasource}"),
+    });
+
+    let mut panels = Vec::new();
+    let mut y = 0;
+    for sensor in sensors.iter().filter(|sensor| !sensor.disabled) {
+        panels.push(json!({
+            "type": "row",
+            "title": sensor.name,
+            "collapsed": false,
+            "gridPos": {"h": 1, "w": 24, "x": 0, "y": y},
+            "panels": [],
+        }));
+        y += 1;
+
+        // Plugins and JSON formatted sensors only tell which metrics they write when they do
+        let metrics = config::known_metrics(sensor);
+        let targets = match metrics.is_empty() {
+            true => vec![None],
+            false => metrics.iter().map(Some).collect(),
+        };
+        for (index, metric) in targets.into_iter().enumerate() {
+            let x = index % PANELS_PER_ROW * PANEL_WIDTH;
+            if index > 0 && x == 0 {
+                y += PANEL_HEIGHT;
+            }
+            panels.push(panel(sensor, metric.map(String::as_str), &datasource, x, y));
+        }
+        y += PANEL_HEIGHT;
+    }
+    for (id, panel) in panels.iter_mut().enumerate() {
+        panel["id"] = json!(id + 1);
+    }
+
+    let mut templating = Vec::new();
+    if datasource["uid"] == "${datasource}" {
+        templating.push(json!({
+            "name": "datasource",
+            "label": "Graphite",
+            "type": "datasource",
+            "query": "graphite",
+        }));
+    }
+
+    json!({
+        "title": title,
+        "tags": [ANNOTATION_TAG],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "1m",
+        "time": {"from": "now-24h", "to": "now"},
+        "templating": {"list": templating},
+        "annotations": {
+            "list": [{
+                "name": "Monitoring events",
+                "datasource": {"type": "grafana", "uid": "-- Grafana --"},
+                "enable": true,
+                "iconColor": "orange",
+                "target": {
+                    "type": "tags",
+                    "tags": [ANNOTATION_TAG],
+                    "limit": 100,
+                    "matchAny": false,
+                },
+            }],
+        },
+        "panels": panels,
+    })
+}
+
+/// A time series panel of one of the sensor's metrics, or of all of them if they aren't known
+fn panel(sensor: &Sensor, metric: Option<&str>, datasource: &Value, x: usize, y: usize) -> Value {
+    let (title, target) = match metric {
+        Some(metric) => (format!("{} {}", sensor.name, metric), sensor.series(metric)),
+        None => (sensor.name.clone(), format!("{}.*", sensor.path())),
+    };
+    let unit = match metric {
+        Some("temperature") => "celsius",
+        Some("humidity") => "humidity",
+        _ => "none",
+    };
+
+    let mut steps = vec![json!({"color": "green", "value": null})];
+    for alert in sensor
+        .alerts
+        .iter()
+        .filter(|alert| Some(alert.metric.as_str()) == metric)
+    {
+        if let Some(below) = alert.below {
+            steps[0] = json!({"color": "blue", "value": null});
+            steps.push(json!({"color": "green", "value": below}));
+        }
+        if let Some(above) = alert.above {
+            steps.push(json!({"color": "red", "value": above}));
+        }
+    }
+    let show_thresholds = steps.len() > 1;
+
+    json!({
+        "type": "timeseries",
+        "title": title,
+        "datasource": datasource,
+        "gridPos": {"h": PANEL_HEIGHT, "w": PANEL_WIDTH, "x": x, "y": y},
+        "targets": [{"refId": "A", "target": target}],
+        "fieldConfig": {
+            "defaults": {
+                "unit": unit,
+                "thresholds": {"mode": "absolute", "steps": steps},
+                "custom": {
+                    "thresholdsStyle": {
+                        "mode": if show_thresholds { "line" } else { "off" },
+                    },
+                },
+            },
+            "overrides": [],
+        },
+    })
+}
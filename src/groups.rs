@@ -0,0 +1,147 @@
+//! Checking co-located sensors against each other, to catch one drifting or dying before its
+//! readings are trusted for too long
+//!
+//! Sensors sharing a `group` are expected to read about the same. Whenever one of them is read,
+//! the spread between the group members' latest readings of each metric with a
+//! `max_divergence` is checked against it, warning once when it's exceeded and again once the
+//! readings agree. The spread can also be written as a `<group>.<metric>.divergence` series.
+
+use crate::{config::Sensor, Datapoint};
+use std::collections::{BTreeMap, HashSet};
+
+pub(crate) struct Groups {
+    groups: Vec<Group>,
+    /// The latest `(time, value)` of every grouped series
+    latest: BTreeMap<String, (i64, f64)>,
+    write_divergence: bool,
+}
+
+struct Group {
+    name: String,
+    /// The members' paths, see [`Sensor::path`]
+    members: Vec<String>,
+    /// The greatest spread allowed per metric, the lowest any member allows
+    thresholds: BTreeMap<String, f64>,
+    diverging: HashSet<String>,
+}
+
+impl Groups {
+    pub(crate) fn new(sensors: &[Sensor], write_divergence: bool) -> Self {
+        let mut groups = Vec::<Group>::new();
+        for sensor in sensors.iter().filter(|sensor| !sensor.disabled) {
+            let Some(name) = &sensor.group else {
+                continue;
+            };
+            let index = match groups.iter().position(|group| &group.name == name) {
+                Some(index) => index,
+                None => {
+                    groups.push(Group {
+                        name: name.clone(),
+                        members: Vec::new(),
+                        thresholds: BTreeMap::new(),
+                        diverging: HashSet::new(),
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            group.members.push(sensor.path());
+            for (metric, threshold) in &sensor.max_divergence {
+                let lowest = group.thresholds.entry(metric.clone()).or_insert(*threshold);
+                *lowest = lowest.min(*threshold);
+            }
+        }
+        // A group of one has nothing to be compared with
+        groups.retain(|group| group.members.len() > 1);
+
+        Groups {
+            groups,
+            latest: BTreeMap::new(),
+            write_divergence,
+        }
+    }
+
+    /// Regroups the sensors after they've changed, still warning only once for the groups already
+    /// disagreeing
+    pub(crate) fn update(&mut self, sensors: &[Sensor]) {
+        let mut groups = Groups::new(sensors, self.write_divergence);
+        for group in &mut groups.groups {
+            if let Some(previous) = self
+                .groups
+                .iter_mut()
+                .find(|known| known.name == group.name)
+            {
+                group.diverging = std::mem::take(&mut previous.diverging);
+            }
+        }
+        groups.latest = std::mem::take(&mut self.latest);
+        *self = groups;
+    }
+
+    /// Checks the groups of the sensors just read, returning the divergence datapoints to write
+    pub(crate) fn check(&mut self, readings: &[Datapoint]) -> Vec<Datapoint> {
+        let mut divergence = Vec::new();
+        if self.groups.is_empty() {
+            return divergence;
+        }
+
+        for datapoint in readings {
+            self.latest
+                .insert(datapoint.name.clone(), (datapoint.time, datapoint.value));
+        }
+
+        for group in &mut self.groups {
+            for (metric, threshold) in &group.thresholds {
+                let suffix = format!(".{}", metric);
+                let Some(read) = readings.iter().find(|datapoint| {
+                    datapoint
+                        .name
+                        .strip_suffix(&suffix)
+                        .is_some_and(|path| group.members.iter().any(|member| member == path))
+                }) else {
+                    continue;
+                };
+
+                // Members that stopped reporting aren't held against the others
+                let recent = read.time - 2 * i64::from(read.interval.max(1));
+                let values = group
+                    .members
+                    .iter()
+                    .filter_map(|member| self.latest.get(&format!("{}{}", member, suffix)))
+                    .filter(|(time, _)| *time >= recent)
+                    .map(|(_, value)| *value)
+                    .collect::<Vec<_>>();
+                if values.len() < 2 {
+                    continue;
+                }
+                let spread = values.iter().copied().fold(f64::MIN, f64::max)
+                    - values.iter().copied().fold(f64::MAX, f64::min);
+
+                let diverging = spread > *threshold;
+                if diverging && group.diverging.insert(metric.clone()) {
+                    tracing::warn!(
+                        group = %group.name,
+                        "Sensors in {} disagree on {} by {:.2}, more than {}",
+                        group.name,
+                        metric,
+                        spread,
+                        threshold
+                    );
+                } else if !diverging && group.diverging.remove(metric) {
+                    tracing::info!(group = %group.name, "Sensors in {} agree on {} again", group.name, metric);
+                }
+
+                if self.write_divergence {
+                    divergence.push(Datapoint {
+                        name: format!("{}.{}.divergence", group.name, metric),
+                        interval: read.interval,
+                        value: spread,
+                        time: read.time,
+                    });
+                }
+            }
+        }
+
+        divergence
+    }
+}
@@ -0,0 +1,42 @@
+//! Telling a dead agent apart from dead sensors
+//!
+//! Every cycle the service writes a `monitor.heartbeat` series with the value 1, whether or not
+//! any sensor could be read. An alert on the heartbeat going missing then means the agent,
+//! the Pi or its network is down, while a sensor's series going missing with the heartbeat
+//! still there means just that sensor is.
+
+use crate::Datapoint;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, watch};
+
+/// The name of the heartbeat series
+pub const HEARTBEAT_SERIES: &str = "monitor.heartbeat";
+
+/// Writes the heartbeat every `refresh` seconds until shutdown
+pub(crate) async fn run(
+    refresh: i32,
+    sender: mpsc::Sender<Vec<Datapoint>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let period = Duration::from_secs(u64::try_from(refresh).unwrap_or_default());
+    let mut interval = tokio::time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time behind Unix epoch time")
+            .as_secs() as i64;
+        let heartbeat = Datapoint {
+            name: HEARTBEAT_SERIES.to_string(),
+            interval: refresh,
+            value: 1.0,
+            time: now,
+        };
+        // Like the sensors' readings, left out rather than waited on when the queue is full
+        let _ = sender.try_send(vec![heartbeat]);
+    }
+}
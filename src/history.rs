@@ -0,0 +1,102 @@
+//! Recent readings kept in memory, so the dashboard and the `/history` API can show trends
+//! without a round trip to Graphite
+
+use crate::Datapoint;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::RwLock,
+};
+
+/// How many readings are kept per series by default - a day's worth at one reading a minute
+pub const DEFAULT_HISTORY_POINTS: usize = 1440;
+
+pub struct History {
+    capacity: usize,
+    series: RwLock<BTreeMap<String, VecDeque<(i64, f64)>>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_POINTS)
+    }
+}
+
+impl History {
+    /// Keeps up to `capacity` readings per series, forgetting the oldest ones first
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            series: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, datapoints: &[Datapoint]) {
+        let mut series = self.series.write().expect("History lock poisoned");
+        for datapoint in datapoints {
+            // The name's only copied for a new series, which gets all of its room up front
+            let points = match series.get_mut(&datapoint.name) {
+                Some(points) => points,
+                None => series
+                    .entry(datapoint.name.clone())
+                    .or_insert_with(|| VecDeque::with_capacity(self.capacity)),
+            };
+            if points.len() == self.capacity {
+                points.pop_front();
+            }
+            points.push_back((datapoint.time, datapoint.value));
+        }
+    }
+
+    /// How many series have been recorded
+    pub fn len(&self) -> usize {
+        self.series.read().expect("History lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The retained `(time, value)` readings of a series, e.g. `kitchen.temperature`, oldest first
+    pub fn series(&self, name: &str) -> Vec<(i64, f64)> {
+        self.series
+            .read()
+            .expect("History lock poisoned")
+            .get(name)
+            .map(|points| points.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The readings of a series between `from` and `to` (inclusive Unix timestamps), averaged
+    /// into buckets of `step` seconds if given. Buckets are aligned to multiples of `step` and
+    /// timestamped with their start.
+    pub fn range(&self, name: &str, from: i64, to: i64, step: Option<i64>) -> Vec<(i64, f64)> {
+        let series = self.series.read().expect("History lock poisoned");
+        let points = series
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|(time, _)| (from..=to).contains(time));
+
+        let step = match step {
+            Some(step) if step > 0 => step,
+            _ => return points.copied().collect(),
+        };
+
+        let mut buckets: Vec<(i64, f64, u32)> = Vec::new();
+        for (time, value) in points {
+            let start = time - time.rem_euclid(step);
+            match buckets.last_mut() {
+                Some((bucket, sum, count)) if *bucket == start => {
+                    *sum += value;
+                    *count += 1;
+                }
+                _ => buckets.push((start, *value, 1)),
+            }
+        }
+
+        buckets
+            .into_iter()
+            .map(|(start, sum, count)| (start, sum / f64::from(count)))
+            .collect()
+    }
+}
@@ -0,0 +1,185 @@
+//! Running the sensors' own commands on their events, for local automation nothing built in
+//! covers
+//!
+//! A sensor's `on_reading`, `on_alert` and `on_sensor_failure` commands are run with the event
+//! as a JSON object on stdin and the `MONITORING_SENSOR` environment variable set to the
+//! sensor's name:
+//!
+//! ```text
+//! {"event": "reading", "sensor": "kitchen", "time": 1700000000, "values": {"temperature": 21.5}}
+//! {"event": "alert_firing", "sensor": "kitchen", "series": "kitchen.temperature", "value": 28.4}
+//! {"event": "sensor_failing", "sensor": "kitchen", "error": "timeout reading the sensor value"}
+//! ```
+//!
+//! Alerts run `on_alert` both when firing and when resolved (`alert_resolved`). Hooks run in the
+//! background, one process per event, and are killed after [`HOOK_TIMEOUT`]; a failing hook is
+//! only logged.
+//!
+//! The hooks are those of the sensors configured at the time of the event, so sensors added or
+//! renamed over the HTTP API run theirs too.
+
+use crate::{config::Sensor, events::Event, manager::SensorManager, Datapoint};
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    process::Command,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch,
+    },
+    task::JoinSet,
+};
+
+/// How long a hook may run before it's killed
+pub const HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs the hooks of the readings and events until shutdown, then waits for those still running
+pub(crate) async fn run(
+    manager: &SensorManager,
+    mut readings: broadcast::Receiver<Vec<Datapoint>>,
+    mut events: broadcast::Receiver<Event>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut running = JoinSet::new();
+    loop {
+        tokio::select! {
+            batch = readings.recv() => match batch {
+                Ok(batch) => {
+                    for sensor in manager.sensors().await {
+                        if let Some(command) = &sensor.on_reading {
+                            if let Some(event) = reading(&sensor, &batch) {
+                                running.spawn(execute(sensor.name.clone(), command.clone(), event));
+                            }
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "Hooks fell behind, leaving out some readings");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let Some(name) = event.sensor() else {
+                        continue;
+                    };
+                    let sensors = manager.sensors().await;
+                    let Some(sensor) = sensors.iter().find(|sensor| sensor.name == name) else {
+                        continue;
+                    };
+                    let command = match event {
+                        Event::AlertFiring { .. } | Event::AlertResolved { .. } => &sensor.on_alert,
+                        Event::SensorFailing { .. } => &sensor.on_sensor_failure,
+                        _ => &None,
+                    };
+                    if let Some(command) = command {
+                        running.spawn(execute(sensor.name.clone(), command.clone(), json(&event)));
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "Hooks fell behind, leaving out some events");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Reaped as they finish, so the set doesn't grow with every event
+            Some(_) = running.join_next(), if !running.is_empty() => {}
+            // Without holding on to the value, which can't be held across the awaits above
+            _ = async { let _ = shutdown.wait_for(|stop| *stop).await; } => break,
+        }
+    }
+
+    while running.join_next().await.is_some() {}
+}
+
+/// The sensor's reading in a batch, if it's in there
+fn reading(sensor: &Sensor, batch: &[Datapoint]) -> Option<serde_json::Value> {
+    let prefix = format!("{}.", sensor.path());
+    let mut time = None;
+    let values = batch
+        .iter()
+        .filter_map(|datapoint| {
+            let metric = datapoint.name.strip_prefix(&prefix)?;
+            time = Some(datapoint.time);
+            Some((metric.to_string(), serde_json::json!(datapoint.value)))
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Some(serde_json::json!({
+        "event": "reading",
+        "sensor": sensor.name,
+        "time": time?,
+        "values": values,
+    }))
+}
+
+fn json(event: &Event) -> serde_json::Value {
+    match event {
+        Event::AlertFiring {
+            sensor,
+            series,
+            value,
+        } => serde_json::json!({
+            "event": "alert_firing",
+            "sensor": sensor,
+            "series": series,
+            "value": value,
+        }),
+        Event::AlertResolved {
+            sensor,
+            series,
+            value,
+        } => serde_json::json!({
+            "event": "alert_resolved",
+            "sensor": sensor,
+            "series": series,
+            "value": value,
+        }),
+        Event::SensorFailing { sensor, error } => serde_json::json!({
+            "event": "sensor_failing",
+            "sensor": sensor,
+            "error": error,
+        }),
+        _ => serde_json::json!({ "event": event.kind(), "text": event.text() }),
+    }
+}
+
+async fn execute(sensor: String, command: Vec<String>, event: serde_json::Value) {
+    let Some((program, args)) = command.split_first() else {
+        return;
+    };
+    let child = Command::new(program)
+        .args(args)
+        .env("MONITORING_SENSOR", &sensor)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(err) => {
+            tracing::warn!(%sensor, "Unable to start the hook {}: {}", program, err);
+            return;
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that doesn't read its stdin still gets to run
+        let _ = stdin.write_all(event.to_string().as_bytes()).await;
+    }
+
+    match tokio::time::timeout(HOOK_TIMEOUT, child.wait_with_output()).await {
+        Ok(Ok(output)) if output.status.success() => {}
+        Ok(Ok(output)) => tracing::warn!(
+            %sensor,
+            "The hook {} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Ok(Err(err)) => tracing::warn!(%sensor, "The hook {} failed: {}", program, err),
+        Err(_) => {
+            tracing::warn!(%sensor, "The hook {} took over {:?}, killed it", program, HOOK_TIMEOUT)
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! Exposing the latest readings in the Linux hwmon layout through a small FUSE filesystem, for
+//! local tools that already know how to read hwmon devices
+//!
+//! Every sensor becomes a `hwmon<N>` directory in the order of `/readings`, holding its `name`
+//! and, once it's been read, its `temp1_input` in millidegrees Celsius and `humidity1_input` in
+//! thousandths of a percent, like the kernel's own drivers:
+//!
+//! ```text
+//! /run/monitoring/hwmon/hwmon0/name             kitchen
+//! /run/monitoring/hwmon/hwmon0/temp1_input      21400
+//! /run/monitoring/hwmon/hwmon0/humidity1_input  40200
+//! ```
+//!
+//! The filesystem speaks the kernel's FUSE protocol itself, without libfuse. Mounting it needs
+//! root (or `CAP_SYS_ADMIN`), and it's unmounted when the service stops.
+
+use crate::state::{SensorState, State};
+use std::{
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+
+/// The protocol version answered to the kernel's `INIT`
+const FUSE_MAJOR: u32 = 7;
+const FUSE_MINOR: u32 = 31;
+
+/// Reads go through to the filesystem every time rather than the page cache
+const FOPEN_DIRECT_IO: u32 = 1;
+
+const ROOT: u64 = 1;
+/// Inodes per sensor: its directory and its files
+const INODES_PER_SENSOR: u64 = 4;
+/// How long the kernel may cache entries and attributes, in seconds
+const TTL_SECS: u64 = 1;
+
+/// The files of a sensor's directory, with the metric they show and its scale
+const FILES: [(&str, Option<&str>); 3] = [
+    ("name", None),
+    ("temp1_input", Some("temperature")),
+    ("humidity1_input", Some("humidity")),
+];
+
+/// The mounted filesystem, unmounted when dropped
+pub struct HwmonMount {
+    path: PathBuf,
+}
+
+impl HwmonMount {
+    /// Mounts the filesystem on an existing directory and serves it from a thread of its own
+    pub fn mount(path: impl Into<PathBuf>, state: Arc<State>) -> io::Result<Self> {
+        let path = path.into();
+        let device = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/fuse")?;
+
+        let target = c_path(&path)?;
+        let options = CString::new(format!(
+            "fd={},rootmode=40000,user_id=0,group_id=0,default_permissions,allow_other",
+            device.as_raw_fd()
+        ))?;
+        // SAFETY: every pointer is to a NUL terminated string outliving the call
+        let mounted = unsafe {
+            libc::mount(
+                c"monitoring".as_ptr(),
+                target.as_ptr(),
+                c"fuse.monitoring".as_ptr(),
+                libc::MS_NOSUID | libc::MS_NODEV,
+                options.as_ptr().cast(),
+            )
+        };
+        if mounted != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        std::thread::Builder::new()
+            .name("hwmon".to_string())
+            .spawn(move || serve(device, &state))?;
+
+        Ok(HwmonMount { path })
+    }
+}
+
+impl Drop for HwmonMount {
+    fn drop(&mut self) {
+        // Detached, so a tool still holding a file open can't keep it mounted
+        let unmounted = c_path(&self.path)
+            // SAFETY: the path is a NUL terminated string outliving the call
+            .map(|target| unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } == 0);
+        if !matches!(unmounted, Ok(true)) {
+            tracing::warn!(
+                "Unable to unmount the hwmon filesystem at {}: {}",
+                self.path.display(),
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Answers the kernel's requests until the filesystem is unmounted
+fn serve(mut device: File, state: &State) {
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        let len = match device.read(&mut buffer) {
+            Ok(len) => len,
+            // Interrupted, or the request was abandoned before it was read
+            Err(err) if matches!(err.raw_os_error(), Some(libc::EINTR | libc::ENOENT)) => continue,
+            // Unmounted
+            Err(_) => return,
+        };
+        if len < 40 {
+            continue;
+        }
+
+        let request = &buffer[..len];
+        let opcode = u32_at(request, 4);
+        let unique = u64_at(request, 8);
+        let node = u64_at(request, 16);
+        let body = &request[40..];
+
+        let reply = match opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => continue,
+            FUSE_INIT => Ok(init()),
+            FUSE_DESTROY | FUSE_RELEASE | FUSE_RELEASEDIR | FUSE_FLUSH | FUSE_ACCESS => {
+                Ok(Vec::new())
+            }
+            FUSE_STATFS => Ok(vec![0; 80]),
+            FUSE_LOOKUP => lookup(&state.snapshot(), node, body),
+            FUSE_GETATTR => getattr(&state.snapshot(), node),
+            FUSE_OPEN | FUSE_OPENDIR => Ok(open()),
+            FUSE_READ => read(&state.snapshot(), node, body),
+            FUSE_READDIR => readdir(&state.snapshot(), node, body),
+            _ => Err(libc::ENOSYS),
+        };
+
+        let (error, payload) = match reply {
+            Ok(payload) => (0, payload),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let mut message = Vec::with_capacity(16 + payload.len());
+        put_u32(&mut message, (16 + payload.len()) as u32);
+        put_u32(&mut message, error as u32);
+        put_u64(&mut message, unique);
+        message.extend_from_slice(&payload);
+        // Each reply has to be a single write; a failed one only fails its request
+        let _ = device.write(&message);
+
+        if opcode == FUSE_DESTROY {
+            return;
+        }
+    }
+}
+
+enum Node {
+    Root,
+    Directory(usize),
+    File(usize, usize),
+}
+
+impl Node {
+    fn parse(inode: u64) -> Option<Node> {
+        match inode {
+            0 => None,
+            ROOT => Some(Node::Root),
+            _ => {
+                let sensor = usize::try_from((inode - 2) / INODES_PER_SENSOR).ok()?;
+                match ((inode - 2) % INODES_PER_SENSOR) as usize {
+                    0 => Some(Node::Directory(sensor)),
+                    file => Some(Node::File(sensor, file - 1)),
+                }
+            }
+        }
+    }
+
+    fn inode(&self) -> u64 {
+        match self {
+            Node::Root => ROOT,
+            Node::Directory(sensor) => 2 + *sensor as u64 * INODES_PER_SENSOR,
+            Node::File(sensor, file) => 2 + *sensor as u64 * INODES_PER_SENSOR + 1 + *file as u64,
+        }
+    }
+}
+
+/// A sensor's file contents, if it has that file
+fn contents(sensor: &SensorState, file: usize) -> Option<String> {
+    match FILES.get(file)? {
+        (_, None) => Some(format!("{}\n", sensor.sensor)),
+        (_, Some(metric)) => sensor
+            .values
+            .get(*metric)
+            .map(|value| format!("{}\n", (value * 1000.0).round() as i64)),
+    }
+}
+
+/// The entries of a directory, with their nodes and whether they're directories
+fn entries(sensors: &[SensorState], node: &Node) -> Option<Vec<(String, Node, bool)>> {
+    match node {
+        Node::Root => Some(
+            (0..sensors.len())
+                .map(|index| (format!("hwmon{}", index), Node::Directory(index), true))
+                .collect(),
+        ),
+        Node::Directory(index) => {
+            let sensor = sensors.get(*index)?;
+            Some(
+                FILES
+                    .iter()
+                    .enumerate()
+                    .filter(|(file, _)| contents(sensor, *file).is_some())
+                    .map(|(file, (name, _))| (name.to_string(), Node::File(*index, file), false))
+                    .collect(),
+            )
+        }
+        Node::File(..) => None,
+    }
+}
+
+fn exists(sensors: &[SensorState], node: &Node) -> bool {
+    match node {
+        Node::Root => true,
+        Node::Directory(index) => *index < sensors.len(),
+        Node::File(index, file) => sensors
+            .get(*index)
+            .is_some_and(|sensor| contents(sensor, *file).is_some()),
+    }
+}
+
+fn init() -> Vec<u8> {
+    let mut out = Vec::with_capacity(64);
+    put_u32(&mut out, FUSE_MAJOR);
+    put_u32(&mut out, FUSE_MINOR);
+    put_u32(&mut out, 0); // max_readahead
+    put_u32(&mut out, 0); // flags
+    out.extend_from_slice(&16u16.to_ne_bytes()); // max_background
+    out.extend_from_slice(&12u16.to_ne_bytes()); // congestion_threshold
+    put_u32(&mut out, 4096); // max_write
+    put_u32(&mut out, 1); // time_gran
+    out.resize(64, 0);
+    out
+}
+
+fn lookup(sensors: &[SensorState], parent: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+    let name = body.split(|byte| *byte == 0).next().unwrap_or_default();
+    let parent = Node::parse(parent).ok_or(libc::ENOENT)?;
+    let (_, node, _) = entries(sensors, &parent)
+        .ok_or(libc::ENOTDIR)?
+        .into_iter()
+        .find(|(entry, _, _)| entry.as_bytes() == name)
+        .ok_or(libc::ENOENT)?;
+
+    let mut out = Vec::with_capacity(128);
+    put_u64(&mut out, node.inode());
+    put_u64(&mut out, 0); // generation
+    put_u64(&mut out, TTL_SECS); // entry_valid
+    put_u64(&mut out, TTL_SECS); // attr_valid
+    put_u32(&mut out, 0);
+    put_u32(&mut out, 0);
+    put_attr(&mut out, &node);
+    Ok(out)
+}
+
+fn getattr(sensors: &[SensorState], inode: u64) -> Result<Vec<u8>, i32> {
+    let node = Node::parse(inode)
+        .filter(|node| exists(sensors, node))
+        .ok_or(libc::ENOENT)?;
+
+    let mut out = Vec::with_capacity(104);
+    put_u64(&mut out, TTL_SECS); // attr_valid
+    put_u32(&mut out, 0);
+    put_u32(&mut out, 0);
+    put_attr(&mut out, &node);
+    Ok(out)
+}
+
+fn put_attr(out: &mut Vec<u8>, node: &Node) {
+    let (mode, links) = match node {
+        Node::File(..) => (libc::S_IFREG | 0o444, 1),
+        _ => (libc::S_IFDIR | 0o555, 2),
+    };
+    put_u64(out, node.inode());
+    // What sysfs reports for its attributes, whose real size isn't known up front
+    put_u64(out, if links == 1 { 4096 } else { 0 });
+    put_u64(out, 0); // blocks
+    out.resize(out.len() + 3 * 8 + 3 * 4, 0); // atime, mtime, ctime
+    put_u32(out, mode);
+    put_u32(out, links);
+    put_u32(out, 0); // uid
+    put_u32(out, 0); // gid
+    put_u32(out, 0); // rdev
+    put_u32(out, 4096); // blksize
+    put_u32(out, 0); // flags
+}
+
+fn open() -> Vec<u8> {
+    let mut out = Vec::with_capacity(16);
+    put_u64(&mut out, 0); // fh
+    put_u32(&mut out, FOPEN_DIRECT_IO);
+    put_u32(&mut out, 0);
+    out
+}
+
+fn read(sensors: &[SensorState], inode: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+    let Some(Node::File(index, file)) = Node::parse(inode) else {
+        return Err(libc::EISDIR);
+    };
+    let contents = sensors
+        .get(index)
+        .and_then(|sensor| contents(sensor, file))
+        .ok_or(libc::ENODATA)?;
+
+    let (offset, size) = (u64_at(body, 8) as usize, u32_at(body, 16) as usize);
+    let bytes = contents.as_bytes();
+    let start = offset.min(bytes.len());
+    Ok(bytes[start..(start + size).min(bytes.len())].to_vec())
+}
+
+fn readdir(sensors: &[SensorState], inode: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+    let node = Node::parse(inode).ok_or(libc::ENOENT)?;
+    let children = entries(sensors, &node).ok_or(libc::ENOTDIR)?;
+    let (offset, size) = (u64_at(body, 8) as usize, u32_at(body, 16) as usize);
+
+    let parent = match node {
+        Node::Directory(_) => ROOT,
+        _ => node.inode(),
+    };
+    let all = [
+        (".".to_string(), node.inode(), true),
+        ("..".to_string(), parent, true),
+    ]
+    .into_iter()
+    .chain(
+        children
+            .into_iter()
+            .map(|(name, node, directory)| (name, node.inode(), directory)),
+    );
+
+    let mut out = Vec::new();
+    for (index, (name, inode, directory)) in all.enumerate().skip(offset) {
+        let padded = (24 + name.len()).next_multiple_of(8);
+        if out.len() + padded > size {
+            break;
+        }
+        put_u64(&mut out, inode);
+        put_u64(&mut out, index as u64 + 1); // the offset of the next entry
+        put_u32(&mut out, name.len() as u32);
+        put_u32(&mut out, if directory { 4 } else { 8 }); // DT_DIR or DT_REG
+        out.extend_from_slice(name.as_bytes());
+        out.resize(out.len() + padded - 24 - name.len(), 0);
+    }
+    Ok(out)
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    bytes.get(at..at + 4).map_or(0, |bytes| {
+        u32::from_ne_bytes(bytes.try_into().expect("4 bytes"))
+    })
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    bytes.get(at..at + 8).map_or(0, |bytes| {
+        u64::from_ne_bytes(bytes.try_into().expect("8 bytes"))
+    })
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
@@ -0,0 +1,82 @@
+//! Telling apart the series of agents deployed with the same `sensors.yaml` to many Pis
+//!
+//! Each agent labels its series with its hostname, and optionally the CPU serial the firmware
+//! reports in `/proc/cpuinfo`, which stays the same when a card is re-imaged with another
+//! hostname. The label goes either in front of the series' paths, e.g.
+//! `pi-attic.kitchen.temperature`, or into a Graphite tag, e.g.
+//! `kitchen.temperature;host=pi-attic`.
+
+use std::{fs, io, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostLabel {
+    /// `<hostname>[.<serial>].<series>`
+    Prefix,
+    /// `<series>;host=<hostname>[;serial=<serial>]`
+    Tag,
+}
+
+impl FromStr for HostLabel {
+    type Err = String;
+
+    fn from_str(label: &str) -> Result<Self, Self::Err> {
+        match label {
+            "prefix" => Ok(HostLabel::Prefix),
+            "tag" => Ok(HostLabel::Tag),
+            _ => Err(format!(
+                "unknown host label {}, expected prefix or tag",
+                label
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub label: HostLabel,
+    pub hostname: String,
+    pub serial: Option<String>,
+}
+
+impl Identity {
+    /// The identity of the Pi this runs on, failing if its CPU serial is wanted but unknown
+    pub fn detect(label: HostLabel, with_serial: bool) -> io::Result<Self> {
+        let serial = match with_serial {
+            true => Some(cpu_serial()?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "no CPU serial in /proc/cpuinfo")
+            })?),
+            false => None,
+        };
+
+        Ok(Identity {
+            label,
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            serial,
+        })
+    }
+
+    /// The name a series is written under
+    pub fn name(&self, series: &str) -> String {
+        // A fully qualified hostname would otherwise add path segments
+        let hostname = self.hostname.replace('.', "_");
+        match (self.label, &self.serial) {
+            (HostLabel::Prefix, None) => format!("{}.{}", hostname, series),
+            (HostLabel::Prefix, Some(serial)) => format!("{}.{}.{}", hostname, serial, series),
+            (HostLabel::Tag, None) => format!("{};host={}", series, hostname),
+            (HostLabel::Tag, Some(serial)) => {
+                format!("{};host={};serial={}", series, hostname, serial)
+            }
+        }
+    }
+}
+
+/// The `Serial` line of `/proc/cpuinfo`, which only a Pi's firmware fills in
+pub fn cpu_serial() -> io::Result<Option<String>> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo")?;
+    Ok(cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "Serial")
+            .then(|| value.trim().to_string())
+            .filter(|serial| !serial.is_empty())
+    }))
+}
@@ -0,0 +1,91 @@
+//! What's running where, for tracking upgrades across a fleet of Pis
+//!
+//! Every cycle the service writes a `monitoring.info` series with the value 1, tagged with the
+//! crate's version, the commit it was built from and a hash of the sensors it's sampling, e.g.
+//! `monitoring.info;version=0.1.0;commit=9879685a1b;config=5f0c3e1d`. Grouped by tag in Grafana,
+//! it shows which Pis run which build and configuration, and when that changed.
+
+use crate::{manager::SensorManager, Datapoint};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{mpsc, watch};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The commit the crate was built from, or `unknown` outside of a git checkout
+pub const GIT_COMMIT: &str = env!("MONITORING_GIT_COMMIT");
+
+/// The day the crate was built on, e.g. `2024-03-01`
+pub const BUILD_DATE: &str = env!("MONITORING_BUILD_DATE");
+
+/// The crate features the build enabled, comma separated
+pub const FEATURES: &str = env!("MONITORING_FEATURES");
+
+/// What `--version` prints
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("MONITORING_GIT_COMMIT"),
+    "\nbuilt: ",
+    env!("MONITORING_BUILD_DATE"),
+    "\nfeatures: ",
+    env!("MONITORING_FEATURES"),
+);
+
+/// The name of the info series
+pub const INFO_SERIES: &str = "monitoring.info";
+
+/// A short hash of the sensors' configuration, telling apart Pis configured differently
+pub fn config_hash(sensors: &[crate::config::Sensor]) -> String {
+    let config = serde_json::to_vec(sensors).expect("Sensors serialize to JSON");
+    // FNV-1a, stable across builds unlike the standard library's hasher
+    let hash = config.iter().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(*byte)).wrapping_mul(0x0100_0193)
+    });
+
+    format!("{:08x}", hash)
+}
+
+/// The info datapoint for the sensors as they're configured now
+pub fn datapoint(sensors: &[crate::config::Sensor], time: i64, interval: i32) -> Datapoint {
+    Datapoint {
+        name: format!(
+            "{};version={};commit={};config={}",
+            INFO_SERIES,
+            VERSION,
+            GIT_COMMIT,
+            config_hash(sensors)
+        ),
+        interval,
+        value: 1.0,
+        time,
+    }
+}
+
+/// Writes the info datapoint every `refresh` seconds until shutdown, picking up the sensors
+/// changed over the HTTP API
+pub(crate) async fn run(
+    manager: Arc<SensorManager>,
+    refresh: i32,
+    sender: mpsc::Sender<Vec<Datapoint>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let period = Duration::from_secs(u64::try_from(refresh).unwrap_or_default());
+    let mut interval = tokio::time::interval(period);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time behind Unix epoch time")
+            .as_secs() as i64;
+        let datapoint = datapoint(&manager.sensors().await, now, refresh);
+        // Like the sensors' readings, left out rather than waited on when the queue is full
+        let _ = sender.try_send(vec![datapoint]);
+    }
+}
@@ -0,0 +1,207 @@
+//! How often something happens, as given in the configuration and on the command line
+//!
+//! Intervals are durations like `30s`, `15m`, `1h` or `250ms`, and may combine units, e.g.
+//! `1m30s`. A plain number is a number of seconds, as intervals used to be given, and may be
+//! fractional, e.g. `0.5`. Intervals are kept to the millisecond and must be positive, so a
+//! sensor like an INA219 can be sampled several times a second.
+//!
+//! Settings named for milliseconds, like `retry_interval_ms`, take intervals too, but a plain
+//! number there is still a number of milliseconds, see [`millis`].
+//!
+//! Readings are still stamped and posted on whole seconds, so the interval posted with a
+//! sub-second sensor's readings is 1s, see [`Interval::secs`].
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr, time::Duration};
+
+const MILLIS_PER_SEC: u64 = 1000;
+
+/// The longest interval, as far as the seconds posted with the readings go
+const MAX_MILLIS: u64 = i32::MAX as u64 * MILLIS_PER_SEC;
+
+/// The units an interval may be given in, longest first, with their length in milliseconds
+const UNITS: [(&str, u64); 4] = [("h", 3_600_000), ("m", 60_000), ("s", 1000), ("ms", 1)];
+
+/// A positive length of time, to the millisecond
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Interval {
+    millis: u64,
+}
+
+impl Interval {
+    /// An interval of `secs` seconds, which must be at least 1
+    pub const fn from_secs(secs: u64) -> Self {
+        assert!(secs > 0 && secs * MILLIS_PER_SEC <= MAX_MILLIS);
+        Interval {
+            millis: secs * MILLIS_PER_SEC,
+        }
+    }
+
+    pub fn as_duration(self) -> Duration {
+        Duration::from_millis(self.millis)
+    }
+
+    /// The interval in whole seconds, rounded up to at least 1, as the interval the readings are
+    /// posted with
+    pub fn secs(self) -> i32 {
+        self.millis.div_ceil(MILLIS_PER_SEC) as i32
+    }
+
+    fn from_millis(millis: f64) -> Result<Self, String> {
+        let millis = millis.round();
+        if millis.is_nan() || millis < 1.0 {
+            return Err("the interval must be at least 1ms".to_string());
+        }
+        if millis > MAX_MILLIS as f64 {
+            return Err(format!("the interval can be {}s at most", i32::MAX));
+        }
+        Ok(Interval {
+            millis: millis as u64,
+        })
+    }
+}
+
+impl TryFrom<Duration> for Interval {
+    type Error = String;
+
+    fn try_from(duration: Duration) -> Result<Self, Self::Error> {
+        Interval::from_millis(duration.as_secs_f64() * MILLIS_PER_SEC as f64)
+    }
+}
+
+impl FromStr for Interval {
+    type Err = String;
+
+    /// Parses e.g. `30s`, `15m`, `1h30m`, `250ms`, or a number of seconds
+    fn from_str(interval: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| format!("invalid interval {}, {}", interval, reason);
+        let trimmed = interval.trim();
+        if let Ok(secs) = trimmed.parse::<f64>() {
+            return Interval::from_millis(secs * MILLIS_PER_SEC as f64).map_err(invalid);
+        }
+
+        let mut millis = 0.0;
+        let mut rest = trimmed;
+        while !rest.is_empty() {
+            let number = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let (value, unit) = rest.split_at(number);
+            let unit_length = unit
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(unit.len());
+            let (unit, remaining) = unit.split_at(unit_length);
+            let (Ok(value), Some((_, length))) = (
+                value.parse::<f64>(),
+                UNITS.iter().find(|(name, _)| *name == unit),
+            ) else {
+                return Err(invalid("expected e.g. 30s, 15m, 1h or 250ms".to_string()));
+            };
+            millis += value * *length as f64;
+            rest = remaining;
+        }
+
+        Interval::from_millis(millis).map_err(invalid)
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (unit, length) = UNITS
+            .iter()
+            .find(|(_, length)| self.millis.is_multiple_of(*length))
+            .expect("Every interval is a number of milliseconds");
+        write!(f, "{}{}", self.millis / length, unit)
+    }
+}
+
+impl Serialize for Interval {
+    /// Whole seconds are written as a plain number, as intervals used to be
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.millis.is_multiple_of(MILLIS_PER_SEC) {
+            serializer.serialize_u64(self.millis / MILLIS_PER_SEC)
+        } else {
+            serializer.collect_str(self)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Interval {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(Visitor {
+            number_millis: MILLIS_PER_SEC,
+        })
+    }
+}
+
+/// Reads an interval, or a plain number of `number_millis` milliseconds each
+struct Visitor {
+    number_millis: u64,
+}
+
+impl de::Visitor<'_> for Visitor {
+    type Value = Interval;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.number_millis {
+            MILLIS_PER_SEC => {
+                f.write_str("a number of seconds or an interval like 30s, 15m or 250ms")
+            }
+            _ => f.write_str("a number of milliseconds or an interval like 250ms, 2s or 1m"),
+        }
+    }
+
+    fn visit_u64<E: de::Error>(self, number: u64) -> Result<Interval, E> {
+        self.visit_f64(number as f64)
+    }
+
+    fn visit_i64<E: de::Error>(self, number: i64) -> Result<Interval, E> {
+        self.visit_f64(number as f64)
+    }
+
+    fn visit_f64<E: de::Error>(self, number: f64) -> Result<Interval, E> {
+        Interval::from_millis(number * self.number_millis as f64)
+            .map_err(|err| E::custom(format!("invalid interval {}, {}", number, err)))
+    }
+
+    fn visit_str<E: de::Error>(self, interval: &str) -> Result<Interval, E> {
+        match interval.trim().parse::<f64>() {
+            Ok(number) => self.visit_f64(number),
+            Err(_) => interval.parse().map_err(E::custom),
+        }
+    }
+}
+
+/// For the optional settings a plain number has always been milliseconds of, like
+/// `retry_interval_ms`, with `#[serde(default, with = "crate::interval::millis")]`
+pub mod millis {
+    use super::{Interval, Visitor};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Written as a plain number of milliseconds, as they used to be
+    pub fn serialize<S: Serializer>(
+        interval: &Option<Interval>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match interval {
+            Some(interval) => serializer.serialize_u64(interval.millis),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Interval>, D::Error> {
+        struct Millis(Interval);
+
+        impl<'de> Deserialize<'de> for Millis {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                deserializer
+                    .deserialize_any(Visitor { number_millis: 1 })
+                    .map(Millis)
+            }
+        }
+
+        Ok(Option::<Millis>::deserialize(deserializer)?.map(|millis| millis.0))
+    }
+}
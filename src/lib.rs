@@ -0,0 +1,91 @@
+//! Reads DHT22 sensors on a Raspberry Pi and ships the readings to a Graphite instance.
+//!
+//! The `monitoring` binary is a thin CLI over this crate, so the same reading/shipping
+//! pipeline can be embedded into other Rust projects.
+
+pub mod aggregator;
+#[cfg(feature = "http")]
+pub mod annotations;
+mod api;
+pub mod audit;
+pub mod capture;
+pub mod coarsen;
+pub mod config;
+mod dashboard;
+pub mod diagnose;
+pub mod display;
+pub mod dns;
+pub mod error;
+pub mod events;
+pub mod extremes;
+pub mod gpio;
+pub mod grafana;
+mod groups;
+pub mod heartbeat;
+pub mod history;
+pub mod hooks;
+#[cfg(target_os = "linux")]
+pub mod hwmon;
+pub mod identity;
+pub mod info;
+pub mod interval;
+pub mod locale;
+pub mod logging;
+mod manager;
+mod mdns;
+pub mod notify;
+pub mod outputs;
+pub mod persist;
+pub mod pipeline;
+pub mod plugins;
+pub mod power;
+pub mod privileges;
+pub mod profiles;
+pub mod pulse;
+pub mod radio;
+pub mod ratelimit;
+pub mod routing;
+pub mod schedule;
+pub mod sensors;
+pub mod serial;
+pub mod service;
+pub mod simulate;
+pub mod sinks;
+pub mod snmp;
+pub mod spool;
+pub mod state;
+pub mod summary;
+pub mod trend;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod wire;
+
+pub use error::{Error, Result};
+
+use serde::{Deserialize, Serialize};
+
+/// A single metric value in the format accepted by Graphite's JSON API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Datapoint {
+    pub name: String,
+    pub interval: i32,
+    pub value: f64,
+    pub time: i64,
+}
+
+impl Datapoint {
+    pub fn new(
+        value: f64,
+        label: &str,
+        sensor: &config::Sensor,
+        timestamp: u64,
+        resolution: i32,
+    ) -> Self {
+        Datapoint {
+            name: sensor.series(label),
+            interval: resolution,
+            value,
+            time: i64::try_from(timestamp).expect("Couldn't convert to i64 from u64"),
+        }
+    }
+}
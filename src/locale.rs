@@ -0,0 +1,143 @@
+//! Formatting the readings for people, on the attached display, the terminal view and the
+//! dashboard page
+//!
+//! A locale picks the decimal separator and how dates and times are written, e.g. `de` shows
+//! `21,5` and `01.03.2024`, and units give the metrics their symbols, e.g. `temperature=°C`.
+//! Without a locale, values are written like `21.5` and dates like `2024-03-01`, unitless. The
+//! readings written to the sinks are never affected.
+
+use chrono::{DateTime, TimeZone};
+use std::{fmt::Display, str::FromStr};
+
+/// How the readings are shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale {
+    /// Write `21,5` rather than `21.5`
+    pub decimal_comma: bool,
+    /// The `strftime` format of dates, e.g. `%d.%m.%Y`
+    pub date_format: String,
+    /// The `strftime` format of times of day, e.g. `%H:%M`
+    pub time_format: String,
+    /// The symbols shown after the metrics' values
+    pub units: Vec<Unit>,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::new(false, "%Y-%m-%d", "%H:%M")
+    }
+}
+
+impl Locale {
+    fn new(decimal_comma: bool, date_format: &str, time_format: &str) -> Self {
+        Locale {
+            decimal_comma,
+            date_format: date_format.to_string(),
+            time_format: time_format.to_string(),
+            units: Vec::new(),
+        }
+    }
+
+    /// A value with one decimal, e.g. `21,5`
+    pub fn number(&self, value: f64) -> String {
+        let number = format!("{:.1}", value);
+        if self.decimal_comma {
+            number.replace('.', ",")
+        } else {
+            number
+        }
+    }
+
+    /// A metric's value with its unit's symbol, e.g. `21,5°C` or `1013,2 hPa`
+    pub fn value(&self, metric: &str, value: f64) -> String {
+        let number = self.number(value);
+        match self.units.iter().find(|unit| unit.metric == metric) {
+            // Degrees and percentages go right after the number, other symbols after a space
+            Some(unit) if unit.symbol.starts_with(['°', '%']) => {
+                format!("{}{}", number, unit.symbol)
+            }
+            Some(unit) => format!("{} {}", number, unit.symbol),
+            None => number,
+        }
+    }
+
+    /// A time of day, e.g. `14:05`
+    pub fn time<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        time.format(&self.time_format).to_string()
+    }
+
+    /// A date and time of day, e.g. `01.03.2024 14:05`
+    pub fn date_time<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> String
+    where
+        Tz::Offset: Display,
+    {
+        format!(
+            "{} {}",
+            time.format(&self.date_format),
+            time.format(&self.time_format)
+        )
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Picks the separator and formats for a language tag, e.g. `de`, `de-AT` or `lt_LT.UTF-8`
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        let tag = tag.split('.').next().unwrap_or_default().replace('_', "-");
+        let (language, region) = tag.split_once('-').unwrap_or((&tag, ""));
+
+        match (
+            language.to_ascii_lowercase().as_str(),
+            region.to_ascii_uppercase().as_str(),
+        ) {
+            ("c" | "posix", "") => Ok(Locale::default()),
+            ("en", "US") => Ok(Locale::new(false, "%m/%d/%Y", "%I:%M %p")),
+            ("en", "GB" | "IE" | "AU" | "NZ") => Ok(Locale::new(false, "%d/%m/%Y", "%H:%M")),
+            ("en", _) => Ok(Locale::default()),
+            ("de" | "pl" | "cs" | "sk" | "fi" | "nb" | "da" | "et" | "lv" | "ru" | "uk", _) => {
+                Ok(Locale::new(true, "%d.%m.%Y", "%H:%M"))
+            }
+            ("fr" | "es" | "it" | "pt" | "el", _) => Ok(Locale::new(true, "%d/%m/%Y", "%H:%M")),
+            ("nl", _) => Ok(Locale::new(true, "%d-%m-%Y", "%H:%M")),
+            ("lt" | "sv", _) => Ok(Locale::new(true, "%Y-%m-%d", "%H:%M")),
+            ("hu", _) => Ok(Locale::new(true, "%Y.%m.%d", "%H:%M")),
+            _ => Err(format!(
+                "unknown locale {}, expected e.g. en, en-US, de, fr, nl or lt",
+                tag
+            )),
+        }
+    }
+}
+
+/// The symbol shown after a metric's values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unit {
+    /// The metric, e.g. `temperature`
+    pub metric: String,
+    /// Its symbol, e.g. `°C`
+    pub symbol: String,
+}
+
+impl FromStr for Unit {
+    type Err = String;
+
+    /// Parses `<metric>=<symbol>`, e.g. `temperature=°C`
+    fn from_str(unit: &str) -> Result<Self, Self::Err> {
+        match unit.split_once('=') {
+            Some((metric, symbol)) if !metric.trim().is_empty() && !symbol.trim().is_empty() => {
+                Ok(Unit {
+                    metric: metric.trim().to_string(),
+                    symbol: symbol.trim().to_string(),
+                })
+            }
+            _ => Err(format!(
+                "invalid unit {}, expected e.g. temperature=°C",
+                unit
+            )),
+        }
+    }
+}
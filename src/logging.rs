@@ -0,0 +1,440 @@
+//! Where the service's own log goes when it isn't stderr
+//!
+//! [`RotatingFile`] is for installs without journald (Alpine, plain Raspbian init): it caps how
+//! much of the SD card the log may take and keeps it across reboots. [`Syslog`] hands the log to
+//! the local syslog daemon with each event's level as its severity; journald is logged to
+//! directly with `tracing-journald`. [`Loki`] ships a copy of the log to Grafana Loki, next to
+//! the metrics.
+
+#[cfg(feature = "http")]
+use crate::error::SinkError;
+#[cfg(feature = "http")]
+use chrono::Utc;
+use chrono::{Local, NaiveDate};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(feature = "http")]
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Where the local syslog daemon listens
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// The `daemon` facility, which system services log under
+#[cfg(unix)]
+const SYSLOG_FACILITY: u8 = 3;
+
+/// Rotated log files kept besides the current one, unless configured otherwise
+pub const DEFAULT_MAX_LOG_FILES: usize = 5;
+
+/// A log file that's rotated once it reaches a size, and optionally every day: `monitoring.log`
+/// becomes `monitoring.log.1`, `monitoring.log.1` becomes `monitoring.log.2` and so on, with the
+/// oldest beyond `max_files` deleted
+#[derive(Clone)]
+pub struct RotatingFile {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+    daily: bool,
+    opened_on: NaiveDate,
+}
+
+impl RotatingFile {
+    /// Opens the log file for appending, creating it if needed
+    pub fn open(
+        path: impl Into<PathBuf>,
+        max_size: u64,
+        max_files: usize,
+        daily: bool,
+    ) -> io::Result<Self> {
+        let path = path.into();
+        let file = open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(RotatingFile {
+            inner: Arc::new(Mutex::new(Inner {
+                path,
+                file,
+                size,
+                max_size,
+                max_files,
+                daily,
+                opened_on: Local::now().date_naive(),
+            })),
+        })
+    }
+}
+
+impl Inner {
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..=self.max_files).rev() {
+            let from = rotated(&self.path, index);
+            if !from.exists() {
+                continue;
+            }
+            if index == self.max_files {
+                fs::remove_file(&from)?;
+            } else {
+                fs::rename(&from, rotated(&self.path, index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.file = open(&self.path)?;
+        self.size = 0;
+        self.opened_on = Local::now().date_naive();
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `monitoring.log.<index>`
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingFile;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Write for RotatingFile {
+    /// Writes a whole event, rotating first if it would take the file past its maximum size
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().expect("Log file lock poisoned");
+        let full = inner.size > 0 && inner.size + bytes.len() as u64 > inner.max_size;
+        let new_day = inner.daily && Local::now().date_naive() != inner.opened_on;
+        if full || new_day {
+            // Losing a rotation isn't worth losing the log over, keep appending
+            if let Err(err) = inner.rotate() {
+                eprintln!(
+                    "Unable to rotate the log file {}: {}",
+                    inner.path.display(),
+                    err
+                );
+            }
+        }
+
+        let written = inner.file.write(bytes)?;
+        inner.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner
+            .lock()
+            .expect("Log file lock poisoned")
+            .file
+            .flush()
+    }
+}
+
+/// Sends each log event to the local syslog daemon as one message, tagged `monitoring[<pid>]`
+#[cfg(unix)]
+#[derive(Clone)]
+pub struct Syslog {
+    socket: Arc<UnixDatagram>,
+}
+
+#[cfg(unix)]
+impl Syslog {
+    /// Connects to the syslog daemon's socket, usually [`SYSLOG_SOCKET`]
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+
+        Ok(Syslog {
+            socket: Arc::new(socket),
+        })
+    }
+}
+
+#[cfg(unix)]
+impl<'a> MakeWriter<'a> for Syslog {
+    type Writer = SyslogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        SyslogWriter {
+            socket: self.socket.clone(),
+            severity: 6,
+            message: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        SyslogWriter {
+            socket: self.socket.clone(),
+            severity: severity(meta.level()),
+            message: Vec::new(),
+        }
+    }
+}
+
+/// The syslog severity of a log level
+#[cfg(unix)]
+fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// Writes one event to [`Syslog`], sending it once the event is complete
+#[cfg(unix)]
+pub struct SyslogWriter {
+    socket: Arc<UnixDatagram>,
+    severity: u8,
+    message: Vec<u8>,
+}
+
+#[cfg(unix)]
+impl Write for SyslogWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.message.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SyslogWriter {
+    fn drop(&mut self) {
+        let message = String::from_utf8_lossy(&self.message);
+        let message = message.trim_end();
+        if message.is_empty() {
+            return;
+        }
+
+        let line = format!(
+            "<{}>monitoring[{}]: {}",
+            SYSLOG_FACILITY * 8 + self.severity,
+            std::process::id(),
+            message
+        );
+        // Nowhere left to report a lost message to
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// How often the log is pushed to Loki
+#[cfg(feature = "http")]
+pub const LOKI_PUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Log lines kept while Loki can't be reached, the oldest dropped beyond it
+#[cfg(feature = "http")]
+const LOKI_MAX_PENDING: usize = 10_000;
+
+/// Ships log events to Loki's push API, e.g. Grafana Cloud's
+/// `https://logs-prod-012.grafana.net`, in one stream per level labelled with the service and
+/// host name
+///
+/// Events are kept in memory as they're logged and pushed by [`Loki::ship`] every
+/// [`LOKI_PUSH_INTERVAL`]. Use it with the JSON formatter for structured lines.
+#[cfg(feature = "http")]
+#[derive(Clone)]
+pub struct Loki {
+    url: String,
+    auth: Option<(String, String)>,
+    host: String,
+    client: reqwest::Client,
+    pending: Arc<Mutex<VecDeque<LokiLine>>>,
+}
+
+#[cfg(feature = "http")]
+struct LokiLine {
+    /// Nanoseconds since the Unix epoch
+    time: i64,
+    level: &'static str,
+    line: String,
+}
+
+#[cfg(feature = "http")]
+impl Loki {
+    pub fn new(url: impl Into<String>) -> Self {
+        Loki {
+            url: url.into().trim_end_matches('/').to_string(),
+            auth: None,
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            client: reqwest::Client::new(),
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Authenticates the pushes, with Grafana Cloud's Loki user ID and an API key
+    pub fn basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((user.into(), password.into()));
+        self
+    }
+
+    /// Pushes the pending log events, keeping them for the next push if it's worth retrying
+    pub async fn flush(&self) -> Result<(), SinkError> {
+        let lines = std::mem::take(&mut *self.pending.lock().expect("Loki lock poisoned"));
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let mut streams = BTreeMap::<&str, Vec<[String; 2]>>::new();
+        for line in &lines {
+            streams
+                .entry(line.level)
+                .or_default()
+                .push([line.time.to_string(), line.line.clone()]);
+        }
+        let streams = streams
+            .into_iter()
+            .map(|(level, values)| {
+                serde_json::json!({
+                    "stream": {"service_name": "monitoring", "host": self.host, "level": level},
+                    "values": values,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut request = self
+            .client
+            .post(format!("{}/loki/api/v1/push", self.url))
+            .json(&serde_json::json!({ "streams": streams }));
+        if let Some((user, password)) = &self.auth {
+            request = request.basic_auth(user, Some(password));
+        }
+        let result = match request.send().await {
+            Ok(response) => match response.status() {
+                status if status.is_success() => Ok(()),
+                status @ (reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                    Err(SinkError::Unauthorized(status))
+                }
+                status => Err(SinkError::Status(status)),
+            },
+            Err(err) => Err(SinkError::Request(err)),
+        };
+
+        if matches!(&result, Err(err) if err.is_retryable()) {
+            let mut pending = self.pending.lock().expect("Loki lock poisoned");
+            for line in lines.into_iter().rev() {
+                pending.push_front(line);
+            }
+            let excess = pending.len().saturating_sub(LOKI_MAX_PENDING);
+            pending.drain(..excess);
+        }
+        result
+    }
+
+    /// Pushes the log every [`LOKI_PUSH_INTERVAL`], forever
+    pub async fn ship(self) {
+        let mut interval = tokio::time::interval(LOKI_PUSH_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            // Logging the failure would only add to what's waiting to be pushed
+            if let Err(err) = self.flush().await {
+                eprintln!("Unable to push the log to Loki: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl<'a> MakeWriter<'a> for Loki {
+    type Writer = LokiWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LokiWriter {
+            pending: self.pending.clone(),
+            level: "info",
+            line: Vec::new(),
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        LokiWriter {
+            pending: self.pending.clone(),
+            level: loki_level(meta.level()),
+            line: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+fn loki_level(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warn",
+        Level::INFO => "info",
+        Level::DEBUG => "debug",
+        _ => "trace",
+    }
+}
+
+/// Writes one event to [`Loki`], queueing it for the next push once the event is complete
+#[cfg(feature = "http")]
+pub struct LokiWriter {
+    pending: Arc<Mutex<VecDeque<LokiLine>>>,
+    level: &'static str,
+    line: Vec<u8>,
+}
+
+#[cfg(feature = "http")]
+impl Write for LokiWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "http")]
+impl Drop for LokiWriter {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+
+        let mut pending = self.pending.lock().expect("Loki lock poisoned");
+        if pending.len() >= LOKI_MAX_PENDING {
+            pending.pop_front();
+        }
+        pending.push_back(LokiLine {
+            time: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            level: self.level,
+            line: line.to_string(),
+        });
+    }
+}
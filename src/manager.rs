@@ -0,0 +1,254 @@
+//! Adding, removing, renaming and disabling sensors while the service is running
+
+use crate::{
+    config::{self, ConfigFile, Sensor},
+    error::ConfigError,
+    events::Event,
+    gpio::Gpio,
+    interval::Interval,
+    outputs, pipeline, profiles,
+    sensors::{Backend, ReadOptions},
+    state::State,
+    Datapoint,
+};
+use std::{collections::HashMap, sync::Arc};
+use tokio::{
+    sync::{mpsc, watch, Mutex},
+    task::JoinHandle,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum ManageError {
+    #[error("there's no sensor called {0}")]
+    NotFound(String),
+    #[error("a sensor called {0} already exists")]
+    Exists(String),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Service(#[from] crate::Error),
+}
+
+/// Changes to apply to an existing sensor
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SensorUpdate {
+    pub name: Option<String>,
+    pub disabled: Option<bool>,
+}
+
+/// Owns the configured sensors and the tasks sampling them
+pub(crate) struct SensorManager {
+    refresh: Interval,
+    backend: Arc<dyn Backend>,
+    state: Arc<State>,
+    /// Where changes are persisted, if anywhere
+    config_file: Option<ConfigFile>,
+    options: ReadOptions,
+    inner: Mutex<Inner>,
+    /// The sensors as of their latest change, for what follows them while the service runs
+    changes: watch::Sender<Vec<Sensor>>,
+}
+
+struct Inner {
+    sensors: Vec<Sensor>,
+    tasks: HashMap<String, JoinHandle<()>>,
+    gpio: Option<Gpio>,
+    /// Dropped on shutdown, so the aggregator stops once the last sensor task has
+    sender: Option<mpsc::Sender<Vec<Datapoint>>>,
+}
+
+impl SensorManager {
+    pub fn new(
+        sensors: Vec<Sensor>,
+        refresh: Interval,
+        backend: Arc<dyn Backend>,
+        state: Arc<State>,
+        config_file: Option<ConfigFile>,
+        options: ReadOptions,
+    ) -> Self {
+        SensorManager {
+            refresh,
+            backend,
+            state,
+            config_file,
+            options,
+            changes: watch::Sender::new(sensors.clone()),
+            inner: Mutex::new(Inner {
+                sensors,
+                tasks: HashMap::new(),
+                gpio: None,
+                sender: None,
+            }),
+        }
+    }
+
+    /// The current configuration, including disabled sensors
+    pub async fn sensors(&self) -> Vec<Sensor> {
+        self.inner.lock().await.sensors.clone()
+    }
+
+    /// Gets the sensors every time they're added, changed or removed
+    pub fn subscribe(&self) -> watch::Receiver<Vec<Sensor>> {
+        self.changes.subscribe()
+    }
+
+    /// Starts sampling every enabled sensor, sending their readings to `sender`
+    pub async fn start(&self, sender: mpsc::Sender<Vec<Datapoint>>) -> crate::Result<()> {
+        let mut inner = self.inner.lock().await;
+        inner.gpio = outputs::setup_gpio(&inner.sensors)?;
+        inner.sender = Some(sender);
+
+        let enabled = inner
+            .sensors
+            .iter()
+            .filter(|sensor| !sensor.disabled)
+            .cloned()
+            .collect::<Vec<_>>();
+        for sensor in enabled {
+            self.spawn(&mut inner, sensor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops every sensor task and lets go of the readings queue
+    pub async fn stop(&self) {
+        let mut inner = self.inner.lock().await;
+        for (_, task) in inner.tasks.drain() {
+            task.abort();
+        }
+        inner.sender = None;
+    }
+
+    pub async fn add(&self, sensor: Sensor) -> Result<(), ManageError> {
+        let mut inner = self.inner.lock().await;
+        if inner.sensors.iter().any(|known| known.name == sensor.name) {
+            return Err(ManageError::Exists(sensor.name));
+        }
+
+        let mut sensors = inner.sensors.clone();
+        sensors.push(sensor.clone());
+        config::validate(&sensors)?;
+        config::validate_refresh(&sensors, self.refresh)?;
+        config::validate_schedule(&sensors, &self.options.schedule)?;
+        self.persist(&sensors).await?;
+        self.changes.send_replace(sensors.clone());
+        inner.sensors = sensors;
+
+        self.state
+            .record_event(Event::SensorsChanged(format!("added {}", sensor.name)));
+        if !sensor.disabled {
+            self.state.add_sensor(&sensor);
+            self.spawn(&mut inner, sensor)?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn remove(&self, name: &str) -> Result<(), ManageError> {
+        let mut inner = self.inner.lock().await;
+        let sensors = inner
+            .sensors
+            .iter()
+            .filter(|sensor| sensor.name != name)
+            .cloned()
+            .collect::<Vec<_>>();
+        if sensors.len() == inner.sensors.len() {
+            return Err(ManageError::NotFound(name.to_string()));
+        }
+
+        self.persist(&sensors).await?;
+        self.changes.send_replace(sensors.clone());
+        inner.sensors = sensors;
+        Self::halt(&mut inner, name).await;
+        self.state.remove_sensor(name);
+        self.state
+            .record_event(Event::SensorsChanged(format!("removed {}", name)));
+
+        Ok(())
+    }
+
+    /// Renames, disables or re-enables a sensor, restarting its task
+    pub async fn update(&self, name: &str, update: SensorUpdate) -> Result<(), ManageError> {
+        let mut inner = self.inner.lock().await;
+        let index = inner
+            .sensors
+            .iter()
+            .position(|sensor| sensor.name == name)
+            .ok_or_else(|| ManageError::NotFound(name.to_string()))?;
+
+        let mut sensors = inner.sensors.clone();
+        let sensor = &mut sensors[index];
+        if let Some(new_name) = update.name {
+            if new_name != name && inner.sensors.iter().any(|known| known.name == new_name) {
+                return Err(ManageError::Exists(new_name));
+            }
+            sensor.name = new_name;
+        }
+        if let Some(disabled) = update.disabled {
+            sensor.disabled = disabled;
+        }
+        let sensor = sensor.clone();
+
+        config::validate(&sensors)?;
+        config::validate_refresh(&sensors, self.refresh)?;
+        config::validate_schedule(&sensors, &self.options.schedule)?;
+        self.persist(&sensors).await?;
+        self.changes.send_replace(sensors.clone());
+        inner.sensors = sensors;
+
+        Self::halt(&mut inner, name).await;
+        self.state.remove_sensor(name);
+        self.state
+            .record_event(Event::SensorsChanged(format!("updated {}", name)));
+        if !sensor.disabled {
+            self.state.add_sensor(&sensor);
+            self.spawn(&mut inner, sensor)?;
+        }
+
+        Ok(())
+    }
+
+    fn spawn(&self, inner: &mut Inner, sensor: Sensor) -> crate::Result<()> {
+        let Some(sender) = inner.sender.clone() else {
+            // Not started yet, or shutting down
+            return Ok(());
+        };
+        if inner.gpio.is_none() {
+            inner.gpio = outputs::setup_gpio(std::slice::from_ref(&sensor))?;
+        }
+
+        let name = sensor.name.clone();
+        let task = pipeline::spawn_sensor(
+            sensor,
+            self.refresh,
+            self.backend.clone(),
+            inner.gpio.as_ref(),
+            sender,
+            self.state.clone(),
+            self.options.clone(),
+        )?;
+        inner.tasks.insert(name, task);
+
+        Ok(())
+    }
+
+    /// Stops a sensor's task, waiting for it to let go of its GPIO outputs
+    async fn halt(inner: &mut Inner, name: &str) {
+        if let Some(task) = inner.tasks.remove(name) {
+            task.abort();
+            let _ = task.await;
+        }
+    }
+
+    async fn persist(&self, sensors: &[Sensor]) -> Result<(), ConfigError> {
+        match &self.config_file {
+            Some(ConfigFile::Sensors(path)) => config::save_sensors_config(path, sensors).await,
+            Some(ConfigFile::Profile { path, name }) => {
+                profiles::save_sensors(path, name, sensors).await
+            }
+            None => Ok(()),
+        }
+    }
+}
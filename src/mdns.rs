@@ -0,0 +1,92 @@
+//! Advertising the HTTP API over mDNS as `_rpitemp._tcp`, so every monitoring Pi on the LAN can
+//! be discovered without knowing its address
+
+use std::net::SocketAddr;
+#[cfg(feature = "mdns")]
+use std::time::Duration;
+
+/// The DNS-SD service type the API is advertised as
+#[cfg(feature = "mdns")]
+pub(crate) const SERVICE_TYPE: &str = "_rpitemp._tcp.local.";
+
+/// Keeps the service advertised until dropped
+#[cfg(feature = "mdns")]
+pub(crate) struct Advertisement {
+    daemon: mdns_sd::ServiceDaemon,
+    fullname: String,
+}
+
+/// Starts advertising the API listening on `addr`, as the host name or, for a profile, as
+/// `<host name>-<profile>` so the profiles' APIs don't clash. Failing to do so only costs
+/// discoverability, so it's logged rather than failing the service.
+#[cfg(feature = "mdns")]
+pub(crate) fn advertise(addr: SocketAddr, profile: Option<&str>) -> Option<Advertisement> {
+    let hostname = gethostname::gethostname().to_string_lossy().into_owned();
+    let instance = match profile {
+        Some(profile) => format!("{}-{}", hostname, profile),
+        None => hostname.clone(),
+    };
+    let properties = [
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("path", "/readings"),
+    ];
+
+    let info = mdns_sd::ServiceInfo::new(
+        SERVICE_TYPE,
+        &instance,
+        &format!("{}.local.", hostname),
+        if addr.ip().is_unspecified() {
+            String::new()
+        } else {
+            addr.ip().to_string()
+        },
+        addr.port(),
+        &properties[..],
+    )
+    .map(|info| {
+        if addr.ip().is_unspecified() {
+            info.enable_addr_auto()
+        } else {
+            info
+        }
+    });
+
+    let advertised = info.and_then(|info| {
+        let daemon = mdns_sd::ServiceDaemon::new()?;
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info)?;
+        Ok(Advertisement { daemon, fullname })
+    });
+
+    match advertised {
+        Ok(advertisement) => {
+            tracing::info!("Advertising the HTTP API as {}", advertisement.fullname);
+            Some(advertisement)
+        }
+        Err(err) => {
+            tracing::warn!("Unable to advertise the HTTP API over mDNS: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Give the goodbye announcement a moment to go out, so browsers forget about us now
+        // rather than when the record expires
+        if let Ok(unregistered) = self.daemon.unregister(&self.fullname) {
+            let _ = unregistered.recv_timeout(Duration::from_secs(1));
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+#[cfg(not(feature = "mdns"))]
+pub(crate) struct Advertisement;
+
+#[cfg(not(feature = "mdns"))]
+pub(crate) fn advertise(addr: SocketAddr, _profile: Option<&str>) -> Option<Advertisement> {
+    tracing::debug!(%addr, "Built without the mdns feature, not advertising the HTTP API");
+    None
+}
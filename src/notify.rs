@@ -0,0 +1,75 @@
+//! Sending messages to people rather than metrics to Graphite: to an ntfy topic or a Telegram
+//! chat
+
+#[cfg(feature = "http")]
+use crate::error::SinkError;
+use std::str::FromStr;
+
+/// Where messages are sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notifier {
+    /// An ntfy topic's URL, e.g. `https://ntfy.sh/greenhouse`
+    Ntfy { url: String },
+    /// A Telegram chat, messaged by a bot
+    Telegram { chat_id: String, bot_token: String },
+}
+
+#[cfg(feature = "http")]
+impl Notifier {
+    /// Sends a message with a title, e.g. `Daily summary`
+    pub async fn send(
+        &self,
+        client: &reqwest::Client,
+        title: &str,
+        message: &str,
+    ) -> Result<(), SinkError> {
+        let request = match self {
+            Notifier::Ntfy { url } => client
+                .post(url)
+                .header("Title", title)
+                .body(message.to_string()),
+            Notifier::Telegram { chat_id, bot_token } => client
+                .post(format!(
+                    "https://api.telegram.org/bot{}/sendMessage",
+                    bot_token
+                ))
+                .json(&serde_json::json!({
+                    "chat_id": chat_id,
+                    "text": format!("{}\n\n{}", title, message),
+                })),
+        };
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(SinkError::Status(response.status()));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Notifier {
+    type Err = String;
+
+    /// `ntfy:<topic URL>` or `telegram:<chat ID>:<bot token>`
+    fn from_str(notifier: &str) -> Result<Self, Self::Err> {
+        match notifier.split_once(':') {
+            Some(("ntfy", url)) if !url.is_empty() => Ok(Notifier::Ntfy {
+                url: url.to_string(),
+            }),
+            Some(("telegram", chat)) => match chat.split_once(':') {
+                Some((chat_id, bot_token)) if !chat_id.is_empty() && !bot_token.is_empty() => {
+                    Ok(Notifier::Telegram {
+                        chat_id: chat_id.to_string(),
+                        bot_token: bot_token.to_string(),
+                    })
+                }
+                _ => Err("expected telegram:<chat ID>:<bot token>".to_string()),
+            },
+            _ => Err(format!(
+                "unknown notifier {}, expected ntfy:<topic URL> or telegram:<chat ID>:<bot token>",
+                notifier
+            )),
+        }
+    }
+}
@@ -0,0 +1,375 @@
+//! GPIO outputs driven by alert rules and control loops
+
+use crate::{
+    config::{Control, Fan, GpioAction, Sensor},
+    events::Event,
+    gpio::{Gpio, OutputPin, PwmPin},
+    interval::Interval,
+    persist::OutputStates,
+    sensors::SeriesNames,
+    state::State,
+    Datapoint, Result,
+};
+use std::time::{Duration, Instant};
+
+/// Runtime state of a single alert rule
+struct AlertState {
+    firing: bool,
+    output: Option<GpioOutput>,
+}
+
+/// A GPIO output pin that refuses to switch faster than its min on/off times allow
+pub struct GpioOutput {
+    pin: OutputPin,
+    active_low: bool,
+    min_on: Duration,
+    min_off: Duration,
+    active: bool,
+    last_change: Option<Instant>,
+}
+
+impl GpioOutput {
+    pub fn new(gpio: &Gpio, action: &GpioAction) -> Result<Self> {
+        let mut output = GpioOutput {
+            pin: gpio.output(action.pin)?,
+            active_low: action.active_low,
+            min_on: action
+                .min_on_secs
+                .map_or(Duration::ZERO, Interval::as_duration),
+            min_off: action
+                .min_off_secs
+                .map_or(Duration::ZERO, Interval::as_duration),
+            active: false,
+            last_change: None,
+        };
+        output.write(false);
+
+        Ok(output)
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set(&mut self, active: bool) {
+        if active == self.active {
+            return;
+        }
+
+        if let Some(last_change) = self.last_change {
+            let min_hold = if self.active {
+                self.min_on
+            } else {
+                self.min_off
+            };
+            if last_change.elapsed() < min_hold {
+                tracing::info!(
+                    "Holding GPIO {} {} for at least {:?}",
+                    self.pin.pin(),
+                    if self.active { "on" } else { "off" },
+                    min_hold
+                );
+                return;
+            }
+        }
+
+        self.write(active);
+        self.active = active;
+        self.last_change = Some(Instant::now());
+        tracing::info!(
+            "Switched GPIO {} {}",
+            self.pin.pin(),
+            if active { "on" } else { "off" }
+        );
+    }
+
+    fn write(&mut self, active: bool) {
+        if active != self.active_low {
+            self.pin.set_high();
+        } else {
+            self.pin.set_low();
+        }
+    }
+}
+
+/// PWM frequency of fans that don't set one, in Hz
+pub const DEFAULT_FAN_FREQUENCY: f64 = 100.0;
+
+/// A fan's PWM output and the value its current speed was set at
+pub struct FanOutput {
+    pin: PwmPin,
+    /// Duty cycle in whole percent
+    duty: Option<u8>,
+    set_at: f64,
+}
+
+impl FanOutput {
+    pub fn new(gpio: &Gpio, fan: &Fan) -> Result<Self> {
+        Ok(FanOutput {
+            pin: gpio.pwm(fan.pin, fan.frequency.unwrap_or(DEFAULT_FAN_FREQUENCY))?,
+            duty: None,
+            set_at: 0.0,
+        })
+    }
+
+    /// Follows the fan curve, only slowing down once the value dropped past the hysteresis
+    pub fn follow(&mut self, fan: &Fan, value: f64) {
+        let duty = (fan.duty_cycle(value) * 100.0).round() as u8;
+        if let Some(current) = self.duty {
+            let within_hysteresis = value > self.set_at - f64::from(fan.hysteresis);
+            if duty == current || (duty < current && within_hysteresis) {
+                return;
+            }
+        }
+
+        self.set(duty, value);
+    }
+
+    fn set(&mut self, duty: u8, value: f64) {
+        self.pin.set_duty_cycle(f64::from(duty) / 100.0);
+        self.duty = Some(duty);
+        self.set_at = value;
+        tracing::info!("Set the fan on GPIO {} to {}%", self.pin.pin(), duty);
+    }
+}
+
+/// Only opens the GPIO peripheral when some sensor is configured to drive an output
+pub fn setup_gpio(sensors: &[Sensor]) -> Result<Option<Gpio>> {
+    let needs_gpio = sensors.iter().any(|sensor| {
+        sensor.control.is_some()
+            || sensor.fan.is_some()
+            || sensor.power_pin.is_some()
+            || sensor.alerts.iter().any(|alert| alert.gpio.is_some())
+    });
+
+    Ok(if needs_gpio { Some(Gpio::new()?) } else { None })
+}
+
+/// How long a power cycled sensor is kept off
+const POWER_OFF: Duration = Duration::from_secs(2);
+
+/// How long a sensor is given to start up once it's powered on again, unless it has a warm-up
+const POWER_ON_SETTLE: Duration = Duration::from_secs(2);
+
+/// A GPIO pin powering a sensor, kept high unless the sensor is being power cycled
+pub struct PowerSwitch {
+    pin: OutputPin,
+    /// The sensor's failed reads in a row when it was last power cycled
+    cycled_at: u32,
+}
+
+impl PowerSwitch {
+    pub fn new(gpio: &Gpio, pin: u8) -> Result<Self> {
+        let mut pin = gpio.output(pin)?;
+        pin.set_high();
+
+        Ok(PowerSwitch { pin, cycled_at: 0 })
+    }
+
+    /// Whether the sensor failed `after` more reads in a row, given `failures` in all, since it
+    /// was last power cycled or read fine
+    pub fn is_due(&self, failures: u32, after: u32) -> bool {
+        failures.saturating_sub(self.cycled_at) >= after
+    }
+
+    /// Starts counting the failed reads over, after the sensor read fine
+    pub fn reset(&mut self) {
+        self.cycled_at = 0;
+    }
+
+    /// Switches the sensor off and on again after `failures` failed reads in a row, returning
+    /// once it's had time to start up. It's switched on again even if this is cancelled.
+    pub async fn cycle(&mut self, sensor: &Sensor, failures: u32) {
+        tracing::warn!(power_pin = self.pin.pin(), "Power cycling the sensor");
+        self.cycled_at = failures;
+        self.pin.set_low();
+        let off = SwitchedOff(&mut self.pin);
+        tokio::time::sleep(POWER_OFF).await;
+        drop(off);
+
+        let settle = sensor
+            .warmup_secs
+            .map(Interval::as_duration)
+            .unwrap_or(POWER_ON_SETTLE);
+        tokio::time::sleep(settle).await;
+    }
+}
+
+/// Switches a sensor on again when dropped, so it isn't left off by a cancelled power cycle
+struct SwitchedOff<'a>(&'a mut OutputPin);
+
+impl Drop for SwitchedOff<'_> {
+    fn drop(&mut self) {
+        self.0.set_high();
+    }
+}
+
+/// The alert states, control loop, fan and power outputs belonging to one sensor
+pub struct SensorOutputs {
+    alerts: Vec<AlertState>,
+    control: Option<GpioOutput>,
+    fan: Option<FanOutput>,
+    /// Switched by the reads themselves, when they keep failing
+    pub power: Option<PowerSwitch>,
+}
+
+impl SensorOutputs {
+    pub fn new(sensor: &Sensor, gpio: Option<&Gpio>) -> Result<Self> {
+        let alerts = sensor
+            .alerts
+            .iter()
+            .map(|alert| {
+                let output = match (gpio, &alert.gpio) {
+                    (Some(gpio), Some(action)) => Some(GpioOutput::new(gpio, action)?),
+                    _ => None,
+                };
+
+                Ok(AlertState {
+                    firing: false,
+                    output,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let control = match (gpio, &sensor.control) {
+            (Some(gpio), Some(control)) => Some(GpioOutput::new(gpio, &control.gpio)?),
+            _ => None,
+        };
+
+        let fan = match (gpio, &sensor.fan) {
+            (Some(gpio), Some(fan)) => Some(FanOutput::new(gpio, fan)?),
+            _ => None,
+        };
+
+        let power = match (gpio, sensor.power_pin) {
+            (Some(gpio), Some(pin)) => Some(PowerSwitch::new(gpio, pin)?),
+            _ => None,
+        };
+
+        Ok(SensorOutputs {
+            alerts,
+            control,
+            fan,
+            power,
+        })
+    }
+
+    /// Where the alerts and outputs are at, to pick up after a restart
+    pub fn saved(&self) -> OutputStates {
+        OutputStates {
+            alerts: self.alerts.iter().map(|alert| alert.firing).collect(),
+            control: self.control.as_ref().is_some_and(GpioOutput::is_active),
+            fan: self
+                .fan
+                .as_ref()
+                .and_then(|fan| Some((fan.duty?, fan.set_at))),
+        }
+    }
+
+    /// Picks up where the alerts and outputs were at before a restart. The alert states are
+    /// only taken if the sensor still has as many alerts, as they're told apart by position.
+    pub fn restore(&mut self, saved: &OutputStates) {
+        if saved.alerts.len() == self.alerts.len() {
+            for (alert, &firing) in self.alerts.iter_mut().zip(&saved.alerts) {
+                alert.firing = firing;
+                if let Some(output) = &mut alert.output {
+                    output.set(firing);
+                }
+            }
+        }
+        if let Some(output) = &mut self.control {
+            output.set(saved.control);
+        }
+        if let (Some(output), Some((duty, set_at))) = (&mut self.fan, saved.fan) {
+            output.set(duty, set_at);
+        }
+    }
+
+    /// Evaluates the sensor's alerts and runs its control loop and fan curve on a fresh set of
+    /// readings, finding the metrics' series by their `names`
+    pub fn apply(
+        &mut self,
+        sensor: &Sensor,
+        names: &mut SeriesNames,
+        datapoints: &[Datapoint],
+        state: &State,
+    ) {
+        evaluate_alerts(sensor, names, datapoints, &mut self.alerts, state);
+
+        if let (Some(control), Some(output)) = (&sensor.control, &mut self.control) {
+            run_control(control, names, datapoints, output);
+        }
+
+        if let (Some(fan), Some(output)) = (&sensor.fan, &mut self.fan) {
+            let name = names.get(&fan.metric);
+            match datapoints.iter().find(|datapoint| datapoint.name == name) {
+                Some(datapoint) => output.follow(fan, datapoint.value),
+                None => tracing::warn!("Fan on {} refers to a metric that wasn't read", name),
+            }
+        }
+
+        state.record_outputs(&sensor.name, self.saved());
+    }
+}
+
+fn evaluate_alerts(
+    sensor: &Sensor,
+    names: &mut SeriesNames,
+    datapoints: &[Datapoint],
+    states: &mut [AlertState],
+    state: &State,
+) {
+    for (alert, alert_state) in sensor.alerts.iter().zip(states) {
+        let name = names.get(&alert.metric);
+        let Some(datapoint) = datapoints.iter().find(|datapoint| datapoint.name == name) else {
+            tracing::warn!("Alert on {} refers to a metric that wasn't read", name);
+            continue;
+        };
+
+        let firing = alert.fires(&sensor.name, datapoint.value, |sensor, metric| {
+            state.latest(sensor, metric)
+        });
+        if firing != alert_state.firing {
+            let (sensor, series, value) = (sensor.name.clone(), name.to_string(), datapoint.value);
+            if firing {
+                tracing::warn!("Alert on {} is firing (value: {})", series, value);
+                state.record_event(Event::AlertFiring {
+                    sensor,
+                    series,
+                    value,
+                });
+            } else {
+                tracing::info!("Alert on {} resolved (value: {})", series, value);
+                state.record_event(Event::AlertResolved {
+                    sensor,
+                    series,
+                    value,
+                });
+            }
+            alert_state.firing = firing;
+        }
+
+        if let Some(output) = &mut alert_state.output {
+            output.set(firing);
+        }
+    }
+}
+
+fn run_control(
+    control: &Control,
+    names: &mut SeriesNames,
+    datapoints: &[Datapoint],
+    output: &mut GpioOutput,
+) {
+    let name = names.get(&control.metric);
+    let Some(datapoint) = datapoints.iter().find(|datapoint| datapoint.name == name) else {
+        tracing::warn!(
+            "Control loop on {} refers to a metric that wasn't read",
+            name
+        );
+        return;
+    };
+
+    let on = control.wants_on(datapoint.value, output.is_active());
+    output.set(on);
+}
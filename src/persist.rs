@@ -0,0 +1,98 @@
+//! Keeping the sensors' state in a file across restarts
+//!
+//! With a state file, the latest known values and failure counts of the sensors, and whether
+//! their alerts were firing, their control loops on and their fans spinning, are saved every
+//! [`SAVE_INTERVAL`] and on shutdown, and picked up again on the next start. A reboot then
+//! neither fires the alerts that were already firing again nor switches outputs off and back on
+//! within their hysteresis band.
+//!
+//! The file is only a cache: a missing or unreadable one starts the sensors afresh.
+
+use crate::{spool, state::State};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io, path::Path, time::Duration};
+use tokio::sync::watch;
+
+/// How often the state is saved while running
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What's kept of every sensor's state
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SavedState {
+    pub sensors: BTreeMap<String, SavedSensor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SavedSensor {
+    /// Unix timestamp of the latest successful reading
+    pub time: Option<i64>,
+    #[serde(default)]
+    pub values: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Failed attempts since the latest successful reading
+    #[serde(default)]
+    pub failures: u32,
+    #[serde(default)]
+    pub outputs: OutputStates,
+}
+
+/// Where a sensor's alerts and outputs were at
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OutputStates {
+    /// Whether each of its alerts was firing, in the order they're configured
+    #[serde(default)]
+    pub alerts: Vec<bool>,
+    /// Whether its control loop's output was on
+    #[serde(default)]
+    pub control: bool,
+    /// The duty cycle its fan was set to in whole percent, and the value it was set at
+    #[serde(default)]
+    pub fan: Option<(u8, f64)>,
+}
+
+/// Reads the state saved by the last run, if there's one to read
+pub fn load(path: &Path) -> Option<SavedState> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+        Err(err) => {
+            tracing::warn!("Unable to read the state file {}: {}", path.display(), err);
+            return None;
+        }
+    };
+
+    match serde_json::from_slice(&contents) {
+        Ok(saved) => Some(saved),
+        Err(err) => {
+            tracing::warn!(
+                "Ignoring the unreadable state file {}: {}",
+                path.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Saves the current state, replacing the file at once so a power cut can't leave half of it
+pub fn save(path: &Path, state: &State) -> io::Result<()> {
+    spool::write_atomically(path, &serde_json::to_vec(&state.saved())?)
+}
+
+/// Saves the state every [`SAVE_INTERVAL`] until shutdown
+pub(crate) async fn run(path: &Path, state: &State, mut shutdown: watch::Receiver<bool>) {
+    let mut interval = tokio::time::interval(SAVE_INTERVAL);
+    // The first tick completes right away, with nothing read yet
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(err) = save(path, state) {
+                    tracing::warn!("Unable to save the state to {}: {}", path.display(), err);
+                }
+            }
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+    }
+}
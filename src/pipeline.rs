@@ -0,0 +1,623 @@
+//! The serve loop tying sensors, outputs and sinks together
+//!
+//! Every sensor runs in its own task on its own schedule, so a slow or hung sensor can't
+//! hold the others back. Data then flows through bounded queues:
+//!
+//! ```text
+//! sensor tasks -> readings queue -> aggregator -> sink queue -> sink worker
+//! ```
+//!
+//! Nothing upstream ever waits on a full queue: sensor tasks drop their batch when the readings
+//! queue is full, and the aggregator applies the configured [`DropPolicy`] to the sink queue.
+//! A slow or stalled sink can therefore never delay or skew sensor sampling.
+
+use crate::{
+    config::Sensor,
+    error::SensorError,
+    events::Event,
+    extremes::DailyExtremes,
+    gpio::Gpio,
+    groups::Groups,
+    history::History,
+    interval::Interval,
+    outputs::SensorOutputs,
+    pulse, schedule,
+    sensors::{self, read_sensor, Backend, Outcome, ReadOptions, SeriesNames},
+    service::MonitorService,
+    sinks::Sink,
+    spool::{self, Spool},
+    state::State,
+    trend::Trends,
+    Datapoint, Result,
+};
+use chrono::Local;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError, error::TryRecvError},
+    mpsc::{self, error::TrySendError},
+    watch,
+};
+use tokio::time;
+use tracing::Instrument;
+
+/// Samples every sensor on its own interval (defaulting to `refresh`), runs the
+/// alerts and control loops on the readings and ships them to the sink. Only returns if the
+/// outputs can't be set up.
+pub async fn run(
+    sensors: Vec<Sensor>,
+    refresh: Interval,
+    backend: Arc<dyn Backend>,
+    sink: Arc<dyn Sink>,
+) -> Result<()> {
+    MonitorService::builder()
+        .sensors(sensors)
+        .interval(refresh.as_duration())
+        .backend(backend)
+        .sink(sink)
+        .build()?
+        .run()
+        .await
+}
+
+/// Starts the task sampling a single sensor, returning an error if its outputs can't be set up
+pub fn spawn_sensor(
+    sensor: Sensor,
+    refresh: Interval,
+    backend: Arc<dyn Backend>,
+    gpio: Option<&Gpio>,
+    sender: mpsc::Sender<Vec<Datapoint>>,
+    state: Arc<State>,
+    options: ReadOptions,
+) -> Result<tokio::task::JoinHandle<()>> {
+    let mut outputs = SensorOutputs::new(&sensor, gpio)?;
+    if let Some(saved) = state.take_restored_outputs(&sensor.name) {
+        outputs.restore(&saved);
+    }
+    let sensor = Arc::new(sensor);
+    let fallback = sensor.interval.unwrap_or(refresh);
+    let schedule = match sensor.interval.is_none() && sensor.schedule.is_empty() {
+        true => options.schedule.clone(),
+        false => sensor.schedule.clone(),
+    };
+    let tick = schedule::shortest(&schedule, fallback);
+
+    Ok(tokio::spawn(async move {
+        let _counting = pulse::claim(&sensor);
+        if let Some(warmup) = sensors::warmup_remaining(&sensor) {
+            tracing::info!(sensor = %sensor.name, "Warming up for {}s before sampling", warmup.as_secs());
+            state.record_warming_up(&sensor.name);
+            time::sleep(warmup).await;
+        }
+
+        let period = tick.as_duration();
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(options.missed_ticks.into());
+
+        let mut sampled: Option<time::Instant> = None;
+        let mut checked = false;
+        let mut names = SeriesNames::new(&sensor);
+        for cycle in 1u64.. {
+            interval.tick().await;
+            state.record_tick();
+            if state
+                .low_power()
+                .is_some_and(|mode| !cycle.is_multiple_of(u64::from(mode.interval_factor)))
+            {
+                continue;
+            }
+            let resolution = schedule::interval_at(&schedule, Local::now().time(), fallback);
+            // Half a tick early is close enough, as the ticks don't land exactly on time
+            let due = resolution.as_duration();
+            if sampled.is_some_and(|at| at.elapsed() + period / 2 < due) {
+                continue;
+            }
+            let started = time::Instant::now();
+            sampled = Some(started);
+
+            let span = tracing::info_span!("cycle", sensor = %sensor.name, cycle);
+            let deadline = options.cycle_deadline.unwrap_or(period);
+            let outcome = async {
+                let read = read_sensor(
+                    &backend,
+                    &sensor,
+                    resolution.secs(),
+                    &state,
+                    &options,
+                    &mut names,
+                    outputs.power.as_mut(),
+                );
+                match time::timeout(deadline, read).await {
+                    Ok(Outcome::Read(datapoints)) => {
+                        outputs.apply(&sensor, &mut names, &datapoints, &state);
+                        Outcome::Read(datapoints)
+                    }
+                    Ok(outcome) => outcome,
+                    Err(_) => {
+                        let error = SensorError::Deadline(deadline);
+                        tracing::warn!("Giving up on the sensor: {}", error);
+                        let failures = state.record_error(&sensor.name, &error);
+                        sensors::power_cycle_if_due(
+                            &sensor,
+                            failures,
+                            outputs.power.as_mut(),
+                            &state,
+                        )
+                        .await;
+                        Outcome::GaveUp
+                    }
+                }
+            }
+            .instrument(span)
+            .await;
+
+            let took = started.elapsed();
+            state.record_cycle(&sensor.name, took);
+            if took > period {
+                tracing::warn!(
+                    sensor = %sensor.name,
+                    cycle,
+                    "Cycle took {:.1?}, longer than the {} interval ({:?} missed cycles)",
+                    took,
+                    resolution,
+                    options.missed_ticks
+                );
+            }
+
+            let datapoints = match outcome {
+                Outcome::Read(datapoints) => datapoints,
+                // Not written, but still the sensor's first reading as far as its range goes
+                Outcome::Implausible(metrics) => {
+                    if !checked {
+                        checked = true;
+                        let metrics = metrics
+                            .iter()
+                            .map(|(metric, value)| (metric.as_str(), *value))
+                            .collect::<Vec<_>>();
+                        check_physical_range(&sensor, &metrics, &state);
+                    }
+                    continue;
+                }
+                Outcome::GaveUp => continue,
+            };
+            if !checked {
+                checked = true;
+                let path = sensor.path();
+                let metrics = datapoints
+                    .iter()
+                    .filter_map(|datapoint| {
+                        let metric = datapoint.name.strip_prefix(&path)?.strip_prefix('.')?;
+                        Some((metric, datapoint.value))
+                    })
+                    .collect::<Vec<_>>();
+                check_physical_range(&sensor, &metrics, &state);
+            }
+            match sender.try_send(datapoints) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(sensor = %sensor.name, "Readings queue is full, dropping readings");
+                }
+                Err(TrySendError::Closed(_)) => break,
+            }
+        }
+    }))
+}
+
+/// Checks a sensor's first reading against what its type can physically report, catching
+/// swapped pins and wrong sensor types at deploy time rather than in the graphs
+fn check_physical_range(sensor: &Sensor, metrics: &[(&str, f64)], state: &State) {
+    for (metric, value) in metrics {
+        let Some((min, max)) = sensor.kind.physical_range(metric) else {
+            continue;
+        };
+        if (min..=max).contains(value) {
+            continue;
+        }
+
+        let reading = format!(
+            "{} {:.1} is outside of the {} to {} a {} sensor can report, check its pin and type",
+            metric,
+            value,
+            min,
+            max,
+            sensor.kind.name()
+        );
+        tracing::error!(sensor = %sensor.name, "Impossible first reading: {}", reading);
+        state.record_event(Event::ImpossibleReading {
+            sensor: sensor.name.clone(),
+            reading,
+        });
+    }
+}
+
+/// How many batches of readings each queue holds by default
+pub const DEFAULT_QUEUE_CAPACITY: usize = 256;
+
+/// Which readings to give up on when the sink can't keep up and its queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Make room for new readings by dropping the oldest queued ones
+    #[default]
+    Oldest,
+    /// Keep what's queued and drop new readings until there's room again
+    Newest,
+}
+
+impl FromStr for DropPolicy {
+    type Err = String;
+
+    fn from_str(policy: &str) -> std::result::Result<Self, Self::Err> {
+        match policy {
+            "oldest" => Ok(DropPolicy::Oldest),
+            "newest" => Ok(DropPolicy::Newest),
+            _ => Err(format!(
+                "unknown drop policy {}, expected oldest or newest",
+                policy
+            )),
+        }
+    }
+}
+
+/// The sending end of a sink's queue, dropping batches according to its policy when full
+pub(crate) enum SinkQueue {
+    Oldest(broadcast::Sender<Vec<Datapoint>>),
+    Newest(mpsc::Sender<Vec<Datapoint>>),
+}
+
+/// The receiving end of a sink's queue
+pub(crate) enum SinkBatches {
+    Oldest(broadcast::Receiver<Vec<Datapoint>>),
+    Newest(mpsc::Receiver<Vec<Datapoint>>),
+}
+
+pub(crate) fn sink_queue(capacity: usize, policy: DropPolicy) -> (SinkQueue, SinkBatches) {
+    match policy {
+        DropPolicy::Oldest => {
+            let (sender, receiver) = broadcast::channel(capacity);
+            (SinkQueue::Oldest(sender), SinkBatches::Oldest(receiver))
+        }
+        DropPolicy::Newest => {
+            let (sender, receiver) = mpsc::channel(capacity);
+            (SinkQueue::Newest(sender), SinkBatches::Newest(receiver))
+        }
+    }
+}
+
+impl SinkQueue {
+    pub(crate) fn push(&self, batch: Vec<Datapoint>) {
+        match self {
+            // Lagging behind is reported by the receiving end, when it finds out
+            SinkQueue::Oldest(sender) => {
+                let _ = sender.send(batch);
+            }
+            SinkQueue::Newest(sender) => {
+                if let Err(TrySendError::Full(batch)) = sender.try_send(batch) {
+                    tracing::warn!(
+                        datapoints = batch.len(),
+                        "Sink queue is full, dropping the newest readings"
+                    );
+                }
+            }
+        }
+    }
+}
+
+impl SinkBatches {
+    async fn recv(&mut self) -> Option<Vec<Datapoint>> {
+        match self {
+            SinkBatches::Oldest(receiver) => loop {
+                match receiver.recv().await {
+                    Ok(batch) => return Some(batch),
+                    Err(RecvError::Lagged(dropped)) => {
+                        tracing::warn!(dropped, "Sink queue is full, dropped the oldest readings");
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+            SinkBatches::Newest(receiver) => receiver.recv().await,
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<Vec<Datapoint>> {
+        match self {
+            SinkBatches::Oldest(receiver) => loop {
+                match receiver.try_recv() {
+                    Ok(batch) => return Some(batch),
+                    Err(TryRecvError::Lagged(dropped)) => {
+                        tracing::warn!(dropped, "Sink queue is full, dropped the oldest readings");
+                    }
+                    Err(_) => return None,
+                }
+            },
+            SinkBatches::Newest(receiver) => receiver.try_recv().ok(),
+        }
+    }
+}
+
+/// The series derived from the readings as they come in
+pub(crate) struct Derived {
+    /// How far grouped sensors disagree
+    pub(crate) groups: Groups,
+    pub(crate) extremes: Option<DailyExtremes>,
+    pub(crate) trends: Option<Trends>,
+    /// The sensors as they're added, changed or removed, to derive the series of
+    pub(crate) sensors: watch::Receiver<Vec<Sensor>>,
+}
+
+impl Derived {
+    fn extend(&mut self, readings: &mut Vec<Datapoint>) {
+        if self.sensors.has_changed().unwrap_or(false) {
+            let sensors = self.sensors.borrow_and_update().clone();
+            self.groups.update(&sensors);
+            if let Some(extremes) = &mut self.extremes {
+                extremes.update(&sensors);
+            }
+            self.trends = Trends::new(&sensors).map(|trends| trends.continuing(self.trends.take()));
+        }
+
+        let divergence = self.groups.check(readings);
+        readings.extend(divergence);
+        if let Some(extremes) = &mut self.extremes {
+            let daily = extremes.track(readings);
+            readings.extend(daily);
+        }
+        if let Some(trends) = &mut self.trends {
+            let trends = trends.track(readings);
+            readings.extend(trends);
+        }
+    }
+}
+
+/// Collects readings from the sensor tasks as soon as they arrive, batching together whatever
+/// is already queued, and hands them to the history, subscribers and the sink queue. Never
+/// waits on the sink. Warns once if more series than `series_budget` show up, and when grouped
+/// sensors disagree. Adds the daily extremes and trends, if tracked. Returns once all the sensor
+/// tasks have stopped.
+pub(crate) async fn aggregate(
+    mut receiver: mpsc::Receiver<Vec<Datapoint>>,
+    queue: SinkQueue,
+    history: &History,
+    subscribers: &broadcast::Sender<Vec<Datapoint>>,
+    series_budget: Option<usize>,
+    mut derived: Derived,
+) {
+    let mut over_budget = false;
+    while let Some(mut readings) = receiver.recv().await {
+        while let Ok(more) = receiver.try_recv() {
+            readings.extend(more);
+        }
+        derived.extend(&mut readings);
+
+        history.record(&readings);
+        if let Some(budget) = series_budget.filter(|budget| !over_budget && history.len() > *budget)
+        {
+            tracing::warn!(
+                "Writing {} series, more than the budget of {}",
+                history.len(),
+                budget
+            );
+            over_budget = true;
+        }
+        // Nobody listening is fine, and spares copying the batch
+        if subscribers.receiver_count() > 0 {
+            let _ = subscribers.send(readings.clone());
+        }
+        queue.push(readings);
+    }
+}
+
+/// Sorts a batch by time and drops the datapoints written again for the same series and time,
+/// keeping the value written last. With `merge_unchanged`, datapoints holding the same value as
+/// the series' previous one in the batch are dropped too.
+pub fn compact(readings: &mut Vec<Datapoint>, merge_unchanged: bool) {
+    // Stable, so the datapoints of the same time stay in the order they were read
+    readings.sort_by_key(|datapoint| datapoint.time);
+
+    // Compacted in place, going by the names in the batch rather than copies of them
+    let mut latest = HashMap::with_capacity(readings.len());
+    for (index, datapoint) in readings.iter().enumerate() {
+        latest.insert((datapoint.name.as_str(), datapoint.time), index);
+    }
+    let mut keep = vec![false; readings.len()];
+    let mut rewritten = Vec::new();
+    let mut previous = HashMap::new();
+    for (index, datapoint) in readings.iter().enumerate() {
+        // Only the first of the same series and time is kept, holding the last one's value
+        let Some(last) = latest.remove(&(datapoint.name.as_str(), datapoint.time)) else {
+            continue;
+        };
+        let value = readings[last].value;
+        keep[index] =
+            !merge_unchanged || previous.insert(datapoint.name.as_str(), value) != Some(value);
+        if last != index {
+            rewritten.push((index, last));
+        }
+    }
+
+    for (index, last) in rewritten {
+        readings[index].value = readings[last].value;
+        readings[index].interval = readings[last].interval;
+    }
+    let mut keep = keep.into_iter();
+    readings.retain(|_| keep.next().unwrap_or_default());
+}
+
+/// Writes queued batches to the sink, merging whatever piled up during the previous write, or
+/// until the next upload is due in low-power mode, and [`compact`]ing them. With a spool, batches that fail to write are saved to it and written
+/// again once the sink takes a batch. Returns once the aggregator has stopped and the queue is
+/// drained.
+pub(crate) async fn write_batches(
+    mut batches: SinkBatches,
+    sink: &dyn Sink,
+    state: &State,
+    spool: Option<&Spool>,
+    merge_unchanged: bool,
+) {
+    if let Some(spool) = spool {
+        evict(spool);
+        report_gap(spool);
+        backfill(spool, sink, state, merge_unchanged).await;
+    }
+
+    let mut last_upload = time::Instant::now();
+    while let Some(mut readings) = batches.recv().await {
+        // In low-power mode, more readings are collected until an upload is due, letting the
+        // radio sleep in between. Shutting down closes the queue and uploads them right away.
+        if let Some(mode) = state.low_power() {
+            let due = last_upload + mode.upload_every;
+            while let Ok(Some(more)) = time::timeout_at(due, batches.recv()).await {
+                readings.extend(more);
+            }
+        }
+        last_upload = time::Instant::now();
+        while let Some(more) = batches.try_recv() {
+            readings.extend(more);
+        }
+        compact(&mut readings, merge_unchanged);
+        if let Some(evicted) = spool.and_then(|spool| evicted_datapoint(spool, &readings)) {
+            readings.push(evicted);
+        }
+
+        match sink.write(&readings).await {
+            Ok(()) => {
+                state.record_write();
+                if let Some(spool) = spool {
+                    if let Err(err) = spool.record_sent(&readings) {
+                        tracing::warn!("Unable to record the last written datapoint: {}", err);
+                    }
+                    backfill(spool, sink, state, merge_unchanged).await;
+                }
+            }
+            Err(err) => {
+                tracing::error!("Failed to write data: {}", err);
+                state.record_write_error(&err);
+                // Batches the sink rejected outright would only be rejected again
+                if let Some(spool) = spool.filter(|_| err.is_retryable()) {
+                    match spool.push(&readings) {
+                        Ok(()) => tracing::info!(
+                            datapoints = readings.len(),
+                            "Spooled the readings to write them later"
+                        ),
+                        Err(err) => tracing::error!("Unable to spool the readings: {}", err),
+                    }
+                    evict(spool);
+                }
+            }
+        }
+    }
+}
+
+/// Evicts the oldest spooled batches past the spool's caps
+fn evict(spool: &Spool) {
+    match spool.evict() {
+        Ok(0) => {}
+        Ok(evicted) => tracing::warn!(
+            evicted,
+            total = spool.evicted(),
+            "Evicted the oldest spooled readings to stay within the spool's limits"
+        ),
+        Err(err) => tracing::error!("Unable to evict spooled readings: {}", err),
+    }
+}
+
+/// The count of datapoints evicted from the spool so far, once any were
+fn evicted_datapoint(spool: &Spool, readings: &[Datapoint]) -> Option<Datapoint> {
+    let evicted = spool.evicted();
+    let latest = readings.iter().max_by_key(|datapoint| datapoint.time)?;
+    (evicted > 0).then(|| Datapoint {
+        name: spool::EVICTED_SERIES.to_string(),
+        interval: latest.interval,
+        value: evicted as f64,
+        time: latest.time,
+    })
+}
+
+/// Logs how long it's been since the sink last took a datapoint, e.g. across a reboot
+fn report_gap(spool: &Spool) {
+    let spooled = spool
+        .pending()
+        .map(|batches| batches.len())
+        .unwrap_or_default();
+    let Some(last_sent) = spool.last_sent() else {
+        tracing::info!(spooled, "No datapoint was written before");
+        return;
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time behind Unix epoch time")
+        .as_secs() as i64;
+    let gap_secs = (now - last_sent).max(0);
+    tracing::info!(
+        gap_secs,
+        spooled,
+        "The last datapoint written is {}h {:02}m old, {} spooled batches to backfill",
+        gap_secs / 3600,
+        gap_secs % 3600 / 60,
+        spooled
+    );
+}
+
+/// Writes the spooled batches, oldest first, until the sink fails to take one
+async fn backfill(spool: &Spool, sink: &dyn Sink, state: &State, merge_unchanged: bool) {
+    let pending = match spool.pending() {
+        Ok(pending) => pending,
+        Err(err) => {
+            tracing::error!("Unable to read the spool: {}", err);
+            return;
+        }
+    };
+
+    for batch in pending {
+        let readings = match spool.load(&batch) {
+            Ok(mut readings) => {
+                compact(&mut readings, merge_unchanged);
+                readings
+            }
+            // Most likely spooled under another key, which may be back later
+            Err(err) if spool::is_sealed(&batch) => {
+                tracing::warn!(
+                    "Skipping spooled batch {}, it can't be opened: {}",
+                    batch.display(),
+                    err
+                );
+                continue;
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Dropping unreadable spooled batch {}: {}",
+                    batch.display(),
+                    err
+                );
+                let _ = spool.remove(&batch);
+                continue;
+            }
+        };
+
+        match sink.write(&readings).await {
+            Ok(()) => {
+                tracing::info!(datapoints = readings.len(), "Backfilled spooled readings");
+                state.record_write();
+                if let Err(err) = spool
+                    .remove(&batch)
+                    .and_then(|()| spool.record_sent(&readings))
+                {
+                    tracing::warn!("Unable to update the spool: {}", err);
+                }
+            }
+            Err(err) if err.is_retryable() => {
+                tracing::warn!("Failed to backfill spooled readings: {}", err);
+                state.record_write_error(&err);
+                return;
+            }
+            Err(err) => {
+                tracing::error!("Dropping spooled readings the sink rejected: {}", err);
+                let _ = spool.remove(&batch);
+            }
+        }
+    }
+}
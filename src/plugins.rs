@@ -0,0 +1,57 @@
+//! External sensor plugins
+//!
+//! A sensor with `type: command` is read by running its `command` once per reading. The
+//! protocol is deliberately small so plugins can be written in any language:
+//!
+//! - The program is started with no stdin and the `MONITORING_SENSOR` environment variable
+//!   set to the sensor's name.
+//! - On success it prints a single JSON object mapping metric names to numbers on stdout,
+//!   e.g. `{"co2": 612, "temperature": 22.4}`, and exits with status 0. Every key becomes a
+//!   `<sensor name>.<metric>` series.
+//! - Any other exit status is a failed read and the read is retried, anything the program
+//!   wrote to stderr is logged.
+//! - A plugin that doesn't finish within the sensor's `timeout_secs` is killed.
+
+use crate::{config::Sensor, error::SensorError};
+use std::{collections::BTreeMap, process::Stdio, time::Duration};
+use tokio::process::Command;
+
+/// How long a plugin may run unless the sensor sets `timeout_secs`
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+/// Runs the sensor's plugin command once and parses the metrics it reports
+pub async fn read(sensor: &Sensor, timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    let (program, args) = sensor
+        .command
+        .split_first()
+        .ok_or_else(|| SensorError::Plugin("no command configured".to_string()))?;
+
+    let child = Command::new(program)
+        .args(args)
+        .env("MONITORING_SENSOR", &sensor.name)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|err| SensorError::Plugin(format!("unable to start {}: {}", program, err)))?;
+
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| SensorError::Timeout)?
+        .map_err(|err| SensorError::Plugin(err.to_string()))?;
+
+    if !output.status.success() {
+        return Err(SensorError::Plugin(format!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let metrics: BTreeMap<String, f64> = serde_json::from_slice(&output.stdout)
+        .map_err(|err| SensorError::Plugin(format!("invalid output from {}: {}", program, err)))?;
+
+    Ok(metrics.into_iter().collect())
+}
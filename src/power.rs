@@ -0,0 +1,226 @@
+//! Saving power while running on battery
+//!
+//! On UPS HATs and solar installs the service can switch to a low-power mode while the mains is
+//! out, as signalled by a [`PowerTrigger`]: a GPIO input driven by the UPS, the supply voltage
+//! measured by an INA219 dropping below a threshold, or a flag file some other daemon creates.
+//! In low-power mode the sensors are only read every few cycles, the HTTP API answers nothing
+//! but its health checks, and the readings are uploaded in batches rather than as they're taken,
+//! so the radio can sleep in between. Everything is back to normal once the trigger clears.
+
+use crate::state::State;
+use std::{io, path::PathBuf, str::FromStr, time::Duration};
+use tokio::sync::watch;
+
+/// How often the trigger is checked by default
+pub const DEFAULT_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The INA219's I2C address with both of its address pins low
+pub const DEFAULT_INA219_ADDRESS: u16 = 0x40;
+
+/// How far above its threshold the supply voltage has to come back before leaving low-power mode,
+/// so that a voltage hovering around it doesn't flip the mode every check
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+const VOLTAGE_HYSTERESIS: f32 = 0.1;
+
+/// What switches the service to low-power mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum PowerTrigger {
+    /// A GPIO input going high, or low when `active_low`, e.g. a UPS HAT's power-loss pin
+    Gpio { pin: u8, active_low: bool },
+    /// The bus voltage measured by an INA219 dropping below `below` volts
+    Ina219 { below: f32, address: u16 },
+    /// A file existing
+    File(PathBuf),
+}
+
+impl FromStr for PowerTrigger {
+    type Err = String;
+
+    /// Parses `gpio:<pin>[:low]`, `ina219:<volts>[@<address>]` or `file:<path>`
+    fn from_str(trigger: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid power trigger {}, expected gpio:<pin>[:low], ina219:<volts>[@<address>] or file:<path>",
+                trigger
+            )
+        };
+        let (kind, spec) = trigger.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "gpio" => {
+                let (pin, active_low) = match spec.split_once(':') {
+                    Some((pin, "low")) => (pin, true),
+                    Some(_) => return Err(invalid()),
+                    None => (spec, false),
+                };
+                let pin = pin.parse().map_err(|_| invalid())?;
+                Ok(PowerTrigger::Gpio { pin, active_low })
+            }
+            "ina219" => {
+                let (below, address) = match spec.split_once('@') {
+                    Some((below, address)) => {
+                        let address = match address.strip_prefix("0x") {
+                            Some(hex) => u16::from_str_radix(hex, 16),
+                            None => address.parse(),
+                        }
+                        .map_err(|_| invalid())?;
+                        (below, address)
+                    }
+                    None => (spec, DEFAULT_INA219_ADDRESS),
+                };
+                let below = below
+                    .parse()
+                    .ok()
+                    .filter(|below: &f32| *below > 0.0)
+                    .ok_or_else(invalid)?;
+                Ok(PowerTrigger::Ina219 { below, address })
+            }
+            "file" if !spec.is_empty() => Ok(PowerTrigger::File(spec.into())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How the service runs while in low-power mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowPowerMode {
+    /// Read the sensors only every this many cycles
+    pub interval_factor: u32,
+    /// Upload the readings at most this often
+    pub upload_every: Duration,
+}
+
+impl Default for LowPowerMode {
+    fn default() -> Self {
+        LowPowerMode {
+            interval_factor: 4,
+            upload_every: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LowPowerConfig {
+    pub trigger: PowerTrigger,
+    pub mode: LowPowerMode,
+    /// How often the trigger is checked (default: [`DEFAULT_CHECK_INTERVAL`])
+    pub check_every: Duration,
+}
+
+impl LowPowerConfig {
+    pub fn new(trigger: PowerTrigger) -> Self {
+        LowPowerConfig {
+            trigger,
+            mode: LowPowerMode::default(),
+            check_every: DEFAULT_CHECK_INTERVAL,
+        }
+    }
+}
+
+/// A trigger ready to be checked, holding on to the hardware it reads
+pub(crate) enum Monitor {
+    Gpio {
+        pin: crate::gpio::InputPin,
+        active_low: bool,
+    },
+    #[cfg(all(feature = "gpio", target_os = "linux"))]
+    Ina219 {
+        i2c: rppal::i2c::I2c,
+        below: f32,
+        /// Whether the voltage was below the threshold on the last check
+        low: bool,
+    },
+    File(PathBuf),
+}
+
+impl Monitor {
+    /// Claims the trigger's pin or I2C bus, while still running as root
+    pub(crate) fn open(trigger: &PowerTrigger) -> io::Result<Self> {
+        match trigger {
+            PowerTrigger::Gpio { pin, active_low } => {
+                let gpio = crate::gpio::Gpio::new().map_err(io::Error::other)?;
+                Ok(Monitor::Gpio {
+                    pin: gpio.input(*pin).map_err(io::Error::other)?,
+                    active_low: *active_low,
+                })
+            }
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            PowerTrigger::Ina219 { below, address } => {
+                let mut i2c = rppal::i2c::I2c::new().map_err(io::Error::other)?;
+                i2c.set_slave_address(*address).map_err(io::Error::other)?;
+                Ok(Monitor::Ina219 {
+                    i2c,
+                    below: *below,
+                    low: false,
+                })
+            }
+            #[cfg(not(all(feature = "gpio", target_os = "linux")))]
+            PowerTrigger::Ina219 { .. } => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "an INA219 can only be read on Linux, when built with the gpio feature",
+            )),
+            PowerTrigger::File(path) => Ok(Monitor::File(path.clone())),
+        }
+    }
+
+    /// Whether the service should be in low-power mode
+    fn check(&mut self) -> io::Result<bool> {
+        match self {
+            Monitor::Gpio { pin, active_low } => Ok(pin.is_high() != *active_low),
+            #[cfg(all(feature = "gpio", target_os = "linux"))]
+            Monitor::Ina219 { i2c, below, low } => {
+                let volts = ina219_bus_voltage(i2c)?;
+                tracing::debug!("The supply is at {:.2}V", volts);
+                let threshold = if *low {
+                    *below + VOLTAGE_HYSTERESIS
+                } else {
+                    *below
+                };
+                *low = volts < threshold;
+                Ok(*low)
+            }
+            Monitor::File(path) => path.try_exists(),
+        }
+    }
+}
+
+/// Reads the INA219's bus voltage register, in 4mV steps from its 4th bit on
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+fn ina219_bus_voltage(i2c: &mut rppal::i2c::I2c) -> io::Result<f32> {
+    const BUS_VOLTAGE: u8 = 0x02;
+    let mut register = [0; 2];
+    i2c.write_read(&[BUS_VOLTAGE], &mut register)
+        .map_err(io::Error::other)?;
+
+    Ok(f32::from(u16::from_be_bytes(register) >> 3) * 0.004)
+}
+
+/// Checks the trigger every `check_every` until shutdown, switching low-power mode on and off
+pub(crate) async fn run(
+    mut monitor: Monitor,
+    config: &LowPowerConfig,
+    state: &State,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(config.check_every);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let active = state.low_power().is_some();
+                match monitor.check() {
+                    Ok(low_power) if low_power != active => {
+                        if low_power {
+                            tracing::warn!("Running on battery, switching to low-power mode");
+                            state.set_low_power(Some(config.mode));
+                        } else {
+                            tracing::info!("Back on mains power, leaving low-power mode");
+                            state.set_low_power(None);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("Unable to check the power supply: {}", err),
+                }
+            }
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! Dropping root once everything that needs it is set up
+//!
+//! The GPIO, I2C and SPI devices (`/dev/gpiomem`, `/dev/i2c-*`, `/dev/spidev*`) belong to the
+//! `gpio`, `i2c` and `spi` groups on Raspberry Pi OS, and serial ports to `dialout`, so a user in
+//! those groups can read every sensor. Root is only needed for listening on a port below 1024,
+//! after which the service switches to that user with [`RunAs::apply`].
+
+use crate::error::ConfigError;
+use std::io;
+#[cfg(unix)]
+use std::{ffi::CString, mem::MaybeUninit, ptr};
+
+/// Large enough for any passwd or group entry
+#[cfg(unix)]
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// A user (and group) the service switches to after startup
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct RunAs {
+    user: CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+#[cfg(unix)]
+impl RunAs {
+    /// Looks up a user by name or ID, running with its primary group unless `group` (a name
+    /// or ID) is given
+    pub fn lookup(user: &str, group: Option<&str>) -> Result<Self, ConfigError> {
+        let invalid =
+            |kind: &str, name: &str| ConfigError::Invalid(format!("unknown {} {}", kind, name));
+        let name = CString::new(user).map_err(|_| invalid("user", user))?;
+
+        let mut entry = MaybeUninit::<libc::passwd>::uninit();
+        let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+        let mut found = ptr::null_mut();
+        // SAFETY: every pointer is valid for the duration of the call, and the entry is only
+        // read once the lookup reported finding it
+        let (uid, primary_gid, user) = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                entry.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            );
+            if found.is_null() {
+                let uid = user.parse().map_err(|_| invalid("user", user))?;
+                libc::getpwuid_r(
+                    uid,
+                    entry.as_mut_ptr(),
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut found,
+                );
+            }
+            if found.is_null() {
+                return Err(invalid("user", user));
+            }
+            let entry = entry.assume_init();
+            let user = std::ffi::CStr::from_ptr(entry.pw_name).to_owned();
+            (entry.pw_uid, entry.pw_gid, user)
+        };
+
+        let gid = match group {
+            Some(group) => lookup_group(group).ok_or_else(|| invalid("group", group))?,
+            None => primary_gid,
+        };
+
+        Ok(RunAs { user, uid, gid })
+    }
+
+    /// Switches the whole process to the user, its supplementary groups and the group
+    pub fn apply(&self) -> io::Result<()> {
+        // SAFETY: plain libc calls, with the user's name a valid C string; glibc and musl apply
+        // the IDs to every thread of the process
+        unsafe {
+            check(libc::initgroups(self.user.as_ptr(), self.gid as _))?;
+            check(libc::setgid(self.gid))?;
+            check(libc::setuid(self.uid))?;
+        }
+
+        tracing::info!(
+            uid = self.uid,
+            gid = self.gid,
+            "Dropped privileges to {}",
+            self.user.to_string_lossy()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Option<libc::gid_t> {
+    let name = CString::new(group).ok()?;
+    let mut entry = MaybeUninit::<libc::group>::uninit();
+    let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+    let mut found = ptr::null_mut();
+
+    // SAFETY: as for the user's lookup
+    unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            entry.as_mut_ptr(),
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        );
+        if found.is_null() {
+            return group.parse().ok();
+        }
+        Some(entry.assume_init().gr_gid)
+    }
+}
+
+#[cfg(unix)]
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Users can only be switched on Unix; elsewhere the service keeps running as whoever started it
+#[cfg(not(unix))]
+#[derive(Debug, Clone)]
+pub struct RunAs;
+
+#[cfg(not(unix))]
+impl RunAs {
+    pub fn lookup(user: &str, _group: Option<&str>) -> Result<Self, ConfigError> {
+        Err(ConfigError::Invalid(format!(
+            "can't switch to {}, users can only be switched on Unix",
+            user
+        )))
+    }
+
+    pub fn apply(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
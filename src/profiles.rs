@@ -0,0 +1,227 @@
+//! Isolated sets of sensors run side by side in one process, e.g. for the tenants sharing a Pi
+//!
+//! A profiles file lists the profiles, each with the sensors in the `sensors.yaml` format and
+//! its own metrics endpoint, API key, refresh time, HTTP API, spool and state:
+//!
+//! ```yaml
+//! - name: alice
+//!   endpoint: https://graphite-alice.example/metrics
+//!   apikey: 123:abc
+//!   listen: 0.0.0.0:8081
+//!   spool_dir: /var/spool/monitoring/alice
+//!   sensors:
+//!     - name: living_room
+//!       pin: 4
+//! - name: bob
+//!   endpoint: https://graphite-bob.example/metrics
+//!   apikey: 456:def
+//!   refresh_time: 5m
+//!   sensors:
+//!     - name: garage
+//!       pin: 17
+//! ```
+//!
+//! Every profile runs as a [`MonitorService`](crate::service::MonitorService) of its own, so
+//! one tenant's readings, alerts and failures never reach another's endpoint. The services share
+//! a single [`HardwareAccess`](crate::sensors::HardwareAccess), so sensors on a bus shared
+//! between profiles are still read one at a time. A GPIO pin, serial device or the radio can
+//! only belong to one profile.
+//!
+//! Sensors added, changed or removed over a profile's HTTP API are written back to its entry in
+//! the profiles file.
+
+use crate::{
+    config::{self, Sensor, SensorType},
+    error::ConfigError,
+    interval::Interval,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Profile {
+    /// Tells the profile's logs apart, e.g. `alice`
+    pub name: String,
+
+    pub sensors: Vec<Sensor>,
+
+    /// The metrics endpoint the profile's readings are posted to (default: `--endpoint`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+
+    /// The API key for the profile's endpoint (default: `--apikey`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apikey: Option<String>,
+
+    /// How often the profile's sensors without an `interval` are sampled (default:
+    /// `--refresh-time`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_time: Option<Interval>,
+
+    /// Serve the profile's readings over HTTP on this address
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen: Option<SocketAddr>,
+
+    /// Require this bearer token for the profile's HTTP API
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_token: Option<String>,
+
+    /// Spool the batches the profile's endpoint didn't take to this directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spool_dir: Option<PathBuf>,
+
+    /// Encrypt the profile's spooled batches with the key in this file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spool_key_file: Option<PathBuf>,
+
+    /// Keep the profile's state across restarts in this file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<PathBuf>,
+}
+
+/// Loads and validates a profiles file
+pub fn load(path: &Path) -> Result<Vec<Profile>, ConfigError> {
+    let profiles =
+        fs::read_to_string(path).map_err(|err| ConfigError::from_io(path.to_path_buf(), err))?;
+
+    let profiles: Vec<Profile> = serde_yaml::from_str(&profiles)?;
+    validate(&profiles)?;
+
+    Ok(profiles)
+}
+
+/// Serializes the profiles' changes to their file, so that one can't undo another's
+static SAVING: Mutex<()> = Mutex::new(());
+
+/// Writes a profile's sensors back to the profiles file, leaving the other profiles as they are
+/// and replacing the file in one go. Fails without writing if the change would clash with
+/// another profile.
+pub async fn save_sensors(path: &Path, name: &str, sensors: &[Sensor]) -> Result<(), ConfigError> {
+    let _saving = SAVING.lock().expect("Profiles lock poisoned");
+    let mut profiles = load(path)?;
+    let profile = profiles
+        .iter_mut()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| {
+            ConfigError::Invalid(format!(
+                "profile {} is no longer in {}",
+                name,
+                path.display()
+            ))
+        })?;
+    profile.sensors = sensors.to_vec();
+    validate(&profiles)?;
+
+    let yaml = serde_yaml::to_string(&profiles)?;
+    let temporary = path.with_extension("yaml.tmp");
+    fs::write(&temporary, yaml)
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|err| ConfigError::from_io(path.to_path_buf(), err))
+}
+
+/// Checks that every profile has a unique name and valid sensors, and that the profiles don't
+/// share the hardware or the files and addresses they write to
+pub fn validate(profiles: &[Profile]) -> Result<(), ConfigError> {
+    if profiles.is_empty() {
+        return Err(ConfigError::Invalid(
+            "at least one profile is needed".to_string(),
+        ));
+    }
+
+    let mut names = HashSet::new();
+    let mut claimed = HashMap::<String, &str>::new();
+    for profile in profiles {
+        if profile.name.is_empty()
+            || !profile
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(ConfigError::Invalid(format!(
+                "invalid profile name {:?}, use letters, digits, _ and -",
+                profile.name
+            )));
+        }
+        if !names.insert(&profile.name) {
+            return Err(ConfigError::Invalid(format!(
+                "profile {} is configured more than once",
+                profile.name
+            )));
+        }
+        config::validate(&profile.sensors).map_err(|err| match err {
+            ConfigError::Invalid(reason) => {
+                ConfigError::Invalid(format!("profile {}: {}", profile.name, reason))
+            }
+            err => err,
+        })?;
+
+        // Once per profile, as its sensors may share them among themselves
+        let resources = claims(profile).collect::<HashSet<_>>();
+        for resource in resources {
+            if let Some(other) = claimed.insert(resource.clone(), &profile.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "{} is used by both profiles {} and {}",
+                    resource, other, profile.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// What a profile needs to itself: its GPIO pins, serial devices and the radio, and the files
+/// and addresses it writes to and listens on
+fn claims(profile: &Profile) -> impl Iterator<Item = String> + '_ {
+    let sensors = profile.sensors.iter().filter(|sensor| !sensor.disabled);
+    let pins = sensors
+        .clone()
+        .flat_map(|sensor| {
+            [
+                sensor.pin,
+                sensor.power_pin,
+                sensor.control.as_ref().map(|control| control.gpio.pin),
+                sensor.fan.as_ref().map(|fan| fan.pin),
+            ]
+            .into_iter()
+            .chain(
+                sensor
+                    .alerts
+                    .iter()
+                    .map(|alert| alert.gpio.as_ref().map(|gpio| gpio.pin)),
+            )
+        })
+        .flatten()
+        .map(|pin| format!("GPIO {}", pin));
+    let devices = sensors.clone().filter_map(|sensor| match sensor.kind {
+        SensorType::Serial => sensor
+            .device
+            .as_ref()
+            .map(|device| format!("serial device {}", device)),
+        SensorType::Radio => Some("the radio".to_string()),
+        _ => None,
+    });
+    let outputs = [
+        profile
+            .listen
+            .map(|addr| format!("the HTTP API address {}", addr)),
+        profile
+            .spool_dir
+            .as_ref()
+            .map(|dir| format!("spool directory {}", dir.display())),
+        profile
+            .state_file
+            .as_ref()
+            .map(|path| format!("state file {}", path.display())),
+    ]
+    .into_iter()
+    .flatten();
+
+    pins.chain(devices).chain(outputs)
+}
@@ -0,0 +1,131 @@
+//! Reed switches pulsing a GPIO pin, e.g. rain gauges and anemometers
+//!
+//! A sensor with `type: pulse` counts the falling edges on its `pin` on an interrupt, from its
+//! first reading on, so no pulse is missed between cycles. Every reading then reports, in units
+//! of `pulses_per_unit` pulses:
+//!
+//! - `<metric>` - the amount counted since the last reading, e.g. the mm of rain in the interval
+//! - `<metric>.rate` - that amount per `rate_per`, e.g. the rain in mm/h or the wind in km/h
+//! - `<metric>.total` - the amount counted since the first reading
+//!
+//! The first reading only starts the count, so its amount and rate are 0. The count stops, and
+//! the pin is released, along with the sensor's task, e.g. when the sensor is removed over the
+//! HTTP API. Without GPIO support the pin is a stub that never pulses.
+
+use crate::{
+    config::{Sensor, SensorType},
+    error::SensorError,
+    gpio::{Gpio, PulseCounter},
+    interval::Interval,
+};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Metric name unless the sensor sets `metric`
+pub const DEFAULT_METRIC: &str = "pulses";
+
+/// How long a switch may bounce for unless the sensor sets `debounce_ms`, which still counts up
+/// to 100 pulses a second
+pub const DEFAULT_DEBOUNCE_MS: u64 = 10;
+
+/// A pin's counter and where it was at when the pin was last read
+struct Count {
+    counter: PulseCounter,
+    pull_up: bool,
+    debounce: Duration,
+    read: u64,
+    read_at: Instant,
+}
+
+static COUNTS: Mutex<Vec<Count>> = Mutex::new(Vec::new());
+
+/// Stops counting on a pulse sensor's pin once dropped
+pub struct Claim {
+    pin: u8,
+}
+
+/// Ties the count on a pulse sensor's pin to the sensor's task holding the returned claim, so
+/// the pin isn't counted on, and can't be claimed again, after the sensor is gone
+pub fn claim(sensor: &Sensor) -> Option<Claim> {
+    match sensor.kind {
+        SensorType::Pulse => sensor.pin.map(|pin| Claim { pin }),
+        _ => None,
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        COUNTS
+            .lock()
+            .expect("Pulse counts lock poisoned")
+            .retain(|count| count.counter.pin() != self.pin);
+    }
+}
+
+/// Takes the pulses counted on the sensor's pin since the last read, starting to count on the
+/// first one or when the sensor's `pull_up` or `debounce_ms` changed
+pub fn read(sensor: &Sensor) -> Result<Vec<(String, f64)>, SensorError> {
+    let pin = sensor
+        .pin
+        .expect("Pulse sensors are validated to have a pin");
+    let debounce = sensor.debounce_ms.map_or(
+        Duration::from_millis(DEFAULT_DEBOUNCE_MS),
+        Interval::as_duration,
+    );
+
+    let mut counts = COUNTS.lock().expect("Pulse counts lock poisoned");
+    // Dropping a counter releases its pin, so it can be claimed again with the new settings
+    counts.retain(|count| {
+        count.counter.pin() != pin
+            || (count.pull_up == sensor.pull_up && count.debounce == debounce)
+    });
+    let count = match counts.iter().position(|count| count.counter.pin() == pin) {
+        Some(index) => &mut counts[index],
+        None => {
+            let counter = Gpio::new()
+                .and_then(|gpio| gpio.pulse_counter(pin, sensor.pull_up, debounce))
+                .map_err(SensorError::Gpio)?;
+            tracing::info!(pin, "Counting pulses");
+            counts.push(Count {
+                counter,
+                pull_up: sensor.pull_up,
+                debounce,
+                read: 0,
+                read_at: Instant::now(),
+            });
+            counts.last_mut().expect("Pulse count just pushed")
+        }
+    };
+
+    let total = count.counter.count();
+    let pulses = total - count.read;
+    let elapsed = count.read_at.elapsed();
+    count.read = total;
+    count.read_at = Instant::now();
+
+    Ok(metrics(sensor, pulses, total, elapsed))
+}
+
+/// The sensor's metrics for `pulses` counted over `elapsed`, out of `total` since it started
+/// counting
+pub fn metrics(sensor: &Sensor, pulses: u64, total: u64, elapsed: Duration) -> Vec<(String, f64)> {
+    let metric = sensor.metric.as_deref().unwrap_or(DEFAULT_METRIC);
+    let per_unit = sensor.pulses_per_unit.unwrap_or(1.0);
+    let rate_per = sensor
+        .rate_per
+        .map_or(Duration::from_secs(1), |rate_per| rate_per.as_duration());
+
+    let amount = pulses as f64 / per_unit;
+    let rate = match elapsed.is_zero() {
+        true => 0.0,
+        false => amount * rate_per.as_secs_f64() / elapsed.as_secs_f64(),
+    };
+
+    vec![
+        (metric.to_string(), amount),
+        (format!("{}.rate", metric), rate),
+        (format!("{}.total", metric), total as f64 / per_unit),
+    ]
+}
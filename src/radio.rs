@@ -0,0 +1,234 @@
+//! Remote nodes sending readings over an RFM69 packet radio (behind the `gpio` feature, on Linux)
+//!
+//! For sensors out of WiFi range - beehives, wells, the far end of the garden - a node with an
+//! RFM69 module sends a packet whenever it has a reading. The Pi receives them through its own
+//! RFM69 on the SPI bus, which every `type: radio` sensor shares.
+//!
+//! Radio settings: FSK at 4.8 kbps with 9.6 kHz deviation, whitening, CRC, variable length
+//! packets and the sync words `0x2D 0xD4` - RadioHead's `FSK_Rb4_8Fd9_6` modem config, so
+//! nodes can be written with RadioHead's `RH_RF69` driver. The first payload byte is the
+//! node ID, the rest is the reading formatted like a serial sensor's lines, e.g.
+//! `{"temperature": 14.2}` or `14.2,88` for `format: csv`.
+
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+use crate::serial;
+use crate::{config::Sensor, error::SensorError};
+use std::time::Duration;
+
+/// Radio frequency unless the sensors set `frequency`, in MHz
+pub const DEFAULT_FREQUENCY_MHZ: f32 = 868.0;
+
+/// Waits for the next packet from the sensor's node, if one didn't arrive since the last read,
+/// and parses its metrics
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+pub async fn read(sensor: &Sensor, timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    let node = sensor
+        .node
+        .ok_or_else(|| SensorError::Radio("no node ID configured".to_string()))?;
+    let radio = rfm69::shared(
+        sensor.frequency.unwrap_or(DEFAULT_FREQUENCY_MHZ),
+        sensor.chip_select.unwrap_or_default(),
+    )?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if let Some(packet) = radio.take(node) {
+            let reading = String::from_utf8_lossy(&packet);
+            return serial::parse_line(reading.trim(), sensor).map_err(|reason| {
+                SensorError::Radio(format!("invalid packet from node {}: {}", node, reason))
+            });
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(SensorError::Timeout);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+#[cfg(not(all(feature = "gpio", target_os = "linux")))]
+pub async fn read(sensor: &Sensor, _timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    Err(SensorError::Radio(format!(
+        "built without GPIO support, can't receive from node {}",
+        sensor.node.unwrap_or_default()
+    )))
+}
+
+#[cfg(all(feature = "gpio", target_os = "linux"))]
+mod rfm69 {
+    use crate::error::SensorError;
+    use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    const REG_FIFO: u8 = 0x00;
+    const REG_OP_MODE: u8 = 0x01;
+    const REG_FRF_MSB: u8 = 0x07;
+    const REG_VERSION: u8 = 0x10;
+    const REG_IRQ_FLAGS_2: u8 = 0x28;
+
+    const MODE_STANDBY: u8 = 0x04;
+    const MODE_RX: u8 = 0x10;
+    const IRQ_PAYLOAD_READY: u8 = 0x04;
+    const VERSION: u8 = 0x24;
+    /// The frequency synthesizer's step, 32 MHz / 2^19
+    const FSTEP_HZ: f64 = 61.035_156_25;
+
+    /// The modem registers, see the module documentation
+    const CONFIG: &[(u8, u8)] = &[
+        (0x02, 0x00), // Packet mode, FSK, no shaping
+        (0x03, 0x1A), // Bitrate 4.8 kbps
+        (0x04, 0x0B),
+        (0x05, 0x00), // Frequency deviation 9.6 kHz
+        (0x06, 0x9D),
+        (0x19, 0xF4), // Receiver and AFC bandwidth
+        (0x1A, 0xF4),
+        (0x2C, 0x00), // 4 preamble bytes
+        (0x2D, 0x04),
+        (0x2E, 0x88), // 2 sync words
+        (0x2F, 0x2D),
+        (0x30, 0xD4),
+        (0x37, 0xD0), // Variable length, whitening, CRC, no address filtering
+        (0x38, 0x40), // Up to 64 byte payloads
+        (0x3D, 0x02), // Restart receiving after a packet without waiting for the FIFO to clear
+        (0x6F, 0x30), // Improved fading margin
+    ];
+
+    /// The radio and the latest packet received from every node
+    pub(super) struct Radio {
+        frequency: f32,
+        chip_select: u8,
+        packets: Mutex<HashMap<u8, Vec<u8>>>,
+    }
+
+    impl Radio {
+        /// The latest packet from `node`, if any arrived since the last call
+        pub fn take(&self, node: u8) -> Option<Vec<u8>> {
+            self.packets
+                .lock()
+                .expect("Radio lock poisoned")
+                .remove(&node)
+        }
+    }
+
+    static RADIO: Mutex<Option<Arc<Radio>>> = Mutex::new(None);
+
+    /// The radio every sensor shares, set up and started receiving on first use
+    pub(super) fn shared(frequency: f32, chip_select: u8) -> Result<Arc<Radio>, SensorError> {
+        let mut shared = RADIO.lock().expect("Radio lock poisoned");
+        if let Some(radio) = shared.as_ref() {
+            if radio.frequency != frequency || radio.chip_select != chip_select {
+                return Err(SensorError::Radio(format!(
+                    "the radio is already listening on {} MHz with chip select {}",
+                    radio.frequency, radio.chip_select
+                )));
+            }
+            return Ok(radio.clone());
+        }
+
+        let spi = setup(frequency, chip_select)?;
+        let radio = Arc::new(Radio {
+            frequency,
+            chip_select,
+            packets: Mutex::new(HashMap::new()),
+        });
+        thread::spawn({
+            let radio = radio.clone();
+            move || receive(&spi, &radio)
+        });
+        tracing::info!(frequency, "Listening on the RFM69 radio");
+
+        *shared = Some(radio.clone());
+        Ok(radio)
+    }
+
+    fn setup(frequency: f32, chip_select: u8) -> Result<Spi, SensorError> {
+        let slave = match chip_select {
+            0 => SlaveSelect::Ss0,
+            1 => SlaveSelect::Ss1,
+            other => return Err(SensorError::Radio(format!("no SPI chip select {}", other))),
+        };
+        let spi = Spi::new(Bus::Spi0, slave, 1_000_000, Mode::Mode0).map_err(radio_error)?;
+
+        let version = read_register(&spi, REG_VERSION)?;
+        if version != VERSION {
+            return Err(SensorError::Radio(format!(
+                "no RFM69 found (version register {:#04x})",
+                version
+            )));
+        }
+
+        write_register(&spi, REG_OP_MODE, MODE_STANDBY)?;
+        for (register, value) in CONFIG {
+            write_register(&spi, *register, *value)?;
+        }
+        let frf = (f64::from(frequency) * 1_000_000.0 / FSTEP_HZ) as u32;
+        for (offset, byte) in frf.to_be_bytes()[1..].iter().enumerate() {
+            write_register(&spi, REG_FRF_MSB + offset as u8, *byte)?;
+        }
+        write_register(&spi, REG_OP_MODE, MODE_RX)?;
+
+        Ok(spi)
+    }
+
+    /// Polls for received packets for as long as the process runs
+    fn receive(spi: &Spi, radio: &Radio) {
+        loop {
+            match read_packet(spi) {
+                Ok(Some((node, payload))) => {
+                    tracing::debug!(node, bytes = payload.len(), "Received a radio packet");
+                    radio
+                        .packets
+                        .lock()
+                        .expect("Radio lock poisoned")
+                        .insert(node, payload);
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(10)),
+                Err(err) => {
+                    tracing::warn!("Failed to receive from the radio: {}", err);
+                    thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    /// The node ID and payload of a received packet, if there's one waiting
+    fn read_packet(spi: &Spi) -> Result<Option<(u8, Vec<u8>)>, SensorError> {
+        if read_register(spi, REG_IRQ_FLAGS_2)? & IRQ_PAYLOAD_READY == 0 {
+            return Ok(None);
+        }
+
+        let length = usize::from(read_register(spi, REG_FIFO)?);
+        let mut packet = vec![0; length + 1];
+        let mut command = vec![0; length + 1];
+        command[0] = REG_FIFO;
+        spi.transfer(&mut packet, &command).map_err(radio_error)?;
+
+        // The first byte clocked out while sending the address is meaningless
+        Ok(match &packet[1..] {
+            [node, payload @ ..] => Some((*node, payload.to_vec())),
+            [] => None,
+        })
+    }
+
+    fn read_register(spi: &Spi, register: u8) -> Result<u8, SensorError> {
+        let mut buffer = [0; 2];
+        spi.transfer(&mut buffer, &[register & 0x7F, 0])
+            .map_err(radio_error)?;
+        Ok(buffer[1])
+    }
+
+    fn write_register(spi: &Spi, register: u8, value: u8) -> Result<(), SensorError> {
+        let mut buffer = [0; 2];
+        spi.transfer(&mut buffer, &[register | 0x80, value])
+            .map_err(radio_error)?;
+        Ok(())
+    }
+
+    fn radio_error(err: rppal::spi::Error) -> SensorError {
+        SensorError::Radio(err.to_string())
+    }
+}
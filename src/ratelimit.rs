@@ -0,0 +1,77 @@
+//! Spacing out the requests posted to the metrics endpoint and Grafana
+//!
+//! A single [`RateLimiter`] is shared by everything posting there: the sink's writes, their
+//! retries and the spooled batches backfilled after an outage, the split parts of oversized
+//! batches, and the annotations. Requests over the limit wait their turn rather than fail, so a
+//! large backlog is written as fast as the limit allows and no faster.
+
+use std::{str::FromStr, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+/// At most `requests` requests every `per`, e.g. `10/s` or `300/min`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(limit: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid rate limit {}, expected <requests>/s, /min or /h, e.g. 10/s",
+                limit
+            )
+        };
+        let (requests, per) = limit.split_once('/').ok_or_else(invalid)?;
+        let requests = requests
+            .trim()
+            .parse()
+            .ok()
+            .filter(|requests| *requests > 0)
+            .ok_or_else(invalid)?;
+        let per = match per.trim() {
+            "s" => Duration::from_secs(1),
+            "min" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+
+        Ok(RateLimit { requests, per })
+    }
+}
+
+/// A token bucket holding a single token, so requests are evenly spaced and never burst over
+/// the limit
+pub struct RateLimiter {
+    /// How long it takes for the next request to be allowed
+    spacing: Duration,
+    /// When the next request is allowed
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        RateLimiter {
+            spacing: limit.per / limit.requests,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until another request is allowed, taking its turn
+    pub async fn acquire(&self) {
+        let turn = {
+            let mut next = self.next.lock().expect("Rate limiter lock poisoned");
+            let turn = (*next).max(Instant::now());
+            *next = turn + self.spacing;
+            turn
+        };
+
+        if turn > Instant::now() {
+            tracing::debug!("Rate limited, waiting {:?}", turn - Instant::now());
+            tokio::time::sleep_until(turn).await;
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Choosing which series go to which sink
+//!
+//! The readings can be written to the metrics endpoint and a local agent's socket at the same
+//! time, e.g. the CPU temperature only to the local Telegraf and the greenhouse's sensors only
+//! to Grafana Cloud. Each sink takes the series matching any of its include selectors (all of
+//! them without any), less those matching its exclude selectors. Selectors match the series'
+//! names before any host label or tags are added, with `*` standing for any run of characters,
+//! e.g. `greenhouse.*` or `*.humidity`.
+
+use crate::{config::Sensor, error::SinkError, sinks::Sink, Datapoint};
+use futures::future::BoxFuture;
+use std::{str::FromStr, sync::Arc};
+
+/// A pattern of series names, e.g. `greenhouse.*`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selector(String);
+
+impl FromStr for Selector {
+    type Err = String;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        if selector.trim().is_empty() {
+            return Err("empty series selector, expected e.g. greenhouse.*".to_string());
+        }
+
+        Ok(Selector(selector.trim().to_string()))
+    }
+}
+
+impl Selector {
+    pub fn matches(&self, name: &str) -> bool {
+        let mut parts = self.0.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = name.strip_prefix(first) else {
+            return false;
+        };
+
+        let parts = parts.collect::<Vec<_>>();
+        let Some((last, middle)) = parts.split_last() else {
+            // No wildcard at all
+            return rest.is_empty();
+        };
+        for part in middle {
+            match rest.find(part) {
+                Some(at) => rest = &rest[at + part.len()..],
+                None => return false,
+            }
+        }
+        rest.ends_with(last)
+    }
+}
+
+/// Which series a sink takes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Route {
+    /// Only the series matching any of these, or all of them if empty
+    pub include: Vec<Selector>,
+    /// None of the series matching any of these
+    pub exclude: Vec<Selector>,
+}
+
+impl Route {
+    pub fn allows(&self, name: &str) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|selector| selector.matches(name));
+        included && !self.exclude.iter().any(|selector| selector.matches(name))
+    }
+}
+
+/// A sink only taking the series its [`Route`] allows
+pub struct Routed {
+    inner: Arc<dyn Sink>,
+    route: Route,
+}
+
+impl Routed {
+    /// Wraps `inner`, unless the route allows every series anyway
+    pub fn wrap(inner: Arc<dyn Sink>, route: Route) -> Arc<dyn Sink> {
+        if route == Route::default() {
+            return inner;
+        }
+
+        Arc::new(Routed { inner, route })
+    }
+}
+
+impl Sink for Routed {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let routed = readings
+                .iter()
+                .filter(|datapoint| self.route.allows(&datapoint.name))
+                .cloned()
+                .collect::<Vec<_>>();
+            if routed.is_empty() {
+                return Ok(());
+            }
+
+            self.inner.write(&routed).await
+        })
+    }
+
+    fn sensors_changed(&self, sensors: &[Sensor]) {
+        self.inner.sensors_changed(sensors);
+    }
+}
+
+/// Writes every batch to a primary sink and some others at the same time
+///
+/// Only the primary sink's failures fail the write, and with it what's spooled and backfilled:
+/// the others' are logged and their batches dropped, as backfilling the primary would write
+/// everything to them again.
+pub struct Fanout {
+    primary: Arc<dyn Sink>,
+    others: Vec<Arc<dyn Sink>>,
+}
+
+impl Fanout {
+    pub fn new(primary: Arc<dyn Sink>, others: Vec<Arc<dyn Sink>>) -> Self {
+        Fanout { primary, others }
+    }
+}
+
+impl Sink for Fanout {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async move {
+            let others =
+                futures::future::join_all(self.others.iter().map(|sink| sink.write(readings)));
+            let (written, others) = tokio::join!(self.primary.write(readings), others);
+            for err in others.into_iter().filter_map(Result::err) {
+                tracing::warn!("Failed to write data to a secondary sink: {}", err);
+            }
+
+            written
+        })
+    }
+
+    fn sensors_changed(&self, sensors: &[Sensor]) {
+        self.primary.sensors_changed(sensors);
+        for sink in &self.others {
+            sink.sensors_changed(sensors);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Sampling the sensors more or less often depending on the time of day
+//!
+//! A schedule is a list of daily windows, each with the interval the sensors are sampled at
+//! during it, given with a step in the style of cron: `06:00-22:00/5m` samples every 5 minutes
+//! from 6 in the morning until 10 at night, and `22:00-06:00/30m` every 30 minutes overnight.
+//! The step is an [`Interval`], so a plain number is still a number of seconds.
+//! Windows may wrap around midnight, and the first one matching wins. Outside of all of them,
+//! the sensor's `interval` (or the refresh time) applies. The times are local, so the windows
+//! follow daylight saving time.
+//!
+//! A scheduled sensor's task ticks at the shortest of its intervals and skips the cycles that
+//! come too soon for the current window, so a new window takes effect on the next tick.
+
+use crate::interval::Interval;
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// A daily stretch of time and the interval the sensors are sampled at during it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Window {
+    /// The local time the window starts at, e.g. 06:00
+    pub from: NaiveTime,
+    /// The local time the window ends at, before `from` for a window over midnight, or the same
+    /// for all day
+    pub to: NaiveTime,
+    /// How often to sample during the window
+    pub interval: Interval,
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from < self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = String;
+
+    /// Parses `<from>-<to>/<interval>`, e.g. `06:00-22:00/5m`
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid schedule window {}, expected e.g. 06:00-22:00/5m",
+                window
+            )
+        };
+        let (times, interval) = window.trim().split_once('/').ok_or_else(invalid)?;
+        let (from, to) = times.split_once('-').ok_or_else(invalid)?;
+        let time = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+        let (Ok(from), Ok(to)) = (time(from), time(to)) else {
+            return Err(invalid());
+        };
+        let interval = interval
+            .parse::<Interval>()
+            .map_err(|err| format!("invalid schedule window {}: {}", window, err))?;
+
+        Ok(Window { from, to, interval })
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = String;
+
+    fn try_from(window: String) -> Result<Self, Self::Error> {
+        window.parse()
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}/{}",
+            self.from.format("%H:%M"),
+            self.to.format("%H:%M"),
+            self.interval
+        )
+    }
+}
+
+impl From<Window> for String {
+    fn from(window: Window) -> Self {
+        window.to_string()
+    }
+}
+
+/// The interval to sample at, at a local time, or `fallback` outside of the windows
+pub fn interval_at(windows: &[Window], time: NaiveTime, fallback: Interval) -> Interval {
+    windows
+        .iter()
+        .find(|window| window.contains(time))
+        .map_or(fallback, |window| window.interval)
+}
+
+/// The shortest interval sampled at, which the sensor's task ticks at
+pub fn shortest(windows: &[Window], fallback: Interval) -> Interval {
+    windows
+        .iter()
+        .map(|window| window.interval)
+        .fold(fallback, Interval::min)
+}
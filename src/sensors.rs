@@ -0,0 +1,643 @@
+//! Reading the DHT22 sensors
+//!
+//! Hardware access goes through the [`Backend`] trait, so the pipeline can run against the
+//! real sensors (`Dht22`, behind the `dht22` feature on Linux) or simulated ones ([`MockBackend`])
+//! without a Raspberry Pi.
+
+use crate::{
+    capture::Recorder,
+    config::{Sensor, SensorType},
+    error::SensorError,
+    interval::Interval,
+    outputs::PowerSwitch,
+    plugins, pulse, radio,
+    schedule::Window,
+    serial,
+    state::State,
+    Datapoint,
+};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    sync::{oneshot, OwnedMutexGuard, Semaphore, SemaphorePermit},
+    time,
+};
+
+/// How long a DHT22 read may take by default before it's abandoned as stuck; a healthy one
+/// takes a few milliseconds
+const DHT22_TIMEOUT_SECS: u64 = 2;
+
+/// How many reads in a row fail before a sensor with a power pin is power cycled by default
+const DEFAULT_POWER_CYCLE_AFTER: u32 = 5;
+
+/// How long to wait between attempts at reading a sensor by default, just over the DHT22's
+/// minimum of 2 seconds
+const DEFAULT_RETRY_INTERVAL_MS: u64 = 2100;
+
+/// How many times a cycle reads a sensor again when it returns an implausible value
+pub const IMPLAUSIBLE_REREADS: u32 = 2;
+
+/// Where the kernel reports how long ago the system booted, in seconds
+const UPTIME_PATH: &str = "/proc/uptime";
+
+/// Where the kernel reports the SoC temperature, in thousandths of a degree Celsius
+const CPU_TEMPERATURE_PATH: &str = "/sys/class/thermal/thermal_zone0/temp";
+
+/// A temperature and humidity reading
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub temperature: f32,
+    pub humidity: f32,
+}
+
+/// Something that can take a reading from a sensor connected to a GPIO pin
+pub trait Backend: Send + Sync {
+    fn read(&self, pin: u8) -> Result<Reading, SensorError>;
+
+    /// Enables the pin's internal pull-up resistor before it's read, for sensors with `pull_up`
+    fn pull_up(&self, _pin: u8) -> Result<(), SensorError> {
+        Ok(())
+    }
+}
+
+/// DHT22 sensors connected to the Raspberry Pi's GPIO header
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+pub struct Dht22;
+
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+impl Backend for Dht22 {
+    fn read(&self, pin: u8) -> Result<Reading, SensorError> {
+        let reading = dht22_pi::read(pin)?;
+
+        Ok(Reading {
+            temperature: reading.temperature,
+            humidity: reading.humidity,
+        })
+    }
+
+    fn pull_up(&self, pin: u8) -> Result<(), SensorError> {
+        crate::gpio::Gpio::new()
+            .and_then(|gpio| gpio.pull_up(pin))
+            .map_err(SensorError::Gpio)
+    }
+}
+
+/// Simulated sensors for running without hardware and in tests
+///
+/// Results queued with [`MockBackend::push`] are returned first, in order, for the given pin.
+/// Once a pin's queue is empty it returns slowly drifting, plausible indoor readings.
+#[derive(Default)]
+pub struct MockBackend {
+    scripted: Mutex<HashMap<u8, VecDeque<Result<Reading, SensorError>>>>,
+    pulled_up: Mutex<HashSet<u8>>,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        MockBackend::default()
+    }
+
+    /// Queues a result to be returned by the next read of `pin`
+    pub fn push(&self, pin: u8, result: Result<Reading, SensorError>) {
+        self.scripted
+            .lock()
+            .expect("Mock backend lock poisoned")
+            .entry(pin)
+            .or_default()
+            .push_back(result);
+    }
+
+    /// Whether the internal pull-up resistor of `pin` was enabled
+    pub fn is_pulled_up(&self, pin: u8) -> bool {
+        self.pulled_up
+            .lock()
+            .expect("Mock backend lock poisoned")
+            .contains(&pin)
+    }
+}
+
+impl Backend for MockBackend {
+    fn read(&self, pin: u8) -> Result<Reading, SensorError> {
+        let scripted = self
+            .scripted
+            .lock()
+            .expect("Mock backend lock poisoned")
+            .get_mut(&pin)
+            .and_then(|queue| queue.pop_front());
+        if let Some(result) = scripted {
+            return result;
+        }
+
+        // An hour long cycle, offset per pin so that sensors don't move in lockstep
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System time behind Unix epoch time")
+            .as_secs_f32();
+        let phase = (secs / 3600.0 + f32::from(pin) / 10.0) * std::f32::consts::TAU;
+
+        Ok(Reading {
+            temperature: 21.0 + 2.0 * phase.sin(),
+            humidity: 45.0 + 5.0 * phase.cos(),
+        })
+    }
+
+    fn pull_up(&self, pin: u8) -> Result<(), SensorError> {
+        self.pulled_up
+            .lock()
+            .expect("Mock backend lock poisoned")
+            .insert(pin);
+        Ok(())
+    }
+}
+
+/// Takes a single reading of any kind of sensor, as `(metric, value)` pairs
+async fn take_reading(
+    backend: &Arc<dyn Backend>,
+    sensor: &Sensor,
+    access: &HardwareAccess,
+) -> Result<Vec<(String, f64)>, SensorError> {
+    match sensor.kind {
+        SensorType::Dht22 => {
+            let pin = sensor
+                .pin
+                .expect("DHT22 sensors are validated to have a pin");
+            if sensor.pull_up {
+                backend.pull_up(pin)?;
+            }
+            let timeout = sensor.timeout_secs.map_or(
+                time::Duration::from_secs(DHT22_TIMEOUT_SECS),
+                Interval::as_duration,
+            );
+            let reading = access.read_watched(backend, pin, timeout).await?;
+            tracing::info!("Successfully read {:?}", &reading);
+
+            Ok(vec![
+                ("temperature".to_string(), f64::from(reading.temperature)),
+                ("humidity".to_string(), f64::from(reading.humidity)),
+            ])
+        }
+        SensorType::Command => {
+            let timeout = sensor.timeout_secs.map_or(
+                time::Duration::from_secs(plugins::DEFAULT_TIMEOUT_SECS),
+                Interval::as_duration,
+            );
+            let metrics = plugins::read(sensor, timeout).await?;
+            tracing::info!("Successfully read {:?}", &metrics);
+
+            Ok(metrics)
+        }
+        SensorType::Serial => {
+            let timeout = sensor.timeout_secs.map_or(
+                time::Duration::from_secs(plugins::DEFAULT_TIMEOUT_SECS),
+                Interval::as_duration,
+            );
+            let metrics = serial::read(sensor, timeout).await?;
+            tracing::info!("Successfully read {:?}", &metrics);
+
+            Ok(metrics)
+        }
+        SensorType::Radio => {
+            let timeout = sensor.timeout_secs.map_or(
+                time::Duration::from_secs(plugins::DEFAULT_TIMEOUT_SECS),
+                Interval::as_duration,
+            );
+            let metrics = radio::read(sensor, timeout).await?;
+            tracing::info!("Successfully read {:?}", &metrics);
+
+            Ok(metrics)
+        }
+        SensorType::Cpu => {
+            let millidegrees =
+                std::fs::read_to_string(CPU_TEMPERATURE_PATH).map_err(SensorError::Cpu)?;
+            let millidegrees = millidegrees.trim().parse::<f64>().map_err(|err| {
+                SensorError::Cpu(std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+            })?;
+            tracing::info!(
+                "Successfully read a CPU temperature of {}",
+                millidegrees / 1000.0
+            );
+
+            Ok(vec![("temperature".to_string(), millidegrees / 1000.0)])
+        }
+        SensorType::Pulse => {
+            let metrics = pulse::read(sensor)?;
+            tracing::info!("Successfully read {:?}", &metrics);
+
+            Ok(metrics)
+        }
+    }
+}
+
+/// The first of a raw reading's metrics outside of what a good read of the sensor returns
+fn implausible<'a>(sensor: &Sensor, metrics: &'a [(String, f64)]) -> Option<(&'a str, f64)> {
+    metrics.iter().find_map(|(metric, value)| {
+        let (min, max) = sensor.kind.plausible_range(metric)?;
+        (!(min..=max).contains(value)).then_some((metric.as_str(), *value))
+    })
+}
+
+/// Where the readings' timestamps come from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Clock {
+    /// The system clock at the time of the reading
+    #[default]
+    Wall,
+    /// The system clock when the first reading was taken, plus the monotonic time since, so
+    /// the timestamps don't jump when NTP steps the clock. Keep to the wall clock on boards
+    /// without an RTC that only get the time from the network after startup.
+    Monotonic,
+}
+
+impl Clock {
+    /// The time since the Unix epoch
+    pub fn now(&self) -> Duration {
+        static ANCHOR: OnceLock<(Duration, Instant)> = OnceLock::new();
+
+        let wall = || {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("System time behind Unix epoch time")
+        };
+        match self {
+            Clock::Wall => wall(),
+            Clock::Monotonic => {
+                let (epoch, start) = ANCHOR.get_or_init(|| (wall(), Instant::now()));
+                *epoch + start.elapsed()
+            }
+        }
+    }
+}
+
+impl FromStr for Clock {
+    type Err = String;
+
+    fn from_str(clock: &str) -> Result<Self, Self::Err> {
+        match clock {
+            "wall" => Ok(Clock::Wall),
+            "monotonic" => Ok(Clock::Monotonic),
+            _ => Err(format!(
+                "unknown clock {}, expected wall or monotonic",
+                clock
+            )),
+        }
+    }
+}
+
+/// Keeps the sensors from reading the hardware all at once: sensors sharing a `bus` are read
+/// one at a time, and no more than a set number of sensors are read at the same time. `serial`
+/// and `radio` sensors aren't limited, as they only wait for what their device sends, and
+/// neither are `pulse` sensors, which only take what was counted meanwhile.
+#[derive(Default)]
+pub struct HardwareAccess {
+    limit: Option<Semaphore>,
+    buses: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// GPIO pins being read, including by reads abandoned by the watchdog that are still going
+    busy_pins: Arc<Mutex<HashSet<u8>>>,
+}
+
+impl HardwareAccess {
+    /// Reads at most `max_concurrent_reads` sensors at a time, if set
+    pub fn new(max_concurrent_reads: Option<usize>) -> Self {
+        HardwareAccess {
+            limit: max_concurrent_reads.map(Semaphore::new),
+            buses: Mutex::default(),
+            busy_pins: Arc::default(),
+        }
+    }
+
+    /// Reads a GPIO pin on the runtime's blocking pool, abandoning the read if it takes longer
+    /// than `timeout` (threads can't be killed). The pin isn't read again until an abandoned
+    /// read finishes, so a wedged read ties up one thread at most rather than one per attempt.
+    async fn read_watched(
+        &self,
+        backend: &Arc<dyn Backend>,
+        pin: u8,
+        timeout: time::Duration,
+    ) -> Result<Reading, SensorError> {
+        if !self
+            .busy_pins
+            .lock()
+            .expect("Busy pins lock poisoned")
+            .insert(pin)
+        {
+            return Err(SensorError::Wedged);
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        let backend = backend.clone();
+        let busy = BusyPin {
+            pins: self.busy_pins.clone(),
+            pin,
+        };
+        tokio::task::spawn_blocking(move || {
+            let reading = backend.read(pin);
+            drop(busy);
+            let _ = sender.send(reading);
+        });
+
+        match time::timeout(timeout, receiver).await {
+            Ok(Ok(result)) => result,
+            // The read panicked, dropping the sender
+            Ok(Err(_)) => Err(SensorError::Panicked),
+            Err(_) => {
+                tracing::error!(pin, "The read is stuck, abandoning it");
+                Err(SensorError::Stuck(timeout))
+            }
+        }
+    }
+
+    /// Waits until the sensor may be read, which it can as long as the guards are held
+    async fn acquire(
+        &self,
+        sensor: &Sensor,
+    ) -> (Option<SemaphorePermit<'_>>, Option<OwnedMutexGuard<()>>) {
+        if matches!(
+            sensor.kind,
+            SensorType::Serial | SensorType::Radio | SensorType::Pulse
+        ) {
+            return (None, None);
+        }
+
+        // The bus first, so sensors queued on a busy bus don't hold up the others' permits
+        let bus = match &sensor.bus {
+            Some(bus) => {
+                let lock = self
+                    .buses
+                    .lock()
+                    .expect("Bus locks poisoned")
+                    .entry(bus.clone())
+                    .or_default()
+                    .clone();
+                Some(lock.lock_owned().await)
+            }
+            None => None,
+        };
+        let permit = match &self.limit {
+            Some(limit) => Some(limit.acquire().await.expect("Read limit never closed")),
+            None => None,
+        };
+
+        (permit, bus)
+    }
+}
+
+/// Lets go of a pin once its read returns, or panics
+struct BusyPin {
+    pins: Arc<Mutex<HashSet<u8>>>,
+    pin: u8,
+}
+
+impl Drop for BusyPin {
+    fn drop(&mut self) {
+        self.pins
+            .lock()
+            .expect("Busy pins lock poisoned")
+            .remove(&self.pin);
+    }
+}
+
+/// How much longer the sensor needs to warm up, if at all. Sensors are powered from boot, so
+/// the warm-up counts from then (or from startup when the uptime isn't known) and a restart of
+/// the service doesn't warm them up again.
+pub(crate) fn warmup_remaining(sensor: &Sensor) -> Option<Duration> {
+    let warmup = sensor.warmup_secs?.as_duration();
+    let uptime = std::fs::read_to_string(UPTIME_PATH)
+        .ok()
+        .and_then(|uptime| uptime.split_whitespace().next()?.parse::<f64>().ok())
+        .map(Duration::from_secs_f64)
+        .unwrap_or_default();
+
+    warmup
+        .checked_sub(uptime)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+/// What a sensor's schedule does about the cycles it missed while a cycle ran past its interval
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissedTicks {
+    /// Run the missed cycles right away, one after the other, to catch up
+    #[default]
+    Burst,
+    /// Run the next cycle an interval after the late one, shifting the schedule
+    Delay,
+    /// Leave out the missed cycles and keep to the schedule
+    Skip,
+}
+
+impl From<MissedTicks> for tokio::time::MissedTickBehavior {
+    fn from(missed: MissedTicks) -> Self {
+        match missed {
+            MissedTicks::Burst => tokio::time::MissedTickBehavior::Burst,
+            MissedTicks::Delay => tokio::time::MissedTickBehavior::Delay,
+            MissedTicks::Skip => tokio::time::MissedTickBehavior::Skip,
+        }
+    }
+}
+
+impl FromStr for MissedTicks {
+    type Err = String;
+
+    fn from_str(missed: &str) -> Result<Self, Self::Err> {
+        match missed {
+            "burst" => Ok(MissedTicks::Burst),
+            "delay" => Ok(MissedTicks::Delay),
+            "skip" => Ok(MissedTicks::Skip),
+            _ => Err(format!(
+                "unknown missed tick policy {}, expected burst, delay or skip",
+                missed
+            )),
+        }
+    }
+}
+
+/// How the readings are taken, besides from which sensor
+#[derive(Clone, Default)]
+pub struct ReadOptions {
+    /// Records every read attempt
+    pub recorder: Option<Arc<Recorder>>,
+    /// Timestamps the readings
+    pub clock: Clock,
+    /// Shared by every sensor
+    pub access: Arc<HardwareAccess>,
+    /// What happens to the cycles a long one overran
+    pub missed_ticks: MissedTicks,
+    /// How long a cycle may retry a sensor before giving up on it until the next one
+    /// (default: the sensor's interval)
+    pub cycle_deadline: Option<Duration>,
+    /// The schedule of the sensors without an `interval` or `schedule` of their own
+    pub schedule: Vec<Window>,
+}
+
+/// The names of a sensor's series, formatted on its first reading rather than every cycle
+pub struct SeriesNames {
+    path: String,
+    names: Vec<(String, String)>,
+}
+
+impl SeriesNames {
+    pub fn new(sensor: &Sensor) -> Self {
+        SeriesNames {
+            path: sensor.path(),
+            names: Vec::new(),
+        }
+    }
+
+    /// The name of the metric's series, e.g. `cottage.bedroom.sensor1.temperature`
+    pub fn get(&mut self, metric: &str) -> &str {
+        let index = match self.names.iter().position(|(known, _)| known == metric) {
+            Some(index) => index,
+            None => {
+                let name = format!("{}.{}", self.path, metric);
+                self.names.push((metric.to_string(), name));
+                self.names.len() - 1
+            }
+        };
+        &self.names[index].1
+    }
+}
+
+/// Power cycles the sensor if it has a power switch and failed `power_cycle_after` more reads in
+/// a row since it was last power cycled, going by `failures`, its failed reads in a row across
+/// cycles. Returns whether it did. Counting across cycles power cycles a sensor that gives up
+/// after fewer `max_attempts`, or at a short `--cycle-deadline`, all the same.
+pub(crate) async fn power_cycle_if_due(
+    sensor: &Sensor,
+    failures: u32,
+    power: Option<&mut PowerSwitch>,
+    state: &State,
+) -> bool {
+    let cycle_after = sensor
+        .power_cycle_after
+        .unwrap_or(DEFAULT_POWER_CYCLE_AFTER);
+    let Some(power) = power.filter(|power| power.is_due(failures, cycle_after)) else {
+        return false;
+    };
+
+    // Before the cycle, which the cycle's deadline may cut short
+    state.record_power_cycle(&sensor.name);
+    power.cycle(sensor, failures).await;
+    true
+}
+
+/// How a cycle's reads of a sensor ended
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    /// A valid reading
+    Read(Vec<Datapoint>),
+    /// The raw metrics of a reading that was still implausible once read again, which isn't
+    /// written but tells the range checks of a wrong pin or sensor type
+    Implausible(Vec<(String, f64)>),
+    /// No reading, after `max_attempts` failed attempts
+    GaveUp,
+}
+
+/// Reads the sensor until it returns a valid reading, waiting its `retry_interval_ms` between
+/// attempts, and recording every attempt if there's a recorder. Gives up after `max_attempts`
+/// failed attempts. With a `power` switch, the sensor is power cycled every `power_cycle_after`
+/// failed reads in a row, see [`power_cycle_if_due`].
+///
+/// A reading outside of its type's [plausible range](SensorType::plausible_range) is read
+/// again, up to [`IMPLAUSIBLE_REREADS`] times a cycle, without the sensor counting
+/// as failing. If it's still implausible then, the cycle gives up on it.
+#[tracing::instrument(name = "read", skip_all, fields(pin = sensor.pin))]
+pub async fn read_sensor(
+    backend: &Arc<dyn Backend>,
+    sensor: &Sensor,
+    resolution: i32,
+    state: &State,
+    options: &ReadOptions,
+    names: &mut SeriesNames,
+    mut power: Option<&mut PowerSwitch>,
+) -> Outcome {
+    let start = Instant::now();
+    let mut attempts: u32 = 0;
+    let mut rereads: u32 = 0;
+    let mut read_interval = tokio::time::interval(sensor.retry_interval_ms.map_or(
+        time::Duration::from_millis(DEFAULT_RETRY_INTERVAL_MS),
+        Interval::as_duration,
+    ));
+    loop {
+        read_interval.tick().await;
+        attempts += 1;
+
+        let result = {
+            let _guards = options.access.acquire(sensor).await;
+            take_reading(backend, sensor, &options.access).await
+        };
+        let ts = options.clock.now().as_secs();
+        if let Some(recorder) = &options.recorder {
+            recorder.record(&sensor.name, ts as i64, &result);
+        }
+        let result = match result {
+            Ok(metrics) => match implausible(sensor, &metrics) {
+                Some((metric, value)) => {
+                    let error = SensorError::Implausible(metric.to_string(), value);
+                    if rereads < IMPLAUSIBLE_REREADS && sensor.max_attempts != Some(attempts) {
+                        rereads += 1;
+                        tracing::warn!(attempts, "Reading the sensor again: {}", error);
+                        state.record_reread(&sensor.name, &error);
+                        continue;
+                    }
+
+                    tracing::warn!(
+                        attempts,
+                        "Giving up on the sensor until the next cycle: {}",
+                        error
+                    );
+                    state.record_error(&sensor.name, &error);
+                    break Outcome::Implausible(metrics);
+                }
+                None => Ok(metrics),
+            },
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(mut metrics) => {
+                sensor.calibrate(&mut metrics);
+                state.record_reading(&sensor.name, ts as i64, &metrics);
+                if let Some(power) = power.as_deref_mut() {
+                    power.reset();
+                }
+
+                tracing::debug!(
+                    attempts,
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    "Sensor read completed"
+                );
+
+                break Outcome::Read(
+                    metrics
+                        .iter()
+                        .map(|(metric, value)| Datapoint {
+                            // The one copy of the name left, which the datapoint owns
+                            name: names.get(metric).to_owned(),
+                            interval: resolution,
+                            value: *value,
+                            time: ts as i64,
+                        })
+                        .collect(),
+                );
+            }
+
+            Err(error) => {
+                tracing::warn!(attempts, "Error reading the sensor: {}", error);
+                let failures = state.record_error(&sensor.name, &error);
+
+                if power_cycle_if_due(sensor, failures, power.as_deref_mut(), state).await {
+                    // Rather than catching up on the attempts missed meanwhile
+                    read_interval.reset();
+                }
+                if sensor.max_attempts == Some(attempts) {
+                    tracing::warn!(attempts, "Giving up on the sensor until the next cycle");
+                    break Outcome::GaveUp;
+                }
+                continue;
+            }
+        };
+    }
+}
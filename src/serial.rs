@@ -0,0 +1,111 @@
+//! Microcontroller nodes (Pico, ESP32, Arduino...) writing readings to a serial device
+//!
+//! A sensor with `type: serial` reads its `device` every cycle. The node writes one reading per
+//! line, as often as it likes:
+//!
+//! - `format: json` (the default) - a JSON object mapping metric names to numbers, e.g.
+//!   `{"temperature": 21.4, "humidity": 40.2}`
+//! - `format: csv` - comma separated numbers, named by the sensor's `fields` in order, e.g.
+//!   `21.4,40.2` with `fields: [temperature, humidity]`
+//!
+//! Anything buffered before the read and the first, possibly partial, line are skipped, so
+//! every reading is fresh. Lines that don't parse are ignored until one does or the sensor's
+//! `timeout_secs` runs out.
+
+use crate::{
+    config::{LineFormat, Sensor},
+    error::SensorError,
+};
+use std::{collections::BTreeMap, time::Duration};
+
+/// Baud rate unless the sensor sets `baud`. USB CDC devices such as the Pico ignore it.
+pub const DEFAULT_BAUD: u32 = 115_200;
+
+/// Waits for the next complete line from the sensor's device and parses its metrics
+#[cfg(feature = "serial")]
+pub async fn read(sensor: &Sensor, timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    let sensor = sensor.clone();
+    tokio::task::spawn_blocking(move || read_blocking(&sensor, timeout))
+        .await
+        .map_err(|err| SensorError::Serial(err.to_string()))?
+}
+
+#[cfg(feature = "serial")]
+fn read_blocking(sensor: &Sensor, timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    use std::{
+        io::{BufRead, BufReader, ErrorKind},
+        time::Instant,
+    };
+
+    let device = sensor
+        .device
+        .as_deref()
+        .ok_or_else(|| SensorError::Serial("no device configured".to_string()))?;
+    let port = serialport::new(device, sensor.baud.unwrap_or(DEFAULT_BAUD))
+        .timeout(timeout)
+        .open()
+        .map_err(|err| SensorError::Serial(format!("unable to open {}: {}", device, err)))?;
+    port.clear(serialport::ClearBuffer::Input)
+        .map_err(|err| SensorError::Serial(err.to_string()))?;
+
+    let deadline = Instant::now() + timeout;
+    let mut lines = BufReader::new(port);
+    let mut line = String::new();
+    let mut partial = true;
+    loop {
+        line.clear();
+        match lines.read_line(&mut line) {
+            Ok(0) => return Err(SensorError::Serial(format!("{} closed", device))),
+            Ok(_) if partial => partial = false,
+            Ok(_) => match parse_line(line.trim(), sensor) {
+                Ok(metrics) => return Ok(metrics),
+                Err(reason) => tracing::debug!(line = line.trim(), "Skipping line: {}", reason),
+            },
+            Err(err) if err.kind() == ErrorKind::TimedOut => return Err(SensorError::Timeout),
+            Err(err) => return Err(SensorError::Serial(err.to_string())),
+        }
+
+        if Instant::now() >= deadline {
+            return Err(SensorError::Timeout);
+        }
+    }
+}
+
+#[cfg(not(feature = "serial"))]
+pub async fn read(sensor: &Sensor, _timeout: Duration) -> Result<Vec<(String, f64)>, SensorError> {
+    Err(SensorError::Serial(format!(
+        "built without the serial feature, can't read {}",
+        sensor.device.as_deref().unwrap_or_default()
+    )))
+}
+
+/// Parses one line written by the node
+pub fn parse_line(line: &str, sensor: &Sensor) -> Result<Vec<(String, f64)>, String> {
+    match sensor.format {
+        LineFormat::Json => serde_json::from_str::<BTreeMap<String, f64>>(line)
+            .map(|metrics| metrics.into_iter().collect())
+            .map_err(|err| err.to_string()),
+        LineFormat::Csv => {
+            let values = line.split(',').map(str::trim).collect::<Vec<_>>();
+            if values.len() != sensor.fields.len() {
+                return Err(format!(
+                    "expected {} values, got {}",
+                    sensor.fields.len(),
+                    values.len()
+                ));
+            }
+
+            sensor
+                .fields
+                .iter()
+                .zip(values)
+                .map(|(field, value)| {
+                    value
+                        .parse::<f64>()
+                        .map(|value| (field.clone(), value))
+                        .map_err(|_| format!("{} isn't a number", value))
+                })
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,999 @@
+//! An embeddable handle on the whole monitoring pipeline
+//!
+//! ```no_run
+//! # async fn example(sensors: Vec<monitoring::config::Sensor>) -> monitoring::Result<()> {
+//! use monitoring::{service::MonitorService, sinks::Graphite};
+//! use std::{sync::Arc, time::Duration};
+//!
+//! let service = MonitorService::builder()
+//!     .sensors(sensors)
+//!     .sink(Arc::new(Graphite::new("https://graphite.example/metrics", "api-key")))
+//!     .interval(Duration::from_secs(60))
+//!     .build()?;
+//!
+//! let mut readings = service.subscribe();
+//! tokio::spawn(async move {
+//!     while let Ok(datapoints) = readings.recv().await {
+//!         println!("{:?}", datapoints);
+//!     }
+//! });
+//!
+//! service.run().await
+//! # }
+//! ```
+
+#[cfg(target_os = "linux")]
+use crate::hwmon::HwmonMount;
+#[cfg(feature = "http")]
+use crate::{
+    annotations::{self, GrafanaAnnotations},
+    summary::{self, SummaryConfig},
+};
+use crate::{
+    api::{self, Api},
+    capture::{self, Entry, Recorder},
+    config::{self, ConfigFile, Sensor, DEFAULT_REFRESH},
+    display::{self, DisplayConfig},
+    error::ConfigError,
+    events::Event,
+    extremes::{DailyExtremes, Timezone},
+    groups::Groups,
+    heartbeat,
+    history::History,
+    hooks, info,
+    interval::Interval,
+    locale::Locale,
+    manager::SensorManager,
+    mdns, persist,
+    pipeline::{self, Derived, DropPolicy, DEFAULT_QUEUE_CAPACITY},
+    power::{self, LowPowerConfig},
+    privileges::RunAs,
+    schedule::Window,
+    sensors::{self, Backend, Clock, HardwareAccess, MissedTicks, ReadOptions},
+    sinks::Sink,
+    snmp::{self, SnmpConfig},
+    spool::Spool,
+    state::State,
+    trend::Trends,
+    Datapoint, Error, Result,
+};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_rustls::rustls;
+
+/// How clients of the HTTP API authenticate
+#[derive(Debug, Clone)]
+pub enum ApiAuth {
+    /// An `Authorization: Bearer <token>` header
+    Bearer(String),
+    /// HTTP basic auth, which browsers prompt for, e.g. to open the dashboard
+    Basic { username: String, password: String },
+}
+
+/// How many batches of readings a slow subscriber may fall behind before it starts missing them
+const SUBSCRIBER_CAPACITY: usize = 64;
+
+pub struct MonitorService {
+    sensors: Vec<Sensor>,
+    refresh: Interval,
+    manager: Arc<SensorManager>,
+    sink: Arc<dyn Sink>,
+    spool: Option<Spool>,
+    series_budget: Option<usize>,
+    write_divergence: bool,
+    daily_extremes: Option<Timezone>,
+    merge_unchanged: bool,
+    info_metric: bool,
+    heartbeat: bool,
+    strict: bool,
+    queue_capacity: usize,
+    drop_policy: DropPolicy,
+    listen: Option<SocketAddr>,
+    advertise: bool,
+    /// Tells the profile's HTTP API apart from the others' on the LAN
+    profile: Option<String>,
+    api_token: Option<String>,
+    api_auth: Option<ApiAuth>,
+    ingest_token: Option<String>,
+    api_max_age: Option<Duration>,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    display: Option<DisplayConfig>,
+    locale: Locale,
+    #[cfg(feature = "http")]
+    summary: Option<SummaryConfig>,
+    #[cfg(feature = "http")]
+    annotations: Option<GrafanaAnnotations>,
+    hwmon: Option<PathBuf>,
+    snmp: Option<SnmpConfig>,
+    low_power: Option<LowPowerConfig>,
+    state_file: Option<PathBuf>,
+    run_as: Option<RunAs>,
+    replay: Option<Vec<Entry>>,
+    startup_delay: Duration,
+    wait_for_network: Option<(String, Duration)>,
+    state: Arc<State>,
+    history: Arc<History>,
+    readings: broadcast::Sender<Vec<Datapoint>>,
+    shutdown: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+pub struct MonitorServiceBuilder {
+    sensors: Vec<Sensor>,
+    interval: Option<Duration>,
+    backend: Option<Arc<dyn Backend>>,
+    sink: Option<Arc<dyn Sink>>,
+    spool: Option<Spool>,
+    series_budget: Option<usize>,
+    write_divergence: bool,
+    daily_extremes: Option<Timezone>,
+    merge_unchanged: bool,
+    info_metric: bool,
+    heartbeat: bool,
+    strict: bool,
+    queue_capacity: Option<usize>,
+    max_concurrent_reads: Option<usize>,
+    hardware_access: Option<Arc<HardwareAccess>>,
+    drop_policy: DropPolicy,
+    listen: Option<SocketAddr>,
+    advertise: bool,
+    api_token: Option<String>,
+    api_auth: Option<ApiAuth>,
+    ingest_token: Option<String>,
+    api_max_age: Option<Duration>,
+    tls: Option<(PathBuf, PathBuf)>,
+    display: Option<DisplayConfig>,
+    locale: Locale,
+    #[cfg(feature = "http")]
+    summary: Option<SummaryConfig>,
+    #[cfg(feature = "http")]
+    annotations: Option<GrafanaAnnotations>,
+    hwmon: Option<PathBuf>,
+    snmp: Option<SnmpConfig>,
+    low_power: Option<LowPowerConfig>,
+    state_file: Option<PathBuf>,
+    run_as: Option<RunAs>,
+    recorder: Option<Arc<Recorder>>,
+    replay: Option<Vec<Entry>>,
+    startup_delay: Duration,
+    wait_for_network: Option<(String, Duration)>,
+    clock: Clock,
+    missed_ticks: MissedTicks,
+    cycle_deadline: Option<Duration>,
+    schedule: Vec<Window>,
+    config_file: Option<ConfigFile>,
+}
+
+impl MonitorServiceBuilder {
+    pub fn sensors(mut self, sensors: Vec<Sensor>) -> Self {
+        self.sensors = sensors;
+        self
+    }
+
+    /// How often sensors without their own `interval` are sampled (default: 15 minutes)
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Where sensor readings come from (default: the DHT22 sensors, or simulated ones when
+    /// built without the `dht22` feature or for another OS than Linux)
+    pub fn backend(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Keep the batches the sink fails to take in this spool, and backfill them once it's back
+    pub fn spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Warn when the sensors write more series than this, e.g. the active series limit of a
+    /// Grafana Cloud plan
+    pub fn series_budget(mut self, series: usize) -> Self {
+        self.series_budget = Some(series);
+        self
+    }
+
+    /// Also write how far apart each group's readings are, as `<group>.<metric>.divergence`
+    pub fn write_divergence(mut self, write: bool) -> Self {
+        self.write_divergence = write;
+        self
+    }
+
+    /// Also write each sensor series' lowest and highest reading of the day, as
+    /// `<series>.daily_min` and `<series>.daily_max`, see [`crate::extremes`]
+    pub fn daily_extremes(mut self, timezone: Timezone) -> Self {
+        self.daily_extremes = Some(timezone);
+        self
+    }
+
+    /// Leave out the datapoints holding the same value as their series' previous one in a batch,
+    /// see [`pipeline::compact`]
+    pub fn merge_unchanged(mut self, merge: bool) -> Self {
+        self.merge_unchanged = merge;
+        self
+    }
+
+    /// Also write the `monitoring.info` series every cycle, see [`crate::info`]
+    pub fn info_metric(mut self, write: bool) -> Self {
+        self.info_metric = write;
+        self
+    }
+
+    /// Also write the `monitor.heartbeat` series every cycle, see [`crate::heartbeat`]
+    pub fn heartbeat(mut self, write: bool) -> Self {
+        self.heartbeat = write;
+        self
+    }
+
+    /// How many batches of readings may wait for the sink before some are dropped (default: 256)
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Read at most this many sensors at the same time (default: no limit besides the `bus`es)
+    pub fn max_concurrent_reads(mut self, reads: usize) -> Self {
+        self.max_concurrent_reads = Some(reads);
+        self
+    }
+
+    /// Share the access to the hardware with other services in the process, so the limits on
+    /// reading it hold across all of them. Replaces `max_concurrent_reads`.
+    pub fn hardware_access(mut self, access: Arc<HardwareAccess>) -> Self {
+        self.hardware_access = Some(access);
+        self
+    }
+
+    /// Which readings to drop when the sink can't keep up (default: the oldest)
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Self {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Serve the HTTP API with the latest readings on this address
+    pub fn listen(mut self, addr: SocketAddr) -> Self {
+        self.listen = Some(addr);
+        self
+    }
+
+    /// Stop with an error when a configured sensor's first reading is outside of what its type
+    /// can physically report, rather than only logging it
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Advertise the HTTP API on the LAN over mDNS as `_rpitemp._tcp`
+    pub fn advertise(mut self, advertise: bool) -> Self {
+        self.advertise = advertise;
+        self
+    }
+
+    /// Send a daily or weekly summary of the readings
+    #[cfg(feature = "http")]
+    pub fn summary(mut self, summary: SummaryConfig) -> Self {
+        self.summary = Some(summary);
+        self
+    }
+
+    /// Wait this long before the first cycle, e.g. for the Wi-Fi and NTP to come up on boot
+    pub fn startup_delay(mut self, delay: Duration) -> Self {
+        self.startup_delay = delay;
+        self
+    }
+
+    /// Before the first cycle, wait up to `timeout` for a host to resolve, e.g. the metrics
+    /// endpoint's `graphite-prod-01-eu-west-0.grafana.net:443`
+    pub fn wait_for_network(mut self, host: impl Into<String>, timeout: Duration) -> Self {
+        self.wait_for_network = Some((host.into(), timeout));
+        self
+    }
+
+    /// Post the service's events to Grafana as annotations
+    #[cfg(feature = "http")]
+    pub fn annotations(mut self, annotations: GrafanaAnnotations) -> Self {
+        self.annotations = Some(annotations);
+        self
+    }
+
+    /// Expose the latest readings in the hwmon layout on this directory, see [`crate::hwmon`]
+    pub fn hwmon(mut self, mountpoint: impl Into<PathBuf>) -> Self {
+        self.hwmon = Some(mountpoint.into());
+        self
+    }
+
+    /// Answer SNMP queries for the latest readings, see [`crate::snmp`]
+    pub fn snmp(mut self, config: SnmpConfig) -> Self {
+        self.snmp = Some(config);
+        self
+    }
+
+    /// Switch to a low-power mode while running on battery, see [`crate::power`]
+    pub fn low_power(mut self, config: LowPowerConfig) -> Self {
+        self.low_power = Some(config);
+        self
+    }
+
+    /// Save the sensors' state to this file and pick it up again on start, see
+    /// [`crate::persist`]
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Cycle the sensors' latest readings on an I2C display attached to the Pi
+    pub fn display(mut self, display: DisplayConfig) -> Self {
+        self.display = Some(display);
+        self
+    }
+
+    /// Show the readings on the display, the terminal view and the dashboard page in this
+    /// locale
+    pub fn locale(mut self, locale: Locale) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Require this bearer token for changing the sensors over the HTTP API, which is
+    /// disabled without one
+    pub fn api_token(mut self, token: impl Into<String>) -> Self {
+        self.api_token = Some(token.into());
+        self
+    }
+
+    /// Require these credentials for everything the HTTP API serves but the health checks
+    pub fn api_auth(mut self, auth: ApiAuth) -> Self {
+        self.api_auth = Some(auth);
+        self
+    }
+
+    /// Accept readings pushed to `POST /ingest` with this bearer token, which is disabled
+    /// without one
+    pub fn ingest_token(mut self, token: impl Into<String>) -> Self {
+        self.ingest_token = Some(token.into());
+        self
+    }
+
+    /// Let clients reuse `GET /readings` for this long before asking again, rather than
+    /// revalidating every time
+    pub fn api_max_age(mut self, max_age: Duration) -> Self {
+        self.api_max_age = Some(max_age);
+        self
+    }
+
+    /// Serve the HTTP API over TLS with this PEM certificate chain and private key
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Switch to this user once the HTTP API is listening, rather than keep running as root
+    pub fn run_as(mut self, run_as: RunAs) -> Self {
+        self.run_as = Some(run_as);
+        self
+    }
+
+    /// Where the readings' timestamps come from (default: the wall clock)
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// What a sensor's schedule does after a cycle ran past its interval (default: run the
+    /// missed cycles right away)
+    pub fn missed_ticks(mut self, missed_ticks: MissedTicks) -> Self {
+        self.missed_ticks = missed_ticks;
+        self
+    }
+
+    /// Give up on a sensor that hasn't returned a valid reading this long into a cycle, until
+    /// its next cycle (default: its interval)
+    pub fn cycle_deadline(mut self, deadline: Duration) -> Self {
+        self.cycle_deadline = Some(deadline);
+        self
+    }
+
+    /// Sample the sensors without an `interval` or `schedule` of their own at other intervals at
+    /// some times of day, see [`crate::schedule`]
+    pub fn schedule(mut self, windows: Vec<Window>) -> Self {
+        self.schedule = windows;
+        self
+    }
+
+    /// Record every read attempt, for replaying later
+    pub fn record(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Feed these captured reads through the pipeline instead of sampling the sensors. Unless
+    /// the HTTP API is served, [`MonitorService::run`] returns once they're all written.
+    pub fn replay(mut self, entries: Vec<Entry>) -> Self {
+        self.replay = Some(entries);
+        self
+    }
+
+    /// Persist sensors changed over the HTTP API to this `sensors.yaml`
+    pub fn config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(ConfigFile::Sensors(path.into()));
+        self
+    }
+
+    /// Run as the profile called `name` in the profiles file at `path`, persisting the sensors
+    /// changed over the HTTP API to its entry there and advertising the API under its name
+    pub fn profile(mut self, path: impl Into<PathBuf>, name: impl Into<String>) -> Self {
+        self.config_file = Some(ConfigFile::Profile {
+            path: path.into(),
+            name: name.into(),
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<MonitorService, ConfigError> {
+        config::validate(&self.sensors)?;
+        let sink = self
+            .sink
+            .ok_or_else(|| ConfigError::Invalid("a sink is required".to_string()))?;
+        let refresh = match self.interval {
+            Some(interval) => Interval::try_from(interval).map_err(ConfigError::Invalid)?,
+            None => DEFAULT_REFRESH,
+        };
+        config::validate_refresh(&self.sensors, refresh)?;
+        config::validate_schedule(&self.sensors, &self.schedule)?;
+        let queue_capacity = self.queue_capacity.unwrap_or(DEFAULT_QUEUE_CAPACITY);
+        if queue_capacity == 0 {
+            return Err(ConfigError::Invalid(
+                "the queue capacity must be at least 1".to_string(),
+            ));
+        }
+
+        if self
+            .cycle_deadline
+            .is_some_and(|deadline| deadline.is_zero())
+        {
+            return Err(ConfigError::Invalid(
+                "the cycle deadline can't be zero".to_string(),
+            ));
+        }
+
+        if self.max_concurrent_reads == Some(0) {
+            return Err(ConfigError::Invalid(
+                "at least 1 sensor must be read at a time".to_string(),
+            ));
+        }
+
+        let known_series = config::known_series(&self.sensors);
+        if let Some(budget) = self.series_budget.filter(|budget| known_series > *budget) {
+            tracing::warn!(
+                "The sensors write at least {} series, more than the budget of {}",
+                known_series,
+                budget
+            );
+        }
+
+        let tls = match &self.tls {
+            Some((cert, key)) => Some(api::load_tls(cert, key)?),
+            None => None,
+        };
+
+        let state = Arc::new(State::new(&self.sensors));
+        if let Some(saved) = self.state_file.as_deref().and_then(persist::load) {
+            state.restore(saved);
+        }
+        let profile = match &self.config_file {
+            Some(ConfigFile::Profile { name, .. }) => Some(name.clone()),
+            _ => None,
+        };
+        let manager = SensorManager::new(
+            self.sensors.clone(),
+            refresh,
+            self.backend.unwrap_or_else(default_backend),
+            state.clone(),
+            self.config_file,
+            ReadOptions {
+                recorder: self.recorder,
+                clock: self.clock,
+                missed_ticks: self.missed_ticks,
+                cycle_deadline: self.cycle_deadline,
+                schedule: self.schedule,
+                access: self
+                    .hardware_access
+                    .unwrap_or_else(|| Arc::new(HardwareAccess::new(self.max_concurrent_reads))),
+            },
+        );
+
+        Ok(MonitorService {
+            state,
+            history: Arc::new(History::default()),
+            sensors: self.sensors,
+            refresh,
+            manager: Arc::new(manager),
+            sink,
+            spool: self.spool,
+            series_budget: self.series_budget,
+            write_divergence: self.write_divergence,
+            daily_extremes: self.daily_extremes,
+            merge_unchanged: self.merge_unchanged,
+            info_metric: self.info_metric,
+            heartbeat: self.heartbeat,
+            strict: self.strict,
+            queue_capacity,
+            drop_policy: self.drop_policy,
+            listen: self.listen,
+            advertise: self.advertise,
+            profile,
+            api_token: self.api_token,
+            api_auth: self.api_auth,
+            ingest_token: self.ingest_token,
+            api_max_age: self.api_max_age,
+            tls,
+            display: self.display,
+            locale: self.locale,
+            #[cfg(feature = "http")]
+            summary: self.summary,
+            #[cfg(feature = "http")]
+            annotations: self.annotations,
+            hwmon: self.hwmon,
+            snmp: self.snmp,
+            low_power: self.low_power,
+            state_file: self.state_file,
+            run_as: self.run_as,
+            replay: self.replay,
+            startup_delay: self.startup_delay,
+            wait_for_network: self.wait_for_network,
+            readings: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            shutdown: watch::channel(false).0,
+        })
+    }
+}
+
+/// Tells the sink about the sensors every time they change, until shut down
+async fn follow_sensors(
+    sink: &dyn Sink,
+    mut sensors: watch::Receiver<Vec<Sensor>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            changed = sensors.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                sink.sensors_changed(&sensors.borrow_and_update());
+            }
+            _ = async { let _ = shutdown.wait_for(|stop| *stop).await; } => return,
+        }
+    }
+}
+
+/// Waits for one of the configured sensors' first readings to be impossible, with `strict`
+async fn impossible_reading(
+    events: Option<broadcast::Receiver<Event>>,
+    sensors: &[Sensor],
+) -> ConfigError {
+    let Some(mut events) = events else {
+        return std::future::pending().await;
+    };
+    loop {
+        match events.recv().await {
+            Ok(Event::ImpossibleReading { sensor, reading })
+                if sensors.iter().any(|configured| configured.name == sensor) =>
+            {
+                return ConfigError::Invalid(format!(
+                    "impossible first reading from {}: {}",
+                    sensor, reading
+                ));
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => return std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(all(feature = "dht22", target_os = "linux"))]
+fn default_backend() -> Arc<dyn Backend> {
+    Arc::new(sensors::Dht22)
+}
+
+#[cfg(not(all(feature = "dht22", target_os = "linux")))]
+fn default_backend() -> Arc<dyn Backend> {
+    Arc::new(sensors::MockBackend::new())
+}
+
+impl MonitorService {
+    pub fn builder() -> MonitorServiceBuilder {
+        MonitorServiceBuilder::default()
+    }
+
+    /// Receives every batch of readings as it's handed to the sink
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<Datapoint>> {
+        self.readings.subscribe()
+    }
+
+    /// The latest known state of every sensor
+    pub fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    /// How the readings are shown
+    pub fn locale(&self) -> &Locale {
+        &self.locale
+    }
+
+    /// The recent readings of every series
+    pub fn history(&self) -> &Arc<History> {
+        &self.history
+    }
+
+    /// Samples the sensors and ships their readings until [`MonitorService::shutdown`] is
+    /// called. Readings already taken are written before returning.
+    pub async fn run(&self) -> Result<()> {
+        if !self.wait_to_start().await {
+            return Ok(());
+        }
+        // Subscribed first, so that the start and the first failures are annotated too
+        #[cfg(feature = "http")]
+        let annotations = self
+            .annotations
+            .as_ref()
+            .map(|annotations| (annotations, self.state.subscribe_events()));
+        let impossible = self.strict.then(|| self.state.subscribe_events());
+        let hooks = (self.readings.subscribe(), self.state.subscribe_events());
+        // Subscribed before the sensors can be changed over the API, so no change is missed
+        let sensor_changes = self.manager.subscribe();
+        let derived_changes = sensor_changes.clone();
+        let (sender, receiver) = mpsc::channel(self.queue_capacity);
+        // The API's pushed readings share the queue, and let go of it once the server stops
+        let ingest = sender.clone();
+        // Not while replaying, which stops once the captured reads are written
+        let info = (self.info_metric && self.replay.is_none()).then(|| sender.clone());
+        let heartbeat = (self.heartbeat && self.replay.is_none()).then(|| sender.clone());
+        let replay = match &self.replay {
+            Some(entries) => Some((entries.clone(), sender)),
+            None => {
+                if let Err(err) = self.manager.start(sender).await {
+                    self.manager.stop().await;
+                    return Err(err);
+                }
+                None
+            }
+        };
+
+        let server = match self.listen {
+            Some(addr) => {
+                let api = Arc::new(Api {
+                    manager: self.manager.clone(),
+                    api_token: self.api_token.clone(),
+                    auth: self.api_auth.clone(),
+                    state: self.state.clone(),
+                    history: self.history.clone(),
+                    locale: self.locale.clone(),
+                    liveness_window: self.liveness_window(),
+                    readings: self.readings.clone(),
+                    ingest,
+                    ingest_token: self.ingest_token.clone(),
+                    max_age: self.api_max_age,
+                    readings_cache: Default::default(),
+                    refresh: self.refresh.secs(),
+                    shutdown: self.shutdown.subscribe(),
+                });
+                let mut shutdown = self.shutdown.subscribe();
+                let stopped = async move {
+                    let _ = shutdown.wait_for(|stop| *stop).await;
+                };
+                match api::serve(addr, api, self.tls.clone(), stopped) {
+                    Ok((addr, server)) => Some((
+                        server,
+                        self.advertise
+                            .then(|| mdns::advertise(addr, self.profile.as_deref()))
+                            .flatten(),
+                    )),
+                    Err(err) => {
+                        self.manager.stop().await;
+                        return Err(err.into());
+                    }
+                }
+            }
+            None => {
+                drop(ingest);
+                None
+            }
+        };
+
+        // Mounted while still root, and unmounted once the readings are written
+        #[cfg(target_os = "linux")]
+        let _hwmon = match &self.hwmon {
+            Some(path) => match HwmonMount::mount(path, self.state.clone()) {
+                Ok(mount) => Some(mount),
+                Err(err) => {
+                    self.manager.stop().await;
+                    return Err(Error::Hwmon(err));
+                }
+            },
+            None => None,
+        };
+        #[cfg(not(target_os = "linux"))]
+        if self.hwmon.is_some() {
+            self.manager.stop().await;
+            return Err(Error::Hwmon(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "hwmon devices can only be emulated on Linux",
+            )));
+        }
+
+        // Bound while still root, as the standard port is privileged
+        let snmp = match &self.snmp {
+            Some(config) => match snmp::bind(config).await {
+                Ok(socket) => Some((config, socket)),
+                Err(err) => {
+                    self.manager.stop().await;
+                    return Err(Error::Snmp(err));
+                }
+            },
+            None => None,
+        };
+
+        // Claimed while still root, as the GPIO and I2C devices may not be accessible otherwise
+        let low_power = match &self.low_power {
+            Some(config) => match power::Monitor::open(&config.trigger) {
+                Ok(monitor) => Some((config, monitor)),
+                Err(err) => {
+                    self.manager.stop().await;
+                    return Err(Error::Power(err));
+                }
+            },
+            None => None,
+        };
+
+        if let Some(run_as) = &self.run_as {
+            if let Err(err) = run_as.apply() {
+                self.manager.stop().await;
+                return Err(Error::Privileges(err));
+            }
+        }
+
+        self.state.record_event(Event::Started);
+        let (queue, batches) = pipeline::sink_queue(self.queue_capacity, self.drop_policy);
+        // Subscribed before the aggregator starts, so the summary doesn't miss the first readings
+        #[cfg(feature = "http")]
+        let summary = self
+            .summary
+            .as_ref()
+            .map(|config| (config, self.readings.subscribe()));
+        let work = async {
+            tokio::join!(
+                async {
+                    tokio::join!(
+                        pipeline::aggregate(
+                            receiver,
+                            queue,
+                            &self.history,
+                            &self.readings,
+                            self.series_budget,
+                            Derived {
+                                groups: Groups::new(&self.sensors, self.write_divergence),
+                                extremes: self
+                                    .daily_extremes
+                                    .map(|timezone| DailyExtremes::new(&self.sensors, timezone)),
+                                trends: Trends::new(&self.sensors),
+                                sensors: derived_changes,
+                            },
+                        ),
+                        pipeline::write_batches(
+                            batches,
+                            self.sink.as_ref(),
+                            &self.state,
+                            self.spool.as_ref(),
+                            self.merge_unchanged,
+                        ),
+                    );
+                    // Only once the sensors are stopped, or the captured reads are all replayed,
+                    // which the other tasks are then stopped for too
+                    self.shutdown.send_replace(true);
+                },
+                follow_sensors(
+                    self.sink.as_ref(),
+                    sensor_changes,
+                    self.shutdown.subscribe(),
+                ),
+                async {
+                    if let Some((entries, sender)) = replay {
+                        capture::replay(
+                            entries,
+                            &self.sensors,
+                            self.refresh.secs(),
+                            &self.state,
+                            sender,
+                        )
+                        .await;
+                    }
+                },
+                async {
+                    if let Some(config) = &self.display {
+                        display::run(
+                            config.clone(),
+                            self.locale.clone(),
+                            self.state.clone(),
+                            self.history.clone(),
+                            self.shutdown.subscribe(),
+                        )
+                        .await;
+                    }
+                },
+                async {
+                    #[cfg(feature = "http")]
+                    if let Some((annotations, events)) = annotations {
+                        annotations::run(annotations, events, self.shutdown.subscribe()).await;
+                    }
+                },
+                async {
+                    if let Some((config, socket)) = snmp {
+                        snmp::serve(
+                            socket,
+                            config,
+                            self.state.clone(),
+                            self.shutdown.subscribe(),
+                        )
+                        .await;
+                    }
+                },
+                async {
+                    if let Some((config, monitor)) = low_power {
+                        power::run(monitor, config, &self.state, self.shutdown.subscribe()).await;
+                    }
+                },
+                async {
+                    if let Some(sender) = info {
+                        info::run(
+                            self.manager.clone(),
+                            self.refresh.secs(),
+                            sender,
+                            self.shutdown.subscribe(),
+                        )
+                        .await;
+                    }
+                },
+                async {
+                    if let Some(sender) = heartbeat {
+                        heartbeat::run(self.refresh.secs(), sender, self.shutdown.subscribe())
+                            .await;
+                    }
+                },
+                async {
+                    let (readings, events) = hooks;
+                    hooks::run(&self.manager, readings, events, self.shutdown.subscribe()).await;
+                },
+                async {
+                    if let Some(path) = &self.state_file {
+                        persist::run(path, &self.state, self.shutdown.subscribe()).await;
+                    }
+                },
+                async {
+                    #[cfg(feature = "http")]
+                    if let Some((config, readings)) = summary {
+                        summary::run(config, readings, self.shutdown.subscribe()).await;
+                    }
+                },
+                async {
+                    // The advertisement is withdrawn once the server stops
+                    if let Some((server, _advertisement)) = server {
+                        if let Err(err) = server.await {
+                            tracing::error!("HTTP API stopped: {}", err);
+                        }
+                    }
+                },
+            )
+        };
+        tokio::pin!(work);
+
+        let mut shutdown = self.shutdown.subscribe();
+        let mut failed = None;
+        tokio::select! {
+            _ = &mut work => {
+                self.save_state();
+                return Ok(());
+            }
+            _ = shutdown.wait_for(|stop| *stop) => {}
+            err = impossible_reading(impossible, &self.sensors) => {
+                failed = Some(err);
+                self.shutdown();
+            }
+        }
+
+        tracing::info!("Shutting down");
+        self.manager.stop().await;
+        work.await;
+        self.save_state();
+
+        failed.map_or(Ok(()), |err| Err(err.into()))
+    }
+
+    /// Saves the sensors' state once they're stopped, if there's a state file
+    fn save_state(&self) {
+        if let Some(path) = &self.state_file {
+            if let Err(err) = persist::save(path, &self.state) {
+                tracing::warn!("Unable to save the state to {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    /// Waits out the startup delay and for the network, returning false if shut down meanwhile
+    async fn wait_to_start(&self) -> bool {
+        let waiting = async {
+            if !self.startup_delay.is_zero() {
+                tracing::info!("Waiting {:?} before starting", self.startup_delay);
+                tokio::time::sleep(self.startup_delay).await;
+            }
+            if let Some((host, timeout)) = &self.wait_for_network {
+                wait_for_network(host, *timeout).await;
+            }
+        };
+
+        let mut shutdown = self.shutdown.subscribe();
+        tokio::select! {
+            _ = waiting => true,
+            _ = shutdown.wait_for(|stop| *stop) => false,
+        }
+    }
+
+    /// Twice the longest sampling interval, plus slack for slow reads
+    fn liveness_window(&self) -> Duration {
+        let longest = self
+            .sensors
+            .iter()
+            .filter_map(|sensor| sensor.interval)
+            .chain([self.refresh])
+            .max()
+            .unwrap_or(self.refresh);
+
+        2 * longest.as_duration() + Duration::from_secs(60)
+    }
+
+    /// Stops a running service, making [`MonitorService::run`] return
+    pub fn shutdown(&self) {
+        // Recorded first, so the annotations have it to post before they stop
+        self.state.record_event(Event::Stopping);
+        self.shutdown.send_replace(true);
+    }
+}
+
+/// How often a host that doesn't resolve yet is looked up again
+const NETWORK_RETRY: Duration = Duration::from_secs(2);
+
+/// Looks `host` up until it resolves or `timeout` passes, starting anyway then: the readings
+/// are kept for the sink to retry, or spooled
+async fn wait_for_network(host: &str, timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        let lookup = host.to_string();
+        let resolved = tokio::task::spawn_blocking(move || lookup.to_socket_addrs())
+            .await
+            .is_ok_and(|addrs| addrs.is_ok_and(|mut addrs| addrs.next().is_some()));
+        if resolved {
+            return;
+        }
+        if tokio::time::Instant::now() + NETWORK_RETRY > deadline {
+            tracing::warn!(
+                "{} still doesn't resolve after {:?}, starting anyway",
+                host,
+                timeout
+            );
+            return;
+        }
+        tracing::info!("Waiting for the network, {} doesn't resolve yet", host);
+        tokio::time::sleep(NETWORK_RETRY).await;
+    }
+}
@@ -0,0 +1,89 @@
+//! Plausible readings of the configured sensors, to build and try out dashboards and alerts on
+//! before the hardware is installed
+//!
+//! Every sensor gets a temperature following a daily cycle, coolest before dawn and warmest in
+//! the afternoon around a base that's a little different per sensor, and a humidity moving the
+//! other way. Both drift slowly and carry some noise, rounded to a DHT22's 0.1 resolution, and
+//! then go through the sensor's calibration like real readings do. The same sensors and seed
+//! always give the same readings.
+
+use crate::{config::Sensor, interval::Interval, Datapoint};
+use std::f64::consts::TAU;
+
+const DAY_SECS: i64 = 86_400;
+
+/// The readings every enabled sensor would have taken from `from` to `to` (Unix timestamps),
+/// on its interval or every `refresh` seconds, ordered by time
+pub fn readings(sensors: &[Sensor], refresh: i32, from: i64, to: i64, seed: u64) -> Vec<Datapoint> {
+    let mut readings = Vec::new();
+    for sensor in sensors.iter().filter(|sensor| !sensor.disabled) {
+        let resolution = sensor.interval.map_or(refresh, Interval::secs).max(1);
+        let mut rng = Rng::new(seed ^ hash(&sensor.name));
+        // Rooms sit a few degrees apart, and some are damper than others
+        let base_temperature = 19.0 + rng.uniform() * 5.0;
+        let base_humidity = 40.0 + rng.uniform() * 15.0;
+        let (mut drift, mut noise) = (0.0, 0.0);
+
+        // On multiples of the interval, the way Graphite buckets them anyway
+        let step = i64::from(resolution);
+        let first = from + (step - from.rem_euclid(step)) % step;
+        for time in (first..=to).step_by(resolution as usize) {
+            // Peaking mid-afternoon, at 15:00 UTC
+            let hour = time.rem_euclid(DAY_SECS) as f64 / 3600.0;
+            let daily = (TAU * (hour - 9.0) / 24.0).sin();
+            drift = (drift + rng.normal() * 0.02).clamp(-2.0, 2.0);
+            noise = 0.8 * noise + rng.normal() * 0.1;
+
+            let temperature = base_temperature + 2.5 * daily + drift + noise;
+            let humidity =
+                (base_humidity - 6.0 * daily - 2.0 * drift + 4.0 * noise).clamp(0.0, 100.0);
+            let mut metrics = vec![
+                ("temperature".to_string(), round(temperature)),
+                ("humidity".to_string(), round(humidity)),
+            ];
+            sensor.calibrate(&mut metrics);
+
+            readings.extend(metrics.into_iter().map(|(label, value)| {
+                Datapoint::new(value, &label, sensor, time as u64, resolution)
+            }));
+        }
+    }
+
+    readings.sort_by_key(|datapoint| datapoint.time);
+    readings
+}
+
+fn round(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+/// FNV-1a, so that every sensor gets noise of its own
+fn hash(name: &str) -> u64 {
+    name.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// A xorshift generator, random enough for noise
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Xorshift never leaves zero
+        Rng(seed.max(1))
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Roughly normally distributed around 0 with a standard deviation of 1
+    fn normal(&mut self) -> f64 {
+        // Irwin-Hall: the sum of 12 uniform values, less their mean
+        (0..12).map(|_| self.uniform()).sum::<f64>() - 6.0
+    }
+}
@@ -0,0 +1,889 @@
+//! Destinations the readings are shipped to
+
+#[cfg(feature = "http")]
+use crate::{
+    config,
+    dns::{CachingResolver, IpFamily},
+    error::ConfigError,
+    identity::Identity,
+    ratelimit::RateLimiter,
+};
+use crate::{config::Sensor, error::SinkError, Datapoint};
+use futures::future::BoxFuture;
+#[cfg(feature = "http")]
+use futures::{stream, TryStreamExt};
+#[cfg(feature = "http")]
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use std::{io::Write, path::PathBuf, str::FromStr, sync::Mutex};
+
+/// A destination for batches of datapoints
+pub trait Sink: Send + Sync {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>>;
+
+    /// Follows the sensors being added, changed or removed while the service runs, for sinks
+    /// that write something of the sensors' configuration along with their series
+    fn sensors_changed(&self, _sensors: &[Sensor]) {}
+}
+
+/// How the HTTP sinks connect to the metrics endpoint
+#[cfg(feature = "http")]
+#[derive(Debug, Clone, Default)]
+pub struct HttpClientConfig {
+    /// PEM certificates of CAs to trust besides the built-in roots, e.g. an internal CA
+    pub ca_cert: Option<PathBuf>,
+    /// A PEM client certificate chain and its private key, for endpoints behind mutual TLS
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+    /// Accept any server certificate - only for testing against self-signed endpoints
+    pub insecure_skip_verify: bool,
+    /// An HTTP(S) proxy to send the requests through, overriding the `HTTP_PROXY`/`HTTPS_PROXY`
+    /// environment variables; hosts in `NO_PROXY` are still connected to directly
+    pub proxy: Option<String>,
+    /// Keep the addresses the endpoint resolved to this long, and fall back to them when looking
+    /// it up fails, see [`crate::dns`] (default: resolve it for every connection)
+    pub dns_cache_ttl: Option<Duration>,
+    /// Only connect to the endpoint's IPv4 or IPv6 addresses
+    pub ip_family: IpFamily,
+    /// Look the endpoint up with this DNS server rather than the system's resolver
+    pub dns_server: Option<SocketAddr>,
+}
+
+#[cfg(feature = "http")]
+impl HttpClientConfig {
+    pub fn build(&self) -> Result<reqwest::Client, ConfigError> {
+        let read = |path: &Path| {
+            std::fs::read(path).map_err(|err| ConfigError::from_io(path.to_path_buf(), err))
+        };
+        let invalid = |path: &Path, reason: &dyn std::fmt::Display| {
+            ConfigError::Invalid(format!("{}: {}", path.display(), reason))
+        };
+
+        let mut builder = reqwest::Client::builder();
+        if let Some(path) = &self.ca_cert {
+            let certs = reqwest::Certificate::from_pem_bundle(&read(path)?)
+                .map_err(|err| invalid(path, &err))?;
+            if certs.is_empty() {
+                return Err(invalid(path, &"no certificates found"));
+            }
+            for cert in certs {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some((cert, key)) = &self.client_identity {
+            // The identity is read from a single PEM with the key and the certificates
+            let mut pem = read(key)?;
+            pem.push(b'\n');
+            pem.extend(read(cert)?);
+            let identity = reqwest::Identity::from_pem(&pem).map_err(|err| invalid(cert, &err))?;
+            builder = builder.identity(identity);
+        }
+        if let Some(url) = &self.proxy {
+            let proxy = reqwest::Proxy::all(url)
+                .map_err(|err| ConfigError::Invalid(format!("invalid proxy {}: {}", url, err)))?
+                .no_proxy(reqwest::NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+        if self.dns_cache_ttl.is_some()
+            || self.ip_family != IpFamily::Any
+            || self.dns_server.is_some()
+        {
+            let mut resolver =
+                CachingResolver::new(self.dns_cache_ttl.unwrap_or_default()).family(self.ip_family);
+            if let Some(server) = self.dns_server {
+                resolver = resolver.server(server);
+            }
+            builder = builder.dns_resolver(Arc::new(resolver));
+        }
+        if self.insecure_skip_verify {
+            tracing::warn!("Not verifying the metrics endpoint's TLS certificate");
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        builder.build().map_err(|err| {
+            ConfigError::Invalid(format!("unable to set up the HTTP client: {}", err))
+        })
+    }
+}
+
+/// How batches of datapoints are encoded in request bodies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// An array of objects, the format Graphite's JSON API takes
+    #[default]
+    Json,
+    /// The same array in MessagePack, with the field names kept
+    MessagePack,
+    /// The same array in CBOR, with the field names kept
+    Cbor,
+}
+
+impl Encoding {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    pub fn encode<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<Vec<u8>, SinkError> {
+        let mut body = Vec::new();
+        self.encode_into(value, &mut body)?;
+        Ok(body)
+    }
+
+    /// Encodes straight into a writer, e.g. a request body allocated up front
+    pub fn encode_into<T: serde::Serialize + ?Sized>(
+        &self,
+        value: &T,
+        mut writer: impl std::io::Write,
+    ) -> Result<(), SinkError> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_writer(writer, value)?),
+            Encoding::MessagePack => rmp_serde::encode::write_named(&mut writer, value)
+                .map_err(|err| SinkError::Encode(err.to_string())),
+            Encoding::Cbor => ciborium::into_writer(value, writer)
+                .map_err(|err| SinkError::Encode(err.to_string())),
+        }
+    }
+
+    /// How many bytes a value encodes to, without allocating them
+    pub fn encoded_len<T: serde::Serialize + ?Sized>(&self, value: &T) -> Result<usize, SinkError> {
+        let mut counter = ByteCounter(0);
+        self.encode_into(value, &mut counter)?;
+        Ok(counter.0)
+    }
+}
+
+/// Counts the bytes written to it, and drops them
+struct ByteCounter(usize);
+
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.0 += bytes.len();
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(encoding: &str) -> Result<Self, Self::Err> {
+        match encoding {
+            "json" => Ok(Encoding::Json),
+            "msgpack" => Ok(Encoding::MessagePack),
+            "cbor" => Ok(Encoding::Cbor),
+            _ => Err(format!(
+                "unknown encoding {}, expected json, msgpack or cbor",
+                encoding
+            )),
+        }
+    }
+}
+
+/// How a dry run prints the request bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DryRun {
+    /// The datapoints as indented JSON, whatever the encoding
+    Pretty,
+    /// The body as it would be sent, in hex unless it's JSON
+    Raw,
+}
+
+impl FromStr for DryRun {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "pretty" => Ok(DryRun::Pretty),
+            "raw" => Ok(DryRun::Raw),
+            _ => Err(format!("unknown format {}, expected pretty or raw", format)),
+        }
+    }
+}
+
+/// The unit the sinks' timestamps are written in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Precision {
+    /// What Graphite takes
+    #[default]
+    Seconds,
+    Milliseconds,
+    /// What InfluxDB and OTLP receivers take by default
+    Nanoseconds,
+}
+
+impl Precision {
+    /// How many of the unit there are in a second
+    fn per_second(&self) -> i64 {
+        match self {
+            Precision::Seconds => 1,
+            Precision::Milliseconds => 1_000,
+            Precision::Nanoseconds => 1_000_000_000,
+        }
+    }
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(precision: &str) -> Result<Self, Self::Err> {
+        match precision {
+            "s" => Ok(Precision::Seconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "ns" => Ok(Precision::Nanoseconds),
+            _ => Err(format!(
+                "unknown precision {}, expected s, ms or ns",
+                precision
+            )),
+        }
+    }
+}
+
+/// The largest request body posted to Graphite by default; bigger batches are split up
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 1 << 20;
+
+/// How the requests to the metrics endpoint authenticate
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub enum Auth {
+    /// An `Authorization: Bearer <token>` header
+    Bearer(String),
+    /// HTTP basic auth, e.g. Grafana Cloud's instance ID and an access policy token
+    Basic { username: String, password: String },
+    /// The key as the value of a header of its own, e.g. `X-API-Key`
+    Header {
+        name: reqwest::header::HeaderName,
+        value: String,
+    },
+    /// No credentials, e.g. for a receiver on the LAN
+    None,
+}
+
+#[cfg(feature = "http")]
+impl Auth {
+    fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Auth::Bearer(token) => request.bearer_auth(token),
+            Auth::Basic { username, password } => request.basic_auth(username, Some(password)),
+            Auth::Header { name, value } => request.header(name, value),
+            Auth::None => request,
+        }
+    }
+}
+
+/// Posts datapoints to a Graphite instance's JSON API (e.g. on Grafana Cloud)
+#[cfg(feature = "http")]
+pub struct Graphite {
+    endpoint: String,
+    auth: Auth,
+    client: reqwest::Client,
+    max_payload_bytes: usize,
+    max_chunk_datapoints: Option<usize>,
+    upload_concurrency: usize,
+    encoding: Encoding,
+    precision: Precision,
+    identity: Option<Identity>,
+    /// What the sensors' series are tagged with, following the sensors as they change
+    tags: RwLock<SeriesTags>,
+    dry_run: Option<DryRun>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+#[cfg(feature = "http")]
+impl Graphite {
+    pub fn new(endpoint: impl Into<String>, apikey: impl Into<String>) -> Self {
+        Graphite {
+            endpoint: endpoint.into(),
+            auth: Auth::Bearer(apikey.into()),
+            client: reqwest::Client::new(),
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            max_chunk_datapoints: None,
+            upload_concurrency: 1,
+            encoding: Encoding::Json,
+            precision: Precision::Seconds,
+            identity: None,
+            tags: RwLock::default(),
+            dry_run: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Posts with a client set up by [`HttpClientConfig::build`] rather than a default one
+    pub fn with_client(
+        endpoint: impl Into<String>,
+        apikey: impl Into<String>,
+        client: reqwest::Client,
+    ) -> Self {
+        Graphite {
+            endpoint: endpoint.into(),
+            auth: Auth::Bearer(apikey.into()),
+            client,
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+            max_chunk_datapoints: None,
+            upload_concurrency: 1,
+            encoding: Encoding::Json,
+            precision: Precision::Seconds,
+            identity: None,
+            tags: RwLock::default(),
+            dry_run: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Authenticates some other way than with the API key as a bearer token
+    pub fn auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Splits batches into several requests whose bodies stay under this size
+    pub fn max_payload_bytes(mut self, bytes: usize) -> Self {
+        self.max_payload_bytes = bytes;
+        self
+    }
+
+    /// Also splits batches into requests of at most this many datapoints, e.g. to post the
+    /// readings of many sensors in parallel
+    pub fn max_chunk_datapoints(mut self, datapoints: usize) -> Self {
+        self.max_chunk_datapoints = Some(datapoints.max(1));
+        self
+    }
+
+    /// Posts up to this many of a batch's requests at the same time rather than one after the
+    /// other
+    pub fn upload_concurrency(mut self, requests: usize) -> Self {
+        self.upload_concurrency = requests.max(1);
+        self
+    }
+
+    /// Encodes the batches as something other than Graphite's JSON, for custom receivers
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Writes the timestamps in this unit rather than in seconds
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Labels every series with the Pi it comes from
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Wait for the rate limiter before every request, retries and backfills included
+    pub fn rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    /// Adds the sensors' `tags` to the names of their series, as Graphite tags
+    pub fn sensor_tags(mut self, sensors: &[Sensor]) -> Self {
+        self.tags.get_mut().expect("Series tags lock poisoned").tags = tags_by_path(sensors);
+        self
+    }
+
+    /// Also tags the sensors' series with the sensor's type and the metric's unit, e.g.
+    /// `;type=dht22;unit=°C`, for dashboards to label them by. Tags of the same name in the
+    /// sensors' `tags` win.
+    pub fn metadata_tags(mut self, sensors: &[Sensor]) -> Self {
+        self.tags
+            .get_mut()
+            .expect("Series tags lock poisoned")
+            .metadata = Some(metadata_by_path(sensors));
+        self
+    }
+
+    /// Print every request body that would be posted to stdout rather than post it
+    pub fn dry_run(mut self, dry_run: DryRun) -> Self {
+        self.dry_run = Some(dry_run);
+        self
+    }
+
+    /// Posts the batch in as many requests as it takes to keep each body under the maximum size,
+    /// up to the upload concurrency of them at a time. If one of them fails the whole batch
+    /// counts as failed, and the endpoint is left to ignore the datapoints it already got when
+    /// it's written again.
+    async fn post_all(&self, readings: &[Datapoint]) -> Result<(), SinkError> {
+        let relabelled = {
+            let tags = self.tags.read().expect("Series tags lock poisoned");
+            match (self.precision.per_second(), &self.identity) {
+                (1, None) if tags.is_empty() => None,
+                (per_second, identity) => Some(
+                    readings
+                        .iter()
+                        .map(|datapoint| {
+                            let name = tags.tagged(&datapoint.name);
+                            Datapoint {
+                                name: match identity {
+                                    Some(identity) => identity.name(&name),
+                                    None => name,
+                                },
+                                time: datapoint.time * per_second,
+                                ..datapoint.clone()
+                            }
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+            }
+        };
+        let readings = relabelled.as_deref().unwrap_or(readings);
+        let bodies = self.split(readings)?;
+        if bodies.len() > 1 {
+            tracing::info!(
+                requests = bodies.len(),
+                "Splitting {} datapoints to stay under {} bytes per request",
+                readings.len(),
+                self.max_payload_bytes
+            );
+        }
+
+        let Some(dry_run) = self.dry_run else {
+            return stream::iter(bodies.into_iter().map(Ok))
+                .try_for_each_concurrent(self.upload_concurrency, |(body, count)| {
+                    self.post(body, count)
+                })
+                .await;
+        };
+        let mut offset = 0;
+        for (body, count) in bodies {
+            self.print(dry_run, &body, &readings[offset..offset + count])?;
+            offset += count;
+        }
+        Ok(())
+    }
+
+    /// Prints what would have been posted, instead of posting it
+    fn print(&self, dry_run: DryRun, body: &[u8], readings: &[Datapoint]) -> Result<(), SinkError> {
+        let payload = match (dry_run, self.encoding) {
+            (DryRun::Pretty, _) => serde_json::to_string_pretty(readings)?,
+            (DryRun::Raw, Encoding::Json) => String::from_utf8_lossy(body).into_owned(),
+            (DryRun::Raw, _) => body.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(
+            stdout,
+            "POST {} ({} datapoints, {} bytes of {})\n{}",
+            self.endpoint,
+            readings.len(),
+            body.len(),
+            self.encoding.content_type(),
+            payload
+        );
+        Ok(())
+    }
+
+    /// Encoded arrays of consecutive datapoints, each as large as fits the maximum size (and
+    /// count) but holding at least one datapoint, with how many datapoints they hold
+    fn split(&self, readings: &[Datapoint]) -> Result<Vec<(Vec<u8>, usize)>, SinkError> {
+        // More than any encoding's array header, or JSON's brackets
+        const ARRAY_OVERHEAD: usize = 5;
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut size = ARRAY_OVERHEAD;
+        for (index, datapoint) in readings.iter().enumerate() {
+            // With JSON's separating comma
+            let len = self.encoding.encoded_len(datapoint)? + 1;
+            let full = self
+                .max_chunk_datapoints
+                .is_some_and(|max| index - start >= max);
+            if index > start && (full || size + len > self.max_payload_bytes) {
+                chunks.push((&readings[start..index], size));
+                start = index;
+                size = ARRAY_OVERHEAD;
+            }
+            size += len;
+        }
+        chunks.push((&readings[start..], size));
+
+        // Encoded straight into bodies of the size they'll take, which are posted as they are
+        chunks
+            .into_iter()
+            .map(|(chunk, size)| {
+                let mut body = Vec::with_capacity(size);
+                self.encoding.encode_into(chunk, &mut body)?;
+                Ok((body, chunk.len()))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(name = "write", skip(self, body))]
+    async fn post(&self, body: Vec<u8>, datapoints: usize) -> Result<(), SinkError> {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
+        }
+        let start = Instant::now();
+
+        match self.encoding {
+            Encoding::Json => tracing::info!(
+                "Sending a POST request to Grafana with: {}",
+                String::from_utf8_lossy(&body)
+            ),
+            encoding => tracing::info!(
+                "Sending a POST request with {} bytes of {}",
+                body.len(),
+                encoding.content_type()
+            ),
+        }
+
+        let request = self
+            .client
+            .post(&self.endpoint)
+            .header("Content-Type", self.encoding.content_type());
+        let response = self.auth.apply(request).body(body).send().await?;
+
+        tracing::info!("Received response: {:?}", &response);
+
+        match response.status() {
+            // Graphite answers 200, custom receivers may well answer 202 or 204
+            status if status.is_success() => {
+                tracing::info!(
+                    duration_ms = start.elapsed().as_millis() as u64,
+                    "Data submitted to Graphite successfully!"
+                );
+                Ok(())
+            }
+            status @ (reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN) => {
+                Err(SinkError::Unauthorized(status))
+            }
+            reqwest::StatusCode::BAD_REQUEST => Err(SinkError::BadRequest(
+                response.text().await.unwrap_or_default(),
+            )),
+            status => Err(SinkError::Status(status)),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+impl Sink for Graphite {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(self.post_all(readings))
+    }
+
+    fn sensors_changed(&self, sensors: &[Sensor]) {
+        let mut tags = self.tags.write().expect("Series tags lock poisoned");
+        tags.tags = tags_by_path(sensors);
+        if tags.metadata.is_some() {
+            tags.metadata = Some(metadata_by_path(sensors));
+        }
+    }
+}
+
+/// Writes the readings to a local agent's unix socket, for sites already shipping everything
+/// through collectd or Telegraf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketSink {
+    agent: Agent,
+    dry_run: bool,
+}
+
+/// The local agent a [`SocketSink`] writes to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Agent {
+    /// collectd's `unixsock` plugin, as `PUTVAL` commands, e.g.
+    /// `PUTVAL "pi/monitoring-kitchen/temperature" interval=60 1700000000:21.5`
+    Collectd { path: PathBuf, hostname: String },
+    /// Telegraf's `socket_listener` on a `unix://` address, in InfluxDB line protocol, e.g.
+    /// `temperature,sensor=kitchen value=21.5 1700000000000000000`
+    Influx { path: PathBuf },
+}
+
+impl FromStr for SocketSink {
+    type Err = String;
+
+    /// `collectd:<socket path>` or `influx:<socket path>`
+    fn from_str(sink: &str) -> Result<Self, Self::Err> {
+        match sink.split_once(':') {
+            Some(("collectd", path)) if !path.is_empty() => Ok(SocketSink::new(Agent::Collectd {
+                path: path.into(),
+                hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            })),
+            Some(("influx", path)) if !path.is_empty() => {
+                Ok(SocketSink::new(Agent::Influx { path: path.into() }))
+            }
+            _ => Err(format!(
+                "unknown socket sink {}, expected collectd:<socket path> or influx:<socket path>",
+                sink
+            )),
+        }
+    }
+}
+
+impl SocketSink {
+    pub fn new(agent: Agent) -> Self {
+        SocketSink {
+            agent,
+            dry_run: false,
+        }
+    }
+
+    /// Print what would be written to the socket to stdout rather than write it
+    pub fn dry_run(mut self) -> Self {
+        self.dry_run = true;
+        self
+    }
+
+    /// Prints what would have been written, instead of writing it
+    fn print(&self, readings: &[Datapoint]) {
+        let (path, text) = match &self.agent {
+            Agent::Collectd { path, hostname } => (
+                path,
+                readings
+                    .iter()
+                    .map(|datapoint| putval(hostname, datapoint))
+                    .collect::<String>(),
+            ),
+            Agent::Influx { path } => (path, line_protocol(readings)),
+        };
+
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(
+            stdout,
+            "WRITE {} ({} datapoints)\n{}",
+            path.display(),
+            readings.len(),
+            text
+        );
+    }
+
+    #[cfg(unix)]
+    async fn write_all(&self, readings: &[Datapoint]) -> Result<(), SinkError> {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        // Connected for every batch, so the agent restarting doesn't need noticing
+        match &self.agent {
+            Agent::Collectd { path, hostname } => {
+                let (reader, mut writer) =
+                    tokio::net::UnixStream::connect(path).await?.into_split();
+                let mut replies = BufReader::new(reader).lines();
+                // The plugin answers every command before reading the next
+                for datapoint in readings {
+                    writer
+                        .write_all(putval(hostname, datapoint).as_bytes())
+                        .await?;
+                    let reply = replies.next_line().await?.unwrap_or_default();
+                    // `0 Success: ...`, or a negative status and why
+                    if !reply.starts_with('0') {
+                        return Err(SinkError::BadRequest(reply));
+                    }
+                }
+                Ok(())
+            }
+            Agent::Influx { path } => {
+                let mut socket = tokio::net::UnixStream::connect(path).await?;
+                socket.write_all(line_protocol(readings).as_bytes()).await?;
+                socket.shutdown().await?;
+                Ok(())
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn write_all(&self, _readings: &[Datapoint]) -> Result<(), SinkError> {
+        Err(SinkError::Socket(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix sockets are only available on Unix",
+        )))
+    }
+}
+
+/// The collectd `PUTVAL` command writing a datapoint, with its newline
+fn putval(hostname: &str, datapoint: &Datapoint) -> String {
+    let (sensor, metric) = datapoint
+        .name
+        .rsplit_once('.')
+        .unwrap_or(("", &datapoint.name));
+    let kind = match metric {
+        "temperature" | "humidity" => metric.to_string(),
+        _ => format!("gauge-{}", metric),
+    };
+    let plugin = match sensor {
+        "" => "monitoring".to_string(),
+        _ => format!("monitoring-{}", sensor),
+    };
+    format!(
+        "PUTVAL \"{}/{}/{}\" interval={} {}:{}\n",
+        hostname,
+        plugin,
+        kind,
+        datapoint.interval.max(1),
+        datapoint.time,
+        datapoint.value
+    )
+}
+
+/// The datapoints in InfluxDB line protocol, a line each
+fn line_protocol(readings: &[Datapoint]) -> String {
+    let mut lines = String::new();
+    for datapoint in readings {
+        let (sensor, metric) = datapoint
+            .name
+            .rsplit_once('.')
+            .unwrap_or(("", &datapoint.name));
+        lines.push_str(&escape_influx(metric));
+        if !sensor.is_empty() {
+            lines.push_str(",sensor=");
+            lines.push_str(&escape_influx(sensor));
+        }
+        lines.push_str(&format!(
+            " value={} {}\n",
+            datapoint.value,
+            datapoint.time * Precision::Nanoseconds.per_second()
+        ));
+    }
+    lines
+}
+
+/// What the sensors' series are tagged with, by the sensors' paths
+#[cfg(feature = "http")]
+#[derive(Default)]
+struct SeriesTags {
+    /// The Graphite tags of the sensors' series
+    tags: BTreeMap<String, String>,
+    /// The sensors' types and units to tag their series with, if they're tagged with them
+    metadata: Option<BTreeMap<String, TaggedMetadata>>,
+}
+
+#[cfg(feature = "http")]
+impl SeriesTags {
+    fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.metadata.as_ref().is_none_or(BTreeMap::is_empty)
+    }
+
+    /// A series' name with its sensor's tags and metadata, if it belongs to a sensor that has
+    /// some
+    fn tagged(&self, series: &str) -> String {
+        let mut tagged = series.to_string();
+        if let Some((_, tags)) = sensor_of(&self.tags, series) {
+            tagged.push_str(tags);
+        }
+        if let Some((path, metadata)) = self
+            .metadata
+            .as_ref()
+            .and_then(|metadata| sensor_of(metadata, series))
+        {
+            if let Some(kind) = metadata.kind {
+                tagged.push_str(&format!(";type={}", kind));
+            }
+            let metric = &series[path.len() + 1..];
+            if let Some(unit) = metadata.units.as_ref().and_then(|units| {
+                units
+                    .get(metric)
+                    .map(String::as_str)
+                    .or_else(|| config::default_unit(metric))
+            }) {
+                tagged.push_str(&format!(";unit={}", unit));
+            }
+        }
+        tagged
+    }
+}
+
+/// The sensors' Graphite tags, by the sensors' paths
+#[cfg(feature = "http")]
+fn tags_by_path(sensors: &[Sensor]) -> BTreeMap<String, String> {
+    sensors
+        .iter()
+        .filter(|sensor| !sensor.tags.is_empty())
+        .map(|sensor| (sensor.path(), sensor.graphite_tags()))
+        .collect()
+}
+
+/// The sensors' types and units to tag their series with, by the sensors' paths
+#[cfg(feature = "http")]
+fn metadata_by_path(sensors: &[Sensor]) -> BTreeMap<String, TaggedMetadata> {
+    sensors
+        .iter()
+        .map(|sensor| {
+            let metadata = TaggedMetadata {
+                kind: (!sensor.tags.contains_key("type")).then(|| sensor.kind.name()),
+                units: (!sensor.tags.contains_key("unit")).then(|| sensor.units.clone()),
+            };
+            (sensor.path(), metadata)
+        })
+        .collect()
+}
+
+/// What a sensor's series are tagged with by [`Graphite::metadata_tags`], leaving out what the
+/// sensor's own tags say already
+#[cfg(feature = "http")]
+struct TaggedMetadata {
+    kind: Option<&'static str>,
+    /// The configured units, with the defaults for temperatures and humidity
+    units: Option<BTreeMap<String, String>>,
+}
+
+/// The entry of the sensor a series belongs to, by the sensors' paths. The longest matching
+/// path wins, for sensors whose paths start with another one's.
+#[cfg(feature = "http")]
+fn sensor_of<'a, T>(by_path: &'a BTreeMap<String, T>, series: &str) -> Option<(&'a String, &'a T)> {
+    by_path
+        .iter()
+        .filter(|(path, _)| {
+            series
+                .strip_prefix(path.as_str())
+                .is_some_and(|metric| metric.starts_with('.'))
+        })
+        .max_by_key(|(path, _)| path.len())
+}
+
+/// Escapes the characters InfluxDB line protocol separates measurements and tags with
+fn escape_influx(name: &str) -> String {
+    name.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+impl Sink for SocketSink {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        if self.dry_run {
+            self.print(readings);
+            return Box::pin(async { Ok(()) });
+        }
+        Box::pin(self.write_all(readings))
+    }
+}
+
+/// Keeps every written datapoint in memory, for tests and for embedding applications that
+/// want to consume readings directly
+#[derive(Default)]
+pub struct Memory {
+    datapoints: Mutex<Vec<Datapoint>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory::default()
+    }
+
+    /// Returns and clears everything written so far
+    pub fn take(&self) -> Vec<Datapoint> {
+        std::mem::take(&mut *self.datapoints.lock().expect("Memory sink lock poisoned"))
+    }
+}
+
+impl Sink for Memory {
+    fn write<'a>(&'a self, readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        self.datapoints
+            .lock()
+            .expect("Memory sink lock poisoned")
+            .extend_from_slice(readings);
+
+        Box::pin(futures::future::ready(Ok(())))
+    }
+}
@@ -0,0 +1,383 @@
+//! Answering SNMPv2c queries for the latest readings, for network management systems like
+//! LibreNMS or PRTG that poll everything over SNMP
+//!
+//! The agent serves a table of the sensors under its base OID, with a row per sensor in the order
+//! of `/readings`, see `MONITORING-MIB.txt`:
+//!
+//! ```text
+//! <base>.1.1.1.<row>  sensorIndex        INTEGER
+//! <base>.1.1.2.<row>  sensorName         OCTET STRING, e.g. "kitchen"
+//! <base>.1.1.3.<row>  sensorStatus       INTEGER, pending(1), warmingUp(2), ok(3), failing(4)
+//! <base>.1.1.4.<row>  sensorTemperature  INTEGER, in hundredths of a degree Celsius
+//! <base>.1.1.5.<row>  sensorHumidity     INTEGER, in hundredths of a percent
+//! <base>.1.1.6.<row>  sensorFailures     Gauge32, failed attempts since the last reading
+//! ```
+//!
+//! Only `GetRequest`, `GetNextRequest` and `GetBulkRequest` are answered; the agent is read-only.
+
+use crate::state::{SensorState, SensorStatus, State};
+use std::{fmt, io, net::SocketAddr, str::FromStr, sync::Arc};
+use tokio::{net::UdpSocket, sync::watch};
+
+/// NET-SNMP's `netSnmpPlaypen`, set aside for agents without an enterprise number of their own
+pub const DEFAULT_BASE_OID: &str = "1.3.6.1.4.1.8072.9999.9999";
+
+/// The most rows a single `GetBulkRequest` is answered with, keeping replies within a datagram
+const MAX_REPETITIONS: usize = 50;
+
+/// The largest response sent, leaving room in a 1500 byte Ethernet frame for the IP and UDP
+/// headers. `GetBulkRequest`s are answered with as many bindings as fit; other requests that
+/// don't fit get a `tooBig` error, as RFC 3416 has it.
+const MAX_RESPONSE_BYTES: usize = 1400;
+
+const SNMP_V2C: i64 = 1;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GAUGE32: u8 = 0x42;
+const TOO_BIG: u8 = 1;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+const GET_REQUEST: u8 = 0xa0;
+const GET_NEXT_REQUEST: u8 = 0xa1;
+const RESPONSE: u8 = 0xa2;
+const GET_BULK_REQUEST: u8 = 0xa5;
+
+/// An object identifier, e.g. `1.3.6.1.4.1.8072.9999.9999`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Oid(Vec<u32>);
+
+impl FromStr for Oid {
+    type Err = String;
+
+    fn from_str(oid: &str) -> Result<Self, Self::Err> {
+        let arcs = oid
+            .trim_start_matches('.')
+            .split('.')
+            .map(|arc| arc.parse::<u32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid OID {}, expected e.g. {}", oid, DEFAULT_BASE_OID))?;
+        match arcs.as_slice() {
+            [first, second, ..] if *first <= 2 && *second < 40 => Ok(Oid(arcs)),
+            _ => Err(format!(
+                "invalid OID {}, expected e.g. {}",
+                oid, DEFAULT_BASE_OID
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arcs = self.0.iter().map(u32::to_string).collect::<Vec<_>>();
+        write!(f, "{}", arcs.join("."))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SnmpConfig {
+    pub listen: SocketAddr,
+    /// The community string queries have to present
+    pub community: String,
+    /// Where the sensor table is rooted (default: [`DEFAULT_BASE_OID`])
+    pub base: Oid,
+}
+
+impl SnmpConfig {
+    pub fn new(listen: SocketAddr, community: impl Into<String>) -> Self {
+        SnmpConfig {
+            listen,
+            community: community.into(),
+            base: DEFAULT_BASE_OID.parse().expect("valid default OID"),
+        }
+    }
+}
+
+enum Value {
+    Integer(i64),
+    String(String),
+    Gauge(u32),
+}
+
+/// Binds the agent's socket, so a port already in use fails the service's start
+pub(crate) async fn bind(config: &SnmpConfig) -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(config.listen).await?;
+    tracing::info!("SNMP agent listening on {}", socket.local_addr()?);
+    Ok(socket)
+}
+
+/// Answers queries until shutdown
+pub(crate) async fn serve(
+    socket: UdpSocket,
+    config: &SnmpConfig,
+    state: Arc<State>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut buffer = vec![0; 65_535];
+    loop {
+        let (len, peer) = tokio::select! {
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok(received) => received,
+                Err(err) => {
+                    tracing::warn!("Unable to receive an SNMP query: {}", err);
+                    continue;
+                }
+            },
+            _ = shutdown.wait_for(|stop| *stop) => return,
+        };
+
+        match respond(&buffer[..len], config, &state.snapshot()) {
+            Some(response) => {
+                if let Err(err) = socket.send_to(&response, peer).await {
+                    tracing::warn!("Unable to answer an SNMP query from {}: {}", peer, err);
+                }
+            }
+            None => tracing::debug!("Ignoring an invalid SNMP query from {}", peer),
+        }
+    }
+}
+
+/// The response to a query, if it's a well formed SNMPv2c request with the right community
+fn respond(query: &[u8], config: &SnmpConfig, sensors: &[SensorState]) -> Option<Vec<u8>> {
+    let (SEQUENCE, message, _) = read_tlv(query)? else {
+        return None;
+    };
+    let (version, rest) = read_integer(message)?;
+    let (OCTET_STRING, community, rest) = read_tlv(rest)? else {
+        return None;
+    };
+    if version != SNMP_V2C || community != config.community.as_bytes() {
+        return None;
+    }
+    let (kind, pdu, _) = read_tlv(rest)?;
+    let (request_id, pdu) = read_integer(pdu)?;
+    let (first, pdu) = read_integer(pdu)?;
+    let (second, pdu) = read_integer(pdu)?;
+    let (SEQUENCE, mut bindings, _) = read_tlv(pdu)? else {
+        return None;
+    };
+
+    let mut requested = Vec::new();
+    while !bindings.is_empty() {
+        let (SEQUENCE, binding, rest) = read_tlv(bindings)? else {
+            return None;
+        };
+        let (OBJECT_IDENTIFIER, oid, _) = read_tlv(binding)? else {
+            return None;
+        };
+        requested.push(decode_oid(oid)?);
+        bindings = rest;
+    }
+
+    let objects = objects(&config.base, sensors);
+    let next = |oid: &Oid| objects.iter().find(|(object, _)| object > oid);
+    let mut answers = Vec::new();
+    match kind {
+        GET_REQUEST => {
+            for oid in requested {
+                let value = objects.iter().find(|(object, _)| *object == oid);
+                answers.push((oid, value.map(|(_, value)| value)));
+            }
+        }
+        GET_NEXT_REQUEST => {
+            for oid in requested {
+                match next(&oid) {
+                    Some((object, value)) => answers.push((object.clone(), Some(value))),
+                    None => answers.push((oid, None)),
+                }
+            }
+        }
+        GET_BULK_REQUEST => {
+            let non_repeaters = usize::try_from(first).unwrap_or(0).min(requested.len());
+            let repetitions = usize::try_from(second).unwrap_or(0).min(MAX_REPETITIONS);
+            for oid in &requested[..non_repeaters] {
+                match next(oid) {
+                    Some((object, value)) => answers.push((object.clone(), Some(value))),
+                    None => answers.push((oid.clone(), None)),
+                }
+            }
+            let mut cursors = requested[non_repeaters..].to_vec();
+            for _ in 0..repetitions {
+                if cursors.is_empty() {
+                    break;
+                }
+                for cursor in &mut cursors {
+                    match next(cursor) {
+                        Some((object, value)) => {
+                            *cursor = object.clone();
+                            answers.push((object.clone(), Some(value)));
+                        }
+                        None => answers.push((cursor.clone(), None)),
+                    }
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    // The response without any bindings, and the bytes the longer lengths of the bindings'
+    // sequence, the PDU and the message may take once they're in
+    let budget = MAX_RESPONSE_BYTES
+        .saturating_sub(response(config, request_id, 0, &[]).len())
+        .saturating_sub(6);
+    let mut bindings = Vec::new();
+    for (oid, value) in answers {
+        let mut binding = tlv(OBJECT_IDENTIFIER, &encode_oid(&oid));
+        binding.extend(match value {
+            Some(Value::Integer(value)) => tlv(INTEGER, &encode_integer(*value)),
+            Some(Value::String(value)) => tlv(OCTET_STRING, value.as_bytes()),
+            Some(Value::Gauge(value)) => tlv(GAUGE32, &encode_integer(i64::from(*value))),
+            // GETs of what isn't there, and walks past the table's end
+            None if kind == GET_REQUEST => tlv(NO_SUCH_OBJECT, &[]),
+            None => tlv(END_OF_MIB_VIEW, &[]),
+        });
+        let binding = tlv(SEQUENCE, &binding);
+        if bindings.len() + binding.len() > budget {
+            if kind == GET_BULK_REQUEST {
+                break;
+            }
+            return Some(response(config, request_id, TOO_BIG, &[]));
+        }
+        bindings.extend(binding);
+    }
+
+    Some(response(config, request_id, 0, &bindings))
+}
+
+/// A `Response` PDU in its message, with the encoded variable bindings
+fn response(config: &SnmpConfig, request_id: i64, error_status: u8, bindings: &[u8]) -> Vec<u8> {
+    let mut pdu = tlv(INTEGER, &encode_integer(request_id));
+    pdu.extend(tlv(INTEGER, &[error_status]));
+    pdu.extend(tlv(INTEGER, &[0])); // error-index
+    pdu.extend(tlv(SEQUENCE, bindings));
+
+    let mut message = tlv(INTEGER, &encode_integer(SNMP_V2C));
+    message.extend(tlv(OCTET_STRING, config.community.as_bytes()));
+    message.extend(tlv(RESPONSE, &pdu));
+    tlv(SEQUENCE, &message)
+}
+
+/// The sensor table's objects, in the order they're walked
+fn objects(base: &Oid, sensors: &[SensorState]) -> Vec<(Oid, Value)> {
+    let hundredths = |value: f64| Value::Integer((value * 100.0).round() as i64);
+    let mut objects = Vec::new();
+    for (index, sensor) in sensors.iter().enumerate() {
+        let row = index as u32 + 1;
+        let columns = [
+            (1, Some(Value::Integer(i64::from(row)))),
+            (2, Some(Value::String(sensor.sensor.clone()))),
+            (
+                3,
+                Some(Value::Integer(match sensor.status {
+                    SensorStatus::Pending => 1,
+                    SensorStatus::WarmingUp => 2,
+                    SensorStatus::Ok => 3,
+                    SensorStatus::Failing => 4,
+                })),
+            ),
+            (4, sensor.values.get("temperature").copied().map(hundredths)),
+            (5, sensor.values.get("humidity").copied().map(hundredths)),
+            (6, Some(Value::Gauge(sensor.failures))),
+        ];
+        for (column, value) in columns {
+            if let Some(value) = value {
+                let mut oid = base.0.clone();
+                oid.extend([1, 1, column, row]);
+                objects.push((Oid(oid), value));
+            }
+        }
+    }
+    objects.sort_by(|(a, _), (b, _)| a.cmp(b));
+    objects
+}
+
+/// A BER tag, its contents and what follows it
+fn read_tlv(bytes: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = bytes.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = match first {
+        0..=0x7f => (usize::from(first), rest),
+        0x81..=0x84 => {
+            let octets = usize::from(first & 0x7f);
+            let len = rest
+                .get(..octets)?
+                .iter()
+                .fold(0, |len, byte| len << 8 | usize::from(*byte));
+            (len, &rest[octets..])
+        }
+        _ => return None,
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+fn read_integer(bytes: &[u8]) -> Option<(i64, &[u8])> {
+    let (INTEGER, value, rest) = read_tlv(bytes)? else {
+        return None;
+    };
+    if value.is_empty() || value.len() > 8 {
+        return None;
+    }
+    let sign = if value[0] & 0x80 != 0 { -1 } else { 0 };
+    Some((
+        value
+            .iter()
+            .fold(sign, |int: i64, byte| int << 8 | i64::from(*byte)),
+        rest,
+    ))
+}
+
+fn decode_oid(bytes: &[u8]) -> Option<Oid> {
+    let (&first, rest) = bytes.split_first()?;
+    let mut arcs = vec![u32::from(first / 40).min(2), 0];
+    arcs[1] = u32::from(first) - arcs[0] * 40;
+    let mut arc = 0u32;
+    for byte in rest {
+        arc = arc.checked_mul(128)? | u32::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        }
+    }
+    Some(Oid(arcs))
+}
+
+fn encode_oid(oid: &Oid) -> Vec<u8> {
+    let mut bytes = vec![(oid.0[0] * 40 + oid.0[1]) as u8];
+    for arc in &oid.0[2..] {
+        let mut septets = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            septets.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        bytes.extend(septets.iter().rev());
+    }
+    bytes
+}
+
+/// The fewest two's complement octets holding a value
+fn encode_integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7
+        && ((bytes[start] == 0 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    match contents.len() {
+        len @ 0..=0x7f => bytes.push(len as u8),
+        len @ 0x80..=0xff => bytes.extend([0x81, len as u8]),
+        len => bytes.extend([0x82, (len >> 8) as u8, len as u8]),
+    }
+    bytes.extend_from_slice(contents);
+    bytes
+}
@@ -0,0 +1,327 @@
+//! Keeping readings the metrics endpoint couldn't take on disk until it's back
+//!
+//! Every batch that failed to write is saved as its own file in the spool directory and written
+//! again, oldest first, once the endpoint takes a batch. The time of the newest datapoint the
+//! endpoint accepted is kept there too, so a restart can tell how long it was away.
+//!
+//! A spool can be capped in size and in age, so a long outage can't fill a small SD card: past
+//! either cap, the oldest batches are evicted first. The datapoints evicted are counted, and
+//! written as the `monitoring.spool.evicted` series along with the readings.
+//!
+//! With a key, the batches are encrypted with ChaCha20-Poly1305 before they're written, for
+//! Pis in places where the SD card could walk away with weeks of readings. Batches spooled
+//! before the key was set are still read and written as they were. Encrypted batches the key
+//! can't open, e.g. after it was rotated, are kept as they are rather than dropped, and left out
+//! of the caps, so that they can still be written once the old key is back.
+
+use crate::{error::ConfigError, Datapoint};
+use base64::Engine;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The file holding the time of the newest datapoint that was written
+const LAST_SENT_FILE: &str = "last-sent";
+
+/// The extension of the spooled batches' files
+const BATCH_EXTENSION: &str = "json";
+
+/// The extension of the encrypted batches' files, holding the nonce and then the sealed batch
+const SEALED_EXTENSION: &str = "sealed";
+
+/// The name of the series counting the datapoints evicted from the spool since the start
+pub const EVICTED_SERIES: &str = "monitoring.spool.evicted";
+
+/// The key the spooled batches are encrypted with
+pub struct SpoolKey(LessSafeKey);
+
+impl SpoolKey {
+    /// Loads a base64 encoded 32 byte key, e.g. made with `head -c 32 /dev/urandom | base64`
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let encoded = fs::read_to_string(path)
+            .map_err(|err| ConfigError::from_io(path.to_path_buf(), err))?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|key| UnboundKey::new(&CHACHA20_POLY1305, &key).ok())
+            .map(|key| SpoolKey(LessSafeKey::new(key)))
+            .ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "{}: expected a base64 encoded 32 byte key, e.g. from `head -c 32 /dev/urandom | base64`",
+                    path.display()
+                ))
+            })
+    }
+}
+
+pub struct Spool {
+    dir: PathBuf,
+    /// Encrypts the batches, which are written as plain JSON without one
+    key: Option<SpoolKey>,
+    /// Tells apart batches spooled within the same millisecond
+    sequence: AtomicU64,
+    /// The most bytes the spooled batches may take up
+    max_size: Option<u64>,
+    /// The oldest a spooled batch may get
+    max_age: Option<Duration>,
+    /// The datapoints evicted since the start
+    evicted: AtomicU64,
+}
+
+impl Spool {
+    /// Opens the spool directory, creating it if needed
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, ConfigError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).map_err(|err| ConfigError::from_io(dir.clone(), err))?;
+
+        Ok(Spool {
+            dir,
+            key: None,
+            sequence: AtomicU64::new(0),
+            max_size: None,
+            max_age: None,
+            evicted: AtomicU64::new(0),
+        })
+    }
+
+    /// Evict the oldest batches once the spooled ones take up more than this many bytes
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Evict the batches spooled longer ago than this
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Encrypt the batches with this key
+    pub fn encrypt(mut self, key: SpoolKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Checks that the spool can open its encrypted batches, if it holds any, failing if it
+    /// doesn't have a key rather than have them pile up unwritten
+    pub fn check_key(&self) -> Result<(), ConfigError> {
+        if self.key.is_some() {
+            return Ok(());
+        }
+        let pending = self
+            .pending()
+            .map_err(|err| ConfigError::from_io(self.dir.clone(), err))?;
+        if pending.iter().any(|batch| is_sealed(batch)) {
+            return Err(ConfigError::Invalid(format!(
+                "the spool at {} holds encrypted batches, give the key they were encrypted with",
+                self.dir.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Saves a batch to be written later
+    pub fn push(&self, datapoints: &[Datapoint]) -> io::Result<()> {
+        let millis = now_millis();
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{:013}-{:06}", millis, sequence);
+        let batch = serde_json::to_vec(datapoints)?;
+
+        let Some(key) = &self.key else {
+            return write_atomically(
+                &self.dir.join(format!("{}.{}", name, BATCH_EXTENSION)),
+                &batch,
+            );
+        };
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| io::Error::other("unable to generate a nonce"))?;
+        let mut sealed = batch;
+        // The name is authenticated along with the batch, so batches can't be swapped around
+        key.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| io::Error::other("unable to encrypt the batch"))?;
+
+        write_atomically(
+            &self.dir.join(format!("{}.{}", name, SEALED_EXTENSION)),
+            &[nonce.as_slice(), &sealed].concat(),
+        )
+    }
+
+    /// The spooled batches' files, oldest first
+    pub fn pending(&self) -> io::Result<Vec<PathBuf>> {
+        let mut batches = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == BATCH_EXTENSION || ext == SEALED_EXTENSION)
+            })
+            .collect::<Vec<_>>();
+        batches.sort();
+
+        Ok(batches)
+    }
+
+    /// Evicts the oldest batches past the size and age caps, returning how many datapoints were
+    /// evicted
+    pub fn evict(&self) -> io::Result<u64> {
+        if self.max_size.is_none() && self.max_age.is_none() {
+            return Ok(0);
+        }
+
+        let batches = self
+            .pending()?
+            .into_iter()
+            .map(|batch| {
+                let size = fs::metadata(&batch).map_or(0, |metadata| metadata.len());
+                (batch, size)
+            })
+            .collect::<Vec<_>>();
+        let mut size = batches.iter().map(|(_, size)| size).sum::<u64>();
+        let oldest = self
+            .max_age
+            .map(|age| now_millis().saturating_sub(age.as_millis()));
+
+        let mut evicted = 0;
+        for (batch, batch_size) in batches {
+            let too_old = oldest
+                .zip(spooled_at(&batch))
+                .is_some_and(|(oldest, spooled)| spooled < oldest);
+            let too_large = self.max_size.is_some_and(|max| size > max);
+            if !too_old && !too_large {
+                // The rest are newer and fit
+                break;
+            }
+
+            match self.load(&batch) {
+                Ok(readings) => {
+                    evicted += readings.len() as u64;
+                    self.remove(&batch)?;
+                }
+                Err(err) if is_sealed(&batch) => tracing::warn!(
+                    "Keeping spooled batch {} out of the spool's limits, it can't be opened: {}",
+                    batch.display(),
+                    err
+                ),
+                Err(err) => {
+                    tracing::warn!(
+                        "Evicting unreadable spooled batch {}: {}",
+                        batch.display(),
+                        err
+                    );
+                    self.remove(&batch)?;
+                }
+            }
+            size -= batch_size;
+        }
+
+        self.evicted.fetch_add(evicted, Ordering::Relaxed);
+        Ok(evicted)
+    }
+
+    /// The datapoints evicted since the spool was opened
+    pub fn evicted(&self) -> u64 {
+        self.evicted.load(Ordering::Relaxed)
+    }
+
+    /// Reads a spooled batch
+    pub fn load(&self, batch: &Path) -> io::Result<Vec<Datapoint>> {
+        let contents = fs::read(batch)?;
+        if !is_sealed(batch) {
+            return Ok(serde_json::from_slice(&contents)?);
+        }
+
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason);
+        let key = self
+            .key
+            .as_ref()
+            .ok_or_else(|| invalid("the batch is encrypted, but the spool has no key"))?;
+        if contents.len() < NONCE_LEN {
+            return Err(invalid("the encrypted batch is cut short"));
+        }
+        let (nonce, sealed) = contents.split_at(NONCE_LEN);
+        let name = batch
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let mut sealed = sealed.to_vec();
+        let opened = key
+            .0
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).expect("Nonce of the right length"),
+                Aad::from(name.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| {
+                invalid("unable to decrypt the batch, it was encrypted with another key")
+            })?;
+
+        Ok(serde_json::from_slice(opened)?)
+    }
+
+    /// Forgets a batch once it's written
+    pub fn remove(&self, batch: &Path) -> io::Result<()> {
+        fs::remove_file(batch)
+    }
+
+    /// The time of the newest datapoint the endpoint accepted, as of the last run
+    pub fn last_sent(&self) -> Option<i64> {
+        fs::read_to_string(self.dir.join(LAST_SENT_FILE))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    /// Remembers the newest datapoint of a written batch, if it's newer than the last one
+    pub fn record_sent(&self, datapoints: &[Datapoint]) -> io::Result<()> {
+        let Some(newest) = datapoints.iter().map(|datapoint| datapoint.time).max() else {
+            return Ok(());
+        };
+        if self.last_sent().is_some_and(|last| last >= newest) {
+            return Ok(());
+        }
+
+        write_atomically(
+            &self.dir.join(LAST_SENT_FILE),
+            newest.to_string().as_bytes(),
+        )
+    }
+}
+
+fn now_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time behind Unix epoch time")
+        .as_millis()
+}
+
+/// Whether the batch was encrypted when it was spooled
+pub fn is_sealed(batch: &Path) -> bool {
+    batch.extension().is_some_and(|ext| ext == SEALED_EXTENSION)
+}
+
+/// When a batch was spooled, from the milliseconds its file is named after
+fn spooled_at(batch: &Path) -> Option<u128> {
+    let name = batch.file_stem()?.to_str()?;
+    name.split_once('-')?.0.parse().ok()
+}
+
+/// Writes next to the file and renames it into place, so a power cut can't leave half of it
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, contents)?;
+    fs::rename(partial, path)
+}
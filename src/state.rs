@@ -0,0 +1,446 @@
+//! The latest known state of every sensor, shared between the pipeline and the HTTP API
+
+use crate::{
+    config::Sensor,
+    error::{SensorError, SinkError},
+    events::Event,
+    persist::{OutputStates, SavedSensor, SavedState},
+    power::LowPowerMode,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex, RwLock, RwLockWriteGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::broadcast;
+
+/// How many events are kept for subscribers lagging behind
+const EVENTS_CAPACITY: usize = 64;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorStatus {
+    /// Not read yet since the service started
+    Pending,
+    /// Not read yet as the sensor is still warming up after boot
+    #[serde(rename = "warming_up")]
+    WarmingUp,
+    /// The last attempt to read the sensor succeeded
+    Ok,
+    /// The last attempt to read the sensor failed
+    Failing,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SensorState {
+    pub sensor: String,
+    /// Where its series are written, e.g. `cottage.bedroom.sensor1`
+    pub path: String,
+    pub status: SensorStatus,
+    /// Unix timestamp of the latest successful reading
+    pub time: Option<i64>,
+    /// The latest successful reading's values, by metric
+    pub values: BTreeMap<String, f64>,
+    pub last_error: Option<String>,
+    /// Failed attempts since the last successful reading
+    pub failures: u32,
+    /// Reads abandoned as stuck since the service started
+    pub stuck_reads: u32,
+    /// Times the sensor was power cycled since the service started
+    pub power_cycles: u32,
+    /// How long its latest sampling cycle took in seconds, retries included
+    pub cycle_secs: Option<f64>,
+}
+
+/// Tells the sensors' states at one point in time from another, for conditional requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    /// When the state was set up in Unix milliseconds, telling one run's versions from another's
+    pub epoch: u64,
+    /// How many times the sensors' states changed since
+    pub changes: u64,
+    /// Unix timestamp of the latest change
+    pub changed_at: i64,
+}
+
+pub struct State {
+    sensors: RwLock<BTreeMap<String, SensorState>>,
+    epoch: u64,
+    /// Bumped on every change to the sensors' states
+    changes: AtomicU64,
+    changed_at: AtomicI64,
+    last_tick: Mutex<Option<Instant>>,
+    last_write: Mutex<Option<Instant>>,
+    /// Why the latest write failed, until one succeeds again
+    last_write_error: Mutex<Option<String>>,
+    /// The latest state of every sensor's alerts and outputs
+    outputs: RwLock<BTreeMap<String, OutputStates>>,
+    /// The alert and output states saved by the last run, until the sensors pick them up
+    restored_outputs: Mutex<BTreeMap<String, OutputStates>>,
+    /// How the service runs while in low-power mode, if it is
+    low_power: Mutex<Option<LowPowerMode>>,
+    events: broadcast::Sender<Event>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        State {
+            sensors: RwLock::default(),
+            epoch: now.as_millis() as u64,
+            changes: AtomicU64::new(0),
+            changed_at: AtomicI64::new(now.as_secs() as i64),
+            last_tick: Mutex::default(),
+            last_write: Mutex::default(),
+            last_write_error: Mutex::default(),
+            outputs: RwLock::default(),
+            restored_outputs: Mutex::default(),
+            low_power: Mutex::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+}
+
+impl State {
+    pub fn new(sensors: &[Sensor]) -> Self {
+        let state = State::default();
+        for sensor in sensors.iter().filter(|sensor| !sensor.disabled) {
+            state.add_sensor(sensor);
+        }
+
+        state
+    }
+
+    pub fn add_sensor(&self, sensor: &Sensor) {
+        self.write_sensors().insert(
+            sensor.name.clone(),
+            SensorState {
+                sensor: sensor.name.clone(),
+                path: sensor.path(),
+                status: SensorStatus::Pending,
+                time: None,
+                values: BTreeMap::new(),
+                last_error: None,
+                failures: 0,
+                stuck_reads: 0,
+                power_cycles: 0,
+                cycle_secs: None,
+            },
+        );
+    }
+
+    pub fn remove_sensor(&self, name: &str) {
+        self.write_sensors().remove(name);
+        self.outputs
+            .write()
+            .expect("State lock poisoned")
+            .remove(name);
+    }
+
+    pub fn record_reading(&self, sensor: &str, time: i64, values: &[(String, f64)]) {
+        let mut sensors = self.write_sensors();
+        if let Some(state) = sensors.get_mut(sensor) {
+            if state.status == SensorStatus::Failing {
+                self.record_event(Event::SensorRecovered {
+                    sensor: sensor.to_string(),
+                });
+            }
+            state.status = SensorStatus::Ok;
+            state.time = Some(time);
+            // Updated in place, as a sensor's metrics rarely change from one reading to the next
+            state
+                .values
+                .retain(|metric, _| values.iter().any(|(name, _)| name == metric));
+            for (metric, value) in values {
+                match state.values.get_mut(metric) {
+                    Some(previous) => *previous = *value,
+                    None => {
+                        state.values.insert(metric.clone(), *value);
+                    }
+                }
+            }
+            state.failures = 0;
+        }
+    }
+
+    /// Records readings pushed by a sensor the service doesn't sample itself, adding it on its
+    /// first push. Metrics it didn't push this time keep their previous values.
+    pub fn record_pushed(&self, sensor: &str, time: i64, values: &[(String, f64)]) {
+        let mut sensors = self.write_sensors();
+        let state = sensors
+            .entry(sensor.to_string())
+            .or_insert_with(|| SensorState {
+                sensor: sensor.to_string(),
+                path: sensor.to_string(),
+                status: SensorStatus::Ok,
+                time: None,
+                values: BTreeMap::new(),
+                last_error: None,
+                failures: 0,
+                stuck_reads: 0,
+                power_cycles: 0,
+                cycle_secs: None,
+            });
+        state.status = SensorStatus::Ok;
+        state.time = Some(time);
+        state.values.extend(values.iter().cloned());
+    }
+
+    /// Notes that a sensor was power cycled to recover it
+    pub fn record_power_cycle(&self, sensor: &str) {
+        let mut sensors = self.write_sensors();
+        if let Some(state) = sensors.get_mut(sensor) {
+            state.power_cycles += 1;
+        }
+    }
+
+    /// Notes how long a sensor's sampling cycle took
+    pub fn record_cycle(&self, sensor: &str, took: Duration) {
+        let mut sensors = self.write_sensors();
+        if let Some(state) = sensors.get_mut(sensor) {
+            state.cycle_secs = Some(took.as_secs_f64());
+        }
+    }
+
+    /// Notes that a sensor won't be read until it's warmed up
+    pub fn record_warming_up(&self, sensor: &str) {
+        let mut sensors = self.write_sensors();
+        if let Some(state) = sensors.get_mut(sensor) {
+            state.status = SensorStatus::WarmingUp;
+        }
+    }
+
+    /// Notes a failed read of a sensor, returning how many of its reads failed in a row
+    pub fn record_error(&self, sensor: &str, error: &SensorError) -> u32 {
+        let mut sensors = self.write_sensors();
+        let Some(state) = sensors.get_mut(sensor) else {
+            return 0;
+        };
+        if state.status != SensorStatus::Failing {
+            self.record_event(Event::SensorFailing {
+                sensor: sensor.to_string(),
+                error: error.to_string(),
+            });
+        }
+        state.status = SensorStatus::Failing;
+        state.last_error = Some(error.to_string());
+        state.failures += 1;
+        if matches!(error, SensorError::Stuck(_)) {
+            state.stuck_reads += 1;
+        }
+        state.failures
+    }
+
+    /// Notes an implausible reading that's read again, without the sensor counting as failing
+    pub fn record_reread(&self, sensor: &str, error: &SensorError) {
+        if let Some(state) = self.write_sensors().get_mut(sensor) {
+            state.last_error = Some(error.to_string());
+        }
+    }
+
+    /// Notes where a sensor's alerts and outputs are at after its latest reading
+    pub fn record_outputs(&self, sensor: &str, outputs: OutputStates) {
+        self.outputs
+            .write()
+            .expect("State lock poisoned")
+            .insert(sensor.to_string(), outputs);
+    }
+
+    /// Hands out the alert and output states a sensor had in the last run, once
+    pub fn take_restored_outputs(&self, sensor: &str) -> Option<OutputStates> {
+        self.restored_outputs
+            .lock()
+            .expect("State lock poisoned")
+            .remove(sensor)
+    }
+
+    /// What's worth keeping across a restart, see [`crate::persist`]
+    pub fn saved(&self) -> SavedState {
+        let outputs = self.outputs.read().expect("State lock poisoned");
+        let restored = self.restored_outputs.lock().expect("State lock poisoned");
+        let sensors = self
+            .sensors
+            .read()
+            .expect("State lock poisoned")
+            .iter()
+            .map(|(name, state)| {
+                let saved = SavedSensor {
+                    time: state.time,
+                    values: state.values.clone(),
+                    last_error: state.last_error.clone(),
+                    failures: state.failures,
+                    // Sensors not read since the restart still have the last run's
+                    outputs: outputs
+                        .get(name)
+                        .or_else(|| restored.get(name))
+                        .cloned()
+                        .unwrap_or_default(),
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+
+        SavedState { sensors }
+    }
+
+    /// Picks up the state saved by the last run for the sensors that are still configured. A
+    /// sensor that was failing stays failing until it reads fine, without failing anew.
+    pub fn restore(&self, saved: SavedState) {
+        let mut sensors = self.write_sensors();
+        let mut restored = self.restored_outputs.lock().expect("State lock poisoned");
+        for (name, saved) in saved.sensors {
+            let Some(state) = sensors.get_mut(&name) else {
+                continue;
+            };
+            state.time = saved.time;
+            state.values = saved.values;
+            state.last_error = saved.last_error;
+            state.failures = saved.failures;
+            if saved.failures > 0 {
+                state.status = SensorStatus::Failing;
+            }
+            restored.insert(name, saved.outputs);
+        }
+    }
+
+    /// Publishes an event to its subscribers, if there are any
+    pub fn record_event(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    /// Receives every event from now on
+    pub fn subscribe_events(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Notes that a sensor task started a sampling cycle
+    pub fn record_tick(&self) {
+        *self.last_tick.lock().expect("State lock poisoned") = Some(Instant::now());
+    }
+
+    /// Notes that a batch of readings was written to the sink successfully
+    pub fn record_write(&self) {
+        *self.last_write.lock().expect("State lock poisoned") = Some(Instant::now());
+        *self.last_write_error.lock().expect("State lock poisoned") = None;
+    }
+
+    /// Notes that writing a batch of readings to the sink failed
+    pub fn record_write_error(&self, error: &SinkError) {
+        *self.last_write_error.lock().expect("State lock poisoned") = Some(error.to_string());
+    }
+
+    /// How long ago a batch of readings was last written to the sink
+    pub fn since_last_write(&self) -> Option<Duration> {
+        self.last_write
+            .lock()
+            .expect("State lock poisoned")
+            .map(|write| write.elapsed())
+    }
+
+    /// Why the latest write to the sink failed, if it did
+    pub fn last_write_error(&self) -> Option<String> {
+        self.last_write_error
+            .lock()
+            .expect("State lock poisoned")
+            .clone()
+    }
+
+    /// How the service runs while in low-power mode, or `None` when it's not
+    pub fn low_power(&self) -> Option<LowPowerMode> {
+        *self.low_power.lock().expect("State lock poisoned")
+    }
+
+    /// Switches low-power mode on or off, see [`crate::power`]
+    pub fn set_low_power(&self, mode: Option<LowPowerMode>) {
+        *self.low_power.lock().expect("State lock poisoned") = mode;
+    }
+
+    /// How long ago a sensor task last started a sampling cycle
+    pub fn since_last_tick(&self) -> Option<Duration> {
+        self.last_tick
+            .lock()
+            .expect("State lock poisoned")
+            .map(|tick| tick.elapsed())
+    }
+
+    /// Whether any batch of readings has made it to the sink yet
+    pub fn has_written(&self) -> bool {
+        self.last_write
+            .lock()
+            .expect("State lock poisoned")
+            .is_some()
+    }
+
+    /// Whether any sensor has been read successfully yet
+    pub fn has_read(&self) -> bool {
+        self.sensors
+            .read()
+            .expect("State lock poisoned")
+            .values()
+            .any(|sensor| sensor.time.is_some())
+    }
+
+    /// The latest successful reading of a sensor's metric
+    pub fn latest(&self, sensor: &str, metric: &str) -> Option<f64> {
+        self.sensors
+            .read()
+            .expect("State lock poisoned")
+            .get(sensor)?
+            .values
+            .get(metric)
+            .copied()
+    }
+
+    /// Where a sensor's series are written, see [`Sensor::path`]
+    pub fn path(&self, sensor: &str) -> Option<String> {
+        self.sensors
+            .read()
+            .expect("State lock poisoned")
+            .get(sensor)
+            .map(|state| state.path.clone())
+    }
+
+    /// Takes the sensors' states to change them, counting it as a change
+    fn write_sensors(&self) -> RwLockWriteGuard<'_, BTreeMap<String, SensorState>> {
+        let sensors = self.sensors.write().expect("State lock poisoned");
+        // Bumped while holding the lock, so a reader never sees a version with the wrong states
+        self.changes.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.changed_at
+            .store(now.as_secs() as i64, Ordering::Relaxed);
+        sensors
+    }
+
+    /// The version of the sensors' states right now
+    pub fn version(&self) -> Version {
+        Version {
+            epoch: self.epoch,
+            changes: self.changes.load(Ordering::Relaxed),
+            changed_at: self.changed_at.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The current state of every sensor, with the version it's at
+    pub fn versioned_snapshot(&self) -> (Version, Vec<SensorState>) {
+        let sensors = self.sensors.read().expect("State lock poisoned");
+        (self.version(), sensors.values().cloned().collect())
+    }
+
+    /// The current state of every sensor, ordered by name
+    pub fn snapshot(&self) -> Vec<SensorState> {
+        self.sensors
+            .read()
+            .expect("State lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
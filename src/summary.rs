@@ -0,0 +1,188 @@
+//! Daily or weekly digests of the readings, for people who'd rather get a message in the
+//! morning than watch a dashboard
+//!
+//! Every batch of readings handed to the sink is also folded into the minimum, maximum and
+//! average of its series since the last summary, which goes out through a [`Notifier`] at a set
+//! local time:
+//!
+//! ```text
+//! Since Mon 13 Oct 08:00
+//! kitchen.humidity: min 38.2, avg 41.9, max 47.0
+//! kitchen.temperature: min 19.5, avg 21.2, max 23.1
+//! ```
+
+#[cfg(feature = "http")]
+use crate::{notify::Notifier, Datapoint};
+#[cfg(feature = "http")]
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Local, NaiveTime, Weekday};
+use std::str::FromStr;
+#[cfg(feature = "http")]
+use std::{collections::BTreeMap, fmt::Write, io::Write as _};
+#[cfg(feature = "http")]
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+
+/// How often a summary is sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Daily,
+    /// On Mondays
+    Weekly,
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    fn from_str(period: &str) -> Result<Self, Self::Err> {
+        match period {
+            "daily" => Ok(Period::Daily),
+            "weekly" => Ok(Period::Weekly),
+            _ => Err(format!(
+                "unknown summary period {}, expected daily or weekly",
+                period
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub struct SummaryConfig {
+    pub period: Period,
+    /// The local time the summary is sent at (default: 08:00)
+    pub at: NaiveTime,
+    pub notifier: Notifier,
+    pub client: reqwest::Client,
+    /// Print the summary to stdout rather than send it (default: false)
+    pub dry_run: bool,
+}
+
+#[cfg(feature = "http")]
+impl SummaryConfig {
+    pub fn new(period: Period, notifier: Notifier) -> Self {
+        SummaryConfig {
+            period,
+            at: NaiveTime::from_hms_opt(8, 0, 0).expect("08:00 is a valid time"),
+            notifier,
+            client: reqwest::Client::new(),
+            dry_run: false,
+        }
+    }
+
+    /// When the first summary after `now` is due
+    pub fn next_due(&self, now: DateTime<Local>) -> DateTime<Local> {
+        let mut day = now.date_naive();
+        loop {
+            let due = day.and_time(self.at).and_local_timezone(Local).earliest();
+            let on_schedule = self.period == Period::Daily || day.weekday() == Weekday::Mon;
+            match due {
+                Some(due) if on_schedule && due > now => return due,
+                _ => day += ChronoDuration::days(1),
+            }
+        }
+    }
+}
+
+/// The readings of a series since the last summary
+#[cfg(feature = "http")]
+struct Stats {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u32,
+}
+
+/// Folds the readings into their series' stats until shutdown, sending the summary whenever
+/// it's due
+#[cfg(feature = "http")]
+pub(crate) async fn run(
+    config: &SummaryConfig,
+    mut readings: broadcast::Receiver<Vec<Datapoint>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut since = Local::now();
+    let mut series = BTreeMap::<String, Stats>::new();
+    loop {
+        let due = config.next_due(Local::now());
+        let wait = (due - Local::now()).to_std().unwrap_or_default();
+
+        let send = tokio::select! {
+            batch = readings.recv() => match batch {
+                Ok(batch) => {
+                    fold(&mut series, &batch);
+                    false
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(missed, "The summary fell behind, leaving out some readings");
+                    false
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = tokio::time::sleep(wait) => true,
+            _ = shutdown.wait_for(|stop| *stop) => break,
+        };
+        if !send {
+            continue;
+        }
+
+        let (title, message) = render(config.period, since, &series);
+        if config.dry_run {
+            let _ = writeln!(std::io::stdout().lock(), "{}\n{}", title, message);
+        } else {
+            match config.notifier.send(&config.client, title, &message).await {
+                Ok(()) => tracing::info!("Sent the {}", title.to_lowercase()),
+                Err(err) => tracing::warn!("Unable to send the {}: {}", title.to_lowercase(), err),
+            }
+        }
+        since = Local::now();
+        series.clear();
+    }
+}
+
+#[cfg(feature = "http")]
+fn fold(series: &mut BTreeMap<String, Stats>, batch: &[Datapoint]) {
+    for datapoint in batch {
+        let value = datapoint.value;
+        let stats = series.entry(datapoint.name.clone()).or_insert(Stats {
+            min: value,
+            max: value,
+            sum: 0.0,
+            count: 0,
+        });
+        stats.min = stats.min.min(value);
+        stats.max = stats.max.max(value);
+        stats.sum += value;
+        stats.count += 1;
+    }
+}
+
+#[cfg(feature = "http")]
+fn render(
+    period: Period,
+    since: DateTime<Local>,
+    series: &BTreeMap<String, Stats>,
+) -> (&'static str, String) {
+    let title = match period {
+        Period::Daily => "Daily summary",
+        Period::Weekly => "Weekly summary",
+    };
+
+    let mut message = format!("Since {}", since.format("%a %-d %b %H:%M"));
+    if series.is_empty() {
+        message.push_str("\nNo readings");
+    }
+    for (name, stats) in series {
+        let _ = write!(
+            message,
+            "\n{}: min {:.1}, avg {:.1}, max {:.1}",
+            name,
+            stats.min,
+            stats.sum / f64::from(stats.count),
+            stats.max
+        );
+    }
+
+    (title, message)
+}
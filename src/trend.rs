@@ -0,0 +1,156 @@
+//! Whether the readings are rising, falling or holding steady, for status tiles and alerts like
+//! "the heating came on but the temperature didn't go up"
+//!
+//! A sensor's `trends` each follow one of its metrics, writing a `<series>.trend` series along
+//! with every reading, e.g. `kitchen.temperature.trend`: `1` while the metric is rising, `-1`
+//! while it's falling and `0` while it's steady. The trend is the slope of a straight line fit
+//! through the readings of the last `window_secs` (30 minutes by default), rising or falling
+//! once it's steeper than `threshold` per hour (0.5 by default), so a single noisy reading
+//! doesn't flip it. The readings are only kept in memory, so after a restart the trend is taken
+//! over the readings since.
+
+use crate::{config::Sensor, Datapoint};
+use std::collections::{HashMap, VecDeque};
+
+/// How far back the readings go by default, in seconds
+pub const DEFAULT_WINDOW_SECS: u64 = 1800;
+
+/// How much a metric changes by per hour at least to be rising or falling by default
+pub const DEFAULT_THRESHOLD: f64 = 0.5;
+
+/// Where a metric is heading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Falling,
+    Steady,
+    Rising,
+}
+
+impl Direction {
+    /// The value written to the `.trend` series
+    pub fn value(self) -> f64 {
+        match self {
+            Direction::Falling => -1.0,
+            Direction::Steady => 0.0,
+            Direction::Rising => 1.0,
+        }
+    }
+}
+
+/// The direction a series' readings are heading, from the slope of the least squares line
+/// through them, or none for fewer than two readings at different times
+pub fn direction(readings: &[(i64, f64)], threshold: f64) -> Option<Direction> {
+    let (first, _) = *readings.first()?;
+    let count = readings.len() as f64;
+    // Hours since the first reading, keeping the sums small
+    let hours = |time: i64| (time - first) as f64 / 3600.0;
+    let mean_hours = readings.iter().map(|(time, _)| hours(*time)).sum::<f64>() / count;
+    let mean_value = readings.iter().map(|(_, value)| value).sum::<f64>() / count;
+    let (covariance, variance) =
+        readings
+            .iter()
+            .fold((0.0, 0.0), |(covariance, variance), (time, value)| {
+                let dx = hours(*time) - mean_hours;
+                (covariance + dx * (value - mean_value), variance + dx * dx)
+            });
+    if variance == 0.0 {
+        return None;
+    }
+
+    let slope = covariance / variance;
+    Some(if slope > threshold {
+        Direction::Rising
+    } else if slope < -threshold {
+        Direction::Falling
+    } else {
+        Direction::Steady
+    })
+}
+
+/// A followed series' recent readings
+struct Followed {
+    window_secs: i64,
+    threshold: f64,
+    readings: VecDeque<(i64, f64)>,
+}
+
+pub(crate) struct Trends {
+    /// By the names of the followed series
+    series: HashMap<String, Followed>,
+}
+
+impl Trends {
+    /// Follows the metrics of the enabled sensors' `trends`, or none if there are none to follow
+    pub(crate) fn new(sensors: &[Sensor]) -> Option<Self> {
+        let series = sensors
+            .iter()
+            .filter(|sensor| !sensor.disabled)
+            .flat_map(|sensor| {
+                sensor.trends.iter().map(|trend| {
+                    let followed = Followed {
+                        window_secs: trend
+                            .window_secs
+                            .map_or(DEFAULT_WINDOW_SECS as i64, |window| window.secs().into()),
+                        threshold: trend.threshold.unwrap_or(DEFAULT_THRESHOLD),
+                        readings: VecDeque::new(),
+                    };
+                    (sensor.series(&trend.metric), followed)
+                })
+            })
+            .collect::<HashMap<_, _>>();
+
+        (!series.is_empty()).then_some(Trends { series })
+    }
+
+    /// Keeps the readings of the series `previous` already followed, for trends followed again
+    /// after the sensors have changed
+    pub(crate) fn continuing(mut self, previous: Option<Trends>) -> Self {
+        for (name, followed) in previous.into_iter().flat_map(|previous| previous.series) {
+            if let Some(continued) = self.series.get_mut(&name) {
+                continued.readings = followed.readings;
+            }
+        }
+        self
+    }
+
+    /// Adds the readings to their series' windows, returning the trends to write
+    pub(crate) fn track(&mut self, readings: &[Datapoint]) -> Vec<Datapoint> {
+        let mut trends = Vec::new();
+        for datapoint in readings {
+            let Some(followed) = self.series.get_mut(&datapoint.name) else {
+                continue;
+            };
+            // A late reading, e.g. pushed over the API, doesn't move the window back
+            if followed
+                .readings
+                .back()
+                .is_some_and(|(time, _)| *time > datapoint.time)
+            {
+                continue;
+            }
+
+            followed
+                .readings
+                .push_back((datapoint.time, datapoint.value));
+            while followed
+                .readings
+                .front()
+                .is_some_and(|(time, _)| datapoint.time - time > followed.window_secs)
+            {
+                followed.readings.pop_front();
+            }
+
+            let readings = followed.readings.make_contiguous();
+            if let Some(direction) = direction(readings, followed.threshold) {
+                trends.push(Datapoint {
+                    name: format!("{}.trend", datapoint.name),
+                    interval: datapoint.interval,
+                    value: direction.value(),
+                    time: datapoint.time,
+                });
+            }
+        }
+
+        trends
+    }
+}
@@ -0,0 +1,357 @@
+//! A live view of the service in the terminal for `serve --tui` (behind the `tui` feature), for
+//! a quick look when SSHed into the Pi
+//!
+//! Shows every sensor's latest values and health, a sparkline of each metric's recent history,
+//! whether the last write to the metrics endpoint went through, and the log. While it's open the
+//! log is kept in a [`LogBuffer`] rather than written to the terminal; it's printed when the view
+//! closes.
+
+use crate::{
+    history::History,
+    locale::Locale,
+    service::MonitorService,
+    state::{SensorState, SensorStatus, State},
+    Error, Result,
+};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph, Row, Sparkline, Table},
+    Frame,
+};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How often the view is redrawn
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many log lines are kept while the view is open
+const LOG_LINES: usize = 500;
+
+/// Holds the log lines while the view is open, writing to stderr again once it's closed
+#[derive(Clone, Default)]
+pub struct LogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        LogBuffer::default()
+    }
+
+    fn tail(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().expect("Log buffer lock poisoned");
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// Prints the held lines and stops holding new ones
+    fn close(&self) {
+        let lines = std::mem::take(&mut *self.lines.lock().expect("Log buffer lock poisoned"));
+        self.closed.store(true, Ordering::SeqCst);
+
+        let mut stderr = io::stderr().lock();
+        for line in lines {
+            let _ = writeln!(stderr, "{}", line);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogWriter {
+            buffer: self.clone(),
+            line: Vec::new(),
+        }
+    }
+}
+
+/// Writes one event to a [`LogBuffer`]
+pub struct LogWriter {
+    buffer: LogBuffer,
+    line: Vec<u8>,
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.buffer.closed.load(Ordering::SeqCst) {
+            return io::stderr().write(bytes);
+        }
+
+        self.line.extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+
+        let mut lines = self.buffer.lines.lock().expect("Log buffer lock poisoned");
+        for line in String::from_utf8_lossy(&self.line).lines() {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+}
+
+/// Runs the service with the live view open, until `q`, `Esc` or `Ctrl-C` is pressed or the
+/// service stops by itself
+pub async fn run(service: Arc<MonitorService>, logs: LogBuffer) -> Result<()> {
+    let stopped = Arc::new(AtomicBool::new(false));
+    let running = tokio::spawn({
+        let service = service.clone();
+        let stopped = stopped.clone();
+        async move {
+            let result = service.run().await;
+            stopped.store(true, Ordering::SeqCst);
+            result
+        }
+    });
+
+    let viewed = tokio::task::spawn_blocking({
+        let service = service.clone();
+        let logs = logs.clone();
+        move || view(&service, &logs, &stopped)
+    })
+    .await
+    .expect("Terminal view panicked");
+    logs.close();
+
+    service.shutdown();
+    let result = running.await.expect("Service task panicked");
+    viewed.map_err(Error::Terminal)?;
+    result
+}
+
+fn view(service: &MonitorService, logs: &LogBuffer, stopped: &AtomicBool) -> io::Result<()> {
+    let mut terminal = ratatui::try_init()?;
+    let shown = (|| loop {
+        terminal.draw(|frame| draw(frame, service, logs))?;
+        if stopped.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if event::poll(REDRAW_INTERVAL)? {
+            if let Event::Key(key) = event::read()? {
+                let ctrl_c =
+                    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                if key.kind == KeyEventKind::Press
+                    && (ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc))
+                {
+                    return Ok(());
+                }
+            }
+        }
+    })();
+    ratatui::restore();
+
+    shown
+}
+
+fn draw(frame: &mut Frame, service: &MonitorService, logs: &LogBuffer) {
+    let sensors = service.state().snapshot();
+    let series = sensors
+        .iter()
+        .flat_map(|sensor| {
+            sensor
+                .values
+                .keys()
+                .map(move |metric| format!("{}.{}", sensor.path, metric))
+        })
+        .collect::<Vec<_>>();
+
+    let [header, table, graphs, log] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(sensors.len() as u16 + 3),
+        Constraint::Min(2 * series.len().min(1) as u16),
+        Constraint::Length(10),
+    ])
+    .areas(frame.area());
+
+    frame.render_widget(status_line(service.state(), sensors.len()), header);
+    frame.render_widget(sensor_table(&sensors, service.locale()), table);
+    draw_graphs(frame, graphs, &series, service.history(), service.locale());
+
+    let lines = logs
+        .tail(log.height.saturating_sub(2).into())
+        .into_iter()
+        .map(Line::from)
+        .collect::<Vec<_>>();
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Log ")),
+        log,
+    );
+}
+
+/// The sensor count and how the writes to the metrics endpoint are going
+fn status_line(state: &State, sensors: usize) -> Paragraph<'static> {
+    let write = match (state.last_write_error(), state.since_last_write()) {
+        (Some(err), _) => Span::raw(format!("last write failed: {}", err)).red(),
+        (None, Some(since)) => Span::raw(format!("written {} ago", age(since))).green(),
+        (None, None) => Span::raw("nothing written yet").yellow(),
+    };
+
+    Paragraph::new(Line::from(vec![
+        Span::raw(format!(" monitoring · {} sensors · ", sensors)).bold(),
+        write,
+        Span::raw(" · q to quit").dark_gray(),
+    ]))
+}
+
+fn sensor_table(sensors: &[SensorState], locale: &Locale) -> Table<'static> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time behind Unix epoch time")
+        .as_secs() as i64;
+
+    let rows = sensors.iter().map(|sensor| {
+        let status = match sensor.status {
+            SensorStatus::Pending => Span::raw("pending").yellow(),
+            SensorStatus::WarmingUp => Span::raw("warming up").yellow(),
+            SensorStatus::Ok => Span::raw("ok").green(),
+            SensorStatus::Failing => Span::raw("failing").red(),
+        };
+        let values = sensor
+            .values
+            .iter()
+            .map(|(metric, value)| format!("{} {}", metric, locale.value(metric, *value)))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let updated = sensor
+            .time
+            .map(|time| {
+                format!(
+                    "{} ago",
+                    age(Duration::from_secs((now - time).max(0) as u64))
+                )
+            })
+            .unwrap_or_default();
+
+        Row::new(vec![
+            Line::from(sensor.sensor.clone()),
+            Line::from(status),
+            Line::from(values),
+            Line::from(sensor.failures.to_string()),
+            Line::from(updated),
+            Line::from(sensor.last_error.clone().unwrap_or_default()).red(),
+        ])
+    });
+
+    Table::new(
+        rows,
+        [
+            Constraint::Length(16),
+            Constraint::Length(8),
+            Constraint::Min(30),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(
+        Row::new([
+            "Sensor",
+            "Status",
+            "Values",
+            "Failures",
+            "Updated",
+            "Last error",
+        ])
+        .bold()
+        .bottom_margin(1),
+    )
+    .block(Block::bordered().title(" Sensors "))
+}
+
+/// A label and a sparkline of the latest readings for as many series as fit
+fn draw_graphs(
+    frame: &mut Frame,
+    area: Rect,
+    series: &[String],
+    history: &History,
+    locale: &Locale,
+) {
+    let fitting = usize::from(area.height / 2);
+    let rows = Layout::vertical(vec![Constraint::Length(2); fitting.min(series.len())]).split(area);
+
+    for (name, row) in series.iter().zip(rows.iter()) {
+        let points = history.series(name);
+        let width = usize::from(row.width);
+        let values = points
+            .iter()
+            .skip(points.len().saturating_sub(width))
+            .map(|(_, value)| *value)
+            .collect::<Vec<_>>();
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+
+        let label = match values.last() {
+            Some(latest) => {
+                let metric = name.rsplit('.').next().unwrap_or_default();
+                format!(
+                    "{} {} (min {}, max {})",
+                    name,
+                    locale.value(metric, *latest),
+                    locale.number(min),
+                    locale.number(max)
+                )
+            }
+            None => name.clone(),
+        };
+        // Scaled so the lowest reading still shows as a sliver
+        let bars = values.iter().map(|value| {
+            let range = max - min;
+            let scaled = if range > 0.0 {
+                (value - min) / range * 100.0
+            } else {
+                50.0
+            };
+            scaled.round() as u64 + 1
+        });
+
+        let [label_area, graph_area] =
+            Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(*row);
+        frame.render_widget(Paragraph::new(label).bold(), label_area);
+        frame.render_widget(
+            Sparkline::default()
+                .data(bars)
+                .max(101)
+                .style(Style::default().fg(Color::Cyan)),
+            graph_area,
+        );
+    }
+}
+
+/// A short, human readable duration, e.g. `45s`, `12m` or `3h`
+fn age(duration: Duration) -> String {
+    match duration.as_secs() {
+        secs @ 0..=119 => format!("{}s", secs),
+        secs @ 120..=7199 => format!("{}m", secs / 60),
+        secs => format!("{}h", secs / 3600),
+    }
+}
@@ -0,0 +1,157 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use common::{free_addr, sensors};
+use monitoring::{
+    aggregator::{self, Source},
+    pipeline::DropPolicy,
+    sensors::MockBackend,
+    service::MonitorService,
+    sinks::Memory,
+    wire, Datapoint,
+};
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn sources_are_named_after_their_host_by_default() {
+    let named: Source = "garage=http://10.0.0.5:8080/".parse().unwrap();
+    let unnamed: Source = "http://kitchen-pi.local:8080".parse().unwrap();
+
+    assert_eq!(named.name, "garage");
+    assert_eq!(named.url, "http://10.0.0.5:8080");
+    assert_eq!(unnamed.name, "kitchen-pi.local");
+}
+
+#[tokio::test]
+async fn readings_of_sources_are_shipped_under_their_name() {
+    let addr = free_addr();
+    let source = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors(
+                "- name: workshop\n  pin: 4\n  interval: 1\n  min_interval: 1\n",
+            ))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let source = source.clone();
+        async move { source.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let sink = Arc::new(Memory::new());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let aggregating = tokio::spawn({
+        let sink = sink.clone();
+        let sources = vec![format!("garage=http://{}", addr).parse().unwrap()];
+        async move {
+            aggregator::run(
+                sources,
+                None,
+                sink.as_ref(),
+                16,
+                DropPolicy::Oldest,
+                None,
+                false,
+                async {
+                    let _ = stopped.await;
+                },
+            )
+            .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), aggregating)
+        .await
+        .expect("The aggregator didn't stop")
+        .unwrap();
+    source.shutdown();
+
+    let datapoints = sink.take();
+    assert!(!datapoints.is_empty());
+    assert!(datapoints
+        .iter()
+        .all(|datapoint| datapoint.name.starts_with("garage.workshop.")));
+}
+
+#[test]
+fn the_compact_format_round_trips_in_a_fraction_of_the_bytes() {
+    let batch = |time: i64, value: f64| {
+        [
+            "kitchen.temperature",
+            "kitchen.humidity",
+            "attic.temperature",
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| Datapoint {
+            name: name.to_string(),
+            interval: 60,
+            value: value + i as f64,
+            time: time + i as i64,
+        })
+        .collect::<Vec<_>>()
+    };
+    let first = batch(1_700_000_000, 21.5);
+    let second = batch(1_700_000_060, 21.7);
+
+    let mut encoder = wire::Encoder::new();
+    let first_frame = encoder.encode(&first).unwrap();
+    let second_frame = encoder.encode(&second).unwrap();
+    // The names are only sent in the first frame
+    assert!(second_frame.len() * 2 < first_frame.len());
+    assert!(second_frame.len() * 5 < serde_json::to_vec(&second).unwrap().len());
+
+    let mut decoder = wire::Decoder::new();
+    let stream = [first_frame, wire::keepalive(), second_frame].concat();
+    // Split mid-frame, as it may arrive
+    decoder.extend(&stream[..10]);
+    assert!(decoder.next_batch().unwrap().is_none());
+    decoder.extend(&stream[10..]);
+    for expected in [first, second] {
+        let decoded = decoder.next_batch().unwrap().unwrap();
+        assert_eq!(decoded.len(), expected.len());
+        for (decoded, expected) in decoded.iter().zip(&expected) {
+            assert_eq!(decoded.name, expected.name);
+            assert_eq!(decoded.interval, expected.interval);
+            assert_eq!(decoded.value, expected.value);
+            assert_eq!(decoded.time, expected.time);
+        }
+    }
+    assert!(decoder.next_batch().unwrap().is_none());
+
+    let mut future = wire::Decoder::new();
+    let mut frame = wire::Encoder::new().encode(&batch(0, 0.0)).unwrap();
+    frame[5] = wire::VERSION + 1;
+    future.extend(&frame);
+    assert!(future
+        .next_batch()
+        .unwrap_err()
+        .contains("unsupported version"));
+    // A base time that overflows the points' times
+    let mut overflowing = wire::Decoder::new();
+    let mut frame = wire::Encoder::new()
+        .encode(&[1, 2].map(|time| Datapoint {
+            name: "a".to_string(),
+            interval: 60,
+            value: 0.0,
+            time,
+        }))
+        .unwrap();
+    // After the length, the array, the version and the name, `1` is the base time
+    assert_eq!(frame[9], 0x01);
+    frame[9..10].copy_from_slice(&[0x1b]);
+    frame.splice(10..10, i64::MAX.to_be_bytes());
+    let length = (frame.len() - 4) as u32;
+    frame[..4].copy_from_slice(&length.to_be_bytes());
+    overflowing.extend(&frame);
+    assert!(overflowing
+        .next_batch()
+        .unwrap_err()
+        .contains("malformed frame"));
+}
@@ -0,0 +1,747 @@
+mod common;
+
+use common::{free_addr, next_request, sensors, spawn_server};
+use monitoring::{
+    error::SensorError,
+    power::{LowPowerConfig, PowerTrigger},
+    sensors::{MockBackend, Reading},
+    service::{ApiAuth, MonitorService},
+    sinks::{Graphite, Memory},
+    Datapoint,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+/// Starts a service serving the API, with `kitchen` reading fine and `attic` failing
+async fn start_service() -> (Arc<MonitorService>, SocketAddr) {
+    let backend = MockBackend::new();
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 21.5,
+            humidity: 40.0,
+        }),
+    );
+    for _ in 0..10 {
+        backend.push(5, Err(SensorError::Timeout));
+    }
+
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors(
+                "- name: kitchen\n  pin: 4\n- name: attic\n  pin: 5\n",
+            ))
+            .backend(Arc::new(backend))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    (service, addr)
+}
+
+#[tokio::test]
+async fn readings_report_latest_values_and_status() {
+    let (service, addr) = start_service().await;
+
+    let readings: serde_json::Value = reqwest::get(format!("http://{}/readings", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(readings[0]["sensor"], "attic");
+    assert_eq!(readings[0]["status"], "failing");
+    assert_eq!(
+        readings[0]["last_error"],
+        "timeout reading the sensor value"
+    );
+    assert_eq!(readings[1]["sensor"], "kitchen");
+    assert_eq!(readings[1]["status"], "ok");
+    assert_eq!(readings[1]["values"]["temperature"], 21.5);
+    assert_eq!(readings[1]["values"]["humidity"], 40.0);
+    assert!(readings[1]["time"].is_i64());
+}
+
+#[tokio::test]
+async fn sensors_lists_the_configuration() {
+    let (service, addr) = start_service().await;
+
+    let sensors: serde_json::Value = reqwest::get(format!("http://{}/sensors", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(sensors[0]["name"], "kitchen");
+    assert_eq!(sensors[0]["pin"], 4);
+    assert_eq!(sensors[1]["name"], "attic");
+}
+
+#[tokio::test]
+async fn metadata_describes_the_sensors_and_their_metrics() {
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors(concat!(
+                "- name: kitchen\n  site: home\n  pin: 4\n  tags:\n    floor: ground\n",
+                "  descriptions:\n    humidity: Relative humidity by the fridge\n",
+                "  calibration:\n    - metric: humidity\n      points: [[0, -2.5]]\n",
+            )))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let metadata: serde_json::Value = reqwest::get(format!("http://{}/metadata", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    let kitchen = &metadata[0];
+    assert_eq!(kitchen["sensor"], "kitchen");
+    assert_eq!(kitchen["path"], "home.kitchen");
+    assert_eq!(kitchen["type"], "dht22");
+    assert_eq!(kitchen["tags"]["floor"], "ground");
+    let humidity = &kitchen["metrics"][0];
+    assert_eq!(humidity["metric"], "humidity");
+    assert_eq!(humidity["series"], "home.kitchen.humidity");
+    assert_eq!(humidity["unit"], "%");
+    assert_eq!(humidity["description"], "Relative humidity by the fridge");
+    assert_eq!(humidity["calibration"][0]["points"][0][1], -2.5);
+    assert_eq!(kitchen["metrics"][1]["metric"], "temperature");
+    assert_eq!(kitchen["metrics"][1]["unit"], "°C");
+}
+
+#[tokio::test]
+async fn unknown_paths_are_not_found() {
+    let (service, addr) = start_service().await;
+
+    let response = reqwest::get(format!("http://{}/nope", addr)).await.unwrap();
+    service.shutdown();
+
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn health_checks_pass_once_readings_are_written() {
+    let (service, addr) = start_service().await;
+
+    let healthz = reqwest::get(format!("http://{}/healthz", addr))
+        .await
+        .unwrap();
+    let readyz = reqwest::get(format!("http://{}/readyz", addr))
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(healthz.status(), reqwest::StatusCode::OK);
+    assert_eq!(readyz.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn not_ready_until_a_sensor_is_read() {
+    let backend = MockBackend::new();
+    for _ in 0..10 {
+        backend.push(5, Err(SensorError::Timeout));
+    }
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: attic\n  pin: 5\n"))
+            .backend(Arc::new(backend))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let readyz = reqwest::get(format!("http://{}/readyz", addr))
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(readyz.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = readyz.json().await.unwrap();
+    assert_eq!(body["reason"], "no sensor read successfully yet");
+}
+
+#[tokio::test]
+async fn stream_pushes_new_readings() {
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors(
+                "- name: kitchen\n  pin: 4\n  interval: 1\n  min_interval: 1\n",
+            ))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let mut response = reqwest::get(format!("http://{}/stream", addr))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    let event = tokio::time::timeout(Duration::from_secs(5), response.chunk())
+        .await
+        .expect("Timed out waiting for an event")
+        .unwrap()
+        .unwrap();
+    service.shutdown();
+
+    let event = String::from_utf8(event.to_vec()).unwrap();
+    assert!(event.starts_with("event: readings\ndata: "));
+    assert!(event.contains("kitchen.temperature"));
+}
+
+#[tokio::test]
+async fn dashboard_shows_sensors_and_their_health() {
+    let (service, addr) = start_service().await;
+
+    let response = reqwest::get(format!("http://{}/", addr)).await.unwrap();
+    service.shutdown();
+
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    let page = response.text().await.unwrap();
+    assert!(page.contains("kitchen"));
+    assert!(page.contains("21.5"));
+    assert!(page.contains("attic"));
+    assert!(page.contains("timeout reading the sensor value"));
+}
+
+#[tokio::test]
+async fn history_returns_recent_readings() {
+    let (service, addr) = start_service().await;
+
+    let history: serde_json::Value = reqwest::get(format!(
+        "http://{}/history?sensor=kitchen&metric=temperature&step=60",
+        addr
+    ))
+    .await
+    .unwrap()
+    .json()
+    .await
+    .unwrap();
+    let missing_metric = reqwest::get(format!("http://{}/history?sensor=kitchen", addr))
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(history["sensor"], "kitchen");
+    assert_eq!(history["points"].as_array().unwrap().len(), 1);
+    assert_eq!(history["points"][0][0].as_i64().unwrap() % 60, 0);
+    assert_eq!(history["points"][0][1], 21.5);
+    assert_eq!(missing_metric.status(), reqwest::StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn sensors_can_be_managed_at_runtime() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("managed.yaml");
+    std::fs::write(&path, "- name: kitchen\n  pin: 4\n").unwrap();
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(
+                monitoring::config::load_sensors_config(&path)
+                    .await
+                    .unwrap(),
+            )
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .api_token("secret")
+            .config_path(&path)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let client = reqwest::Client::new();
+    let sensors_url = format!("http://{}/sensors", addr);
+
+    let unauthorized = client
+        .post(&sensors_url)
+        .json(&serde_json::json!({"name": "attic", "pin": 5}))
+        .send()
+        .await
+        .unwrap();
+    let added = client
+        .post(&sensors_url)
+        .bearer_auth("secret")
+        .json(&serde_json::json!({"name": "attic", "pin": 5}))
+        .send()
+        .await
+        .unwrap();
+    let renamed = client
+        .post(format!("{}/kitchen", sensors_url))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({"name": "pantry"}))
+        .send()
+        .await
+        .unwrap();
+    let removed = client
+        .delete(format!("{}/nope", sensors_url))
+        .bearer_auth("secret")
+        .send()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let readings: serde_json::Value = reqwest::get(format!("http://{}/readings", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(unauthorized.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(added.status(), reqwest::StatusCode::CREATED);
+    assert_eq!(renamed.status(), reqwest::StatusCode::OK);
+    assert_eq!(removed.status(), reqwest::StatusCode::NOT_FOUND);
+    assert_eq!(readings[0]["sensor"], "attic");
+    assert_eq!(readings[0]["status"], "ok");
+    assert_eq!(readings[1]["sensor"], "pantry");
+
+    let saved = monitoring::config::load_sensors_config(&path)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].name, "pantry");
+    assert_eq!(saved[1].name, "attic");
+}
+
+#[tokio::test]
+async fn hooks_of_sensors_added_over_the_api_run() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("added-hooks");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("reading.json");
+
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .api_token("secret")
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let added = reqwest::Client::new()
+        .post(format!("http://{}/sensors", addr))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({
+            "name": "attic",
+            "pin": 5,
+            "on_reading": ["sh", "-c", format!("cat > {}", out.display())],
+        }))
+        .send()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(800)).await;
+    service.shutdown();
+
+    assert_eq!(added.status(), reqwest::StatusCode::CREATED);
+    let event: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert_eq!(event["sensor"], "attic");
+}
+
+#[tokio::test]
+async fn renamed_sensors_keep_their_graphite_tags() {
+    let (url, mut requests) = spawn_server(reqwest::StatusCode::OK);
+    let configured = sensors("- name: kitchen\n  pin: 4\n  tags:\n    floor: ground\n");
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(configured.clone())
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(
+                Graphite::new(url, "secret")
+                    .sensor_tags(&configured)
+                    .metadata_tags(&configured),
+            ))
+            .listen(addr)
+            .api_token("secret")
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let renamed = reqwest::Client::new()
+        .post(format!("http://{}/sensors/kitchen", addr))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({"name": "pantry"}))
+        .send()
+        .await
+        .unwrap();
+    let posted = loop {
+        let request = next_request(&mut requests).await;
+        let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+        if let Some(datapoint) = datapoints
+            .into_iter()
+            .find(|datapoint| datapoint.name.starts_with("pantry."))
+        {
+            break datapoint;
+        }
+    };
+    service.shutdown();
+
+    assert_eq!(renamed.status(), reqwest::StatusCode::OK);
+    assert_eq!(
+        posted.name,
+        "pantry.temperature;floor=ground;type=dht22;unit=°C"
+    );
+}
+
+#[tokio::test]
+async fn daily_extremes_of_sensors_added_over_the_api_are_written() {
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .daily_extremes("utc".parse().unwrap())
+            .listen(addr)
+            .api_token("secret")
+            .build()
+            .unwrap(),
+    );
+    let mut readings = service.subscribe();
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let added = reqwest::Client::new()
+        .post(format!("http://{}/sensors", addr))
+        .bearer_auth("secret")
+        .json(&serde_json::json!({"name": "attic", "pin": 5}))
+        .send()
+        .await
+        .unwrap();
+    let written = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let batch = readings.recv().await.unwrap();
+            if batch
+                .iter()
+                .any(|datapoint| datapoint.name == "attic.temperature.daily_min")
+            {
+                break;
+            }
+        }
+    })
+    .await;
+    service.shutdown();
+
+    assert_eq!(added.status(), reqwest::StatusCode::CREATED);
+    assert!(
+        written.is_ok(),
+        "The added sensor's extremes weren't written"
+    );
+}
+
+#[tokio::test]
+async fn sensors_cannot_be_changed_without_an_api_token() {
+    let (service, addr) = start_service().await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("http://{}/sensors/kitchen", addr))
+        .send()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(response.status(), reqwest::StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn api_auth_protects_everything_but_the_health_checks() {
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .api_auth(ApiAuth::Bearer("reader".to_string()))
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let client = reqwest::Client::new();
+
+    let anonymous = client
+        .get(format!("http://{}/readings", addr))
+        .send()
+        .await
+        .unwrap();
+    let wrong_token = client
+        .get(format!("http://{}/readings", addr))
+        .bearer_auth("writer")
+        .send()
+        .await
+        .unwrap();
+    let authenticated = client
+        .get(format!("http://{}/readings", addr))
+        .bearer_auth("reader")
+        .send()
+        .await
+        .unwrap();
+    let health = client
+        .get(format!("http://{}/healthz", addr))
+        .send()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(anonymous.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(wrong_token.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(authenticated.status(), reqwest::StatusCode::OK);
+    assert_eq!(health.status(), reqwest::StatusCode::OK);
+}
+
+#[tokio::test]
+async fn pushed_readings_join_the_pipeline() {
+    let addr = free_addr();
+    let sink = Arc::new(Memory::new());
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(sink.clone())
+            .listen(addr)
+            .ingest_token("device")
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let client = reqwest::Client::new();
+    let ingest_url = format!("http://{}/ingest", addr);
+
+    let wrong_token = client
+        .post(&ingest_url)
+        .bearer_auth("nope")
+        .json(&serde_json::json!({"name": "porch", "metric": "temperature", "value": 9.5}))
+        .send()
+        .await
+        .unwrap();
+    let invalid = client
+        .post(&ingest_url)
+        .bearer_auth("device")
+        .json(&serde_json::json!({"name": "porch light", "metric": "lux", "value": 3.0}))
+        .send()
+        .await
+        .unwrap();
+    // Nothing of a push with an invalid reading is kept
+    let partly_invalid = client
+        .post(&ingest_url)
+        .bearer_auth("device")
+        .json(&serde_json::json!([
+            {"name": "shed", "metric": "temperature", "value": 5.0},
+            {"name": "shed light", "metric": "lux", "value": 3.0},
+        ]))
+        .send()
+        .await
+        .unwrap();
+    let pushed = client
+        .post(&ingest_url)
+        .bearer_auth("device")
+        .json(&serde_json::json!([
+            {"name": "porch", "metric": "temperature", "value": 9.5, "time": 1700000000},
+            {"name": "porch", "metric": "humidity", "value": 81.0, "time": 1700000000},
+        ]))
+        .send()
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let readings: serde_json::Value = reqwest::get(format!("http://{}/readings", addr))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    service.shutdown();
+
+    assert_eq!(wrong_token.status(), reqwest::StatusCode::UNAUTHORIZED);
+    assert_eq!(invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(partly_invalid.status(), reqwest::StatusCode::BAD_REQUEST);
+    assert_eq!(pushed.status(), reqwest::StatusCode::ACCEPTED);
+    assert_eq!(readings.as_array().unwrap().len(), 2);
+    assert_eq!(readings[1]["sensor"], "porch");
+    assert_eq!(readings[1]["values"]["humidity"], 81.0);
+    assert_eq!(readings[1]["values"]["temperature"], 9.5);
+
+    let written = sink
+        .take()
+        .into_iter()
+        .filter(|datapoint| {
+            datapoint.name.starts_with("porch.") || datapoint.name.starts_with("shed.")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(written.len(), 2);
+    assert_eq!(written[0].name, "porch.temperature");
+    assert_eq!(written[0].time, 1700000000);
+}
+
+#[tokio::test]
+async fn only_health_checks_are_answered_in_low_power_mode() {
+    let flag = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("on-battery");
+    std::fs::write(&flag, "").unwrap();
+    let mut low_power = LowPowerConfig::new(PowerTrigger::File(flag.clone()));
+    low_power.check_every = Duration::from_millis(50);
+
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .low_power(low_power)
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    let status = |path: &'static str| async move {
+        reqwest::get(format!("http://{}{}", addr, path))
+            .await
+            .unwrap()
+            .status()
+    };
+    assert_eq!(status("/readings").await, 503);
+    assert_eq!(status("/healthz").await, 200);
+
+    std::fs::remove_file(&flag).unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(status("/readings").await, 200);
+    service.shutdown();
+}
+
+#[tokio::test]
+async fn readings_answer_conditional_requests_until_they_change() {
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .listen(addr)
+            .api_max_age(Duration::from_secs(5))
+            .build()
+            .unwrap(),
+    );
+    tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{}/readings", addr);
+
+    let first = client.get(&url).send().await.unwrap();
+    assert_eq!(first.status(), 200);
+    assert_eq!(first.headers()["cache-control"], "max-age=5");
+    let etag = first.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = first.headers()["last-modified"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let unchanged = client
+        .get(&url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unchanged.status(), 304);
+    assert_eq!(unchanged.headers()["etag"], etag.as_str());
+    let not_modified = client
+        .get(&url)
+        .header("if-modified-since", &last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(not_modified.status(), 304);
+
+    service.state().record_reading(
+        "kitchen",
+        1_700_000_000,
+        &[("temperature".to_string(), 19.0)],
+    );
+    let changed = client
+        .get(&url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    service.shutdown();
+    assert_eq!(changed.status(), 200);
+    assert_ne!(changed.headers()["etag"], etag.as_str());
+    let readings: serde_json::Value = changed.json().await.unwrap();
+    assert_eq!(readings[0]["values"]["temperature"], 19.0);
+}
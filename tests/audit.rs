@@ -0,0 +1,73 @@
+mod common;
+
+use common::sensors;
+use monitoring::{audit, capture::Entry};
+
+#[test]
+fn the_audit_finds_failed_reads_gaps_flatlines_and_impossible_values() {
+    let sensors = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n",
+        "- name: attic\n  pin: 5\n",
+        "- name: cellar\n  pin: 6\n",
+    ));
+    let from = 1_700_000_000;
+    let reading = |sensor: &str, time: i64, temperature: f64| Entry {
+        time,
+        sensor: sensor.to_string(),
+        metrics: Some([("temperature".to_string(), temperature)].into()),
+        error: None,
+    };
+
+    let mut entries = Vec::new();
+    for i in 0..20 {
+        let time = from + i * 60;
+        entries.push(reading("kitchen", time, 21.0 + i as f64 / 10.0));
+        // Stuck at the same value, and silent for a while in the middle
+        if !(5..10).contains(&i) {
+            entries.push(reading("attic", time, 18.5));
+        }
+        if i % 4 == 0 {
+            entries.push(Entry {
+                time,
+                sensor: "cellar".to_string(),
+                metrics: None,
+                error: Some("checksum value of the reading is incorrect".to_string()),
+            });
+        } else {
+            entries.push(reading(
+                "cellar",
+                time,
+                if i == 7 { 655.3 } else { 12.0 + i as f64 },
+            ));
+        }
+    }
+    // Outside of the window
+    entries.push(reading("kitchen", from - 3600, 900.0));
+
+    let reports = audit::audit(&sensors, &entries, 60, from, from + 3600);
+    assert_eq!(reports.len(), 3);
+
+    let kitchen = &reports[0];
+    assert!(kitchen.is_ok(), "{:?}", kitchen);
+    assert_eq!(kitchen.reads, 20);
+    assert_eq!(kitchen.to_string().lines().next(), Some("kitchen: ok"));
+
+    let attic = &reports[1];
+    assert_eq!(attic.gaps.len(), 1);
+    assert_eq!(attic.gaps[0].secs(), 6 * 60);
+    assert_eq!(attic.flatlines.len(), 1);
+    assert_eq!(attic.flatlines[0].readings, 15);
+    assert_eq!(attic.flatlines[0].value, 18.5);
+
+    let cellar = &reports[2];
+    assert_eq!((cellar.reads, cellar.failed), (20, 5));
+    assert_eq!(
+        cellar.common_error.as_deref(),
+        Some("checksum value of the reading is incorrect")
+    );
+    assert_eq!(cellar.out_of_range.len(), 1);
+    assert_eq!(cellar.out_of_range[0].worst, 655.3);
+    assert!(cellar
+        .to_string()
+        .contains("reads: 20, 5 failed (25.0%), mostly: checksum value"));
+}
@@ -0,0 +1,128 @@
+//! A local HTTP server standing in for Graphite, capturing every request it receives
+
+#![allow(dead_code)]
+
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Response, Server, StatusCode,
+};
+use monitoring::config::Sensor;
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+pub struct CapturedRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: String,
+    pub bytes: Vec<u8>,
+}
+
+/// How many requests a server was answering at once
+#[derive(Clone, Default)]
+pub struct Concurrency {
+    now: Arc<AtomicUsize>,
+    most: Arc<AtomicUsize>,
+}
+
+impl Concurrency {
+    /// The most requests that were answered at once
+    pub fn most(&self) -> usize {
+        self.most.load(Ordering::SeqCst)
+    }
+}
+
+/// Starts a server answering every request with `status`, returning its URL and the
+/// stream of requests it received
+pub fn spawn_server(status: StatusCode) -> (String, mpsc::UnboundedReceiver<CapturedRequest>) {
+    let (url, receiver, _) = spawn_slow_server(status, Duration::ZERO);
+    (url, receiver)
+}
+
+/// Like [`spawn_server`], but answering each request only after `delay`, keeping track of how
+/// many were waiting for their answer at once
+pub fn spawn_slow_server(
+    status: StatusCode,
+    delay: Duration,
+) -> (
+    String,
+    mpsc::UnboundedReceiver<CapturedRequest>,
+    Concurrency,
+) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let concurrency = Concurrency::default();
+
+    let make_service = make_service_fn({
+        let concurrency = concurrency.clone();
+        move |_| {
+            let sender = sender.clone();
+            let concurrency = concurrency.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: hyper::Request<Body>| {
+                    let sender = sender.clone();
+                    let concurrency = concurrency.clone();
+                    async move {
+                        let (parts, body) = request.into_parts();
+                        let body = hyper::body::to_bytes(body).await.unwrap();
+                        let _ = sender.send(CapturedRequest {
+                            method: parts.method.to_string(),
+                            path: parts.uri.path().to_string(),
+                            headers: parts.headers,
+                            body: String::from_utf8_lossy(&body).into_owned(),
+                            bytes: body.to_vec(),
+                        });
+
+                        if !delay.is_zero() {
+                            let now = concurrency.now.fetch_add(1, Ordering::SeqCst) + 1;
+                            concurrency.most.fetch_max(now, Ordering::SeqCst);
+                            tokio::time::sleep(delay).await;
+                            concurrency.now.fetch_sub(1, Ordering::SeqCst);
+                        }
+
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::from("rejected"))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        }
+    });
+
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}/metrics", server.local_addr());
+    tokio::spawn(server);
+
+    (url, receiver, concurrency)
+}
+
+/// Waits for the next captured request, failing the test if none arrives in time
+pub async fn next_request(
+    receiver: &mut mpsc::UnboundedReceiver<CapturedRequest>,
+) -> CapturedRequest {
+    tokio::time::timeout(Duration::from_secs(10), receiver.recv())
+        .await
+        .expect("Timed out waiting for a request")
+        .expect("Server stopped")
+}
+
+/// Finds a local address nothing is listening on
+pub fn free_addr() -> std::net::SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+pub fn sensors(yaml: &str) -> Vec<Sensor> {
+    serde_yaml::from_str(yaml).unwrap()
+}
@@ -0,0 +1,401 @@
+mod common;
+
+use chrono::{NaiveTime, TimeZone, Utc};
+use common::sensors;
+use monitoring::{
+    config,
+    error::ConfigError,
+    interval::Interval,
+    locale::{Locale, Unit},
+    privileges::RunAs,
+    schedule::{self, Window},
+};
+use std::path::Path;
+
+#[tokio::test]
+async fn loads_sensors_from_yaml() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("sensors.yaml");
+    std::fs::write(
+        &path,
+        "- name: kitchen\n  pin: 4\n- name: bedroom\n  pin: 17\n",
+    )
+    .unwrap();
+
+    let sensors = config::load_sensors_config(&path).await.unwrap();
+
+    assert_eq!(sensors.len(), 2);
+    assert_eq!(sensors[0].name, "kitchen");
+    assert_eq!(sensors[1].pin, Some(17));
+    assert!(sensors[0].alerts.is_empty());
+}
+
+#[tokio::test]
+async fn missing_config_is_reported() {
+    let err = config::load_sensors_config(Path::new("/nonexistent/sensors.yaml"))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, ConfigError::NotFound { .. }));
+}
+
+#[tokio::test]
+async fn invalid_config_is_reported() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("invalid.yaml");
+    std::fs::write(&path, "- name: kitchen\n  pin: four\n").unwrap();
+
+    let err = config::load_sensors_config(&path).await.unwrap_err();
+
+    assert!(matches!(err, ConfigError::Parse(_)));
+}
+
+#[test]
+fn alerts_fire_outside_their_thresholds() {
+    let configured = sensors(
+        "- name: freezer\n  pin: 4\n  alerts:\n    - metric: temperature\n      above: -15\n      below: -25\n",
+    );
+    let alert = &configured[0].alerts[0];
+
+    assert!(alert.is_breached(-10.0));
+    assert!(alert.is_breached(-30.0));
+    assert!(!alert.is_breached(-18.0));
+
+    let saved = serde_yaml::to_string(&configured).unwrap();
+    assert!(!saved.contains("null"));
+    let never = sensors("- name: freezer\n  pin: 4\n  alerts:\n    - metric: temperature\n");
+    assert!(config::validate(&never).is_err());
+}
+
+#[test]
+fn composite_alerts_combine_sensors_and_the_dew_point() {
+    let configured = sensors(concat!(
+        "- name: outdoor\n  pin: 4\n",
+        "- name: greenhouse\n  pin: 5\n  alerts:\n",
+        "    - metric: temperature\n      below: 3\n",
+        "      and:\n        - sensor: outdoor\n          metric: temperature\n          below: 0\n",
+        "- name: window\n  pin: 6\n  alerts:\n",
+        "    - metric: temperature\n      dew_point_within: 1\n      dew_point_of: living_room\n",
+        "- name: living_room\n  pin: 7\n",
+    ));
+    assert!(config::validate(&configured).is_ok());
+    let latest = |outdoor: f64| {
+        move |sensor: &str, metric: &str| match (sensor, metric) {
+            ("outdoor", "temperature") => Some(outdoor),
+            ("living_room", "temperature") => Some(20.0),
+            ("living_room", "humidity") => Some(50.0),
+            _ => None,
+        }
+    };
+
+    let frost = &configured[1].alerts[0];
+    assert!(frost.fires("greenhouse", 2.0, latest(-1.0)));
+    assert!(!frost.fires("greenhouse", 2.0, latest(1.0)));
+    assert!(!frost.fires("greenhouse", 4.0, latest(-1.0)));
+
+    // The dew point at 20 °C and 50% is about 9.3 °C
+    assert!((config::dew_point(20.0, 50.0) - 9.26).abs() < 0.05);
+    let condensation = &configured[2].alerts[0];
+    assert!(condensation.fires("window", 10.0, latest(0.0)));
+    assert!(!condensation.fires("window", 10.5, latest(0.0)));
+    assert!(!condensation.fires("window", 10.0, |_, _| None));
+
+    let unknown = sensors(concat!(
+        "- name: greenhouse\n  pin: 5\n  alerts:\n",
+        "    - metric: temperature\n      below: 3\n",
+        "      and:\n        - sensor: outdoor\n          metric: temperature\n          below: 0\n",
+    ));
+    assert!(config::validate(&unknown).is_err());
+}
+
+#[test]
+fn control_loop_keeps_its_state_inside_the_hysteresis_band() {
+    let sensors = sensors(
+        "- name: greenhouse\n  pin: 4\n  control:\n    metric: temperature\n    target: 12\n    hysteresis: 1\n    mode: raise\n    gpio:\n      pin: 22\n",
+    );
+    let control = sensors[0].control.as_ref().unwrap();
+
+    assert!(control.wants_on(10.5, false));
+    assert!(control.wants_on(12.5, true));
+    assert!(!control.wants_on(12.5, false));
+    assert!(!control.wants_on(13.5, true));
+}
+
+#[test]
+fn fan_curves_are_interpolated_and_held_flat_beyond_their_ends() {
+    let sensors = sensors(
+        "- name: cpu\n  type: cpu\n  fan:\n    metric: temperature\n    pin: 18\n    curve: [[45, 0], [55, 40], [70, 100]]\n",
+    );
+    let fan = sensors[0].fan.as_ref().unwrap();
+
+    assert_eq!(fan.duty_cycle(30.0), 0.0);
+    assert!((fan.duty_cycle(50.0) - 0.2).abs() < 1e-9);
+    assert!((fan.duty_cycle(62.5) - 0.7).abs() < 1e-9);
+    assert_eq!(fan.duty_cycle(85.0), 1.0);
+    assert!(config::validate(&sensors).is_ok());
+}
+
+#[test]
+fn calibration_corrects_each_metric_by_the_raw_readings() {
+    let kitchen = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  calibration:\n",
+        "    - metric: temperature\n      points: [[0, -0.5]]\n",
+        "    - metric: humidity\n      by: temperature\n      points: [[10, 4], [30, -2]]\n",
+    ));
+    let mut metrics = vec![
+        ("temperature".to_string(), 20.0),
+        ("humidity".to_string(), 50.0),
+    ];
+
+    kitchen[0].calibrate(&mut metrics);
+
+    assert_eq!(metrics[0].1, 19.5);
+    // Corrected by the raw 20.0 degrees, half way between the points
+    assert_eq!(metrics[1].1, 51.0);
+    assert!(config::validate(&kitchen).is_ok());
+
+    let mut raw = kitchen[0].clone();
+    raw.write_raw = true;
+    let mut metrics = vec![
+        ("temperature".to_string(), 20.0),
+        ("pressure".to_string(), 1013.0),
+    ];
+    raw.calibrate(&mut metrics);
+    assert_eq!(metrics[2], ("temperature.raw".to_string(), 20.0));
+    assert_eq!(metrics.len(), 3);
+
+    let unordered =
+        "- name: kitchen\n  pin: 4\n  calibration:\n    - metric: humidity\n      points: [[30, 1], [10, 2]]\n";
+    assert!(config::validate(&sensors(unordered)).is_err());
+}
+
+#[test]
+fn sensors_need_the_settings_of_their_type() {
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n")).is_ok());
+    assert!(config::validate(&sensors("- name: kitchen\n")).is_err());
+    assert!(config::validate(&sensors("- name: co2\n  type: command\n")).is_err());
+    assert!(config::validate(&sensors("- name: pico\n  type: serial\n")).is_err());
+    assert!(config::validate(&sensors("- name: well\n  type: radio\n")).is_err());
+    assert!(config::validate(&sensors(
+        "- name: well\n  type: radio\n  node: 1\n- name: hive\n  type: radio\n  node: 2\n  frequency: 433\n"
+    ))
+    .is_err());
+    assert!(config::validate(&sensors(
+        "- name: pico\n  type: serial\n  device: /dev/ttyACM0\n  format: csv\n"
+    ))
+    .is_err());
+    assert!(config::validate(&sensors(
+        "- name: cpu\n  type: cpu\n  fan:\n    metric: temperature\n    pin: 18\n    curve: [[55, 40], [45, 0]]\n"
+    ))
+    .is_err());
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  power_pin: 17\n")).is_ok());
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  power_pin: 4\n")).is_err());
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  pull_up: true\n")).is_ok());
+    assert!(config::validate(&sensors("- name: cpu\n  type: cpu\n  pull_up: true\n")).is_err());
+    assert!(config::validate(&sensors("- name: rain\n  type: pulse\n")).is_err());
+    assert!(config::validate(&sensors(
+        "- name: rain\n  type: pulse\n  pin: 6\n  pull_up: true\n  pulses_per_unit: 3.58\n"
+    ))
+    .is_ok());
+    assert!(config::validate(&sensors(
+        "- name: rain\n  type: pulse\n  pin: 6\n  pulses_per_unit: 0\n"
+    ))
+    .is_err());
+    assert!(config::validate(&sensors(
+        "- name: kitchen\n  pin: 4\n  power_pin: 17\n  power_cycle_after: 0\n"
+    ))
+    .is_err());
+}
+
+#[test]
+fn run_as_users_and_groups_must_exist() {
+    assert!(RunAs::lookup("root", None).is_ok());
+    assert!(RunAs::lookup("0", Some("0")).is_ok());
+    assert!(matches!(
+        RunAs::lookup("no-such-user", None),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        RunAs::lookup("root", Some("no-such-group")),
+        Err(ConfigError::Invalid(_))
+    ));
+}
+
+#[test]
+fn known_series_count_the_metrics_each_sensor_type_writes() {
+    let sensors = sensors(
+        "- name: kitchen\n  pin: 4\n- name: cpu\n  type: cpu\n- name: shed\n  type: serial\n  device: /dev/ttyACM0\n  format: csv\n  fields: [temperature, humidity, pressure]\n- name: office\n  type: command\n  command: [reader]\n- name: rain\n  type: pulse\n  pin: 6\n- name: attic\n  pin: 17\n  disabled: true\n",
+    );
+
+    assert_eq!(config::known_series(&sensors), 9);
+}
+
+#[test]
+fn sites_and_rooms_prefix_the_series_paths() {
+    let located = sensors(concat!(
+        "- name: sensor1\n  site: cottage\n  room: bedroom\n  pin: 4\n",
+        "- name: shed\n  site: cottage\n  pin: 5\n",
+        "- name: kitchen\n  pin: 6\n",
+    ));
+
+    assert_eq!(
+        located[0].series("temperature"),
+        "cottage.bedroom.sensor1.temperature"
+    );
+    assert_eq!(located[1].path(), "cottage.shed");
+    assert_eq!(located[2].path(), "kitchen");
+
+    let dotted = sensors("- name: sensor1\n  site: cottage.north\n  pin: 4\n");
+    assert!(matches!(
+        config::validate(&dotted),
+        Err(ConfigError::Invalid(_))
+    ));
+}
+
+#[test]
+fn sensors_are_not_sampled_faster_than_their_minimum_interval() {
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  interval: 1\n")).is_err());
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  interval: 2\n")).is_ok());
+    assert!(config::validate(&sensors(
+        "- name: co2\n  type: command\n  command: [scd30]\n  interval: 5\n  min_interval: 10\n"
+    ))
+    .is_err());
+
+    let kitchen = sensors("- name: kitchen\n  pin: 4\n- name: attic\n  pin: 5\n  interval: 60\n");
+    assert!(config::validate_refresh(&kitchen, Interval::from_secs(1)).is_err());
+    assert!(config::validate_refresh(&kitchen, Interval::from_secs(30)).is_ok());
+}
+
+#[test]
+fn intervals_are_given_as_durations_or_seconds() {
+    let millis = |interval: &str| {
+        interval
+            .parse::<Interval>()
+            .unwrap()
+            .as_duration()
+            .as_millis()
+    };
+    assert_eq!(millis("30s"), 30_000);
+    assert_eq!(millis("15m"), 900_000);
+    assert_eq!(millis("1h"), 3_600_000);
+    assert_eq!(millis("1m30s"), 90_000);
+    assert_eq!(millis("250ms"), 250);
+    assert_eq!(millis("1.5s"), 1500);
+    assert_eq!(millis("60"), 60_000);
+    assert_eq!(millis("0.5"), 500);
+    for invalid in ["", "0", "-5", "-5s", "5x", "s", "1m 30s", "0.1ms"] {
+        assert!(invalid.parse::<Interval>().is_err(), "{:?}", invalid);
+    }
+    assert_eq!("90s".parse::<Interval>().unwrap().to_string(), "90s");
+    assert_eq!("0.25".parse::<Interval>().unwrap().to_string(), "250ms");
+    assert_eq!("250ms".parse::<Interval>().unwrap().secs(), 1);
+    assert_eq!("61s".parse::<Interval>().unwrap().secs(), 61);
+
+    let fast = sensors(concat!(
+        "- name: current\n  type: command\n  command: [ina219]\n",
+        "  interval: 250ms\n  min_interval: 100ms\n",
+        "- name: kitchen\n  pin: 4\n  interval: 1m\n  schedule: [\"22:00-06:00/30m\"]\n",
+    ));
+    assert_eq!(fast[0].interval, Some("250ms".parse().unwrap()));
+    assert_eq!(fast[1].interval, Some(Interval::from_secs(60)));
+    assert!(config::validate(&fast).is_ok());
+    assert_eq!(
+        serde_json::to_value(&fast[0]).unwrap()["interval"],
+        serde_json::json!("250ms")
+    );
+    assert_eq!(
+        serde_json::to_value(&fast[1]).unwrap()["interval"],
+        serde_json::json!(60)
+    );
+
+    let negative = "- name: kitchen\n  pin: 4\n  interval: -60\n";
+    assert!(serde_yaml::from_str::<Vec<config::Sensor>>(negative).is_err());
+    assert!(config::validate(&sensors(
+        "- name: current\n  type: command\n  command: [ina219]\n  interval: 250ms\n"
+    ))
+    .is_err());
+}
+
+#[test]
+fn timeouts_and_delays_are_given_as_durations_or_their_old_units() {
+    let old = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  timeout_secs: 5\n  warmup_secs: 120\n",
+        "  retry_interval_ms: 4000\n",
+    ));
+    let new = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  timeout_secs: 5s\n  warmup_secs: 2m\n",
+        "  retry_interval_ms: 4s\n",
+    ));
+    for sensors in [&old, &new] {
+        assert_eq!(sensors[0].timeout_secs, Some(Interval::from_secs(5)));
+        assert_eq!(sensors[0].warmup_secs, Some(Interval::from_secs(120)));
+        assert_eq!(sensors[0].retry_interval_ms, Some(Interval::from_secs(4)));
+    }
+    // Written back in the units the settings are named for
+    let written = serde_json::to_value(&new[0]).unwrap();
+    assert_eq!(written["warmup_secs"], serde_json::json!(120));
+    assert_eq!(written["retry_interval_ms"], serde_json::json!(4000));
+
+    let rain = sensors("- name: rain\n  type: pulse\n  pin: 6\n  debounce_ms: 5\n");
+    assert_eq!(rain[0].debounce_ms, Some("5ms".parse().unwrap()));
+    let zero = "- name: kitchen\n  pin: 4\n  retry_interval_ms: 0\n";
+    assert!(serde_yaml::from_str::<Vec<config::Sensor>>(zero).is_err());
+}
+
+#[test]
+fn schedules_pick_the_interval_by_the_time_of_day() {
+    let day: Window = "06:00-22:00/300".parse().unwrap();
+    let night: Window = "22:00-06:00/1800".parse().unwrap();
+    let at = |time| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+    let secs = Interval::from_secs;
+    assert_eq!(
+        schedule::interval_at(&[day, night], at("12:00"), secs(900)),
+        secs(300)
+    );
+    assert_eq!(
+        schedule::interval_at(&[day, night], at("23:30"), secs(900)),
+        secs(1800)
+    );
+    assert_eq!(
+        schedule::interval_at(&[day, night], at("05:59"), secs(900)),
+        secs(1800)
+    );
+    assert_eq!(
+        schedule::interval_at(&[day], at("03:00"), secs(900)),
+        secs(900)
+    );
+    assert_eq!(schedule::shortest(&[day, night], secs(900)), secs(300));
+    assert!("06:00-22:00".parse::<Window>().is_err());
+    assert!("06:00-22:00/0".parse::<Window>().is_err());
+
+    let kitchen = sensors(
+        "- name: kitchen\n  pin: 4\n  schedule: [\"06:00-22:00/300\", \"22:00-06:00/1800\"]\n",
+    );
+    assert_eq!(kitchen[0].schedule, [day, night]);
+    assert!(config::validate(&sensors(
+        "- name: kitchen\n  pin: 4\n  schedule: [\"06:00-22:00/1\"]\n"
+    ))
+    .is_err());
+    let fast: Window = "00:00-00:00/1".parse().unwrap();
+    assert!(config::validate_schedule(&kitchen, &[fast]).is_ok());
+    assert!(config::validate_schedule(&sensors("- name: attic\n  pin: 5\n"), &[fast]).is_err());
+}
+
+#[test]
+fn locales_pick_the_decimal_separator_date_format_and_units() {
+    let time = Utc.with_ymd_and_hms(2024, 3, 1, 14, 5, 0).unwrap();
+    let mut german: Locale = "de_DE.UTF-8".parse().unwrap();
+    german.units = vec![
+        "temperature=°C".parse().unwrap(),
+        "pressure=hPa".parse().unwrap(),
+    ];
+    assert_eq!(german.value("temperature", 21.54), "21,5°C");
+    assert_eq!(german.value("pressure", 1013.25), "1013,2 hPa");
+    assert_eq!(german.value("humidity", 48.0), "48,0");
+    assert_eq!(german.date_time(&time), "01.03.2024 14:05");
+
+    let american: Locale = "en-US".parse().unwrap();
+    assert_eq!(american.number(-3.5), "-3.5");
+    assert_eq!(american.date_time(&time), "03/01/2024 02:05 PM");
+    assert_eq!(Locale::default().date_time(&time), "2024-03-01 14:05");
+    assert!("xx".parse::<Locale>().is_err());
+    assert!("temperature".parse::<Unit>().is_err());
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{free_addr, next_request, spawn_server};
+use hyper::StatusCode;
+use std::{path::Path, process::Stdio, time::Duration};
+use tokio::process::Command;
+
+#[tokio::test]
+async fn the_binary_reads_secrets_from_files_and_stops_on_sigterm() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("container");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("sensors.yaml"), "- name: kitchen\n  pin: 4\n").unwrap();
+    std::fs::write(dir.join("apikey"), "secret\n").unwrap();
+
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let listen = free_addr().to_string();
+    let mut serve = Command::new(env!("CARGO_BIN_EXE_monitoring"))
+        .args(["serve", "--mock-sensors", "--no-mdns", "--listen", &listen])
+        .arg("--sensors-config-path")
+        .arg(dir.join("sensors.yaml"))
+        .env_clear()
+        .env("GRAPHITE_ENDPOINT", url)
+        .env("GRAFANA_API_KEY_FILE", dir.join("apikey"))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let request = next_request(&mut requests).await;
+    assert_eq!(request.headers["authorization"], "Bearer secret");
+
+    let healthcheck = Command::new(env!("CARGO_BIN_EXE_monitoring"))
+        .args(["healthcheck", "--listen", &listen])
+        .env_clear()
+        .output()
+        .await
+        .unwrap();
+    assert!(healthcheck.status.success());
+
+    let terminated = Command::new("kill")
+        .arg("-TERM")
+        .arg(serve.id().unwrap().to_string())
+        .status()
+        .await
+        .unwrap();
+    assert!(terminated.success());
+    let status = tokio::time::timeout(Duration::from_secs(5), serve.wait())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+async fn the_binary_runs_on_a_current_thread_runtime() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("current-thread");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("sensors.yaml"), "- name: kitchen\n  pin: 4\n").unwrap();
+
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let _serve = Command::new(env!("CARGO_BIN_EXE_monitoring"))
+        .args([
+            "serve",
+            "--mock-sensors",
+            "--no-mdns",
+            "--no-info-metric",
+            "--no-heartbeat",
+        ])
+        .args(["--runtime", "current-thread", "--max-blocking-threads", "2"])
+        .arg("--sensors-config-path")
+        .arg(dir.join("sensors.yaml"))
+        .env_clear()
+        .env("GRAPHITE_ENDPOINT", url)
+        .env("GRAFANA_API_KEY", "secret")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let request = next_request(&mut requests).await;
+    assert!(request.body.contains("kitchen.temperature"));
+}
@@ -0,0 +1,68 @@
+use monitoring::diagnose::{self, Capture, Pulse, Verdict};
+
+/// The pulses of a read of 5 bytes, the last one their checksum, with the 1 bits' high pulses
+/// `one_us` long
+fn read(bytes: [u8; 4], one_us: u32) -> Vec<Pulse> {
+    let checksum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes
+        .iter()
+        .chain([checksum].iter())
+        .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+        .map(|one| Pulse {
+            low_us: 50,
+            high_us: if one { one_us } else { 27 },
+        })
+        .collect()
+}
+
+#[test]
+fn pulse_timings_point_at_the_cause_of_failed_reads() {
+    // A humidity of 40.0% and a temperature of 21.5°C
+    let reading = [0x01, 0x90, 0x00, 0xd7];
+    let mut corrupted = read(reading, 70);
+    corrupted[7].high_us = 45;
+
+    let healthy = diagnose::analyse(4, &vec![Capture::Complete(read(reading, 70)); 3]);
+    assert_eq!(healthy.verdict, Verdict::Ok);
+    assert_eq!((healthy.reads, healthy.valid, healthy.bits), (3, 3, 120));
+    assert_eq!(healthy.one_high.unwrap().mean_us, 70.0);
+    assert_eq!(healthy.to_string().lines().next(), Some("GPIO 4: ok"));
+
+    let interrupted = diagnose::analyse(
+        4,
+        &[
+            Capture::Complete(read(reading, 70)),
+            Capture::Complete(corrupted),
+            Capture::Truncated(read(reading, 70)[..12].to_vec()),
+        ],
+    );
+    assert_eq!(interrupted.verdict, Verdict::Interrupted);
+    assert_eq!(
+        (interrupted.checksum_failures, interrupted.truncated),
+        (1, 1)
+    );
+    assert_eq!(interrupted.ambiguous_bits, 1);
+    assert!(interrupted
+        .to_string()
+        .contains("reads: 3, 1 valid, 1 checksum failures (33.3%), 1 cut short"));
+
+    let mut slow = read(reading, 52);
+    // The line didn't rise in time for the last bit of the first byte
+    slow[7].high_us = 45;
+    let weak = diagnose::analyse(
+        4,
+        &[
+            Capture::Complete(read(reading, 52)),
+            Capture::Complete(slow),
+        ],
+    );
+    assert_eq!(weak.verdict, Verdict::WeakPullUp);
+
+    let floating = diagnose::analyse(
+        17,
+        &[Capture::LineLow, Capture::LineLow, Capture::NoResponse],
+    );
+    assert_eq!(floating.verdict, Verdict::NoPullUp);
+    assert_eq!(floating.bits, 0);
+    assert!(floating.to_string().contains("pull-up resistor"));
+}
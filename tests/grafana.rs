@@ -0,0 +1,101 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use common::{next_request, sensors, spawn_server};
+use hyper::StatusCode;
+use monitoring::{
+    annotations::GrafanaAnnotations, events::Event, grafana, sensors::MockBackend,
+    service::MonitorService, sinks::Memory,
+};
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn dashboards_have_a_row_per_sensor_and_a_panel_per_metric() {
+    let sensors = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  alerts:\n    - metric: temperature\n      above: 28\n",
+        "- name: co2\n  type: command\n  command: [scd30-reader]\n",
+        "- name: attic\n  pin: 5\n  disabled: true\n",
+    ));
+    let dashboard = grafana::dashboard(&sensors, "Home", None);
+
+    let panels = dashboard["panels"].as_array().unwrap();
+    let titles = panels
+        .iter()
+        .map(|panel| panel["title"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        titles,
+        [
+            "kitchen",
+            "kitchen temperature",
+            "kitchen humidity",
+            "co2",
+            "co2"
+        ]
+    );
+    assert_eq!(panels[1]["targets"][0]["target"], "kitchen.temperature");
+    assert_eq!(panels[2]["gridPos"]["x"], 12);
+    assert_eq!(panels[4]["targets"][0]["target"], "co2.*");
+    assert_eq!(panels[4]["datasource"]["uid"], "${datasource}");
+
+    let steps = &panels[1]["fieldConfig"]["defaults"]["thresholds"]["steps"];
+    assert_eq!(steps[1]["value"], 28.0);
+    assert_eq!(
+        panels[2]["fieldConfig"]["defaults"]["custom"]["thresholdsStyle"]["mode"],
+        "off"
+    );
+    assert_eq!(
+        dashboard["annotations"]["list"][0]["target"]["tags"][0],
+        grafana::ANNOTATION_TAG
+    );
+}
+
+#[tokio::test]
+async fn service_starts_and_stops_are_annotated() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let grafana = url.trim_end_matches("/metrics");
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(Memory::new()))
+            .annotations(GrafanaAnnotations::new(grafana, "token"))
+            .build()
+            .unwrap(),
+    );
+    let running = tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+
+    let started = next_request(&mut requests).await;
+    assert_eq!(started.path, "/api/annotations");
+    assert_eq!(started.headers["authorization"], "Bearer token");
+    let annotation: serde_json::Value = serde_json::from_str(&started.body).unwrap();
+    assert_eq!(annotation["text"], "Monitoring started");
+    assert_eq!(
+        annotation["tags"],
+        serde_json::json!([grafana::ANNOTATION_TAG, "service"])
+    );
+
+    service.shutdown();
+    let stopping = next_request(&mut requests).await;
+    assert!(stopping.body.contains("Monitoring stopping"));
+    tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn dry_runs_annotate_nothing() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let annotations = GrafanaAnnotations::new(url.trim_end_matches("/metrics"), "token").dry_run();
+
+    annotations.post(&Event::Started).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(requests.try_recv().is_err());
+}
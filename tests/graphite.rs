@@ -0,0 +1,505 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use common::{next_request, sensors, spawn_server, spawn_slow_server};
+use hyper::StatusCode;
+use monitoring::{
+    dns::{self, CachingResolver, IpFamily},
+    error::{ConfigError, SinkError},
+    identity::{HostLabel, Identity},
+    ratelimit::{RateLimit, RateLimiter},
+    sinks::{Auth, DryRun, Encoding, Graphite, HttpClientConfig, Precision, Sink},
+    Datapoint,
+};
+use reqwest::dns::Resolve;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+fn datapoints() -> Vec<Datapoint> {
+    vec![
+        Datapoint {
+            name: "kitchen.temperature".to_string(),
+            interval: 900,
+            value: 21.5,
+            time: 1_700_000_000,
+        },
+        Datapoint {
+            name: "kitchen.humidity".to_string(),
+            interval: 900,
+            value: 40.25,
+            time: 1_700_000_000,
+        },
+    ]
+}
+
+#[tokio::test]
+async fn posts_datapoints_as_graphite_json() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let sink = Graphite::new(url, "secret");
+
+    sink.write(&datapoints()).await.unwrap();
+
+    let request = next_request(&mut requests).await;
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/metrics");
+    assert_eq!(request.headers["authorization"], "Bearer secret");
+    assert_eq!(request.headers["content-type"], "application/json");
+
+    let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!([
+            {"name": "kitchen.temperature", "interval": 900, "value": 21.5, "time": 1_700_000_000},
+            {"name": "kitchen.humidity", "interval": 900, "value": 40.25, "time": 1_700_000_000},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn rejected_credentials_are_not_retryable() {
+    for status in [StatusCode::UNAUTHORIZED, StatusCode::FORBIDDEN] {
+        let (url, _requests) = spawn_server(status);
+        let err = Graphite::new(url, "wrong")
+            .write(&datapoints())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, SinkError::Unauthorized(_)));
+        assert!(!err.is_retryable());
+    }
+}
+
+#[tokio::test]
+async fn bad_request_carries_the_response_body() {
+    let (url, _requests) = spawn_server(StatusCode::BAD_REQUEST);
+    let err = Graphite::new(url, "secret")
+        .write(&datapoints())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(&err, SinkError::BadRequest(body) if body == "rejected"));
+    assert!(!err.is_retryable());
+}
+
+#[tokio::test]
+async fn server_errors_are_retryable() {
+    let (url, _requests) = spawn_server(StatusCode::SERVICE_UNAVAILABLE);
+    let err = Graphite::new(url, "secret")
+        .write(&datapoints())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(
+        err,
+        SinkError::Status(StatusCode::SERVICE_UNAVAILABLE)
+    ));
+    assert!(err.is_retryable());
+}
+
+#[tokio::test]
+async fn unreachable_endpoints_are_retryable() {
+    let err = Graphite::new("http://127.0.0.1:1/metrics", "secret")
+        .write(&datapoints())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, SinkError::Request(_)));
+    assert!(err.is_retryable());
+}
+
+#[test]
+fn missing_or_empty_ca_certs_fail_the_client_setup() {
+    let missing = HttpClientConfig {
+        ca_cert: Some("/nonexistent/ca.pem".into()),
+        ..HttpClientConfig::default()
+    };
+    assert!(matches!(missing.build(), Err(ConfigError::NotFound { .. })));
+
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("empty-ca.pem");
+    std::fs::write(&path, "not a certificate\n").unwrap();
+    let empty = HttpClientConfig {
+        ca_cert: Some(path),
+        ..HttpClientConfig::default()
+    };
+    assert!(matches!(empty.build(), Err(ConfigError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn uploads_go_through_the_proxy() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let client = HttpClientConfig {
+        proxy: Some(url.trim_end_matches("/metrics").to_string()),
+        ..HttpClientConfig::default()
+    }
+    .build()
+    .unwrap();
+    let sink = Graphite::with_client("http://graphite.example/metrics", "secret", client);
+
+    sink.write(&datapoints()).await.unwrap();
+
+    let request = next_request(&mut requests).await;
+    assert_eq!(request.path, "/metrics");
+    assert_eq!(request.headers["host"], "graphite.example");
+}
+
+#[tokio::test]
+async fn oversized_batches_are_split_into_several_posts() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let sink = Graphite::new(url, "secret").max_payload_bytes(300);
+    let datapoints = (0..10)
+        .map(|minute| Datapoint {
+            name: "kitchen.temperature".to_string(),
+            interval: 60,
+            value: 21.5,
+            time: 1_700_000_000 + minute * 60,
+        })
+        .collect::<Vec<_>>();
+
+    sink.write(&datapoints).await.unwrap();
+
+    let mut posted = Vec::new();
+    let mut posts = 0;
+    while let Ok(request) = requests.try_recv() {
+        assert!(request.body.len() <= 300);
+        posted.extend(serde_json::from_str::<Vec<Datapoint>>(&request.body).unwrap());
+        posts += 1;
+    }
+    assert!(posts > 1);
+    assert_eq!(posted.len(), datapoints.len());
+    assert_eq!(posted.last().unwrap().time, datapoints.last().unwrap().time);
+}
+
+#[tokio::test]
+async fn chunks_of_a_batch_are_posted_in_parallel() {
+    let (url, mut requests, concurrency) =
+        spawn_slow_server(StatusCode::OK, Duration::from_millis(100));
+    let sink = Graphite::new(url, "secret")
+        .max_chunk_datapoints(5)
+        .upload_concurrency(4);
+    let datapoints = (0..40)
+        .map(|sensor| Datapoint {
+            name: format!("sensor{}.temperature", sensor),
+            interval: 60,
+            value: 21.5,
+            time: 1_700_000_000,
+        })
+        .collect::<Vec<_>>();
+
+    sink.write(&datapoints).await.unwrap();
+    let mut posts = 0;
+    while requests.try_recv().is_ok() {
+        posts += 1;
+    }
+    assert_eq!(posts, 8);
+    assert!((2..=4).contains(&concurrency.most()));
+}
+
+#[tokio::test]
+async fn posts_wait_for_the_shared_rate_limit() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let limiter = Arc::new(RateLimiter::new("10/s".parse().unwrap()));
+    // Split into a post per datapoint
+    let sink = Graphite::new(url, "secret")
+        .max_payload_bytes(1)
+        .rate_limiter(limiter.clone());
+
+    let start = Instant::now();
+    sink.write(&datapoints()).await.unwrap();
+    limiter.acquire().await;
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(requests.try_recv().is_ok() && requests.try_recv().is_ok());
+
+    assert_eq!(
+        "300/min".parse::<RateLimit>().unwrap().per,
+        Duration::from_secs(60)
+    );
+    assert!("0/s".parse::<RateLimit>().is_err());
+    assert!("10/day".parse::<RateLimit>().is_err());
+}
+
+#[tokio::test]
+async fn endpoint_lookups_are_cached_and_their_failures_counted() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let config = HttpClientConfig {
+        dns_cache_ttl: Some(Duration::from_secs(300)),
+        ..HttpClientConfig::default()
+    };
+    let url = url.replace("127.0.0.1", "localhost");
+    let sink = Graphite::with_client(url, "secret", config.build().unwrap());
+    sink.write(&datapoints()).await.unwrap();
+    assert!(requests.try_recv().is_ok());
+
+    let resolver = CachingResolver::new(Duration::ZERO);
+    let resolve = |host: &str| resolver.resolve(host.parse().unwrap());
+    assert!(resolve("localhost").await.unwrap().next().is_some());
+    assert!(resolve("nonexistent.invalid").await.is_err());
+    assert_eq!(resolver.failures(), 1);
+}
+
+/// A DNS server answering every A query with 127.0.0.1 and every AAAA query with ::1
+async fn spawn_dns_server() -> std::net::SocketAddr {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut query = [0; 512];
+        loop {
+            let (len, from) = socket.recv_from(&mut query).await.unwrap();
+            let question = &query[12..len];
+            let kind =
+                u16::from_be_bytes([question[question.len() - 4], question[question.len() - 3]]);
+            let data = match kind {
+                1 => vec![127, 0, 0, 1],
+                _ => std::net::Ipv6Addr::LOCALHOST.octets().to_vec(),
+            };
+
+            let mut response = query[..2].to_vec();
+            response.extend([0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0]);
+            response.extend(question);
+            response.extend([0xc0, 12]);
+            response.extend(kind.to_be_bytes());
+            response.extend([0, 1, 0, 0, 0, 60, 0, data.len() as u8]);
+            response.extend(data);
+            socket.send_to(&response, from).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn the_endpoint_can_be_looked_up_with_another_dns_server_for_one_ip_family() {
+    let server = spawn_dns_server().await;
+    let resolve = |family: IpFamily| {
+        let resolver = CachingResolver::new(Duration::ZERO)
+            .family(family)
+            .server(server);
+        async move {
+            resolver
+                .resolve("graphite.example".parse().unwrap())
+                .await
+                .unwrap()
+                .map(|addr| addr.ip().to_string())
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(resolve(IpFamily::Ipv4).await, ["127.0.0.1"]);
+    assert_eq!(resolve(IpFamily::Ipv6).await, ["::1"]);
+    assert_eq!(resolve(IpFamily::Any).await, ["127.0.0.1", "::1"]);
+
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let config = HttpClientConfig {
+        ip_family: IpFamily::Ipv4,
+        dns_server: Some(server),
+        ..HttpClientConfig::default()
+    };
+    let url = url.replace("127.0.0.1", "graphite.example");
+    let sink = Graphite::with_client(url, "secret", config.build().unwrap());
+    sink.write(&datapoints()).await.unwrap();
+    assert!(requests.try_recv().is_ok());
+
+    assert_eq!(
+        dns::parse_server("1.1.1.1").unwrap().to_string(),
+        "1.1.1.1:53"
+    );
+    assert_eq!(
+        dns::parse_server("[::1]:5353").unwrap().to_string(),
+        "[::1]:5353"
+    );
+    assert!(dns::parse_server("dns.google").is_err());
+}
+
+#[tokio::test]
+async fn posts_authenticate_with_basic_auth_a_custom_header_or_nothing() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let basic = Auth::Basic {
+        username: "123456".to_string(),
+        password: "secret".to_string(),
+    };
+    let header = Auth::Header {
+        name: "x-api-key".parse().unwrap(),
+        value: "secret".to_string(),
+    };
+    for auth in [basic, header, Auth::None] {
+        let sink = Graphite::new(url.clone(), "unused").auth(auth.clone());
+        sink.write(&datapoints()).await.unwrap();
+
+        let request = next_request(&mut requests).await;
+        let authorization = request
+            .headers
+            .get("authorization")
+            .map(|value| value.to_str().unwrap());
+        match auth {
+            // base64 of 123456:secret
+            Auth::Basic { .. } => assert_eq!(authorization, Some("Basic MTIzNDU2OnNlY3JldA==")),
+            Auth::Header { .. } => {
+                assert_eq!(authorization, None);
+                assert_eq!(request.headers["x-api-key"], "secret");
+            }
+            _ => assert_eq!(authorization, None),
+        }
+    }
+}
+
+#[tokio::test]
+async fn batches_can_be_encoded_for_custom_receivers() {
+    for (encoding, content_type) in [
+        (Encoding::MessagePack, "application/msgpack"),
+        (Encoding::Cbor, "application/cbor"),
+    ] {
+        let (url, mut requests) = spawn_server(StatusCode::OK);
+        let sink = Graphite::new(url, "secret").encoding(encoding);
+
+        sink.write(&datapoints()).await.unwrap();
+
+        let request = next_request(&mut requests).await;
+        assert_eq!(request.headers["content-type"], content_type);
+        let decoded: Vec<Datapoint> = match encoding {
+            Encoding::MessagePack => rmp_serde::from_slice(&request.bytes).unwrap(),
+            _ => ciborium::from_reader(request.bytes.as_slice()).unwrap(),
+        };
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].name, "kitchen.humidity");
+        assert_eq!(decoded[1].value, 40.25);
+        assert!(request.bytes.len() < serde_json::to_vec(&datapoints()).unwrap().len());
+    }
+}
+
+#[test]
+fn encoded_lengths_match_the_encoded_bodies() {
+    for encoding in [Encoding::Json, Encoding::MessagePack, Encoding::Cbor] {
+        let datapoints = datapoints();
+        let body = encoding.encode(&datapoints).unwrap();
+        assert_eq!(encoding.encoded_len(&datapoints).unwrap(), body.len());
+        assert_eq!(
+            encoding.encoded_len(&datapoints[0]).unwrap(),
+            encoding.encode(&datapoints[0]).unwrap().len()
+        );
+    }
+}
+
+#[tokio::test]
+async fn dry_runs_post_nothing() {
+    let (url, mut requests) = spawn_server(StatusCode::INTERNAL_SERVER_ERROR);
+    for dry_run in [DryRun::Pretty, DryRun::Raw] {
+        let sink = Graphite::new(url.clone(), "secret")
+            .encoding(Encoding::Cbor)
+            .dry_run(dry_run);
+
+        sink.write(&datapoints()).await.unwrap();
+    }
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(requests.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn timestamps_are_written_in_the_sinks_precision() {
+    for (precision, time) in [
+        (Precision::Seconds, 1_700_000_000),
+        (Precision::Milliseconds, 1_700_000_000_000),
+        (Precision::Nanoseconds, 1_700_000_000_000_000_000),
+    ] {
+        let (url, mut requests) = spawn_server(StatusCode::OK);
+        let sink = Graphite::new(url, "secret").precision(precision);
+
+        sink.write(&datapoints()).await.unwrap();
+
+        let request = next_request(&mut requests).await;
+        let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+        assert!(datapoints.iter().all(|datapoint| datapoint.time == time));
+    }
+}
+
+#[tokio::test]
+async fn series_are_labelled_with_the_pi_they_come_from() {
+    for (label, serial, name) in [
+        (HostLabel::Prefix, None, "pi-attic.kitchen.temperature"),
+        (
+            HostLabel::Prefix,
+            Some("10000000abcdef12"),
+            "pi-attic.10000000abcdef12.kitchen.temperature",
+        ),
+        (HostLabel::Tag, None, "kitchen.temperature;host=pi-attic"),
+        (
+            HostLabel::Tag,
+            Some("10000000abcdef12"),
+            "kitchen.temperature;host=pi-attic;serial=10000000abcdef12",
+        ),
+    ] {
+        let (url, mut requests) = spawn_server(StatusCode::OK);
+        let sink = Graphite::new(url, "secret").identity(Identity {
+            label,
+            hostname: "pi-attic".to_string(),
+            serial: serial.map(str::to_string),
+        });
+
+        sink.write(&datapoints()).await.unwrap();
+
+        let request = next_request(&mut requests).await;
+        let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(datapoints[0].name, name);
+    }
+}
+
+#[tokio::test]
+async fn sensor_tags_are_added_to_their_series_names() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let tagged = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  tags:\n    floor: ground\n    zone: north\n",
+        "- name: kitchen_window\n  pin: 5\n",
+    ));
+    let sink = Graphite::new(url, "secret")
+        .sensor_tags(&tagged)
+        .identity(Identity {
+            label: HostLabel::Tag,
+            hostname: "pi-attic".to_string(),
+            serial: None,
+        });
+    let mut readings = datapoints();
+    readings[1].name = "kitchen_window.humidity".to_string();
+
+    sink.write(&readings).await.unwrap();
+
+    let request = next_request(&mut requests).await;
+    let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(
+        datapoints[0].name,
+        "kitchen.temperature;floor=ground;zone=north;host=pi-attic"
+    );
+    assert_eq!(datapoints[1].name, "kitchen_window.humidity;host=pi-attic");
+
+    let invalid = sensors("- name: kitchen\n  pin: 4\n  tags:\n    floor: ground;1\n");
+    assert!(monitoring::config::validate(&invalid).is_err());
+}
+
+#[tokio::test]
+async fn metadata_tags_label_series_with_their_type_and_unit() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let configured = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n  units:\n    humidity: '%RH'\n",
+        "- name: co2\n  type: command\n  command: [scd30]\n  tags:\n    type: scd30\n  units:\n    co2: ppm\n",
+    ));
+    let sink = Graphite::new(url, "secret")
+        .sensor_tags(&configured)
+        .metadata_tags(&configured);
+    let mut readings = datapoints();
+    readings.push(Datapoint {
+        name: "co2.co2".to_string(),
+        interval: 900,
+        value: 612.0,
+        time: 1_700_000_000,
+    });
+
+    sink.write(&readings).await.unwrap();
+
+    let request = next_request(&mut requests).await;
+    let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(datapoints[0].name, "kitchen.temperature;type=dht22;unit=°C");
+    assert_eq!(datapoints[1].name, "kitchen.humidity;type=dht22;unit=%RH");
+    assert_eq!(datapoints[2].name, "co2.co2;type=scd30;unit=ppm");
+
+    let invalid = sensors("- name: kitchen\n  pin: 4\n  units:\n    humidity: '%;RH'\n");
+    assert!(monitoring::config::validate(&invalid).is_err());
+}
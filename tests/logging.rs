@@ -0,0 +1,104 @@
+mod common;
+
+#[cfg(feature = "http")]
+use common::{next_request, spawn_server};
+#[cfg(feature = "http")]
+use hyper::StatusCode;
+#[cfg(feature = "http")]
+use monitoring::logging::Loki;
+use monitoring::logging::{RotatingFile, Syslog};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+#[test]
+fn log_files_are_rotated_once_full() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("rotated-logs");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("monitoring.log");
+
+    let mut log = RotatingFile::open(&path, 20, 2, false).unwrap();
+    for line in [
+        "first line\n",
+        "second line\n",
+        "third line\n",
+        "fourth line\n",
+    ] {
+        log.write_all(line.as_bytes()).unwrap();
+    }
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("monitoring.log"), "fourth line\n");
+    assert_eq!(read("monitoring.log.1"), "third line\n");
+    assert_eq!(read("monitoring.log.2"), "second line\n");
+    assert!(!dir.join("monitoring.log.3").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn syslog_messages_carry_the_event_severity() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("syslog.sock");
+    let _ = std::fs::remove_file(&path);
+    let daemon = UnixDatagram::bind(&path).unwrap();
+
+    let subscriber = tracing_subscriber::fmt()
+        .without_time()
+        .with_level(false)
+        .with_target(false)
+        .with_writer(Syslog::connect(&path).unwrap())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::warn!("Sensor greenhouse failed");
+        tracing::info!("Wrote 2 datapoints");
+    });
+
+    let received = || {
+        let mut message = [0; 256];
+        let len = daemon.recv(&mut message).unwrap();
+        String::from_utf8_lossy(&message[..len]).into_owned()
+    };
+    let pid = std::process::id();
+    assert_eq!(
+        received(),
+        format!("<28>monitoring[{}]: Sensor greenhouse failed", pid)
+    );
+    assert_eq!(
+        received(),
+        format!("<30>monitoring[{}]: Wrote 2 datapoints", pid)
+    );
+}
+
+#[cfg(feature = "http")]
+#[tokio::test]
+async fn loki_gets_a_stream_per_level() {
+    let (url, mut requests) = spawn_server(StatusCode::NO_CONTENT);
+    let loki = Loki::new(url.trim_end_matches("/metrics")).basic_auth("123456", "secret");
+
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(loki.clone())
+        .finish();
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::warn!(sensor = "greenhouse", "Read failed");
+        tracing::info!("Wrote 2 datapoints");
+    });
+    loki.flush().await.unwrap();
+
+    let request = next_request(&mut requests).await;
+    assert_eq!(request.path, "/loki/api/v1/push");
+    assert!(request.headers.contains_key("authorization"));
+    let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+    let streams = body["streams"].as_array().unwrap();
+    assert_eq!(streams.len(), 2);
+    assert_eq!(streams[0]["stream"]["level"], "info");
+    assert_eq!(streams[1]["stream"]["level"], "warn");
+    assert_eq!(streams[1]["stream"]["service_name"], "monitoring");
+    let line: serde_json::Value =
+        serde_json::from_str(streams[1]["values"][0][1].as_str().unwrap()).unwrap();
+    assert_eq!(line["fields"]["sensor"], "greenhouse");
+
+    // Nothing's left to push
+    loki.flush().await.unwrap();
+    assert!(requests.try_recv().is_err());
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use common::sensors;
+use monitoring::{
+    config, error::SensorError, plugins, sensors::MockBackend, service::MonitorService,
+    sinks::Memory,
+};
+use std::{sync::Arc, time::Duration};
+
+fn plugin(script: &str) -> monitoring::config::Sensor {
+    let yaml = format!(
+        "- name: co2\n  type: command\n  command: [sh, -c, '{}']\n",
+        script.replace('\'', "''")
+    );
+    sensors(&yaml).remove(0)
+}
+
+#[tokio::test]
+async fn plugin_metrics_are_parsed_from_stdout() {
+    let sensor = plugin(r#"echo '{"co2": 612, "tvoc": 0.5}'"#);
+
+    let metrics = plugins::read(&sensor, Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        metrics,
+        vec![("co2".to_string(), 612.0), ("tvoc".to_string(), 0.5)]
+    );
+}
+
+#[tokio::test]
+async fn plugins_know_which_sensor_they_read() {
+    let sensor = plugin(r#"echo "{\"$MONITORING_SENSOR\": 1}""#);
+
+    let metrics = plugins::read(&sensor, Duration::from_secs(5))
+        .await
+        .unwrap();
+
+    assert_eq!(metrics, vec![("co2".to_string(), 1.0)]);
+}
+
+#[tokio::test]
+async fn failing_plugins_report_their_stderr() {
+    let sensor = plugin("echo 'sensor unplugged' >&2; exit 1");
+
+    let err = plugins::read(&sensor, Duration::from_secs(5))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(&err, SensorError::Plugin(message) if message.contains("sensor unplugged")));
+}
+
+#[tokio::test]
+async fn slow_plugins_time_out() {
+    let sensor = plugin("sleep 5");
+
+    let err = plugins::read(&sensor, Duration::from_millis(200))
+        .await
+        .unwrap_err();
+
+    assert!(matches!(err, SensorError::Timeout));
+}
+
+#[tokio::test]
+async fn reading_hooks_get_the_reading_on_stdin() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("hooks");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("reading.json");
+
+    let yaml = format!(
+        "- name: kitchen\n  pin: 4\n  on_reading: [sh, -c, 'cat > {}']\n",
+        out.display()
+    );
+    let service = MonitorService::builder()
+        .sensors(sensors(&yaml))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(Arc::new(Memory::new()))
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+    let read = async {
+        readings.recv().await.unwrap();
+        // The hook runs in the background
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        service.shutdown();
+    };
+    let (result, _) = tokio::join!(service.run(), read);
+    result.unwrap();
+
+    let event: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&out).unwrap()).unwrap();
+    assert_eq!(event["event"], "reading");
+    assert_eq!(event["sensor"], "kitchen");
+    assert!(event["values"]["temperature"].is_number());
+    assert!(config::validate(&sensors("- name: kitchen\n  pin: 4\n  on_alert: []\n")).is_err());
+}
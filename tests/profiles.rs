@@ -0,0 +1,132 @@
+mod common;
+
+use monitoring::{
+    profiles::{self, Profile},
+    sensors::{HardwareAccess, MockBackend},
+    service::MonitorService,
+    sinks::Memory,
+};
+use std::{path::Path, sync::Arc, time::Duration};
+
+fn profiles(yaml: &str) -> Vec<Profile> {
+    serde_yaml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn profiles_are_loaded_and_kept_apart() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("profiles.yaml");
+    std::fs::write(
+        &path,
+        concat!(
+            "- name: alice\n  endpoint: https://alice.example/metrics\n  apikey: a\n",
+            "  listen: 127.0.0.1:8081\n  sensors:\n    - name: living_room\n      pin: 4\n",
+            "- name: bob\n  apikey: b\n  refresh_time: 5m\n",
+            "  sensors:\n    - name: garage\n      pin: 17\n      power_pin: 27\n",
+        ),
+    )
+    .unwrap();
+    let loaded = profiles::load(&path).unwrap();
+    assert_eq!(loaded.len(), 2);
+    assert_eq!(loaded[0].name, "alice");
+    assert_eq!(loaded[0].sensors[0].name, "living_room");
+    assert_eq!(loaded[1].endpoint, None);
+    assert_eq!(loaded[1].refresh_time, Some("5m".parse().unwrap()));
+
+    let invalid = [
+        // The same pin in both
+        "- name: alice\n  sensors: [{name: a, pin: 4}]\n- name: bob\n  sensors: [{name: b, pin: 5, power_pin: 4}]\n",
+        "- name: alice\n  sensors: []\n- name: alice\n  sensors: []\n",
+        "- name: al ice\n  sensors: []\n",
+        "- name: alice\n  listen: 127.0.0.1:8081\n  sensors: []\n- name: bob\n  listen: 127.0.0.1:8081\n  sensors: []\n",
+        "- name: alice\n  spool_dir: /var/spool/m\n  sensors: []\n- name: bob\n  spool_dir: /var/spool/m\n  sensors: []\n",
+        "- name: alice\n  sensors: [{name: a}]\n",
+        "[]",
+    ];
+    for yaml in invalid {
+        assert!(profiles::validate(&profiles(yaml)).is_err(), "{}", yaml);
+    }
+    let err = profiles::validate(&profiles(invalid[0])).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("GPIO 4 is used by both profiles alice and bob"),
+        "{}",
+        err
+    );
+
+    // A disabled sensor doesn't hold on to its pin
+    assert!(profiles::validate(&profiles(concat!(
+        "- name: alice\n  sensors: [{name: a, pin: 4, disabled: true}]\n",
+        "- name: bob\n  sensors: [{name: b, pin: 4}]\n",
+    )))
+    .is_ok());
+}
+
+#[tokio::test]
+async fn a_profiles_changed_sensors_are_written_to_its_entry() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("profiles-saved.yaml");
+    std::fs::write(
+        &path,
+        concat!(
+            "- name: alice\n  apikey: a\n  sensors:\n    - name: living_room\n      pin: 4\n",
+            "- name: bob\n  apikey: b\n  sensors:\n    - name: garage\n      pin: 17\n",
+        ),
+    )
+    .unwrap();
+
+    let mut sensors = profiles::load(&path).unwrap()[1].sensors.clone();
+    sensors.push(common::sensors("- name: shed\n  pin: 22\n").remove(0));
+    profiles::save_sensors(&path, "bob", &sensors)
+        .await
+        .unwrap();
+    let saved = profiles::load(&path).unwrap();
+    assert_eq!(saved[0].sensors.len(), 1);
+    assert_eq!(saved[0].apikey.as_deref(), Some("a"));
+    assert_eq!(saved[1].sensors[1].name, "shed");
+
+    // Alice's pin can't be taken from another profile's API
+    sensors.push(common::sensors("- name: cellar\n  pin: 4\n").remove(0));
+    assert!(profiles::save_sensors(&path, "bob", &sensors)
+        .await
+        .is_err());
+    assert_eq!(profiles::load(&path).unwrap()[1].sensors.len(), 2);
+}
+
+#[tokio::test]
+async fn profiles_sharing_the_hardware_write_to_their_own_sinks() {
+    let access = Arc::new(HardwareAccess::new(Some(1)));
+    let backend = Arc::new(MockBackend::new());
+    let services = [
+        "- name: living_room\n  pin: 4\n  interval: 1\n  min_interval: 1\n",
+        "- name: garage\n  pin: 17\n  interval: 1\n  min_interval: 1\n",
+    ]
+    .map(|yaml| {
+        let sink = Arc::new(Memory::new());
+        let service = MonitorService::builder()
+            .sensors(common::sensors(yaml))
+            .backend(backend.clone())
+            .sink(sink.clone())
+            .hardware_access(access.clone())
+            .info_metric(false)
+            .heartbeat(false)
+            .build()
+            .unwrap();
+        (Arc::new(service), sink)
+    });
+    for (service, _) in &services {
+        tokio::spawn({
+            let service = service.clone();
+            async move { service.run().await }
+        });
+    }
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    for (service, _) in &services {
+        service.shutdown();
+    }
+
+    let alice = services[0].1.take();
+    let bob = services[1].1.take();
+    assert!(!alice.is_empty() && !bob.is_empty());
+    assert!(alice.iter().all(|d| d.name.starts_with("living_room.")));
+    assert!(bob.iter().all(|d| d.name.starts_with("garage.")));
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::sensors;
+use monitoring::pulse::metrics;
+use std::time::Duration;
+
+#[test]
+fn pulses_are_reported_as_amounts_rates_and_totals() {
+    let rain = &sensors(
+        "- name: gauge\n  type: pulse\n  pin: 6\n  metric: rain\n  pulses_per_unit: 5\n  rate_per: 1h\n",
+    )[0];
+
+    assert_eq!(
+        metrics(rain, 10, 25, Duration::from_secs(900)),
+        vec![
+            ("rain".to_string(), 2.0),
+            ("rain.rate".to_string(), 8.0),
+            ("rain.total".to_string(), 5.0)
+        ]
+    );
+}
+
+#[test]
+fn the_first_reading_only_starts_the_count() {
+    let anemometer = &sensors("- name: anemometer\n  type: pulse\n  pin: 5\n")[0];
+
+    assert_eq!(
+        metrics(anemometer, 0, 0, Duration::ZERO),
+        vec![
+            ("pulses".to_string(), 0.0),
+            ("pulses.rate".to_string(), 0.0),
+            ("pulses.total".to_string(), 0.0)
+        ]
+    );
+    assert_eq!(
+        metrics(anemometer, 30, 30, Duration::from_secs(10))[1],
+        ("pulses.rate".to_string(), 3.0)
+    );
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::sensors;
+use monitoring::serial::parse_line;
+
+#[test]
+fn json_lines_map_metric_names_to_values() {
+    let sensor = &sensors("- name: pico\n  type: serial\n  device: /dev/ttyACM0\n")[0];
+
+    let metrics = parse_line(r#"{"temperature": 21.4, "humidity": 40}"#, sensor).unwrap();
+
+    assert_eq!(
+        metrics,
+        vec![
+            ("humidity".to_string(), 40.0),
+            ("temperature".to_string(), 21.4)
+        ]
+    );
+    assert!(parse_line(r#"ature": 21.4}"#, sensor).is_err());
+}
+
+#[test]
+fn csv_lines_are_named_by_the_fields() {
+    let sensor = &sensors(
+        "- name: esp\n  type: serial\n  device: /dev/ttyUSB0\n  format: csv\n  fields: [temperature, humidity]\n",
+    )[0];
+
+    let metrics = parse_line("21.4, 40.2", sensor).unwrap();
+
+    assert_eq!(
+        metrics,
+        vec![
+            ("temperature".to_string(), 21.4),
+            ("humidity".to_string(), 40.2)
+        ]
+    );
+    assert!(parse_line("21.4", sensor).is_err());
+    assert!(parse_line("21.4,n/a", sensor).is_err());
+}
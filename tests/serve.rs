@@ -0,0 +1,1319 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use common::{next_request, sensors, spawn_server};
+use futures::future::BoxFuture;
+use hyper::StatusCode;
+use monitoring::{
+    capture::{self, Recorder},
+    coarsen::Coarsened,
+    config::SinkKind,
+    error::{Error, SensorError, SinkError},
+    events::Event,
+    heartbeat,
+    interval::Interval,
+    pipeline::{self, DropPolicy},
+    routing::{Fanout, Route, Routed},
+    sensors::{Backend, MissedTicks, MockBackend, Reading},
+    service::MonitorService,
+    sinks::{Graphite, Memory, Sink},
+    spool::{Spool, SpoolKey},
+    state::SensorStatus,
+    trend::{self, Direction},
+    Datapoint,
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[tokio::test]
+async fn serve_cycle_posts_every_sensor_reading() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let backend = MockBackend::new();
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 21.5,
+            humidity: 40.0,
+        }),
+    );
+
+    let sensors = sensors("- name: kitchen\n  pin: 4\n");
+    let serve = tokio::spawn(pipeline::run(
+        sensors,
+        Interval::from_secs(60),
+        Arc::new(backend),
+        Arc::new(Graphite::new(url, "secret")),
+    ));
+
+    let request = next_request(&mut requests).await;
+    serve.abort();
+
+    let datapoints: Vec<Datapoint> = serde_json::from_str(&request.body).unwrap();
+    assert_eq!(datapoints.len(), 2);
+    assert_eq!(datapoints[0].name, "kitchen.temperature");
+    assert_eq!(datapoints[0].value, 21.5);
+    assert_eq!(datapoints[0].interval, 60);
+    assert_eq!(datapoints[1].name, "kitchen.humidity");
+    assert_eq!(datapoints[1].value, 40.0);
+    assert_eq!(datapoints[0].time, datapoints[1].time);
+}
+
+#[tokio::test]
+async fn failed_reads_are_retried_until_they_succeed() {
+    let backend = MockBackend::new();
+    backend.push(4, Err(SensorError::Checksum));
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 19.0,
+            humidity: 55.0,
+        }),
+    );
+
+    let sink = Arc::new(Memory::new());
+    let serve = tokio::spawn(pipeline::run(
+        sensors("- name: attic\n  pin: 4\n"),
+        Interval::from_secs(60),
+        Arc::new(backend),
+        sink.clone(),
+    ));
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    serve.abort();
+
+    let datapoints = sink.take();
+    assert_eq!(datapoints.len(), 2);
+    assert_eq!(datapoints[0].name, "attic.temperature");
+    assert_eq!(datapoints[0].value, 19.0);
+}
+
+#[tokio::test]
+async fn implausible_readings_are_read_again() {
+    let backend = MockBackend::new();
+    for humidity in [1.5, 140.0, 47.0] {
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature: 21.0,
+                humidity,
+            }),
+        );
+    }
+    // Given up on after two rereads
+    for _ in 0..3 {
+        backend.push(
+            5,
+            Ok(Reading {
+                temperature: 21.0,
+                humidity: 2.0,
+            }),
+        );
+    }
+
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(concat!(
+            "- name: attic\n  pin: 4\n  retry_interval_ms: 100\n",
+            "- name: cellar\n  pin: 5\n  retry_interval_ms: 100\n",
+        )))
+        .backend(Arc::new(backend))
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let mut events = state.subscribe_events();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    running.abort();
+
+    let datapoints = sink.take();
+    let humidity = |name: &str| {
+        datapoints
+            .iter()
+            .filter(|datapoint| datapoint.name == name)
+            .map(|datapoint| datapoint.value)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(humidity("attic.humidity"), [47.0]);
+    assert!(humidity("cellar.humidity").is_empty());
+    let snapshot = state.snapshot();
+    assert_eq!(snapshot[0].failures, 0);
+    assert!(snapshot[0]
+        .last_error
+        .as_deref()
+        .is_some_and(|error| error.contains("implausible humidity of 140")));
+    assert_eq!(snapshot[1].failures, 1);
+    // Only the sensor given up on is reported as failing
+    let failing = std::iter::from_fn(|| events.try_recv().ok())
+        .filter_map(|event| match event {
+            Event::SensorFailing { sensor, .. } => Some(sensor),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(failing, ["cellar"]);
+}
+
+#[tokio::test]
+async fn retries_follow_the_sensors_pacing_and_attempt_limit() {
+    let backend = MockBackend::new();
+    backend.push(4, Err(SensorError::Checksum));
+    backend.push(4, Err(SensorError::Checksum));
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 19.0,
+            humidity: 55.0,
+        }),
+    );
+
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(
+            "- name: attic\n  pin: 4\n  retry_interval_ms: 100\n  max_attempts: 2\n",
+        ))
+        .backend(Arc::new(backend))
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    running.abort();
+
+    // Two attempts 100ms apart, and no third one until the next cycle
+    let attic = &state.snapshot()[0];
+    assert_eq!(attic.status, SensorStatus::Failing);
+    assert_eq!(attic.failures, 2);
+    assert!(sink.take().is_empty());
+}
+
+// Needs the GPIO stubs for the power pin
+#[cfg(not(all(feature = "gpio", target_os = "linux")))]
+#[tokio::test]
+async fn power_cycling_counts_the_failed_reads_across_cycles() {
+    let backend = MockBackend::new();
+    for _ in 0..3 {
+        backend.push(4, Err(SensorError::Checksum));
+    }
+
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(concat!(
+            "- name: attic\n  pin: 4\n  interval: 1\n  min_interval: 1\n  retry_interval_ms: 100\n",
+            "  max_attempts: 2\n  power_pin: 17\n  power_cycle_after: 3\n  warmup_secs: 1ms\n",
+        )))
+        .backend(Arc::new(backend))
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    // Two failed attempts in the first cycle, and the third in the next one power cycles it
+    tokio::time::sleep(Duration::from_millis(3600)).await;
+    running.abort();
+
+    let attic = &state.snapshot()[0];
+    assert_eq!(attic.power_cycles, 1);
+    assert_eq!(attic.status, SensorStatus::Ok);
+}
+
+#[tokio::test]
+async fn sensors_use_their_own_interval() {
+    let sink = Arc::new(Memory::new());
+    let serve = tokio::spawn(pipeline::run(
+        sensors(
+            "- name: fast\n  pin: 4\n  interval: 1\n  min_interval: 1\n- name: slow\n  pin: 5\n",
+        ),
+        Interval::from_secs(3600),
+        Arc::new(MockBackend::new()),
+        sink.clone(),
+    ));
+
+    tokio::time::sleep(Duration::from_millis(2500)).await;
+    serve.abort();
+
+    let datapoints = sink.take();
+    let fast = datapoints.iter().filter(|d| d.name == "fast.temperature");
+    let slow = datapoints.iter().filter(|d| d.name == "slow.temperature");
+    assert!(fast.clone().count() >= 2);
+    assert_eq!(slow.clone().count(), 1);
+    assert!(fast.into_iter().all(|d| d.interval == 1));
+    assert!(slow.into_iter().all(|d| d.interval == 3600));
+}
+
+#[tokio::test]
+async fn sensors_can_be_sampled_more_than_once_a_second() {
+    let sink = Arc::new(Memory::new());
+    let serve = tokio::spawn(pipeline::run(
+        sensors("- name: fast\n  pin: 4\n  interval: 200ms\n  min_interval: 100ms\n"),
+        Interval::from_secs(60),
+        Arc::new(MockBackend::new()),
+        sink.clone(),
+    ));
+
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    serve.abort();
+
+    let fast = sink
+        .take()
+        .into_iter()
+        .filter(|d| d.name == "fast.temperature")
+        .collect::<Vec<_>>();
+    assert!(fast.len() >= 4, "{} readings", fast.len());
+    // Posted on whole seconds
+    assert!(fast.iter().all(|d| d.interval == 1));
+}
+
+#[tokio::test]
+async fn sink_errors_do_not_stop_the_service() {
+    let (url, mut requests) = spawn_server(StatusCode::INTERNAL_SERVER_ERROR);
+    let serve = tokio::spawn(pipeline::run(
+        sensors("- name: kitchen\n  pin: 4\n  interval: 1\n  min_interval: 1\n"),
+        Interval::from_secs(60),
+        Arc::new(MockBackend::new()),
+        Arc::new(Graphite::new(url, "secret")),
+    ));
+
+    next_request(&mut requests).await;
+    next_request(&mut requests).await;
+    assert!(!serve.is_finished());
+    serve.abort();
+}
+
+#[tokio::test]
+async fn embedded_service_publishes_readings_and_shuts_down() {
+    let sink = Arc::new(Memory::new());
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(sink.clone())
+            .interval(Duration::from_secs(60))
+            .build()
+            .unwrap(),
+    );
+    let mut readings = service.subscribe();
+
+    let running = tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+
+    let datapoints = tokio::time::timeout(Duration::from_secs(5), readings.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(datapoints.len(), 2);
+
+    service.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(sink.take().len(), 2);
+}
+
+#[tokio::test]
+async fn the_info_series_tracks_the_version_and_configuration() {
+    let sink = Arc::new(Memory::new());
+    let config = sensors("- name: kitchen\n  pin: 4\n");
+    let service = MonitorService::builder()
+        .sensors(config.clone())
+        .sink(sink.clone())
+        .info_metric(true)
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+    let info = async {
+        loop {
+            let batch = readings.recv().await.unwrap();
+            if let Some(datapoint) = batch
+                .iter()
+                .find(|datapoint| datapoint.name.starts_with("monitoring.info;"))
+            {
+                service.shutdown();
+                break datapoint.clone();
+            }
+        }
+    };
+    let (result, info) = tokio::join!(service.run(), info);
+    result.unwrap();
+
+    assert_eq!(
+        info.name,
+        format!(
+            "monitoring.info;version={};commit={};config={}",
+            env!("CARGO_PKG_VERSION"),
+            monitoring::info::GIT_COMMIT,
+            monitoring::info::config_hash(&config)
+        )
+    );
+    assert_eq!(info.value, 1.0);
+    assert_ne!(
+        monitoring::info::config_hash(&config),
+        monitoring::info::config_hash(&sensors("- name: kitchen\n  pin: 5\n"))
+    );
+}
+
+#[test]
+fn embedded_service_requires_a_sink() {
+    assert!(MonitorService::builder().build().is_err());
+}
+
+/// A sink that takes far longer to write than the sensors take to sample
+struct SlowSink;
+
+impl Sink for SlowSink {
+    fn write<'a>(&'a self, _readings: &'a [Datapoint]) -> BoxFuture<'a, Result<(), SinkError>> {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_secs(30)).await;
+            Ok(())
+        })
+    }
+}
+
+#[tokio::test]
+async fn slow_sinks_do_not_delay_sampling() {
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors(
+                "- name: kitchen\n  pin: 4\n  interval: 1\n  min_interval: 1\n",
+            ))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(Arc::new(SlowSink))
+            .queue_capacity(1)
+            .drop_policy(DropPolicy::Oldest)
+            .build()
+            .unwrap(),
+    );
+    let mut readings = service.subscribe();
+    let running = tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+
+    let mut times = Vec::new();
+    for _ in 0..3 {
+        let batch = tokio::time::timeout(Duration::from_secs(5), readings.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        times.push(batch[0].time);
+    }
+    running.abort();
+
+    assert!(times[2] - times[0] <= 3);
+}
+
+#[tokio::test]
+async fn recorded_reads_replay_through_the_pipeline() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("capture.jsonl");
+    let _ = std::fs::remove_file(&path);
+    let backend = MockBackend::new();
+    backend.push(4, Err(SensorError::Checksum));
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 19.0,
+            humidity: 55.0,
+        }),
+    );
+
+    let recording = MonitorService::builder()
+        .sensors(sensors("- name: attic\n  pin: 4\n"))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .record(Recorder::create(&path).unwrap())
+        .build()
+        .unwrap();
+    let mut readings = recording.subscribe();
+    let recorded = async {
+        readings.recv().await.unwrap();
+        recording.shutdown();
+    };
+    let (result, _) = tokio::join!(recording.run(), recorded);
+    result.unwrap();
+
+    let entries = capture::load(&path).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(
+        entries[0].error.as_deref(),
+        Some("checksum value of the reading is incorrect")
+    );
+    let time = entries[1].time;
+
+    let sink = Arc::new(Memory::new());
+    let replaying = MonitorService::builder()
+        .sensors(sensors("- name: attic\n  pin: 4\n"))
+        .sink(sink.clone())
+        .replay(entries)
+        .build()
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(10), replaying.run())
+        .await
+        .expect("Replay didn't finish")
+        .unwrap();
+
+    let mut datapoints = sink.take();
+    datapoints.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(datapoints.len(), 2);
+    assert_eq!(datapoints[0].name, "attic.humidity");
+    assert_eq!(datapoints[0].value, 55.0);
+    assert_eq!(datapoints[1].name, "attic.temperature");
+    assert_eq!(datapoints[1].time, time);
+    assert_eq!(replaying.state().snapshot()[0].failures, 0);
+}
+
+#[tokio::test]
+async fn failed_writes_are_spooled_and_backfilled_after_a_restart() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("spool");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let (url, _requests) = spawn_server(StatusCode::SERVICE_UNAVAILABLE);
+    let failing = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(Arc::new(Graphite::new(url, "secret")))
+        .spool(Spool::open(&dir).unwrap())
+        .build()
+        .unwrap();
+    let mut readings = failing.subscribe();
+    let read = async {
+        readings.recv().await.unwrap();
+        failing.shutdown();
+    };
+    let (result, _) = tokio::join!(failing.run(), read);
+    result.unwrap();
+
+    let spool = Spool::open(&dir).unwrap();
+    assert_eq!(spool.pending().unwrap().len(), 1);
+    assert_eq!(spool.last_sent(), None);
+
+    let sink = Arc::new(Memory::new());
+    let restarted = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(sink.clone())
+        .spool(Spool::open(&dir).unwrap())
+        .build()
+        .unwrap();
+    let mut readings = restarted.subscribe();
+    let read = async {
+        readings.recv().await.unwrap();
+        restarted.shutdown();
+    };
+    let (result, _) = tokio::join!(restarted.run(), read);
+    result.unwrap();
+
+    let datapoints = sink.take();
+    assert_eq!(datapoints.len(), 4);
+    assert!(spool.pending().unwrap().is_empty());
+    assert_eq!(
+        spool.last_sent(),
+        datapoints.iter().map(|datapoint| datapoint.time).max()
+    );
+}
+
+#[tokio::test]
+async fn the_heartbeat_is_written_while_the_sensors_fail() {
+    let backend = MockBackend::new();
+    backend.push(4, Err(SensorError::Timeout));
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n  max_attempts: 1\n"))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .heartbeat(true)
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+    let read = async {
+        let batch = readings.recv().await.unwrap();
+        let failing = async {
+            while service.state().snapshot()[0].status != SensorStatus::Failing {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), failing)
+            .await
+            .unwrap();
+        service.shutdown();
+        batch
+    };
+    let (result, batch) = tokio::join!(service.run(), read);
+    result.unwrap();
+
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0].name, heartbeat::HEARTBEAT_SERIES);
+    assert_eq!(batch[0].value, 1.0);
+    assert!(readings.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn an_impossible_first_reading_stops_a_strict_service() {
+    let backend = MockBackend::new();
+    // Still impossible once read again
+    for _ in 0..=monitoring::sensors::IMPLAUSIBLE_REREADS {
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature: 21.5,
+                humidity: 655.3,
+            }),
+        );
+    }
+    let service = MonitorService::builder()
+        .sensors(sensors(
+            "- name: kitchen\n  pin: 4\n  retry_interval_ms: 100\n",
+        ))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .strict(true)
+        .build()
+        .unwrap();
+    let mut events = service.state().subscribe_events();
+
+    let err = tokio::time::timeout(Duration::from_secs(5), service.run())
+        .await
+        .unwrap()
+        .unwrap_err();
+    assert!(err.to_string().contains("humidity 655.3"), "{}", err);
+    assert!(
+        std::iter::from_fn(|| events.try_recv().ok()).any(|event| matches!(
+            event,
+            Event::ImpossibleReading { sensor, .. } if sensor == "kitchen"
+        ))
+    );
+}
+
+#[test]
+fn the_oldest_spooled_batches_are_evicted_past_the_size_cap() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("spool-capped");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let batch = |time| {
+        vec![Datapoint {
+            name: "kitchen.temperature".to_string(),
+            interval: 10,
+            value: 21.5,
+            time,
+        }]
+    };
+    let spool = Spool::open(&dir).unwrap();
+    for time in [1_700_000_000, 1_700_000_010, 1_700_000_020] {
+        spool.push(&batch(time)).unwrap();
+    }
+    let pending = spool.pending().unwrap();
+    let size = std::fs::metadata(&pending[0]).unwrap().len();
+
+    let capped = Spool::open(&dir).unwrap().max_size(size * 2);
+    assert_eq!(capped.evict().unwrap(), 1);
+    assert_eq!(capped.evicted(), 1);
+    assert_eq!(capped.pending().unwrap(), pending[1..]);
+    assert_eq!(capped.load(&pending[1]).unwrap()[0].time, 1_700_000_010);
+}
+
+#[test]
+fn spooled_batches_are_encrypted_with_the_key() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("spool-encrypted");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("spool.key"),
+        "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("other.key"),
+        "ZmVkY2JhOTg3NjU0MzIxMGZlZGNiYTk4NzY1NDMyMTA=\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("short.key"), "c2hvcnQ=\n").unwrap();
+    let key = |name: &str| SpoolKey::load(&dir.join(name));
+    let batch = vec![Datapoint {
+        name: "kitchen.temperature".to_string(),
+        interval: 10,
+        value: 21.5,
+        time: 1_700_000_000,
+    }];
+
+    // Spooled before the key was set
+    Spool::open(dir.join("batches"))
+        .unwrap()
+        .push(&batch)
+        .unwrap();
+    let spool = Spool::open(dir.join("batches"))
+        .unwrap()
+        .encrypt(key("spool.key").unwrap());
+    spool.push(&batch).unwrap();
+
+    let pending = spool.pending().unwrap();
+    assert_eq!(pending.len(), 2);
+    let sealed = std::fs::read(&pending[1]).unwrap();
+    assert!(!String::from_utf8_lossy(&sealed).contains("kitchen"));
+    assert_eq!(spool.load(&pending[0]).unwrap()[0].value, 21.5);
+    assert_eq!(spool.load(&pending[1]).unwrap()[0].value, 21.5);
+
+    let without_key = Spool::open(dir.join("batches")).unwrap();
+    assert!(without_key.load(&pending[1]).is_err());
+    let other_key = Spool::open(dir.join("batches"))
+        .unwrap()
+        .encrypt(key("other.key").unwrap());
+    assert!(other_key.load(&pending[1]).is_err());
+    assert!(key("short.key").is_err());
+
+    // Without the key the service doesn't start, and under another key they're kept
+    assert!(without_key.check_key().is_err());
+    assert!(spool.check_key().is_ok());
+    let rotated = Spool::open(dir.join("batches"))
+        .unwrap()
+        .encrypt(key("other.key").unwrap())
+        .max_age(std::time::Duration::ZERO);
+    std::thread::sleep(std::time::Duration::from_millis(5));
+    assert_eq!(rotated.evict().unwrap(), 1);
+    assert_eq!(rotated.pending().unwrap(), pending[1..]);
+    assert_eq!(spool.load(&pending[1]).unwrap()[0].value, 21.5);
+}
+
+#[tokio::test]
+async fn sensor_state_is_picked_up_after_a_restart() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("state.json");
+    let _ = std::fs::remove_file(&path);
+    let config =
+        "- name: kitchen\n  pin: 4\n  alerts:\n    - metric: temperature\n      above: 25\n";
+    let too_hot = || {
+        let backend = MockBackend::new();
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature: 30.0,
+                humidity: 40.0,
+            }),
+        );
+        Arc::new(backend)
+    };
+
+    let mut alerts = Vec::new();
+    for _ in 0..2 {
+        let service = MonitorService::builder()
+            .sensors(sensors(config))
+            .backend(too_hot())
+            .sink(Arc::new(Memory::new()))
+            .state_file(&path)
+            .build()
+            .unwrap();
+        let mut events = service.state().subscribe_events();
+        let mut readings = service.subscribe();
+        let read = async {
+            readings.recv().await.unwrap();
+            service.shutdown();
+        };
+        let (result, _) = tokio::join!(service.run(), read);
+        result.unwrap();
+
+        let mut fired = 0;
+        while let Ok(event) = events.try_recv() {
+            fired += usize::from(matches!(event, Event::AlertFiring { .. }));
+        }
+        alerts.push(fired);
+    }
+    // Still firing from before the restart, so not fired again
+    assert_eq!(alerts, [1, 0]);
+
+    let restored = MonitorService::builder()
+        .sensors(sensors(config))
+        .sink(Arc::new(Memory::new()))
+        .state_file(&path)
+        .build()
+        .unwrap();
+    let sensor = &restored.state().snapshot()[0];
+    assert_eq!(sensor.status, SensorStatus::Pending);
+    assert_eq!(sensor.values["temperature"], 30.0);
+}
+
+#[test]
+fn batches_are_sorted_and_deduplicated_before_writing() {
+    let datapoint = |name: &str, time, value| Datapoint {
+        name: name.to_string(),
+        interval: 60,
+        value,
+        time,
+    };
+    let mut readings = vec![
+        datapoint("kitchen.temperature", 120, 21.0),
+        datapoint("kitchen.temperature", 60, 21.0),
+        datapoint("kitchen.humidity", 60, 40.0),
+        datapoint("kitchen.temperature", 60, 21.5),
+        datapoint("kitchen.temperature", 180, 21.0),
+    ];
+
+    pipeline::compact(&mut readings, false);
+    let compacted = readings
+        .iter()
+        .map(|datapoint| (datapoint.name.as_str(), datapoint.time, datapoint.value))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        compacted,
+        [
+            ("kitchen.temperature", 60, 21.5),
+            ("kitchen.humidity", 60, 40.0),
+            ("kitchen.temperature", 120, 21.0),
+            ("kitchen.temperature", 180, 21.0),
+        ]
+    );
+
+    pipeline::compact(&mut readings, true);
+    assert_eq!(readings.len(), 3);
+    assert_eq!(readings[2].time, 120);
+}
+
+/// A backend that takes a while to read, keeping track of how many reads overlapped
+#[derive(Default)]
+struct BusyBackend {
+    reading: AtomicUsize,
+    most: AtomicUsize,
+}
+
+impl Backend for BusyBackend {
+    fn read(&self, _pin: u8) -> Result<Reading, SensorError> {
+        let reading = self.reading.fetch_add(1, Ordering::SeqCst) + 1;
+        self.most.fetch_max(reading, Ordering::SeqCst);
+        std::thread::sleep(Duration::from_millis(200));
+        self.reading.fetch_sub(1, Ordering::SeqCst);
+
+        Ok(Reading {
+            temperature: 20.0,
+            humidity: 50.0,
+        })
+    }
+}
+
+#[tokio::test]
+async fn coarsened_metrics_are_rounded_and_held_back_for_their_sinks() {
+    let config = sensors(
+        "- name: kitchen\n  pin: 4\n  coarsen:\n    - metric: temperature\n      step: 0.5\n    - metric: humidity\n      decimals: 0\n      delay_secs: 3600\n      sinks: [endpoint]\n",
+    );
+    let datapoint = |name: &str, time, value| Datapoint {
+        name: name.to_string(),
+        interval: 60,
+        value,
+        time,
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let memory = Arc::new(Memory::new());
+    let endpoint = Coarsened::wrap(memory.clone(), &config, SinkKind::Endpoint);
+    endpoint
+        .write(&[
+            datapoint("kitchen.temperature", now, 21.3),
+            datapoint("kitchen.humidity", now, 41.6),
+        ])
+        .await
+        .unwrap();
+    let written = memory.take();
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0].value, 21.5);
+
+    // Released with the next batch once it's old enough
+    endpoint
+        .write(&[datapoint("kitchen.humidity", now - 7200, 40.2)])
+        .await
+        .unwrap();
+    let written = memory.take();
+    assert_eq!(written.len(), 1);
+    assert_eq!((written[0].time, written[0].value), (now - 7200, 40.0));
+
+    let socket = Coarsened::wrap(memory.clone(), &config, SinkKind::Socket);
+    socket
+        .write(&[datapoint("kitchen.humidity", now, 41.6)])
+        .await
+        .unwrap();
+    assert_eq!(memory.take()[0].value, 41.6);
+}
+
+#[tokio::test]
+async fn series_are_routed_to_the_sinks_taking_them() {
+    let datapoint = |name: &str| Datapoint {
+        name: name.to_string(),
+        interval: 60,
+        value: 21.0,
+        time: 1_700_000_000,
+    };
+    let (endpoint, socket) = (Arc::new(Memory::new()), Arc::new(Memory::new()));
+    let route = |include: &[&str], exclude: &[&str]| Route {
+        include: include
+            .iter()
+            .map(|selector| selector.parse().unwrap())
+            .collect(),
+        exclude: exclude
+            .iter()
+            .map(|selector| selector.parse().unwrap())
+            .collect(),
+    };
+    let sink = Fanout::new(
+        Routed::wrap(endpoint.clone(), route(&["greenhouse.*"], &["*.humidity"])),
+        vec![Routed::wrap(
+            socket.clone(),
+            route(&["*.cpu.temperature"], &[]),
+        )],
+    );
+
+    sink.write(&[
+        datapoint("greenhouse.bench.temperature"),
+        datapoint("greenhouse.bench.humidity"),
+        datapoint("pi.cpu.temperature"),
+        datapoint("kitchen.temperature"),
+    ])
+    .await
+    .unwrap();
+
+    let names = |sink: &Memory| {
+        sink.take()
+            .into_iter()
+            .map(|datapoint| datapoint.name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&endpoint), ["greenhouse.bench.temperature"]);
+    assert_eq!(names(&socket), ["pi.cpu.temperature"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn sensors_sharing_a_bus_are_read_one_at_a_time() {
+    for (config, most) in [
+        ("- name: a\n  pin: 4\n- name: b\n  pin: 5\n- name: c\n  pin: 6\n", 3),
+        (
+            "- name: a\n  pin: 4\n  bus: i2c-1\n- name: b\n  pin: 5\n  bus: i2c-1\n- name: c\n  pin: 6\n",
+            2,
+        ),
+    ] {
+        let backend = Arc::new(BusyBackend::default());
+        let sink = Arc::new(Memory::new());
+        let service = MonitorService::builder()
+            .sensors(sensors(config))
+            .backend(backend.clone())
+            .sink(sink.clone())
+            .build()
+            .unwrap();
+        let running = tokio::spawn(async move { service.run().await });
+
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        running.abort();
+
+        assert_eq!(sink.take().len(), 6);
+        assert_eq!(backend.most.load(Ordering::SeqCst), most);
+    }
+}
+
+/// A backend whose reads hang for a while
+struct WedgedBackend;
+
+impl Backend for WedgedBackend {
+    fn read(&self, _pin: u8) -> Result<Reading, SensorError> {
+        std::thread::sleep(Duration::from_secs(4));
+        Err(SensorError::Timeout)
+    }
+}
+
+#[tokio::test]
+async fn stuck_reads_are_abandoned_and_counted() {
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n  timeout_secs: 1\n"))
+        .backend(Arc::new(WedgedBackend))
+        .sink(Arc::new(Memory::new()))
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(3000)).await;
+    running.abort();
+
+    let kitchen = &state.snapshot()[0];
+    assert_eq!(kitchen.stuck_reads, 1);
+    assert_eq!(kitchen.failures, 2);
+    assert_eq!(
+        kitchen.last_error.as_deref(),
+        Some("an abandoned read of the sensor is still running")
+    );
+}
+
+/// A backend whose first read panics
+#[derive(Default)]
+struct PanickingBackend {
+    reads: AtomicUsize,
+}
+
+impl Backend for PanickingBackend {
+    fn read(&self, _pin: u8) -> Result<Reading, SensorError> {
+        if self.reads.fetch_add(1, Ordering::SeqCst) == 0 {
+            panic!("The sensor's driver gave up");
+        }
+        Ok(Reading {
+            temperature: 21.5,
+            humidity: 40.0,
+        })
+    }
+}
+
+#[tokio::test]
+async fn a_panicking_read_fails_without_wedging_the_pin() {
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(
+            "- name: kitchen\n  pin: 4\n  retry_interval_ms: 100\n",
+        ))
+        .backend(Arc::new(PanickingBackend::default()))
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    running.abort();
+
+    let kitchen = &state.snapshot()[0];
+    assert_eq!(kitchen.failures, 0);
+    assert_eq!(kitchen.stuck_reads, 0);
+    assert_eq!(sink.take().len(), 2);
+}
+
+#[tokio::test]
+async fn sensors_with_pull_up_enable_the_internal_resistor() {
+    let backend = Arc::new(MockBackend::new());
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(
+            "- name: kitchen\n  pin: 4\n  pull_up: true\n- name: attic\n  pin: 5\n",
+        ))
+        .backend(backend.clone())
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    running.abort();
+
+    assert_eq!(sink.take().len(), 4);
+    assert!(backend.is_pulled_up(4));
+    assert!(!backend.is_pulled_up(5));
+}
+
+#[tokio::test]
+async fn sensors_are_not_sampled_while_warming_up() {
+    let sink = Arc::new(Memory::new());
+    let service = MonitorService::builder()
+        .sensors(sensors(
+            "- name: office\n  pin: 4\n  warmup_secs: 1000000000\n- name: kitchen\n  pin: 5\n",
+        ))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(sink.clone())
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    running.abort();
+
+    let datapoints = sink.take();
+    assert_eq!(datapoints.len(), 2);
+    assert!(datapoints.iter().all(|d| d.name.starts_with("kitchen.")));
+    let snapshot = state.snapshot();
+    assert_eq!(snapshot[1].sensor, "office");
+    assert_eq!(snapshot[1].status, SensorStatus::WarmingUp);
+}
+
+#[tokio::test]
+async fn grouped_sensors_that_disagree_write_their_divergence() {
+    let backend = MockBackend::new();
+    for (pin, temperature) in [(4, 20.0), (5, 23.0)] {
+        backend.push(
+            pin,
+            Ok(Reading {
+                temperature,
+                humidity: 40.0,
+            }),
+        );
+    }
+    let service = MonitorService::builder()
+        .sensors(sensors(concat!(
+            "- name: kitchen1\n  pin: 4\n  group: kitchen\n  max_divergence: {temperature: 1.5}\n",
+            "- name: kitchen2\n  pin: 5\n  group: kitchen\n",
+        )))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .interval(Duration::from_secs(60))
+        .write_divergence(true)
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+
+    let divergence = async {
+        loop {
+            let batch = readings.recv().await.unwrap();
+            if let Some(datapoint) = batch
+                .into_iter()
+                .find(|datapoint| datapoint.name == "kitchen.temperature.divergence")
+            {
+                service.shutdown();
+                break datapoint.value;
+            }
+        }
+    };
+    let (divergence, _) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(divergence, service.run())
+    })
+    .await
+    .unwrap();
+    assert_eq!(divergence, 3.0);
+}
+
+#[tokio::test]
+async fn daily_extremes_track_the_lowest_and_highest_reading() {
+    let backend = MockBackend::new();
+    for temperature in [20.0, 18.0] {
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature,
+                humidity: 40.0,
+            }),
+        );
+    }
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n  interval: 2\n"))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .daily_extremes("utc".parse().unwrap())
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+
+    let extremes = async {
+        let mut batches = 0;
+        loop {
+            let batch = readings.recv().await.unwrap();
+            let value = |name: &str| {
+                batch
+                    .iter()
+                    .find(|datapoint| datapoint.name == name)
+                    .map(|datapoint| datapoint.value)
+            };
+            let extremes =
+                value("kitchen.temperature.daily_min").zip(value("kitchen.temperature.daily_max"));
+            if let Some(extremes) = extremes {
+                batches += 1;
+                if batches == 2 {
+                    service.shutdown();
+                    break extremes;
+                }
+            }
+        }
+    };
+    let (extremes, _) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(extremes, service.run())
+    })
+    .await
+    .unwrap();
+    assert_eq!(extremes, (18.0, 20.0));
+}
+
+#[tokio::test]
+async fn trends_tell_rising_readings_from_steady_ones() {
+    let backend = MockBackend::new();
+    for temperature in [20.0, 21.0] {
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature,
+                humidity: 40.0,
+            }),
+        );
+    }
+    let config = concat!(
+        "- name: kitchen\n  pin: 4\n  interval: 2\n",
+        "  trends:\n    - metric: temperature\n    - metric: humidity\n",
+    );
+    let service = MonitorService::builder()
+        .sensors(sensors(config))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+
+    let trends = async {
+        loop {
+            let batch = readings.recv().await.unwrap();
+            let value = |name: &str| {
+                batch
+                    .iter()
+                    .find(|datapoint| datapoint.name == name)
+                    .map(|datapoint| datapoint.value)
+            };
+            // None for the first reading, there's no telling where it's heading yet
+            if let Some(trends) =
+                value("kitchen.temperature.trend").zip(value("kitchen.humidity.trend"))
+            {
+                service.shutdown();
+                break trends;
+            }
+        }
+    };
+    let (trends, _) = tokio::time::timeout(Duration::from_secs(10), async {
+        tokio::join!(trends, service.run())
+    })
+    .await
+    .unwrap();
+    assert_eq!(trends, (1.0, 0.0));
+
+    // A degree down over an hour
+    let falling = [(0, 21.0), (1800, 20.6), (3600, 20.0)];
+    assert_eq!(trend::direction(&falling, 0.5), Some(Direction::Falling));
+    assert_eq!(trend::direction(&falling, 1.5), Some(Direction::Steady));
+    assert_eq!(trend::direction(&falling[..1], 0.5), None);
+}
+
+#[tokio::test]
+async fn shutting_down_during_the_startup_delay_skips_sampling() {
+    let sink = Arc::new(Memory::new());
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(MockBackend::new()))
+            .sink(sink.clone())
+            .startup_delay(Duration::from_secs(3600))
+            .build()
+            .unwrap(),
+    );
+    let running = tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    service.shutdown();
+    tokio::time::timeout(Duration::from_secs(5), running)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(sink.take().is_empty());
+    assert_eq!(service.state().snapshot()[0].status, SensorStatus::Pending);
+}
+
+#[tokio::test]
+async fn cycle_durations_are_recorded() {
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(Arc::new(Memory::new()))
+        .missed_ticks("skip".parse::<MissedTicks>().unwrap())
+        .build()
+        .unwrap();
+    let mut readings = service.subscribe();
+    let read = async {
+        readings.recv().await.unwrap();
+        service.shutdown();
+    };
+    let (_, result) = tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(read, service.run())
+    })
+    .await
+    .unwrap();
+    result.unwrap();
+
+    let kitchen = &service.state().snapshot()[0];
+    assert!(kitchen.cycle_secs.is_some_and(|secs| secs < 5.0));
+    assert!("catch-up".parse::<MissedTicks>().is_err());
+}
+
+#[tokio::test]
+async fn sensors_are_given_up_on_at_the_cycle_deadline() {
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(WedgedBackend))
+        .sink(Arc::new(Memory::new()))
+        .cycle_deadline(Duration::from_millis(500))
+        .build()
+        .unwrap();
+    let state = service.state().clone();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    running.abort();
+
+    let kitchen = &state.snapshot()[0];
+    assert_eq!(kitchen.status, SensorStatus::Failing);
+    assert_eq!(
+        kitchen.last_error.as_deref(),
+        Some("no valid reading within the cycle's 500ms deadline")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn readings_are_exposed_in_the_hwmon_layout() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("hwmon");
+    std::fs::create_dir_all(&dir).unwrap();
+    let backend = MockBackend::new();
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 21.4,
+            humidity: 40.2,
+        }),
+    );
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .hwmon(&dir)
+        .build()
+        .unwrap();
+
+    let mut readings = service.subscribe();
+    let read = async {
+        readings.recv().await.unwrap();
+        let device = dir.join("hwmon0");
+        let mut listed = std::fs::read_dir(&device)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        listed.sort();
+        let files = listed
+            .iter()
+            .map(|file| std::fs::read_to_string(device.join(file)).unwrap())
+            .collect::<Vec<_>>();
+        service.shutdown();
+        (listed, files)
+    };
+    let run = service.run();
+    tokio::pin!(run);
+    let files = tokio::select! {
+        result = &mut run => match result {
+            // Mounting needs root and /dev/fuse, which not every machine running the tests has
+            Err(Error::Hwmon(err)) => return eprintln!("Skipping, unable to mount: {}", err),
+            result => panic!("stopped before reading: {:?}", result),
+        },
+        files = read => files,
+    };
+    run.await.unwrap();
+
+    assert_eq!(files.0, ["humidity1_input", "name", "temp1_input"]);
+    assert_eq!(files.1, ["40200\n", "kitchen\n", "21400\n"]);
+    assert!(!dir.join("hwmon0").exists());
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::sensors;
+use monitoring::simulate;
+
+#[test]
+fn simulated_readings_follow_a_daily_cycle() {
+    let sensors = sensors(concat!(
+        "- name: kitchen\n  pin: 4\n",
+        "- name: attic\n  pin: 5\n  interval: 3600\n",
+        "- name: cellar\n  pin: 6\n  disabled: true\n",
+    ));
+    let (from, to) = (1_700_006_400, 1_700_006_400 + 2 * 86_400);
+
+    let readings = simulate::readings(&sensors, 900, from, to, 7);
+    let series = |name: &str| {
+        readings
+            .iter()
+            .filter(|datapoint| datapoint.name == name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(series("kitchen.temperature").len(), 2 * 96 + 1);
+    assert_eq!(series("attic.humidity").len(), 2 * 24 + 1);
+    assert!(series("cellar.temperature").is_empty());
+    assert!(readings.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    assert_eq!(
+        simulate::readings(&sensors, 900, from, to, 7)[100].value,
+        readings[100].value
+    );
+
+    // Warmer in the afternoon than before dawn
+    let at = |hour: i64| {
+        series("kitchen.temperature")
+            .iter()
+            .filter(|datapoint| datapoint.time % 86_400 / 3600 == hour)
+            .map(|datapoint| datapoint.value)
+            .sum::<f64>()
+    };
+    assert!(at(15) > at(3) + 10.0);
+    assert!(series("kitchen.humidity")
+        .iter()
+        .all(|datapoint| (0.0..=100.0).contains(&datapoint.value)));
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{free_addr, sensors};
+use monitoring::{
+    sensors::{MockBackend, Reading},
+    service::MonitorService,
+    sinks::Memory,
+    snmp::SnmpConfig,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::net::UdpSocket;
+
+/// `1.3.6.1.4.1.8072.9999.9999`, the default base OID
+const BASE: [u8; 11] = [
+    0x2b, 0x06, 0x01, 0x04, 0x01, 0xbf, 0x08, 0xce, 0x0f, 0xce, 0x0f,
+];
+
+fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+    [&[tag, contents.len() as u8][..], contents].concat()
+}
+
+/// A `GetNextRequest` for `<base>.1.1.4`, the temperature column
+fn get_next_temperature(community: &[u8]) -> Vec<u8> {
+    let oid = [&BASE[..], &[0x01, 0x01, 0x04]].concat();
+    let binding = tlv(0x30, &[tlv(0x06, &oid), tlv(0x05, &[])].concat());
+    let pdu = [
+        tlv(0x02, &[0x07]), // request-id
+        tlv(0x02, &[0x00]), // error-status
+        tlv(0x02, &[0x00]), // error-index
+        tlv(0x30, &binding),
+    ]
+    .concat();
+    let message = [
+        tlv(0x02, &[0x01]), // SNMPv2c
+        tlv(0x04, community),
+        tlv(0xa1, &pdu),
+    ]
+    .concat();
+    tlv(0x30, &message)
+}
+
+/// A `GetBulkRequest` walking the whole table from `columns` places at once, as many times as
+/// the agent allows
+fn get_bulk(community: &[u8], columns: usize) -> Vec<u8> {
+    let binding = tlv(0x30, &[tlv(0x06, &BASE), tlv(0x05, &[])].concat());
+    let pdu = [
+        tlv(0x02, &[0x08]), // request-id
+        tlv(0x02, &[0x00]), // non-repeaters
+        tlv(0x02, &[0x7f]), // max-repetitions
+        tlv(0x30, &binding.repeat(columns)),
+    ]
+    .concat();
+    let message = [
+        tlv(0x02, &[0x01]), // SNMPv2c
+        tlv(0x04, community),
+        tlv(0xa5, &pdu),
+    ]
+    .concat();
+    tlv(0x30, &message)
+}
+
+#[tokio::test]
+async fn readings_are_walked_over_snmp() {
+    let backend = MockBackend::new();
+    backend.push(
+        4,
+        Ok(Reading {
+            temperature: 21.5,
+            humidity: 40.0,
+        }),
+    );
+    let addr = free_addr();
+    let service = Arc::new(
+        MonitorService::builder()
+            .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+            .backend(Arc::new(backend))
+            .sink(Arc::new(Memory::new()))
+            .snmp(SnmpConfig::new(addr, "secret"))
+            .build()
+            .unwrap(),
+    );
+    let mut readings = service.subscribe();
+    let running = tokio::spawn({
+        let service = service.clone();
+        async move { service.run().await }
+    });
+    readings.recv().await.unwrap();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(addr).await.unwrap();
+    let mut response = vec![0; 1500];
+
+    // Queries with another community go unanswered
+    socket.send(&get_next_temperature(b"public")).await.unwrap();
+    let ignored = tokio::time::timeout(Duration::from_millis(300), socket.recv(&mut response));
+    assert!(ignored.await.is_err());
+
+    socket.send(&get_next_temperature(b"secret")).await.unwrap();
+    let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    let row = [&BASE[..], &[0x01, 0x01, 0x04, 0x01]].concat();
+    // The kitchen's temperature, in hundredths of a degree, as the last binding
+    let binding = tlv(0x30, &[tlv(0x06, &row), tlv(0x02, &[0x08, 0x66])].concat());
+    assert!(response[..len].ends_with(&binding));
+
+    // Answered with as many bindings as fit a datagram, rather than all 6 × 50 of them
+    socket.send(&get_bulk(b"secret", 6)).await.unwrap();
+    let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(len <= 1400);
+    assert_eq!(response[..2], [0x30, 0x82]);
+    assert_eq!(
+        usize::from(u16::from_be_bytes([response[2], response[3]])),
+        len - 4
+    );
+
+    service.shutdown();
+    running.await.unwrap().unwrap();
+}
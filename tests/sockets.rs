@@ -0,0 +1,93 @@
+#![cfg(unix)]
+
+use monitoring::{
+    sinks::{Sink, SocketSink},
+    Datapoint,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::UnixListener,
+};
+
+fn readings() -> Vec<Datapoint> {
+    ["kitchen.temperature", "kitchen.co2"]
+        .into_iter()
+        .map(|name| Datapoint {
+            name: name.to_string(),
+            interval: 60,
+            value: 21.5,
+            time: 1_700_000_000,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn readings_are_written_to_local_agents_sockets() {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("sockets");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let collectd = UnixListener::bind(dir.join("collectd.sock")).unwrap();
+    let received = tokio::spawn(async move {
+        let (socket, _) = collectd.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut commands = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            commands.push(line);
+            writer
+                .write_all(b"0 Success: 1 value has been dispatched.\n")
+                .await
+                .unwrap();
+        }
+        commands
+    });
+    let sink = format!("collectd:{}", dir.join("collectd.sock").display())
+        .parse::<SocketSink>()
+        .unwrap();
+    sink.write(&readings()).await.unwrap();
+    drop(sink);
+    let commands = received.await.unwrap();
+    assert_eq!(commands.len(), 2);
+    assert!(commands[0].ends_with("/monitoring-kitchen/temperature\" interval=60 1700000000:21.5"));
+    assert!(commands[1].ends_with("/monitoring-kitchen/gauge-co2\" interval=60 1700000000:21.5"));
+
+    let telegraf = UnixListener::bind(dir.join("telegraf.sock")).unwrap();
+    let received = tokio::spawn(async move {
+        let (mut socket, _) = telegraf.accept().await.unwrap();
+        let mut lines = String::new();
+        socket.read_to_string(&mut lines).await.unwrap();
+        lines
+    });
+    let sink = format!("influx:{}", dir.join("telegraf.sock").display())
+        .parse::<SocketSink>()
+        .unwrap();
+    sink.write(&readings()).await.unwrap();
+    assert_eq!(
+        received.await.unwrap(),
+        concat!(
+            "temperature,sensor=kitchen value=21.5 1700000000000000000\n",
+            "co2,sensor=kitchen value=21.5 1700000000000000000\n",
+        )
+    );
+    assert!("statsd:/run/statsd.sock".parse::<SocketSink>().is_err());
+}
+
+#[tokio::test]
+async fn dry_runs_write_nothing_to_the_socket() {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("dry-run.sock");
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+
+    for agent in ["collectd", "influx"] {
+        let sink = format!("{}:{}", agent, path.display())
+            .parse::<SocketSink>()
+            .unwrap()
+            .dry_run();
+        sink.write(&readings()).await.unwrap();
+    }
+
+    let accepted =
+        tokio::time::timeout(std::time::Duration::from_millis(200), listener.accept()).await;
+    assert!(accepted.is_err());
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "http")]
+
+mod common;
+
+use chrono::{Datelike, Local, NaiveTime, TimeZone, Timelike, Weekday};
+use common::{next_request, sensors, spawn_server};
+use hyper::StatusCode;
+use monitoring::{
+    notify::Notifier,
+    sensors::{MockBackend, Reading},
+    service::MonitorService,
+    sinks::Memory,
+    summary::{Period, SummaryConfig},
+};
+use std::{sync::Arc, time::Duration};
+
+#[test]
+fn summaries_are_due_at_their_time_of_day() {
+    let notifier: Notifier = "ntfy:https://ntfy.sh/greenhouse".parse().unwrap();
+    // A Wednesday
+    let now = Local.with_ymd_and_hms(2026, 10, 14, 9, 0, 0).unwrap();
+
+    let mut daily = SummaryConfig::new(Period::Daily, notifier.clone());
+    let due = daily.next_due(now);
+    assert_eq!((due.day(), due.hour()), (15, 8));
+    daily.at = NaiveTime::from_hms_opt(18, 30, 0).unwrap();
+    let due = daily.next_due(now);
+    assert_eq!((due.day(), due.hour(), due.minute()), (14, 18, 30));
+
+    let weekly = SummaryConfig::new(Period::Weekly, notifier);
+    let due = weekly.next_due(now);
+    assert_eq!(
+        (due.weekday(), due.day(), due.hour()),
+        (Weekday::Mon, 19, 8)
+    );
+}
+
+#[test]
+fn notifiers_are_parsed_from_their_kind_and_address() {
+    assert_eq!(
+        "telegram:-1001234:123456:ABC-DEF".parse::<Notifier>(),
+        Ok(Notifier::Telegram {
+            chat_id: "-1001234".to_string(),
+            bot_token: "123456:ABC-DEF".to_string(),
+        })
+    );
+    assert!("telegram:-1001234".parse::<Notifier>().is_err());
+    assert!("email:me@example.com".parse::<Notifier>().is_err());
+}
+
+#[tokio::test]
+async fn summaries_report_each_series_since_the_last_one() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let backend = MockBackend::new();
+    for temperature in [18.0, 22.0] {
+        backend.push(
+            4,
+            Ok(Reading {
+                temperature,
+                humidity: 40.0,
+            }),
+        );
+    }
+
+    let mut summary = SummaryConfig::new(Period::Daily, Notifier::Ntfy { url });
+    summary.at = (Local::now() + chrono::Duration::seconds(4)).time();
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n  interval: 3\n"))
+        .backend(Arc::new(backend))
+        .sink(Arc::new(Memory::new()))
+        .summary(summary)
+        .build()
+        .unwrap();
+    let running = tokio::spawn(async move { service.run().await });
+
+    let request = next_request(&mut requests).await;
+    running.abort();
+
+    assert_eq!(request.headers["title"], "Daily summary");
+    let lines = request.body.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with("Since "));
+    assert_eq!(lines[1], "kitchen.humidity: min 40.0, avg 40.0, max 40.0");
+    assert_eq!(
+        lines[2],
+        "kitchen.temperature: min 18.0, avg 20.0, max 22.0"
+    );
+}
+
+#[tokio::test]
+async fn dry_runs_send_no_summary() {
+    let (url, mut requests) = spawn_server(StatusCode::OK);
+    let mut summary = SummaryConfig::new(Period::Daily, Notifier::Ntfy { url });
+    summary.at = (Local::now() + chrono::Duration::seconds(1)).time();
+    summary.dry_run = true;
+    let service = MonitorService::builder()
+        .sensors(sensors("- name: kitchen\n  pin: 4\n"))
+        .backend(Arc::new(MockBackend::new()))
+        .sink(Arc::new(Memory::new()))
+        .summary(summary)
+        .build()
+        .unwrap();
+    let running = tokio::spawn(async move { service.run().await });
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    running.abort();
+
+    assert!(requests.try_recv().is_err());
+}